@@ -0,0 +1,816 @@
+use super::bsdf::Lambertian;
+use super::camera::Camera;
+use super::color::Color;
+use super::film::SplatBuffer;
+use super::light::{Light, SHADOW_EPSILON};
+use super::light_sampler::{LightSampler, PowerLightSampler};
+use super::material::{self, Material};
+use super::math::{Normal3, Point3, Ray, Vector3};
+use super::primitive::{Intersection, Scene};
+use rand::Rng;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+/// A point on a camera or light subpath.
+#[derive(Debug, Clone, Copy)]
+struct Vertex {
+    kind: VertexKind,
+    point: Point3,
+    /// The geometric normal, for vertices on a surface.
+    normal: Option<Normal3>,
+    /// Unit vector towards the previous vertex of the subpath.
+    wo: Vector3,
+    bsdf: Option<Lambertian>,
+    /// Index of the light the vertex is on, for light vertices and for
+    /// surfaces of area lights on camera subpaths.
+    light: Option<usize>,
+    /// Radiance a surface on a camera subpath emits along `wo`.
+    emitted: Color,
+    /// Throughput of the subpath up to this vertex over its density.
+    beta: Color,
+    /// Density of the vertex with respect to area, as sampled from the
+    /// previous vertex of its subpath.
+    pdf_fwd: f64,
+    /// Density of the vertex with respect to area, had it been sampled from
+    /// the next vertex of its subpath instead.
+    pdf_rev: f64,
+    /// Whether the vertex stands for a direction towards a light at
+    /// infinity rather than a point.
+    infinite: bool,
+}
+
+impl Vertex {
+    fn new(kind: VertexKind, point: Point3, beta: Color) -> Vertex {
+        Vertex {
+            kind,
+            point,
+            normal: None,
+            wo: Vector3::new(0.0, 0.0, 0.0),
+            bsdf: None,
+            light: None,
+            emitted: Color::black(),
+            beta,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            infinite: false,
+        }
+    }
+
+    /// The normal to shade with, for vertices on a surface.
+    fn shading_normal(&self) -> Option<Normal3> {
+        self.bsdf.map(|bsdf| bsdf.normal()).or(self.normal)
+    }
+
+    /// Unit vector from this vertex towards `other`.
+    fn direction_to(&self, other: &Vertex) -> Vector3 {
+        (other.point - self.point).normalized()
+    }
+
+    /// The fraction of light arriving from `next` that a surface scatters
+    /// back along `wo`. Light subpaths carry importance the other way, which
+    /// shading normals make asymmetric.
+    fn f(&self, next: &Vertex, importance: bool) -> Color {
+        let bsdf = match &self.bsdf {
+            Some(bsdf) => bsdf,
+            None => return Color::black(),
+        };
+        let wi = self.direction_to(next);
+        let f = bsdf.f(self.wo, wi);
+        if importance {
+            f * shading_correction(self, self.wo, wi)
+        } else {
+            f
+        }
+    }
+
+    /// Turn the density `pdf` of sampling the direction towards `next`,
+    /// with respect to solid angle, into a density with respect to area at
+    /// `next`.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        if next.infinite {
+            return pdf;
+        }
+        let w = next.point - self.point;
+        let dist_sq = w.mag_sq();
+        if dist_sq == 0.0 {
+            return 0.0;
+        }
+        let cos = match next.normal {
+            Some(normal) => normal.dot(w).abs() / dist_sq.sqrt(),
+            None => 1.0,
+        };
+        pdf * cos / dist_sq
+    }
+}
+
+/// pbrt's factor for the light a surface scatters from `wi` to `wo` along a
+/// light subpath, which makes up for shading normals breaking the symmetry
+/// of the BSDF.
+fn shading_correction(vertex: &Vertex, wo: Vector3, wi: Vector3) -> f64 {
+    let (ng, ns) = match (vertex.normal, vertex.shading_normal()) {
+        (Some(ng), Some(ns)) => (ng, ns),
+        _ => return 1.0,
+    };
+    let denominator = ng.dot(wo).abs() * ns.dot(wi).abs();
+    if denominator == 0.0 {
+        return 0.0;
+    }
+    ns.dot(wo).abs() * ng.dot(wi).abs() / denominator
+}
+
+/// Make zero densities, which belong to strategies that can never happen,
+/// harmless in ratios.
+fn remap0(pdf: f64) -> f64 {
+    if pdf == 0.0 {
+        1.0
+    } else {
+        pdf
+    }
+}
+
+/// A bidirectional path tracer for diffuse surfaces (Veach 1997).
+///
+/// Each camera sample traces a subpath from the camera and another from a
+/// light, and joins every prefix of one to every prefix of the other. Each
+/// way of building a path is weighted against all the others that could
+/// have built it with the power heuristic. Paths that reach the camera
+/// through another pixel are splatted onto the film.
+///
+/// Lights are picked in proportion to their power. Lights that can't start
+/// subpaths can only be reached by a camera subpath sampling them directly.
+pub struct BidirectionalPathTracer<'a, S: ?Sized> {
+    scene: &'a S,
+    lights: &'a [Box<dyn Light>],
+    materials: &'a [Box<dyn Material>],
+    camera: &'a Camera,
+    light_sampler: PowerLightSampler,
+    /// Whether each light can start subpaths.
+    emits_paths: Vec<bool>,
+    max_bounces: u32,
+}
+
+impl<'a, S: Scene + ?Sized> BidirectionalPathTracer<'a, S> {
+    /// Trace paths through `scene` as seen by `camera`, scattering at most
+    /// `max_bounces` times.
+    pub fn new(
+        scene: &'a S,
+        lights: &'a [Box<dyn Light>],
+        materials: &'a [Box<dyn Material>],
+        camera: &'a Camera,
+        max_bounces: u32,
+    ) -> BidirectionalPathTracer<'a, S> {
+        let emits_paths = lights
+            .iter()
+            .map(|light| light.sample_le((0.5, 0.5), (0.5, 0.5)).is_some())
+            .collect();
+        BidirectionalPathTracer {
+            scene,
+            lights,
+            materials,
+            camera,
+            light_sampler: PowerLightSampler::new(lights),
+            emits_paths,
+            max_bounces,
+        }
+    }
+
+    /// Return the radiance arriving at the camera through the raster
+    /// position (x, y), and splat the light reaching it through other
+    /// pixels into `film`. The film should be scaled by one over the number
+    /// of samples taken per pixel.
+    pub fn li<R: Rng + ?Sized>(
+        &self,
+        x: f64,
+        y: f64,
+        film: &SplatBuffer,
+        rng: &mut R,
+    ) -> Color {
+        let (camera_path, mut radiance) = self.camera_path(x, y, rng);
+        let light_path = self.light_path(rng);
+
+        // sampling a light directly doesn't need the light subpath, which is
+        // empty when the light picked for it can't start one
+        let max_s = light_path.len().max(1);
+        for t in 1..=camera_path.len() {
+            for s in 0..=max_s {
+                let depth = s as i64 + t as i64 - 2;
+                if (s == 1 && t == 1) || depth < 0 {
+                    continue;
+                }
+                if depth > self.max_bounces as i64 {
+                    continue;
+                }
+                if t == 1 {
+                    if let Some((x, y, light)) =
+                        self.connect_to_camera(&light_path, s)
+                    {
+                        film.splat(x, y, light);
+                    }
+                } else {
+                    radiance +=
+                        self.connect(&light_path, &camera_path, s, t, rng);
+                }
+            }
+        }
+
+        radiance
+    }
+
+    /// Trace a subpath from the camera through the raster position (x, y).
+    /// Also returns the light from lights at infinity that the subpath
+    /// escapes towards.
+    fn camera_path<R: Rng + ?Sized>(
+        &self,
+        x: f64,
+        y: f64,
+        rng: &mut R,
+    ) -> (Vec<Vertex>, Color) {
+        let ray = self.camera.ray(x, y);
+        let pdf_dir = self.camera.pdf_dir(ray.direction);
+        let mut path = Vec::with_capacity(self.max_bounces as usize + 2);
+        let white = Color::new(1.0, 1.0, 1.0);
+        path.push(Vertex::new(VertexKind::Camera, ray.origin, white));
+        let escaped = self.walk(ray, white, pdf_dir, false, &mut path, rng);
+        (path, escaped)
+    }
+
+    /// Trace a subpath from a light picked by power.
+    fn light_path<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec<Vertex> {
+        let mut path = Vec::with_capacity(self.max_bounces as usize + 1);
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let up = Normal3::new(0.0, 1.0, 0.0);
+        let picked = self.light_sampler.sample(origin, up, rng.gen());
+        let (index, pmf) = match picked {
+            Some(picked) => picked,
+            None => return path,
+        };
+        let u_pos = (rng.gen(), rng.gen());
+        let u_dir = (rng.gen(), rng.gen());
+        let sample = match self.lights[index].sample_le(u_pos, u_dir) {
+            Some(sample) => sample,
+            None => return path,
+        };
+        if sample.pdf_pos == 0.0
+            || sample.pdf_dir == 0.0
+            || sample.radiance == Color::black()
+        {
+            return path;
+        }
+
+        let pdf_pos = pmf * sample.pdf_pos;
+        let mut vertex = Vertex::new(
+            VertexKind::Light,
+            sample.point,
+            sample.radiance * (1.0 / pdf_pos),
+        );
+        vertex.normal = sample.normal;
+        vertex.light = Some(index);
+        vertex.pdf_fwd = pdf_pos;
+        path.push(vertex);
+
+        let cos = sample.normal.map_or(1.0, |n| n.dot(sample.w).abs());
+        let beta = sample.radiance * (cos / (pdf_pos * sample.pdf_dir));
+        let ray = Ray::new(sample.point, sample.w);
+        self.walk(ray, beta, sample.pdf_dir, true, &mut path, rng);
+        path
+    }
+
+    /// Extend `path` along `ray` by sampling BSDFs, starting with the
+    /// throughput `beta` and the density `pdf_dir` of the ray's direction.
+    /// Camera subpaths return the light they escape towards.
+    fn walk<R: Rng + ?Sized>(
+        &self,
+        mut ray: Ray,
+        mut beta: Color,
+        mut pdf_dir: f64,
+        from_light: bool,
+        path: &mut Vec<Vertex>,
+        rng: &mut R,
+    ) -> Color {
+        // a light subpath's first vertex is on the light, and a camera's
+        // last may be on one
+        let max_vertices = self.max_bounces as usize
+            + if from_light { 1 } else { 2 };
+
+        while path.len() < max_vertices {
+            let hit = match self.scene.intersect(&ray) {
+                Some(hit) => hit,
+                None if from_light => break,
+                None => return self.escaped(path, &ray, beta),
+            };
+
+            let wo = -ray.direction;
+            let vertex = self.surface(&hit, wo, beta, from_light);
+            let prev = path.len() - 1;
+            let pdf_fwd = path[prev].convert_density(pdf_dir, &vertex);
+            path.push(Vertex { pdf_fwd, ..vertex });
+            if path.len() == max_vertices {
+                break;
+            }
+
+            let bsdf = vertex.bsdf.unwrap();
+            let sample = match bsdf.sample_f(wo, (rng.gen(), rng.gen())) {
+                Some(sample) => sample,
+                None => break,
+            };
+            let cos = bsdf.normal().dot(sample.wi).abs();
+            let mut factor = cos / sample.pdf;
+            if from_light {
+                factor *= shading_correction(&vertex, wo, sample.wi);
+            }
+            beta = beta * sample.f * factor;
+            pdf_dir = sample.pdf;
+
+            // the density of the previous vertex had it been sampled from
+            // this one
+            let pdf_rev = bsdf.pdf(sample.wi, wo);
+            path[prev].pdf_rev = vertex.convert_density(pdf_rev, &path[prev]);
+
+            if beta == Color::black() {
+                break;
+            }
+            ray = Ray::new(hit.point, sample.wi);
+        }
+
+        Color::black()
+    }
+
+    /// A vertex for the surface at `hit`, seen along `-wo`.
+    fn surface(
+        &self,
+        hit: &Intersection,
+        wo: Vector3,
+        beta: Color,
+        from_light: bool,
+    ) -> Vertex {
+        let albedo = material::albedo(self.materials, hit);
+        let normal = hit.shading_normal.face_forward(wo);
+        let mut vertex = Vertex::new(VertexKind::Surface, hit.point, beta);
+        vertex.normal = Some(hit.normal);
+        vertex.wo = wo;
+        vertex.bsdf = Some(Lambertian::new(albedo, normal));
+        if !from_light {
+            vertex.emitted = hit.emitted;
+            vertex.light = hit.light;
+        }
+        vertex
+    }
+
+    /// Light from lights at infinity arriving along `ray`, which left the
+    /// last vertex of the camera subpath `path` and hit nothing.
+    fn escaped(&self, path: &[Vertex], ray: &Ray, beta: Color) -> Color {
+        let last = &path[path.len() - 1];
+        let mut radiance = Color::black();
+        for (index, light) in self.lights.iter().enumerate() {
+            let le = light.le(ray);
+            if le == Color::black() {
+                continue;
+            }
+            // weigh the light against sampling it from the last vertex
+            let weight = match &last.bsdf {
+                Some(bsdf) => {
+                    let pdf_bsdf = bsdf.pdf(last.wo, ray.direction);
+                    let pdf_light = self.pmf(index)
+                        * light.pdf(last.point, ray.direction);
+                    let (p, q) = (pdf_bsdf * pdf_bsdf, pdf_light * pdf_light);
+                    p / (p + q)
+                }
+                None => 1.0,
+            };
+            radiance += beta * le * weight;
+        }
+        radiance
+    }
+
+    /// The probability of picking the light at `index`.
+    fn pmf(&self, index: usize) -> f64 {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let up = Normal3::new(0.0, 1.0, 0.0);
+        self.light_sampler.pmf(origin, up, index)
+    }
+
+    /// The light arriving through the first `t` vertices of `camera` from
+    /// the first `s` vertices of `light`, with `t` at least 2, weighted
+    /// against the other ways of building the same path.
+    fn connect<R: Rng + ?Sized>(
+        &self,
+        light: &[Vertex],
+        camera: &[Vertex],
+        s: usize,
+        t: usize,
+        rng: &mut R,
+    ) -> Color {
+        let pt = &camera[t - 1];
+        let mut sampled = None;
+        let radiance = if s == 0 {
+            // the camera subpath found a light by itself
+            pt.beta * pt.emitted
+        } else if s == 1 {
+            match self.sample_light(pt, rng) {
+                Some((vertex, radiance)) => {
+                    sampled = Some(vertex);
+                    radiance
+                }
+                None => return Color::black(),
+            }
+        } else {
+            let qs = &light[s - 1];
+            let radiance = qs.beta
+                * qs.f(pt, true)
+                * pt.f(qs, false)
+                * pt.beta
+                * self.geometry(qs, pt);
+            if radiance != Color::black() && !self.visible(pt, qs) {
+                return Color::black();
+            }
+            radiance
+        };
+
+        if radiance == Color::black() {
+            return Color::black();
+        }
+        radiance * self.mis_weight(light, camera, sampled.as_ref(), s, t)
+    }
+
+    /// Join the first `s` vertices of `light`, with `s` at least 2, straight
+    /// to the camera. Returns the raster position the light arrives at and
+    /// how much arrives, if any does.
+    fn connect_to_camera(
+        &self,
+        light: &[Vertex],
+        s: usize,
+    ) -> Option<(f64, f64, Color)> {
+        let qs = &light[s - 1];
+        let (x, y) = self.camera.raster(qs.point)?;
+        let position = self.camera.position();
+        let to_camera = position - qs.point;
+        let dist_sq = to_camera.mag_sq();
+        let w = -to_camera / dist_sq.sqrt();
+
+        // the importance of the camera over the density of having picked
+        // it from `qs`, with respect to solid angle
+        let cos = self.camera.cos_theta(w);
+        let importance = self.camera.importance(w) * cos / dist_sq;
+        let vertex = Vertex::new(
+            VertexKind::Camera,
+            position,
+            Color::new(importance, importance, importance),
+        );
+
+        let ns = qs.shading_normal()?;
+        let radiance = qs.beta
+            * qs.f(&vertex, true)
+            * vertex.beta
+            * ns.dot(w).abs();
+        if radiance == Color::black() || !self.visible(qs, &vertex) {
+            return None;
+        }
+        let weight = self.mis_weight(light, &[], Some(&vertex), s, 1);
+        Some((x, y, radiance * weight))
+    }
+
+    /// Sample a light from the camera subpath vertex `pt`, returning a
+    /// vertex on it and the light arriving along the whole path.
+    fn sample_light<R: Rng + ?Sized>(
+        &self,
+        pt: &Vertex,
+        rng: &mut R,
+    ) -> Option<(Vertex, Color)> {
+        let ns = pt.shading_normal()?;
+        let (index, pmf) = self.light_sampler.sample(pt.point, ns, rng.gen())?;
+        let light = &self.lights[index];
+        let sample = light.sample_li(pt.point, (rng.gen(), rng.gen()))?;
+        if sample.pdf == 0.0 || sample.radiance == Color::black() {
+            return None;
+        }
+
+        let infinite = sample.distance.is_infinite();
+        let point = if infinite {
+            pt.point + sample.wi
+        } else {
+            pt.point + sample.wi * sample.distance
+        };
+        let beta = sample.radiance * (1.0 / (sample.pdf * pmf));
+        let mut vertex = Vertex::new(VertexKind::Light, point, beta);
+        vertex.normal = sample.normal;
+        vertex.light = Some(index);
+        vertex.infinite = infinite;
+        vertex.pdf_fwd = if self.emits_paths[index] {
+            self.pdf_light_origin(&vertex, pt)
+        } else {
+            pmf * light.pdf(pt.point, sample.wi)
+        };
+
+        let radiance =
+            pt.beta * pt.f(&vertex, false) * beta * ns.dot(sample.wi).abs();
+        if radiance == Color::black() {
+            return None;
+        }
+        if !sample.unoccluded(self.scene, pt.point) {
+            return None;
+        }
+        Some((vertex, radiance))
+    }
+
+    /// The geometry term between two vertices joined by a segment.
+    fn geometry(&self, a: &Vertex, b: &Vertex) -> f64 {
+        let w = b.point - a.point;
+        let dist_sq = w.mag_sq();
+        let w = w / dist_sq.sqrt();
+        let cos_a = a.shading_normal().map_or(1.0, |n| n.dot(w).abs());
+        let cos_b = b.shading_normal().map_or(1.0, |n| n.dot(w).abs());
+        cos_a * cos_b / dist_sq
+    }
+
+    /// Whether nothing in the scene lies between two vertices.
+    fn visible(&self, a: &Vertex, b: &Vertex) -> bool {
+        let w = b.point - a.point;
+        let distance = w.mag();
+        match self.scene.intersect(&Ray::new(a.point, w / distance)) {
+            Some(occluder) => occluder.t >= distance * (1.0 - SHADOW_EPSILON),
+            None => true,
+        }
+    }
+
+    /// The density with which `vertex` samples `next` after being reached
+    /// from `prev`, with respect to area at `next`.
+    fn pdf(
+        &self,
+        vertex: &Vertex,
+        prev: Option<&Vertex>,
+        next: &Vertex,
+    ) -> f64 {
+        let wi = vertex.direction_to(next);
+        let pdf = match vertex.kind {
+            VertexKind::Camera => self.camera.pdf_dir(wi),
+            VertexKind::Light => return self.pdf_light(vertex, next),
+            VertexKind::Surface => match (&vertex.bsdf, prev) {
+                (Some(bsdf), Some(prev)) => {
+                    bsdf.pdf(vertex.direction_to(prev), wi)
+                }
+                _ => 0.0,
+            },
+        };
+        vertex.convert_density(pdf, next)
+    }
+
+    /// The density with which the light `vertex` is on sends light towards
+    /// `next`, with respect to area at `next`.
+    fn pdf_light(&self, vertex: &Vertex, next: &Vertex) -> f64 {
+        let index = match vertex.light {
+            Some(index) if !vertex.infinite => index,
+            _ => return 0.0,
+        };
+        let w = vertex.direction_to(next);
+        let (_, pdf_dir) = self.lights[index].pdf_le(vertex.normal, w);
+        vertex.convert_density(pdf_dir, next)
+    }
+
+    /// The density of a light subpath starting at `vertex` on its way to
+    /// `next`, with respect to area on the light.
+    fn pdf_light_origin(&self, vertex: &Vertex, next: &Vertex) -> f64 {
+        let index = match vertex.light {
+            Some(index) if !vertex.infinite => index,
+            _ => return 0.0,
+        };
+        let w = vertex.direction_to(next);
+        let (pdf_pos, _) = self.lights[index].pdf_le(vertex.normal, w);
+        self.pmf(index) * pdf_pos
+    }
+
+    /// The power heuristic weight of the path built from `s` light and `t`
+    /// camera vertices against every other way of building it. `sampled`
+    /// stands in for the last light vertex when `s` is 1, and for the
+    /// camera when `t` is 1.
+    fn mis_weight(
+        &self,
+        light: &[Vertex],
+        camera: &[Vertex],
+        sampled: Option<&Vertex>,
+        s: usize,
+        t: usize,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+
+        // the vertices either side of the join, and the ones before them
+        let (qs, pt) = match (s, t) {
+            (0, _) => (None, &camera[t - 1]),
+            (1, _) => (sampled, &camera[t - 1]),
+            (_, 1) => (Some(&light[s - 1]), sampled.expect("no camera")),
+            _ => (Some(&light[s - 1]), &camera[t - 1]),
+        };
+        let qs_minus = if s >= 2 { Some(&light[s - 2]) } else { None };
+        let pt_minus = if t >= 2 { Some(&camera[t - 2]) } else { None };
+
+        // emitters that aren't among the lights can only be found by chance
+        let origin = match (s, qs) {
+            (0, _) => pt,
+            (1, Some(qs)) => qs,
+            _ => &light[0],
+        };
+        let index = match origin.light {
+            Some(index) => index,
+            None => return 1.0,
+        };
+        let emits_paths = self.emits_paths[index];
+        let delta_light = self.lights[index].is_delta();
+
+        // the densities of the vertices next to the join had the path been
+        // built from the other end
+        let pt_rev = match (qs, pt_minus) {
+            (Some(qs), _) => self.pdf(qs, qs_minus, pt),
+            (None, Some(pt_minus)) => self.pdf_light_origin(pt, pt_minus),
+            (None, None) => 0.0,
+        };
+        let pt_minus_rev = pt_minus.map(|pt_minus| match qs {
+            Some(qs) => self.pdf(pt, Some(qs), pt_minus),
+            None => self.pdf_light(pt, pt_minus),
+        });
+        let qs_rev = qs.map(|qs| self.pdf(pt, pt_minus, qs));
+        let qs_minus_rev = match (qs, qs_minus) {
+            (Some(qs), Some(qs_minus)) => {
+                Some(self.pdf(qs, Some(pt), qs_minus))
+            }
+            _ => None,
+        };
+
+        let ratio = |rev: f64, fwd: f64| {
+            let r = remap0(rev) / remap0(fwd);
+            r * r
+        };
+        let mut sum = 0.0;
+
+        // strategies with fewer camera vertices
+        let mut r = 1.0;
+        for i in (1..t).rev() {
+            let rev = match (i == t - 1, i + 2 == t) {
+                (true, _) => pt_rev,
+                (_, true) => pt_minus_rev.unwrap_or(0.0),
+                _ => camera[i].pdf_rev,
+            };
+            r *= ratio(rev, camera[i].pdf_fwd);
+            // only some lights can start the light subpath this would take
+            if emits_paths || s + t - i < 2 {
+                sum += r;
+            }
+        }
+
+        // strategies with fewer light vertices
+        let mut r = 1.0;
+        for i in (0..s).rev() {
+            let (fwd, rev) = if i == s - 1 {
+                (qs.map_or(0.0, |qs| qs.pdf_fwd), qs_rev.unwrap_or(0.0))
+            } else if i + 2 == s {
+                (light[i].pdf_fwd, qs_minus_rev.unwrap_or(0.0))
+            } else {
+                (light[i].pdf_fwd, light[i].pdf_rev)
+            };
+            r *= ratio(rev, fwd);
+            // nothing hits a point light by chance
+            if i > 0 || !delta_light {
+                sum += r;
+            }
+        }
+
+        1.0 / (1.0 + sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::light::{self, AreaLight, DirectionalLight};
+    use super::super::light::{PointLight, SpotLight};
+    use super::super::light_sampler::{light_sampler, LightSampling};
+    use super::super::math::{point, vector};
+    use super::super::path::PathTracer;
+    use super::super::primitive::{CornellBox, Parallelogram, Sphere};
+    use super::*;
+    use std::sync::Arc;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use ultraviolet::mat::DMat4;
+
+    /// The average luminance of each quarter of the image with the path
+    /// tracer and with the bidirectional path tracer.
+    fn quarters<S: Scene>(
+        scene: &S,
+        lights: &[Box<dyn Light>],
+        materials: &[Box<dyn Material>],
+        samples: u32,
+    ) -> Vec<(f64, f64)> {
+        let (width, height) = (16, 16);
+        let fov = f64::to_radians(100.0);
+        let camera = Camera::new(DMat4::identity(), fov, width, height);
+        let max_bounces = 3;
+
+        let sampler = light_sampler(LightSampling::Power, lights);
+        let path =
+            PathTracer::new(scene, lights, materials, sampler, max_bounces);
+        let bdpt = BidirectionalPathTracer::new(
+            scene,
+            lights,
+            materials,
+            &camera,
+            max_bounces,
+        );
+        let film = SplatBuffer::new(width, height);
+
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut expected = [Color::black(); 4];
+        let mut quarters = [Color::black(); 4];
+        let quarter = |i, j| (2 * j / height + 2 * (2 * i / width)) as usize;
+        for j in 0..height {
+            for i in 0..width {
+                for _ in 0..samples {
+                    let x = i as f64 + rng.gen::<f64>();
+                    let y = j as f64 + rng.gen::<f64>();
+                    let ray = camera.ray(x, y);
+                    expected[quarter(i, j)] += path.li(&ray, &mut rng);
+                    quarters[quarter(i, j)] += bdpt.li(x, y, &film, &mut rng);
+                }
+            }
+        }
+        // light traced onto the film only shows up once every sample is in
+        for j in 0..height {
+            for i in 0..width {
+                quarters[quarter(i, j)] += film.get(i, j);
+            }
+        }
+
+        quarters
+            .iter()
+            .zip(expected.iter())
+            .map(|(a, b)| (a.luminance(), b.luminance()))
+            .collect()
+    }
+
+    #[test]
+    fn test_agrees_with_path_tracer() {
+        // both estimate the same image of the Cornell box
+        let cornell = CornellBox::new();
+        let lights: Vec<Box<dyn Light>> = vec![Box::new(cornell.light())];
+        light::number_lights(&lights);
+        let materials = cornell.materials();
+        let scene = vec![cornell];
+        for (a, b) in quarters(&scene, &lights, &materials, 64) {
+            assert!((a - b).abs() <= 0.05 * b, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_every_kind_of_light() {
+        // point and spot lights start light subpaths but can't be hit,
+        // directional lights can only be sampled from the camera subpath,
+        // and area lights can be found either way
+        let lamp = Arc::new(AreaLight::new(
+            Sphere::new(point(-1.0, -1.0, -2.5), 0.25),
+            Color::new(8.0, 8.0, 6.0),
+            false,
+        ));
+        let floor = Parallelogram::new(
+            point(-4.0, -1.5, 0.0),
+            vector(8.0, 0.0, 0.0),
+            vector(0.0, 0.0, -8.0),
+        )
+        .with_material(0);
+        let scene: Vec<Box<dyn Scene>> = vec![
+            Box::new(floor),
+            Box::new(Sphere::new(point(1.0, -0.5, -3.0), 1.0)),
+            Box::new(Arc::clone(&lamp)),
+        ];
+        let lights: Vec<Box<dyn Light>> = vec![
+            Box::new(PointLight::new(
+                point(-2.0, 2.0, -1.0),
+                Color::new(24.0, 20.0, 16.0),
+            )),
+            Box::new(DirectionalLight::new(
+                vector(-1.0, -1.0, -0.5),
+                Color::new(0.3, 0.4, 0.7),
+                5.0,
+            )),
+            Box::new(SpotLight::new(
+                point(1.0, 3.0, -2.0),
+                vector(0.0, -3.5, -1.0),
+                Color::new(40.0, 10.0, 10.0),
+                f64::to_radians(20.0),
+                f64::to_radians(15.0),
+            )),
+            Box::new(lamp),
+        ];
+        light::number_lights(&lights);
+        let materials: Vec<Box<dyn Material>> =
+            vec![Box::new(Color::new(0.8, 0.5, 0.5))];
+        // the dimmer quarters are too noisy to compare on their own
+        let (a, b) = quarters(&scene, &lights, &materials, 256)
+            .iter()
+            .fold((0.0, 0.0), |(a, b), (x, y)| (a + x, b + y));
+        assert!((a - b).abs() < 0.03 * b, "{} != {}", a, b);
+    }
+}
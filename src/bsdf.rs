@@ -0,0 +1,128 @@
+use super::color::Color;
+use super::math::{Normal3, Vector3};
+use super::sampling;
+use std::f64::consts::PI;
+
+/// A direction sampled from a BSDF, along with the BSDF's value for it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BsdfSample {
+    /// Unit vector pointing away from the surface, along which light
+    /// arrives.
+    pub wi: Vector3,
+    pub f: Color,
+    /// Probability density of having sampled `wi`, with respect to solid
+    /// angle.
+    pub pdf: f64,
+}
+
+/// Ideal diffuse reflection, which scatters light equally in every
+/// direction on one side of the surface.
+///
+/// Directions are unit vectors pointing away from the surface. Only light
+/// arriving on the side `normal` faces is reflected, so the normal should
+/// be turned towards whichever side the surface is seen from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lambertian {
+    albedo: Color,
+    normal: Normal3,
+}
+
+impl Lambertian {
+    pub fn new(albedo: Color, normal: Normal3) -> Lambertian {
+        Lambertian { albedo, normal }
+    }
+
+    pub fn normal(&self) -> Normal3 {
+        self.normal
+    }
+
+    /// The fraction of the light arriving along `wi` that leaves along
+    /// `wo`, per steradian.
+    pub fn f(&self, wo: Vector3, wi: Vector3) -> Color {
+        if self.normal.dot(wo) > 0.0 && self.normal.dot(wi) > 0.0 {
+            self.albedo * (1.0 / PI)
+        } else {
+            Color::black()
+        }
+    }
+
+    /// Sample a direction for light arriving at the surface and leaving it
+    /// along `wo`, with density proportional to the cosine with the normal,
+    /// using the point `u` in the unit square.
+    pub fn sample_f(&self, wo: Vector3, u: (f64, f64)) -> Option<BsdfSample> {
+        if self.normal.dot(wo) <= 0.0 {
+            return None;
+        }
+        let local = sampling::cosine_hemisphere(u.0, u.1);
+        let wi = sampling::to_world(local, self.normal.into());
+        let pdf = self.pdf(wo, wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.f(wo, wi),
+            pdf,
+        })
+    }
+
+    /// The density with which `sample_f` picks `wi` for `wo`.
+    pub fn pdf(&self, wo: Vector3, wi: Vector3) -> f64 {
+        let cos = self.normal.dot(wi);
+        if self.normal.dot(wo) > 0.0 && cos > 0.0 {
+            sampling::cosine_hemisphere_pdf(cos)
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::math::{normal, vector};
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_f_and_pdf() {
+        let albedo = Color::new(0.5, 0.25, 1.0);
+        let bsdf = Lambertian::new(albedo, normal(0.0, 0.0, 1.0));
+        let wo = vector(0.0, 0.6, 0.8);
+        let wi = vector(0.8, 0.0, 0.6);
+        assert_eq!(bsdf.f(wo, wi), albedo * (1.0 / PI));
+        assert!((bsdf.pdf(wo, wi) - 0.6 / PI).abs() < 1.0e-12);
+
+        // nothing passes through the surface
+        let below = vector(0.0, 0.6, -0.8);
+        assert_eq!(bsdf.f(wo, below), Color::black());
+        assert_eq!(bsdf.f(below, wi), Color::black());
+        assert_eq!(bsdf.pdf(wo, below), 0.0);
+        assert_eq!(bsdf.sample_f(below, (0.3, 0.7)), None);
+    }
+
+    #[test]
+    fn test_sample_f_reflects_albedo() {
+        // a surface lit evenly from every direction reflects its albedo
+        let albedo = Color::new(0.8, 0.4, 0.2);
+        let n = normal(0.6, 0.0, 0.8);
+        let bsdf = Lambertian::new(albedo, n);
+        let wo = vector(0.0, 1.0, 0.0).cross(n.into()).normalized();
+        let wo = (wo + Vector3::from(n)).normalized();
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut reflected = Color::black();
+        let count = 1000;
+        for _ in 0..count {
+            let sample = bsdf.sample_f(wo, (rng.gen(), rng.gen())).unwrap();
+            assert!(n.dot(sample.wi) >= 0.0);
+            assert!((sample.wi.mag() - 1.0).abs() < 1.0e-9);
+            assert_eq!(sample.pdf, bsdf.pdf(wo, sample.wi));
+            reflected += sample.f * (n.dot(sample.wi) / sample.pdf);
+        }
+        // f cos / pdf is the same for every cosine weighted sample
+        let reflected = reflected * (1.0 / count as f64);
+        assert!((reflected.r() - 0.8).abs() < 1.0e-9);
+        assert!((reflected.b() - 0.2).abs() < 1.0e-9);
+    }
+}
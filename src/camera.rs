@@ -1,6 +1,4 @@
-#![allow(dead_code)]
-
-use super::math::vector;
+use super::math::{vector, Point3, Ray, Transform, Vector3};
 use ultraviolet::mat::DMat4;
 use ultraviolet::vec::DVec4;
 
@@ -8,13 +6,14 @@ use ultraviolet::vec::DVec4;
 /// (0, 0, 0) that faces in the negative z directon. The "film" plane is
 /// parallel to the XY plane and is 1 unit away from the origin along the
 /// negative Z axis.
+#[cfg(test)]
 pub fn projection_function(
     fov: f64, // in radians
     x_max: u32,
     y_max: u32,
     x: u32,
     y: u32,
-) -> DVec4 {
+) -> Vector3 {
     // convert to pixel coordinates to normalized device coordinates pixel
     // coordinates start with the origin (0, 0) as the top left-most pixel
     // on the screen, and we compute normalized device coordinates by
@@ -41,6 +40,47 @@ pub fn projection_function(
     vector(x_camera, y_camera, -1.0)
 }
 
+/// The inverse of `projection_function`: find the raster position (continuous
+/// pixel coordinates, so the center of pixel (x, y) is (x + 0.5, y + 0.5))
+/// that the point `p` projects onto. Returns `None` if the point is behind the
+/// camera or projects outside of the film.
+pub fn raster_position(
+    fov: f64, // in radians
+    x_max: u32,
+    y_max: u32,
+    p: Point3,
+) -> Option<(f64, f64)> {
+    // points on or behind the camera plane can't be seen
+    if p.z >= 0.0 {
+        return None;
+    }
+
+    // project the point onto the film plane at z = -1
+    let x_camera = p.x / -p.z;
+    let y_camera = p.y / -p.z;
+
+    // undo the field of view and aspect ratio scaling to get back to screen
+    // coordinates in the range [-1, 1]
+    let fov = f64::tan(fov / 2.0);
+    let aspect_ratio = x_max as f64 / y_max as f64;
+    let x_screen = x_camera / (aspect_ratio * fov);
+    let y_screen = y_camera / fov;
+
+    // screen coordinates to normalized device coordinates to raster coordinates
+    let x_raster = (x_screen + 1.0) / 2.0 * x_max as f64;
+    let y_raster = (1.0 - y_screen) / 2.0 * y_max as f64;
+
+    if x_raster < 0.0
+        || x_raster >= x_max as f64
+        || y_raster < 0.0
+        || y_raster >= y_max as f64
+    {
+        return None;
+    }
+
+    Some((x_raster, y_raster))
+}
+
 /// Create a 3x3 camera matrix, which transforms pixel coordinates of the vector
 /// form (x_pixel y_pixel 1.0)^T into primary rays. This matrix is the
 /// `projection_function` condensed and simplified into an augmented matrix.
@@ -55,16 +95,110 @@ pub fn projection_matrix(fov: f64, x_max: u32, y_max: u32) -> DMat4 {
     let coef_y = -2.0 * fov / y_max;
     let offs_y = (y_max - 1.0) * fov / y_max;
 
-    let c0 = vector(coef_x, 0.0, 0.0); // scaled by pixel_x
-    let c1 = vector(0.0, coef_y, 0.0); // scaled by pixel_y
-    let c2 = vector(offs_x, offs_y, -1.0); // added to pixel_x, pixel_y, sets z
-    let c3 = vector(0.0, 0.0, 0.0);
+    let c0 = DVec4::new(coef_x, 0.0, 0.0, 0.0); // scaled by pixel_x
+    let c1 = DVec4::new(0.0, coef_y, 0.0, 0.0); // scaled by pixel_y
+    // added to pixel_x, pixel_y, sets z
+    let c2 = DVec4::new(offs_x, offs_y, -1.0, 0.0);
+    let c3 = DVec4::new(0.0, 0.0, 0.0, 0.0);
     DMat4::new(c0, c1, c2, c3)
 }
 
+/// A pinhole camera placed in the scene, which can find where light reaching
+/// it lands on the film as well as generate primary rays.
+///
+/// Its importance is normalized over the whole film (as in pbrt), so light
+/// arriving through raster positions picked uniformly over the film
+/// estimates the image.
+pub struct Camera {
+    camera_to_world: Transform,
+    projection: DMat4,
+    fov: f64,
+    width: u32,
+    height: u32,
+    /// Area of the film on the plane z = -1 in camera space.
+    film_area: f64,
+}
+
+impl Camera {
+    /// The camera looks down the -z axis of `camera_to_world`, which should
+    /// be a rigid transform, with y up and the vertical field of view `fov`
+    /// in radians.
+    pub fn new(
+        camera_to_world: DMat4,
+        fov: f64,
+        width: u32,
+        height: u32,
+    ) -> Camera {
+        let tan = f64::tan(fov / 2.0);
+        let aspect_ratio = width as f64 / height as f64;
+        Camera {
+            camera_to_world: Transform::new(camera_to_world),
+            projection: projection_matrix(fov, width, height),
+            fov,
+            width,
+            height,
+            film_area: 4.0 * tan * tan * aspect_ratio,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn position(&self) -> Point3 {
+        self.camera_to_world.point(Point3::new(0.0, 0.0, 0.0))
+    }
+
+    /// The ray through the raster position (x, y), with a unit direction.
+    pub fn ray(&self, x: f64, y: f64) -> Ray {
+        // the projection takes pixel indices to the centers of pixels
+        let dir = self.projection * vector(x - 0.5, y - 0.5, 1.0);
+        let dir = self.camera_to_world.vector(dir).normalized();
+        Ray::new(self.position(), dir)
+    }
+
+    /// The raster position the point `p` is seen at, if it's in view.
+    pub fn raster(&self, p: Point3) -> Option<(f64, f64)> {
+        let p = self.camera_to_world.inverse().point(p);
+        raster_position(self.fov, self.width, self.height, p)
+    }
+
+    /// Cosine of the angle between the unit direction `w` leaving the camera
+    /// and the direction the camera faces.
+    pub fn cos_theta(&self, w: Vector3) -> f64 {
+        -self.camera_to_world.inverse().vector(w).z
+    }
+
+    /// Importance the camera emits along the unit direction `w`, assuming
+    /// that `w` passes through the film.
+    pub fn importance(&self, w: Vector3) -> f64 {
+        let cos = self.cos_theta(w);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        1.0 / (self.film_area * cos.powi(4))
+    }
+
+    /// The density, with respect to solid angle, of rays through raster
+    /// positions picked uniformly over the film leaving along the unit
+    /// direction `w`.
+    pub fn pdf_dir(&self, w: Vector3) -> f64 {
+        let cos = self.cos_theta(w);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        1.0 / (self.film_area * cos.powi(3))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::math::{point, rotation_y, translation};
 
     #[test]
     fn test_projection_matrix() {
@@ -86,4 +220,79 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_raster_position_inverts_projection() {
+        let fov = f64::to_radians(100.0);
+        let x_max = 160;
+        let y_max = 90;
+
+        for pixel_x in 0..x_max {
+            for pixel_y in 0..y_max {
+                let dir =
+                    projection_function(fov, x_max, y_max, pixel_x, pixel_y);
+                // any point along the primary ray lands on the same pixel
+                let p = point(dir.x * 3.0, dir.y * 3.0, dir.z * 3.0);
+                let (x, y) = raster_position(fov, x_max, y_max, p).unwrap();
+                let eps = 1.0e-6;
+                assert!((x - (pixel_x as f64 + 0.5)).abs() < eps);
+                assert!((y - (pixel_y as f64 + 0.5)).abs() < eps);
+            }
+        }
+    }
+
+    #[test]
+    fn test_raster_position_behind_camera() {
+        let fov = f64::to_radians(100.0);
+        let p = point(0.0, 0.0, 1.0);
+        assert_eq!(raster_position(fov, 800, 600, p), None);
+    }
+
+    #[test]
+    fn test_raster_position_outside_film() {
+        let fov = f64::to_radians(90.0);
+        // tan(45) = 1, so the film spans y in [-1, 1] at z = -1
+        let p = point(0.0, 2.0, -1.0);
+        assert_eq!(raster_position(fov, 800, 600, p), None);
+    }
+
+    #[test]
+    fn test_camera() {
+        let camera_to_world = translation(1.0, 2.0, 3.0) * rotation_y(0.5);
+        let camera = Camera::new(camera_to_world, 1.2, 40, 30);
+        assert_eq!(camera.position(), point(1.0, 2.0, 3.0));
+        let ray = camera.ray(12.25, 7.5);
+        assert!((ray.direction.mag() - 1.0).abs() < 1.0e-9);
+
+        // points along the ray land back where it went through the film
+        let (x, y) = camera.raster(ray.position(2.0)).unwrap();
+        assert!((x - 12.25).abs() < 1.0e-9);
+        assert!((y - 7.5).abs() < 1.0e-9);
+        let center = camera.ray(20.0, 15.0).direction;
+        assert!((camera.cos_theta(center) - 1.0).abs() < 1.0e-9);
+        assert_eq!(camera.importance(-center), 0.0);
+    }
+
+    #[test]
+    fn test_camera_pdf_dir() {
+        // rays through evenly spread raster positions cover the solid angle
+        // of the pyramid the film spans
+        let camera = Camera::new(rotation_y(1.0), 1.2, 40, 30);
+        let n = 400;
+        let mut solid_angle = 0.0;
+        for j in 0..n {
+            for i in 0..n {
+                let x = (i as f64 + 0.5) / n as f64 * 40.0;
+                let y = (j as f64 + 0.5) / n as f64 * 30.0;
+                let w = camera.ray(x, y).direction;
+                solid_angle += 1.0 / camera.pdf_dir(w);
+            }
+        }
+        let solid_angle = solid_angle / (n * n) as f64;
+
+        let tan_b = f64::tan(0.6);
+        let a = f64::atan(tan_b * 40.0 / 30.0);
+        let expected = 4.0 * f64::asin(a.sin() * tan_b.atan().sin());
+        assert!((solid_angle - expected).abs() < 1.0e-3 * expected);
+    }
 }
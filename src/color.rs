@@ -1,14 +1,43 @@
 use ultraviolet::DVec3;
-use std::ops::{Add, Sub, Mul};
+use image::Rgb;
+use std::ops::{Add, AddAssign, Sub, Mul};
 use std::convert::From;
 
-#[derive(Debug, PartialEq)]
-struct Color(DVec3);
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Color(DVec3);
 
 impl Color {
-    fn new(r: f64, g: f64, b: f64) -> Self {
+    pub fn new(r: f64, g: f64, b: f64) -> Self {
         Color(DVec3::new(r, g, b))
     }
+
+    pub fn black() -> Self {
+        Color(DVec3::zero())
+    }
+
+    pub fn r(&self) -> f64 {
+        self.0.x
+    }
+
+    pub fn g(&self) -> f64 {
+        self.0.y
+    }
+
+    pub fn b(&self) -> f64 {
+        self.0.z
+    }
+
+    /// The perceived brightness of the color, using the Rec. 709 weights.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.0.x + 0.7152 * self.0.y + 0.0722 * self.0.z
+    }
+
+    /// Encode as an 8 bit pixel. Components outside of [0.0, 1.0] are clamped
+    /// so that bright values saturate instead of overflowing.
+    pub fn to_rgb(self) -> Rgb<u8> {
+        let encode = |c: f64| f64::floor(c.clamp(0.0, 1.0) * 255.0) as u8;
+        Rgb([encode(self.0.x), encode(self.0.y), encode(self.0.z)])
+    }
 }
 
 impl From<DVec3> for Color {
@@ -25,6 +54,12 @@ impl Add<Self> for Color {
     }
 }
 
+impl AddAssign<Self> for Color {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 += rhs.0;
+    }
+}
+
 impl Sub<Self> for Color {
     type Output = Self;
 
@@ -85,4 +120,10 @@ mod test {
         let b = 2.0;
         assert_eps_eq(a * b, Color::new(0.4, 0.6, 0.8));
     }
+
+    #[test]
+    fn test_color_to_rgb() {
+        let a = Color::new(0.5, 2.0, -1.0);
+        assert_eq!(a.to_rgb(), Rgb([127, 255, 0]));
+    }
 }
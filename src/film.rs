@@ -0,0 +1,145 @@
+use super::color::Color;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// An `f64` that can be added to from several threads at once. The standard
+/// library has no atomic floating point type, so the bits are kept in an
+/// `AtomicU64` and updated with a compare and swap loop.
+struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+    fn new(value: f64) -> AtomicF64 {
+        AtomicF64(AtomicU64::new(value.to_bits()))
+    }
+
+    fn load(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn add(&self, value: f64) {
+        let mut current = self.0.load(Ordering::Relaxed);
+        loop {
+            let sum = (f64::from_bits(current) + value).to_bits();
+            match self.0.compare_exchange_weak(
+                current,
+                sum,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+/// A framebuffer that radiance can be splatted into through a shared
+/// reference. Light tracing contributes to whichever pixel a light subpath
+/// happens to be visible from rather than to the pixel being rendered, so
+/// any thread may write to any pixel at any time.
+pub struct SplatBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<[AtomicF64; 3]>,
+}
+
+impl SplatBuffer {
+    pub fn new(width: u32, height: u32) -> SplatBuffer {
+        let pixels = (0..width * height)
+            .map(|_| {
+                [
+                    AtomicF64::new(0.0),
+                    AtomicF64::new(0.0),
+                    AtomicF64::new(0.0),
+                ]
+            })
+            .collect();
+        SplatBuffer {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Add `color` to the pixel containing the raster position (x, y), as
+    /// returned by `camera::raster_position`. Splats that land outside of the
+    /// film are dropped.
+    pub fn splat(&self, x: f64, y: f64, color: Color) {
+        if x < 0.0 || y < 0.0 {
+            return;
+        }
+
+        let (x, y) = (x as u32, y as u32);
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        let pixel = &self.pixels[(y * self.width + x) as usize];
+        pixel[0].add(color.r());
+        pixel[1].add(color.g());
+        pixel[2].add(color.b());
+    }
+
+    /// Return the sum of everything splatted into the pixel (x, y).
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        let pixel = &self.pixels[(y * self.width + x) as usize];
+        Color::new(pixel[0].load(), pixel[1].load(), pixel[2].load())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_splat_accumulates() {
+        let film = SplatBuffer::new(4, 3);
+        film.splat(1.5, 2.5, Color::new(0.25, 0.5, 1.0));
+        film.splat(1.0, 2.99, Color::new(0.25, 0.5, 1.0));
+        assert_eq!(film.get(1, 2), Color::new(0.5, 1.0, 2.0));
+        assert_eq!(film.get(2, 1), Color::black());
+    }
+
+    #[test]
+    fn test_splat_outside_film() {
+        let film = SplatBuffer::new(4, 3);
+        film.splat(-0.5, 1.0, Color::new(1.0, 1.0, 1.0));
+        film.splat(4.0, 1.0, Color::new(1.0, 1.0, 1.0));
+        film.splat(1.0, 3.0, Color::new(1.0, 1.0, 1.0));
+        for y in 0..3 {
+            for x in 0..4 {
+                assert_eq!(film.get(x, y), Color::black());
+            }
+        }
+    }
+
+    #[test]
+    fn test_splat_from_many_threads() {
+        let film = Arc::new(SplatBuffer::new(2, 2));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let film = Arc::clone(&film);
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        film.splat(0.5, 0.5, Color::new(1.0, 2.0, 0.5));
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(film.get(0, 0), Color::new(8000.0, 16000.0, 4000.0));
+    }
+}
@@ -0,0 +1,167 @@
+use super::{number, optional_index, required, required_index};
+use super::{Document, GltfError};
+use super::super::json::Json;
+
+/// The elements of an accessor, converted to floating point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accessor {
+    /// Numbers per element: 1 for `SCALAR`, 3 for `VEC3` and so on.
+    pub components: usize,
+    pub values: Vec<f64>,
+}
+
+impl Accessor {
+    pub fn len(&self) -> usize {
+        self.values.len() / self.components
+    }
+
+    pub fn get(&self, i: usize) -> &[f64] {
+        &self.values[i * self.components..(i + 1) * self.components]
+    }
+
+    /// The elements as whole numbers, as indices are.
+    pub fn indices(&self) -> Vec<usize> {
+        self.values.iter().map(|&v| v as usize).collect()
+    }
+}
+
+/// Reads one component from the start of the bytes, as a normalized
+/// integer if the flag is set.
+type ReadComponent = fn(&[u8], bool) -> f64;
+
+/// The size in bytes of a `componentType`, and how to read one.
+fn component_type(code: usize) -> Result<(usize, ReadComponent), GltfError> {
+    // normalized integers map onto [0, 1] or [-1, 1]
+    let reader: (usize, ReadComponent) = match code {
+        5120 => (1, |b, normalized| {
+            let v = b[0] as i8 as f64;
+            if normalized {
+                (v / 127.0).max(-1.0)
+            } else {
+                v
+            }
+        }),
+        5121 => (1, |b, normalized| {
+            let v = b[0] as f64;
+            if normalized {
+                v / 255.0
+            } else {
+                v
+            }
+        }),
+        5122 => (2, |b, normalized| {
+            let v = i16::from_le_bytes([b[0], b[1]]) as f64;
+            if normalized {
+                (v / 32767.0).max(-1.0)
+            } else {
+                v
+            }
+        }),
+        5123 => (2, |b, normalized| {
+            let v = u16::from_le_bytes([b[0], b[1]]) as f64;
+            if normalized {
+                v / 65535.0
+            } else {
+                v
+            }
+        }),
+        5125 => (4, |b, _| {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+        }),
+        5126 => (4, |b, _| {
+            f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+        }),
+        _ => {
+            let what = format!("component type {}", code);
+            return Err(GltfError::Unsupported(what));
+        }
+    };
+    Ok(reader)
+}
+
+fn components(kind: &str) -> Result<usize, GltfError> {
+    match kind {
+        "SCALAR" => Ok(1),
+        "VEC2" => Ok(2),
+        "VEC3" => Ok(3),
+        "VEC4" | "MAT2" => Ok(4),
+        "MAT3" => Ok(9),
+        "MAT4" => Ok(16),
+        _ => Err(GltfError::Unsupported(format!("accessor type {}", kind))),
+    }
+}
+
+/// The bytes of buffer view `index`, and its stride if it has one.
+pub fn buffer_view(
+    document: &Document,
+    index: usize,
+) -> Result<(&[u8], Option<usize>), GltfError> {
+    let view = document.item("bufferViews", index)?;
+    let buffer = required_index(view, "buffer")?;
+    let buffer = document
+        .buffers
+        .get(buffer)
+        .ok_or_else(|| GltfError::BadIndex("buffers".into(), buffer))?;
+    let offset = number(view, "byteOffset")?.unwrap_or(0.0) as usize;
+    let length = required(view, "byteLength", Json::as_usize)?;
+    let end = offset.checked_add(length).ok_or(GltfError::UnexpectedEnd)?;
+    let bytes = buffer.get(offset..end).ok_or(GltfError::UnexpectedEnd)?;
+    Ok((bytes, optional_index(view, "byteStride")?))
+}
+
+/// Read accessor `index`, converting its elements to floating point.
+pub fn read_accessor(
+    document: &Document,
+    index: usize,
+) -> Result<Accessor, GltfError> {
+    let accessor = document.item("accessors", index)?;
+    if accessor.get("sparse").is_some() {
+        return Err(GltfError::Unsupported("sparse accessors".into()));
+    }
+    let count = required(accessor, "count", Json::as_usize)?;
+    let components = components(required(accessor, "type", Json::as_str)?)?;
+    let code = required(accessor, "componentType", Json::as_usize)?;
+    let (size, read) = component_type(code)?;
+    let normalized = accessor
+        .get("normalized")
+        .and_then(Json::as_bool)
+        .unwrap_or(false);
+
+    let len = count
+        .checked_mul(components)
+        .ok_or_else(|| GltfError::BadProperty("count".into()))?;
+
+    // without a buffer view, every element is zero
+    let view = match optional_index(accessor, "bufferView")? {
+        Some(view) => view,
+        None => {
+            let values = vec![0.0; len];
+            return Ok(Accessor { components, values });
+        }
+    };
+    let (bytes, stride) = buffer_view(document, view)?;
+    let element_size = size * components;
+    let stride = stride.unwrap_or(element_size);
+    let offset = number(accessor, "byteOffset")?.unwrap_or(0.0) as usize;
+    if count > 0 {
+        // where the last element ends, which a huge count or stride could
+        // push past the largest usize
+        let end = stride
+            .checked_mul(count - 1)
+            .and_then(|n| n.checked_add(offset))
+            .and_then(|n| n.checked_add(element_size));
+        match end {
+            Some(end) if end <= bytes.len() => (),
+            _ => return Err(GltfError::UnexpectedEnd),
+        }
+    }
+
+    let mut values = Vec::with_capacity(len);
+    for i in 0..count {
+        let element = &bytes[offset + stride * i..];
+        for c in 0..components {
+            values.push(read(&element[size * c..], normalized));
+        }
+    }
+    Ok(Accessor { components, values })
+}
@@ -0,0 +1,236 @@
+use super::GltfError;
+use std::fs;
+use std::path::Path;
+
+/// A binary glTF file starts with "glTF" and the container version.
+const GLB_MAGIC: &[u8] = b"glTF";
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4e4f_534a;
+const CHUNK_BIN: u32 = 0x004e_4942;
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, GltfError> {
+    match bytes.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(GltfError::UnexpectedEnd),
+    }
+}
+
+/// Split a binary glTF file into its JSON and the binary chunk that buffer
+/// 0 refers to, if it has one.
+pub fn split_glb(bytes: &[u8]) -> Result<(&str, Option<&[u8]>), GltfError> {
+    if !bytes.starts_with(GLB_MAGIC) {
+        return Err(GltfError::NotRecognized);
+    }
+    let version = u32_at(bytes, 4)?;
+    if version != GLB_VERSION {
+        return Err(GltfError::Unsupported(format!("GLB version {}", version)));
+    }
+    let length = u32_at(bytes, 8)? as usize;
+    let bytes = bytes.get(..length).ok_or(GltfError::UnexpectedEnd)?;
+
+    let mut json = None;
+    let mut bin = None;
+    let mut offset = 12;
+    while offset < bytes.len() {
+        let chunk_length = u32_at(bytes, offset)? as usize;
+        let chunk_type = u32_at(bytes, offset + 4)?;
+        let start = offset + 8;
+        let data = bytes
+            .get(start..start + chunk_length)
+            .ok_or(GltfError::UnexpectedEnd)?;
+        match chunk_type {
+            CHUNK_JSON if json.is_none() => json = Some(data),
+            CHUNK_BIN if bin.is_none() => bin = Some(data),
+            // unknown chunks are for extensions, and must be skipped
+            _ => (),
+        }
+        offset = start + chunk_length;
+    }
+
+    let json = json.ok_or(GltfError::NotRecognized)?;
+    let json = std::str::from_utf8(json)
+        .map_err(|_| GltfError::Unsupported("JSON that isn't UTF-8".into()))?;
+    Ok((json, bin))
+}
+
+/// Decode standard base64, as used in data URIs.
+pub fn decode_base64(text: &str) -> Result<Vec<u8>, GltfError> {
+    let bad = || GltfError::BadUri("bad base64 data".into());
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Ok(c - b'A'),
+        b'a'..=b'z' => Ok(c - b'a' + 26),
+        b'0'..=b'9' => Ok(c - b'0' + 52),
+        b'+' => Ok(62),
+        b'/' => Ok(63),
+        _ => Err(bad()),
+    };
+
+    let text = text.trim_end_matches('=').as_bytes();
+    if text.len() % 4 == 1 {
+        return Err(bad());
+    }
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    for group in text.chunks(4) {
+        let mut bits = 0u32;
+        for (i, &c) in group.iter().enumerate() {
+            bits |= (value(c)? as u32) << (18 - 6 * i);
+        }
+        let decoded = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        bytes.extend_from_slice(&decoded[..group.len() - 1]);
+    }
+    Ok(bytes)
+}
+
+/// Undo the percent-encoding of a relative URI, which is how file names
+/// with spaces and the like are written.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = uri
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The contents of a buffer or image `uri`: either the data itself, in a
+/// base64 data URI, or a file relative to the directory `base`.
+///
+/// Nothing is ever fetched over the network; URIs with a scheme other than
+/// `data:` are an error.
+pub fn read_uri(uri: &str, base: &Path) -> Result<Vec<u8>, GltfError> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let comma = data
+            .find(',')
+            .ok_or_else(|| GltfError::BadUri(uri.into()))?;
+        if !data[..comma].ends_with(";base64") {
+            return Err(GltfError::BadUri(uri.into()));
+        }
+        return decode_base64(&data[comma + 1..]);
+    }
+    if uri.contains("://") {
+        return Err(GltfError::Unsupported(format!("remote file {}", uri)));
+    }
+    Ok(fs::read(base.join(percent_decode(uri)))?)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Base64 encode `bytes`, for building data URIs.
+    pub fn encode_base64(bytes: &[u8]) -> String {
+        const DIGITS: &[u8] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut text = String::new();
+        for group in bytes.chunks(3) {
+            let mut bits = 0u32;
+            for (i, &b) in group.iter().enumerate() {
+                bits |= (b as u32) << (16 - 8 * i);
+            }
+            for i in 0..4 {
+                if i <= group.len() {
+                    let digit = (bits >> (18 - 6 * i)) & 0x3f;
+                    text.push(DIGITS[digit as usize] as char);
+                } else {
+                    text.push('=');
+                }
+            }
+        }
+        text
+    }
+
+    /// A binary glTF file holding `json` and, if there is one, `bin`.
+    pub fn glb(json: &str, bin: Option<&[u8]>) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().div_ceil(4) * 4, b' ');
+        let mut chunks = Vec::new();
+        chunks.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        chunks.extend_from_slice(&CHUNK_JSON.to_le_bytes());
+        chunks.extend_from_slice(&json);
+        if let Some(bin) = bin {
+            let mut bin = bin.to_vec();
+            bin.resize(bin.len().div_ceil(4) * 4, 0);
+            chunks.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+            chunks.extend_from_slice(&CHUNK_BIN.to_le_bytes());
+            chunks.extend_from_slice(&bin);
+        }
+
+        let mut bytes = GLB_MAGIC.to_vec();
+        bytes.extend_from_slice(&GLB_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(12 + chunks.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&chunks);
+        bytes
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert_eq!(decode_base64("TWFu").unwrap(), b"Man");
+        assert_eq!(decode_base64("TWE=").unwrap(), b"Ma");
+        assert_eq!(decode_base64("TQ==").unwrap(), b"M");
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode_base64(&encode_base64(&bytes)).unwrap(), bytes);
+        assert!(decode_base64("TQ*=").is_err());
+        assert!(decode_base64("TWFuT").is_err());
+    }
+
+    #[test]
+    fn test_read_uri() {
+        let base = Path::new("/nonexistent");
+        let uri = "data:application/octet-stream;base64,TWFu";
+        assert_eq!(read_uri(uri, base).unwrap(), b"Man");
+        match read_uri("data:text/plain,Man", base) {
+            Err(GltfError::BadUri(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match read_uri("https://example.com/a.bin", base) {
+            Err(GltfError::Unsupported(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match read_uri("missing.bin", base) {
+            Err(GltfError::Io(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(percent_decode("my%20mesh.bin"), "my mesh.bin");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+
+    #[test]
+    fn test_split_glb() {
+        let bytes = glb("{}", Some(&[1, 2, 3]));
+        let (json, bin) = split_glb(&bytes).unwrap();
+        assert_eq!(json.trim(), "{}");
+        assert_eq!(bin, Some(&[1, 2, 3, 0][..]));
+
+        let bytes = glb("{}", None);
+        assert_eq!(split_glb(&bytes).unwrap().1, None);
+
+        match split_glb(&bytes[..bytes.len() - 2]) {
+            Err(GltfError::UnexpectedEnd) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match split_glb(b"{\"asset\": {}}") {
+            Err(GltfError::NotRecognized) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut bytes = glb("{}", None);
+        bytes[4] = 1;
+        match split_glb(&bytes) {
+            Err(GltfError::Unsupported(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
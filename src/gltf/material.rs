@@ -0,0 +1,329 @@
+use super::super::color::Color;
+use super::super::hdr::HdrImage;
+use super::super::json::Json;
+use super::accessor::buffer_view;
+use super::buffer::read_uri;
+use super::{number, numbers, optional_index, required_index};
+use super::{Document, GltfError};
+use std::collections::HashMap;
+use std::sync::Arc;
+use ultraviolet::DVec2;
+
+/// How texture coordinates outside of [0, 1] are brought back into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wrap {
+    ClampToEdge,
+    MirroredRepeat,
+    Repeat,
+}
+
+impl Wrap {
+    /// The wrap mode with the given OpenGL enum value.
+    fn from_code(code: usize) -> Result<Wrap, GltfError> {
+        match code {
+            33071 => Ok(Wrap::ClampToEdge),
+            33648 => Ok(Wrap::MirroredRepeat),
+            10497 => Ok(Wrap::Repeat),
+            _ => Err(GltfError::Unsupported(format!("wrap mode {}", code))),
+        }
+    }
+
+    fn apply(self, t: f64) -> f64 {
+        match self {
+            Wrap::ClampToEdge => t.clamp(0.0, 1.0),
+            Wrap::Repeat => t - t.floor(),
+            Wrap::MirroredRepeat => {
+                let t = t.rem_euclid(2.0);
+                if t > 1.0 {
+                    2.0 - t
+                } else {
+                    t
+                }
+            }
+        }
+    }
+}
+
+/// An image applied to a surface through its texture coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Texture {
+    /// Linear values: the sRGB encoding of color textures has already been
+    /// undone.
+    pub image: Arc<HdrImage>,
+    /// Wrapping of u and of v.
+    pub wrap: (Wrap, Wrap),
+    /// Which of the mesh's `TEXCOORD_n` sets the texture uses.
+    pub tex_coord: usize,
+}
+
+impl Texture {
+    /// The texel at `uv`, where (0, 0) is the top left corner of the image.
+    pub fn lookup(&self, uv: DVec2) -> Color {
+        self.image
+            .lookup(self.wrap.0.apply(uv.x), self.wrap.1.apply(uv.y))
+    }
+}
+
+/// A glTF metallic-roughness material.
+///
+/// Each factor is multiplied by its texture where there is one, so the
+/// factors alone describe untextured surfaces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PbrMaterial {
+    pub name: Option<String>,
+    /// Linear base color: the albedo of dielectrics and the specular color
+    /// of metals.
+    pub base_color: Color,
+    pub base_color_texture: Option<Texture>,
+    pub metallic: f64,
+    pub roughness: f64,
+    /// Roughness in the green channel and metalness in the blue channel.
+    pub metallic_roughness_texture: Option<Texture>,
+    /// Tangent space normals, which need tangents to be of any use.
+    pub normal_texture: Option<Texture>,
+    pub normal_scale: f64,
+    /// Emitted radiance, including any `KHR_materials_emissive_strength`.
+    pub emissive: Color,
+    pub emissive_texture: Option<Texture>,
+    /// Whether back faces are visible rather than culled.
+    pub double_sided: bool,
+}
+
+impl Default for PbrMaterial {
+    /// The material glTF uses for primitives that don't have one.
+    fn default() -> PbrMaterial {
+        PbrMaterial {
+            name: None,
+            base_color: Color::new(1.0, 1.0, 1.0),
+            base_color_texture: None,
+            metallic: 1.0,
+            roughness: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            emissive: Color::black(),
+            emissive_texture: None,
+            double_sided: false,
+        }
+    }
+}
+
+impl PbrMaterial {
+    /// The base color at the texture coordinates `uv`.
+    pub fn base_color_at(&self, uv: DVec2) -> Color {
+        match &self.base_color_texture {
+            Some(texture) => self.base_color * texture.lookup(uv),
+            None => self.base_color,
+        }
+    }
+
+    /// The metalness and roughness at the texture coordinates `uv`.
+    #[cfg(test)]
+    pub fn metallic_roughness_at(&self, uv: DVec2) -> (f64, f64) {
+        match &self.metallic_roughness_texture {
+            Some(texture) => {
+                let texel = texture.lookup(uv);
+                (self.metallic * texel.b(), self.roughness * texel.g())
+            }
+            None => (self.metallic, self.roughness),
+        }
+    }
+
+    /// The emitted radiance at the texture coordinates `uv`.
+    #[cfg(test)]
+    pub fn emissive_at(&self, uv: DVec2) -> Color {
+        match &self.emissive_texture {
+            Some(texture) => self.emissive * texture.lookup(uv),
+            None => self.emissive,
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emissive != Color::black()
+    }
+}
+
+/// Reads textures, decoding each image only once however many materials
+/// use it.
+struct Textures<'a> {
+    document: &'a Document,
+    /// Keyed by image index and whether it holds sRGB colors.
+    images: HashMap<(usize, bool), Arc<HdrImage>>,
+}
+
+impl<'a> Textures<'a> {
+    fn image(
+        &mut self,
+        index: usize,
+        srgb: bool,
+    ) -> Result<Arc<HdrImage>, GltfError> {
+        if let Some(image) = self.images.get(&(index, srgb)) {
+            return Ok(Arc::clone(image));
+        }
+
+        let json = self.document.item("images", index)?;
+        let uri = json.get("uri").and_then(Json::as_str);
+        let bytes = match (uri, optional_index(json, "bufferView")?) {
+            (Some(uri), _) => read_uri(uri, &self.document.base)?,
+            (None, Some(view)) => buffer_view(self.document, view)?.0.to_vec(),
+            (None, None) => return Err(GltfError::BadProperty("uri".into())),
+        };
+        let rgb = image::load_from_memory(&bytes)?.to_rgb();
+        let image = Arc::new(HdrImage::from_rgb(&rgb, srgb));
+        self.images.insert((index, srgb), Arc::clone(&image));
+        Ok(image)
+    }
+
+    /// The texture a material's texture info refers to, if it has one.
+    fn texture(
+        &mut self,
+        info: Option<&Json>,
+        srgb: bool,
+    ) -> Result<Option<Texture>, GltfError> {
+        let info = match info {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+        let texture = self
+            .document
+            .item("textures", required_index(info, "index")?)?;
+        // textures whose images all come from extensions, like WebP ones,
+        // have no source
+        let source = match optional_index(texture, "source")? {
+            Some(source) => source,
+            None => return Ok(None),
+        };
+        let wrap = match optional_index(texture, "sampler")? {
+            Some(sampler) => {
+                let sampler = self.document.item("samplers", sampler)?;
+                let wrap = |key| -> Result<Wrap, GltfError> {
+                    Wrap::from_code(
+                        optional_index(sampler, key)?.unwrap_or(10497),
+                    )
+                };
+                (wrap("wrapS")?, wrap("wrapT")?)
+            }
+            None => (Wrap::Repeat, Wrap::Repeat),
+        };
+
+        Ok(Some(Texture {
+            image: self.image(source, srgb)?,
+            wrap,
+            tex_coord: optional_index(info, "texCoord")?.unwrap_or(0),
+        }))
+    }
+}
+
+fn color(object: &Json, key: &str, default: Color) -> Result<Color, GltfError> {
+    // base colors have a fourth component, alpha, which is ignored
+    Ok(match numbers(object, key)? {
+        Some(c) if c.len() >= 3 => Color::new(c[0], c[1], c[2]),
+        Some(_) => return Err(GltfError::BadProperty(key.into())),
+        None => default,
+    })
+}
+
+/// Read every material in the document.
+pub fn read_materials(
+    document: &Document,
+) -> Result<Vec<PbrMaterial>, GltfError> {
+    let mut textures = Textures {
+        document,
+        images: HashMap::new(),
+    };
+    let mut materials = Vec::new();
+    for json in document.items("materials") {
+        let mut material = PbrMaterial {
+            name: json.get("name").and_then(Json::as_str).map(String::from),
+            double_sided: json
+                .get("doubleSided")
+                .and_then(Json::as_bool)
+                .unwrap_or(false),
+            ..PbrMaterial::default()
+        };
+
+        if let Some(pbr) = json.get("pbrMetallicRoughness") {
+            material.base_color =
+                color(pbr, "baseColorFactor", material.base_color)?;
+            material.base_color_texture =
+                textures.texture(pbr.get("baseColorTexture"), true)?;
+            material.metallic = number(pbr, "metallicFactor")?.unwrap_or(1.0);
+            material.roughness = number(pbr, "roughnessFactor")?.unwrap_or(1.0);
+            material.metallic_roughness_texture =
+                textures.texture(pbr.get("metallicRoughnessTexture"), false)?;
+        }
+
+        let normal = json.get("normalTexture");
+        material.normal_texture = textures.texture(normal, false)?;
+        if let Some(normal) = normal {
+            material.normal_scale = number(normal, "scale")?.unwrap_or(1.0);
+        }
+
+        material.emissive = color(json, "emissiveFactor", Color::black())?;
+        let strength = json
+            .get("extensions")
+            .and_then(|e| e.get("KHR_materials_emissive_strength"));
+        if let Some(strength) = strength {
+            let strength = number(strength, "emissiveStrength")?;
+            material.emissive = material.emissive * strength.unwrap_or(1.0);
+        }
+        material.emissive_texture =
+            textures.texture(json.get("emissiveTexture"), true)?;
+
+        materials.push(material);
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(Wrap::Repeat.apply(1.25), 0.25);
+        assert_eq!(Wrap::Repeat.apply(-0.25), 0.75);
+        assert_eq!(Wrap::ClampToEdge.apply(1.25), 1.0);
+        assert_eq!(Wrap::ClampToEdge.apply(-0.25), 0.0);
+        assert_eq!(Wrap::MirroredRepeat.apply(1.25), 0.75);
+        assert_eq!(Wrap::MirroredRepeat.apply(-0.25), 0.25);
+        assert_eq!(Wrap::MirroredRepeat.apply(2.25), 0.25);
+    }
+
+    #[test]
+    fn test_textured_material() {
+        // metalness is in the blue channel and roughness in the green
+        let image = HdrImage::new(
+            2,
+            1,
+            vec![Color::new(0.0, 0.5, 1.0), Color::new(1.0, 0.25, 0.5)],
+        );
+        let texture = Texture {
+            image: Arc::new(image),
+            wrap: (Wrap::Repeat, Wrap::ClampToEdge),
+            tex_coord: 0,
+        };
+        let material = PbrMaterial {
+            base_color: Color::new(0.5, 0.5, 0.5),
+            base_color_texture: Some(texture.clone()),
+            metallic: 0.5,
+            roughness: 0.8,
+            metallic_roughness_texture: Some(texture.clone()),
+            emissive: Color::new(2.0, 2.0, 2.0),
+            emissive_texture: Some(texture),
+            ..PbrMaterial::default()
+        };
+
+        let left = DVec2::new(0.25, 0.5);
+        let right = DVec2::new(1.75, 0.5);
+        assert_eq!(material.base_color_at(left), Color::new(0.0, 0.25, 0.5));
+        assert_eq!(material.metallic_roughness_at(left), (0.5, 0.4));
+        assert_eq!(material.metallic_roughness_at(right), (0.25, 0.2));
+        assert_eq!(material.emissive_at(right), Color::new(2.0, 0.5, 1.0));
+        assert!(material.is_emissive());
+        assert_eq!(
+            PbrMaterial::default().metallic_roughness_at(left),
+            (1.0, 1.0)
+        );
+    }
+}
@@ -0,0 +1,1229 @@
+mod accessor;
+
+mod buffer;
+
+mod material;
+pub use material::PbrMaterial;
+
+use super::color::Color;
+use super::json::{Json, JsonError};
+use super::light::{AreaLight, DirectionalLight, Light, PointLight, SpotLight};
+use super::light::area_lights;
+use super::math::{point, vector, Normal3, Point3, Quaternion, Transform};
+use super::math::{Trs, Vector3};
+use super::mesh::TriangleMesh;
+use super::primitive::{Aabb, Bvh, Instance, Transformed, Triangle};
+use accessor::read_accessor;
+use image::ImageError;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use ultraviolet::mat::DMat4;
+use ultraviolet::vec::{DVec2, DVec4};
+
+/// Extensions that files can require without being rejected. Files may use
+/// any others, which are ignored, as long as they don't require them.
+const SUPPORTED_EXTENSIONS: &[&str] =
+    &["KHR_lights_punctual", "KHR_materials_emissive_strength"];
+
+/// Something wrong with a glTF file.
+#[derive(Debug)]
+pub enum GltfError {
+    Io(io::Error),
+    Json(JsonError),
+    /// A texture image that can't be decoded.
+    Image(ImageError),
+    /// A binary file doesn't start with the GLB header.
+    NotRecognized,
+    /// A buffer or chunk is shorter than what refers to it.
+    UnexpectedEnd,
+    /// A required property is missing, or a property has the wrong type.
+    BadProperty(String),
+    /// An index refers to something that isn't in its array, like a mesh
+    /// that doesn't exist.
+    BadIndex(String, usize),
+    /// A data URI that can't be decoded.
+    BadUri(String),
+    /// A node has more than one parent, or is its own ancestor.
+    BadHierarchy(usize),
+    /// Part of glTF that isn't supported, like a required extension.
+    Unsupported(String),
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GltfError::Io(err) => write!(f, "{}", err),
+            GltfError::Json(err) => write!(f, "{}", err),
+            GltfError::Image(err) => write!(f, "{}", err),
+            GltfError::NotRecognized => write!(f, "not a binary glTF file"),
+            GltfError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            GltfError::BadProperty(s) => {
+                write!(f, "missing or invalid property '{}'", s)
+            }
+            GltfError::BadIndex(s, i) => {
+                write!(f, "{}[{}] doesn't exist", s, i)
+            }
+            GltfError::BadUri(s) => write!(f, "bad URI '{}'", s),
+            GltfError::BadHierarchy(i) => {
+                write!(f, "node {} isn't part of a tree", i)
+            }
+            GltfError::Unsupported(s) => write!(f, "unsupported {}", s),
+        }
+    }
+}
+
+impl Error for GltfError {}
+
+impl From<io::Error> for GltfError {
+    fn from(err: io::Error) -> GltfError {
+        GltfError::Io(err)
+    }
+}
+
+impl From<JsonError> for GltfError {
+    fn from(err: JsonError) -> GltfError {
+        GltfError::Json(err)
+    }
+}
+
+impl From<ImageError> for GltfError {
+    fn from(err: ImageError) -> GltfError {
+        GltfError::Image(err)
+    }
+}
+
+/// The property `key` of `object`, which must be there.
+fn required<'a, T, F>(
+    object: &'a Json,
+    key: &str,
+    convert: F,
+) -> Result<T, GltfError>
+where
+    F: Fn(&'a Json) -> Option<T>,
+{
+    object
+        .get(key)
+        .and_then(convert)
+        .ok_or_else(|| GltfError::BadProperty(key.into()))
+}
+
+/// The property `key` of `object`, which may be left out but must have the
+/// right type if it isn't.
+fn optional<'a, T, F>(
+    object: &'a Json,
+    key: &str,
+    convert: F,
+) -> Result<Option<T>, GltfError>
+where
+    F: Fn(&'a Json) -> Option<T>,
+{
+    match object.get(key) {
+        Some(value) => convert(value)
+            .map(Some)
+            .ok_or_else(|| GltfError::BadProperty(key.into())),
+        None => Ok(None),
+    }
+}
+
+fn required_index(object: &Json, key: &str) -> Result<usize, GltfError> {
+    required(object, key, Json::as_usize)
+}
+
+fn optional_index(
+    object: &Json,
+    key: &str,
+) -> Result<Option<usize>, GltfError> {
+    optional(object, key, Json::as_usize)
+}
+
+fn number(object: &Json, key: &str) -> Result<Option<f64>, GltfError> {
+    optional(object, key, Json::as_f64)
+}
+
+fn numbers(object: &Json, key: &str) -> Result<Option<Vec<f64>>, GltfError> {
+    optional(object, key, Json::as_f64_array)
+}
+
+/// The JSON of a glTF file together with the contents of its buffers.
+struct Document {
+    json: Json,
+    buffers: Vec<Vec<u8>>,
+    /// The directory that relative URIs start from.
+    base: PathBuf,
+}
+
+impl Document {
+    fn new(bytes: &[u8], base: &Path) -> Result<Document, GltfError> {
+        let (text, bin) = if bytes.starts_with(b"glTF") {
+            buffer::split_glb(bytes)?
+        } else {
+            let text = std::str::from_utf8(bytes).map_err(|_| {
+                GltfError::Unsupported("JSON that isn't UTF-8".into())
+            })?;
+            (text, None)
+        };
+        let json = Json::parse(text.trim_start_matches('\u{feff}'))?;
+
+        let asset = required(&json, "asset", Some)?;
+        let version = required(asset, "version", Json::as_str)?;
+        if !version.starts_with("2.") {
+            let what = format!("glTF version {}", version);
+            return Err(GltfError::Unsupported(what));
+        }
+        let required_extensions = json
+            .get("extensionsRequired")
+            .and_then(Json::as_array)
+            .unwrap_or(&[]);
+        for extension in required_extensions.iter().filter_map(Json::as_str) {
+            if !SUPPORTED_EXTENSIONS.contains(&extension) {
+                let what = format!("extension {}", extension);
+                return Err(GltfError::Unsupported(what));
+            }
+        }
+
+        let mut document = Document {
+            json,
+            buffers: Vec::new(),
+            base: base.to_path_buf(),
+        };
+        let mut buffers = Vec::new();
+        for (i, buffer) in document.items("buffers").iter().enumerate() {
+            // the first buffer of a binary file can be its binary chunk
+            let data = match (buffer.get("uri").and_then(Json::as_str), bin) {
+                (Some(uri), _) => buffer::read_uri(uri, base)?,
+                (None, Some(bin)) if i == 0 => bin.to_vec(),
+                (None, _) => return Err(GltfError::BadProperty("uri".into())),
+            };
+            if data.len() < required_index(buffer, "byteLength")? {
+                return Err(GltfError::UnexpectedEnd);
+            }
+            buffers.push(data);
+        }
+        document.buffers = buffers;
+        Ok(document)
+    }
+
+    /// The top-level array `collection`, like "meshes", which is empty if
+    /// the file doesn't have one.
+    fn items(&self, collection: &str) -> &[Json] {
+        self.json
+            .get(collection)
+            .and_then(Json::as_array)
+            .unwrap_or(&[])
+    }
+
+    fn item(&self, collection: &str, index: usize) -> Result<&Json, GltfError> {
+        self.items(collection)
+            .get(index)
+            .ok_or_else(|| GltfError::BadIndex(collection.into(), index))
+    }
+}
+
+/// The triangles of each mode, from indices into the vertices: 4 is a list
+/// of triangles, 5 a strip and 6 a fan.
+fn triangulate(mode: usize, indices: &[usize]) -> Vec<[usize; 3]> {
+    let n = indices.len();
+    match mode {
+        4 => indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect(),
+        // every other triangle of a strip is wound the other way
+        5 => (2..n)
+            .map(|i| match i % 2 {
+                0 => [indices[i - 2], indices[i - 1], indices[i]],
+                _ => [indices[i - 1], indices[i - 2], indices[i]],
+            })
+            .collect(),
+        6 => (2..n)
+            .map(|i| [indices[0], indices[i - 1], indices[i]])
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Read one primitive of a mesh, or `None` if it's points or lines, which
+/// have no surface to render.
+fn read_primitive(
+    document: &Document,
+    primitive: &Json,
+) -> Result<Option<TriangleMesh>, GltfError> {
+    let mode = optional_index(primitive, "mode")?.unwrap_or(4);
+    if mode < 4 {
+        return Ok(None);
+    } else if mode > 6 {
+        return Err(GltfError::Unsupported(format!("mode {}", mode)));
+    }
+
+    let attributes = required(primitive, "attributes", Some)?;
+    let positions =
+        read_accessor(document, required_index(attributes, "POSITION")?)?;
+    if positions.components != 3 {
+        return Err(GltfError::BadProperty("POSITION".into()));
+    }
+    let count = positions.len();
+    // other attributes need one element of the right size per vertex
+    let attribute = |name: &str, components: &[usize]| match optional_index(
+        attributes, name,
+    )? {
+        Some(index) => {
+            let accessor = read_accessor(document, index)?;
+            if accessor.len() != count
+                || !components.contains(&accessor.components)
+            {
+                return Err(GltfError::BadProperty(name.into()));
+            }
+            Ok(Some(accessor))
+        }
+        None => Ok(None),
+    };
+
+    let mut mesh = TriangleMesh {
+        positions: (0..count)
+            .map(|i| {
+                let p = positions.get(i);
+                point(p[0], p[1], p[2])
+            })
+            .collect(),
+        ..TriangleMesh::default()
+    };
+    if let Some(normals) = attribute("NORMAL", &[3])? {
+        mesh.normals = Some(
+            (0..count)
+                .map(|i| {
+                    let n = normals.get(i);
+                    Normal3::new(n[0], n[1], n[2])
+                })
+                .collect(),
+        );
+    }
+    if let Some(uvs) = attribute("TEXCOORD_0", &[2])? {
+        mesh.uvs = Some(
+            (0..count)
+                .map(|i| DVec2::new(uvs.get(i)[0], uvs.get(i)[1]))
+                .collect(),
+        );
+    }
+    if let Some(colors) = attribute("COLOR_0", &[3, 4])? {
+        mesh.colors = Some(
+            (0..count)
+                .map(|i| {
+                    let c = colors.get(i);
+                    Color::new(c[0], c[1], c[2])
+                })
+                .collect(),
+        );
+    }
+
+    let indices = match optional_index(primitive, "indices")? {
+        Some(index) => read_accessor(document, index)?.indices(),
+        None => (0..count).collect(),
+    };
+    if let Some(&i) = indices.iter().find(|&&i| i >= count) {
+        return Err(GltfError::BadIndex("vertices".into(), i));
+    }
+    mesh.indices = triangulate(mode, &indices);
+    Ok(Some(mesh))
+}
+
+/// One primitive of a glTF mesh: triangles that share a material.
+pub struct GltfMesh {
+    pub mesh: TriangleMesh,
+    /// The triangles, ready to be placed in the scene by instances.
+    pub triangles: Arc<Bvh<Triangle>>,
+    /// Index into the scene's materials, or `None` for the default material.
+    pub material: Option<usize>,
+}
+
+/// A mesh placed in the world by a node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GltfInstance {
+    /// Index into the scene's meshes.
+    pub mesh: usize,
+    pub object_to_world: DMat4,
+}
+
+/// How a camera maps the scene onto the image. Angles are in radians.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective {
+        /// The vertical field of view.
+        yfov: f64,
+        /// Width over height, or `None` to use the image's.
+        aspect_ratio: Option<f64>,
+        znear: f64,
+        /// `None` for a projection that goes on forever.
+        zfar: Option<f64>,
+    },
+    Orthographic {
+        /// Half the width and height of the view.
+        xmag: f64,
+        ymag: f64,
+        znear: f64,
+        zfar: f64,
+    },
+}
+
+/// A camera looking down its own -z axis, with y up, just as the renderer's
+/// camera does.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfCamera {
+    pub name: Option<String>,
+    pub projection: Projection,
+    pub camera_to_world: DMat4,
+}
+
+/// The kinds of light in `KHR_lights_punctual`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PunctualKind {
+    Point,
+    /// Full intensity within the inner cone angle, fading out to nothing at
+    /// the outer one. Angles are from the axis, in radians.
+    Spot {
+        inner_cone_angle: f64,
+        outer_cone_angle: f64,
+    },
+    Directional,
+}
+
+/// A light from `KHR_lights_punctual`. Spot and directional lights shine
+/// down their own -z axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PunctualLight {
+    pub name: Option<String>,
+    pub kind: PunctualKind,
+    pub color: Color,
+    /// In candela for point and spot lights and lux for directional ones.
+    pub intensity: f64,
+    /// Distance beyond which the light has no effect, if there is one.
+    pub range: Option<f64>,
+    pub light_to_world: DMat4,
+}
+
+impl PunctualLight {
+    pub fn position(&self) -> Point3 {
+        self.light_to_world * point(0.0, 0.0, 0.0)
+    }
+
+    /// The unit direction the light shines in.
+    pub fn direction(&self) -> Vector3 {
+        (self.light_to_world * vector(0.0, 0.0, -1.0)).normalized()
+    }
+
+    /// The renderer's light for this one, taking the photometric units as
+    /// they are. The renderer's lights have no range, so that is ignored.
+    pub fn to_light(&self, scene_radius: f64) -> Box<dyn Light> {
+        let intensity = self.color * self.intensity;
+        match self.kind {
+            PunctualKind::Point => {
+                Box::new(PointLight::new(self.position(), intensity))
+            }
+            PunctualKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => Box::new(SpotLight::new(
+                self.position(),
+                self.direction(),
+                intensity,
+                outer_cone_angle,
+                inner_cone_angle,
+            )),
+            PunctualKind::Directional => Box::new(DirectionalLight::new(
+                self.direction(),
+                intensity,
+                scene_radius,
+            )),
+        }
+    }
+}
+
+fn read_camera(json: &Json) -> Result<Projection, GltfError> {
+    match required(json, "type", Json::as_str)? {
+        "perspective" => {
+            let p = required(json, "perspective", Some)?;
+            Ok(Projection::Perspective {
+                yfov: required(p, "yfov", Json::as_f64)?,
+                aspect_ratio: number(p, "aspectRatio")?,
+                znear: required(p, "znear", Json::as_f64)?,
+                zfar: number(p, "zfar")?,
+            })
+        }
+        "orthographic" => {
+            let o = required(json, "orthographic", Some)?;
+            Ok(Projection::Orthographic {
+                xmag: required(o, "xmag", Json::as_f64)?,
+                ymag: required(o, "ymag", Json::as_f64)?,
+                znear: required(o, "znear", Json::as_f64)?,
+                zfar: required(o, "zfar", Json::as_f64)?,
+            })
+        }
+        other => Err(GltfError::Unsupported(format!("camera type {}", other))),
+    }
+}
+
+fn read_light(json: &Json) -> Result<PunctualLight, GltfError> {
+    let kind = match required(json, "type", Json::as_str)? {
+        "point" => PunctualKind::Point,
+        "spot" => {
+            let spot = required(json, "spot", Some)?;
+            PunctualKind::Spot {
+                inner_cone_angle: number(spot, "innerConeAngle")?
+                    .unwrap_or(0.0),
+                outer_cone_angle: number(spot, "outerConeAngle")?
+                    .unwrap_or(PI / 4.0),
+            }
+        }
+        "directional" => PunctualKind::Directional,
+        other => {
+            return Err(GltfError::Unsupported(format!("light type {}", other)))
+        }
+    };
+    let color = match numbers(json, "color")? {
+        Some(c) if c.len() == 3 => Color::new(c[0], c[1], c[2]),
+        Some(_) => return Err(GltfError::BadProperty("color".into())),
+        None => Color::new(1.0, 1.0, 1.0),
+    };
+    Ok(PunctualLight {
+        name: json.get("name").and_then(Json::as_str).map(String::from),
+        kind,
+        color,
+        intensity: number(json, "intensity")?.unwrap_or(1.0),
+        range: number(json, "range")?,
+        light_to_world: DMat4::identity(),
+    })
+}
+
+/// A node's transform relative to its parent, from either its matrix or
+/// its translation, rotation and scale.
+fn local_transform(node: &Json) -> Result<DMat4, GltfError> {
+    let vector3 = |key: &str, default: Vector3| match numbers(node, key)? {
+        Some(v) if v.len() == 3 => Ok(vector(v[0], v[1], v[2])),
+        Some(_) => Err(GltfError::BadProperty(key.into())),
+        None => Ok(default),
+    };
+
+    match numbers(node, "matrix")? {
+        // column major, like DMat4
+        Some(m) if m.len() == 16 => {
+            let column = |i: usize| {
+                DVec4::new(m[4 * i], m[4 * i + 1], m[4 * i + 2], m[4 * i + 3])
+            };
+            Ok(DMat4::new(column(0), column(1), column(2), column(3)))
+        }
+        Some(_) => Err(GltfError::BadProperty("matrix".into())),
+        None => {
+            // quaternions are stored x, y, z, w
+            let rotation = match numbers(node, "rotation")? {
+                Some(q) if q.len() == 4 => {
+                    Quaternion::new(q[3], q[0], q[1], q[2]).normalized()
+                }
+                Some(_) => {
+                    return Err(GltfError::BadProperty("rotation".into()))
+                }
+                None => Quaternion::identity(),
+            };
+            let trs = Trs {
+                translation: vector3("translation", vector(0.0, 0.0, 0.0))?,
+                rotation,
+                scale: vector3("scale", vector(1.0, 1.0, 1.0))?,
+            };
+            Ok(trs.to_matrix())
+        }
+    }
+}
+
+/// Whether `m` squashes space flat, which no instance can be inverted out of.
+fn is_singular(m: &DMat4) -> bool {
+    let column = |i: usize| vector(m.cols[i].x, m.cols[i].y, m.cols[i].z);
+    column(0).cross(column(1)).dot(column(2)) == 0.0
+}
+
+/// A glTF 2.0 scene, read from a `.gltf` file with its buffers and images
+/// alongside or embedded as data URIs, or from a binary `.glb` file.
+///
+/// Everything is read from local files: URIs that would need fetching
+/// from elsewhere are an error. Only the scene the file picks as its
+/// default is read, or the first one if it doesn't pick one.
+pub struct GltfScene {
+    /// Every primitive of every mesh, in order.
+    pub meshes: Vec<GltfMesh>,
+    /// Placements of the meshes by the nodes of the scene.
+    pub instances: Vec<GltfInstance>,
+    pub materials: Vec<PbrMaterial>,
+    /// The cameras the scene's nodes hold, in the order they're reached
+    /// walking down the node hierarchy.
+    pub cameras: Vec<GltfCamera>,
+    pub lights: Vec<PunctualLight>,
+}
+
+impl GltfScene {
+    /// Read a `.gltf` or a `.glb` file, which are told apart by their
+    /// contents. Relative URIs are relative to the file's directory.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<GltfScene, GltfError> {
+        let path = path.as_ref();
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        GltfScene::parse(&fs::read(path)?, base)
+    }
+
+    /// Read glTF JSON or a GLB container, with relative URIs relative to
+    /// the directory `base`.
+    pub fn parse(bytes: &[u8], base: &Path) -> Result<GltfScene, GltfError> {
+        let document = Document::new(bytes, base)?;
+        let materials = material::read_materials(&document)?;
+
+        // each glTF mesh turns into one mesh here per primitive
+        let mut meshes = Vec::new();
+        let mut mesh_primitives = Vec::new();
+        for mesh in document.items("meshes") {
+            let mut primitives = Vec::new();
+            for primitive in required(mesh, "primitives", Json::as_array)? {
+                let triangles = match read_primitive(&document, primitive)? {
+                    Some(triangles) => triangles,
+                    None => continue,
+                };
+                let material = optional_index(primitive, "material")?;
+                if let Some(material) = material {
+                    if material >= materials.len() {
+                        let collection = "materials".into();
+                        return Err(GltfError::BadIndex(collection, material));
+                    }
+                }
+                let mut faces = triangles.triangles();
+                if let Some(material) = material {
+                    faces = faces
+                        .into_iter()
+                        .map(|face| face.with_material(material))
+                        .collect();
+                }
+                primitives.push(meshes.len());
+                meshes.push(GltfMesh {
+                    triangles: Arc::new(Bvh::new(faces)),
+                    mesh: triangles,
+                    material,
+                });
+            }
+            mesh_primitives.push(primitives);
+        }
+
+        let mut scene = GltfScene {
+            meshes,
+            instances: Vec::new(),
+            materials,
+            cameras: Vec::new(),
+            lights: Vec::new(),
+        };
+
+        let light_definitions = match document
+            .json
+            .get("extensions")
+            .and_then(|e| e.get("KHR_lights_punctual"))
+        {
+            Some(lights) => required(lights, "lights", Json::as_array)?
+                .iter()
+                .map(read_light)
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+
+        // walk down from the roots, keeping track of each node's transform
+        let nodes = document.items("nodes");
+        let mut visited = vec![false; nodes.len()];
+        let mut stack: Vec<(usize, DMat4)> = scene_roots(&document)?
+            .into_iter()
+            .rev()
+            .map(|node| (node, DMat4::identity()))
+            .collect();
+        while let Some((index, parent_to_world)) = stack.pop() {
+            let node = document.item("nodes", index)?;
+            if visited[index] {
+                return Err(GltfError::BadHierarchy(index));
+            }
+            visited[index] = true;
+            let node_to_world = parent_to_world * local_transform(node)?;
+
+            if let Some(mesh) = optional_index(node, "mesh")? {
+                let primitives =
+                    mesh_primitives.get(mesh).ok_or_else(|| {
+                        GltfError::BadIndex("meshes".into(), mesh)
+                    })?;
+                if !is_singular(&node_to_world) {
+                    for &mesh in primitives {
+                        scene.instances.push(GltfInstance {
+                            mesh,
+                            object_to_world: node_to_world,
+                        });
+                    }
+                }
+            }
+            if let Some(camera) = optional_index(node, "camera")? {
+                let json = document.item("cameras", camera)?;
+                scene.cameras.push(GltfCamera {
+                    name: json
+                        .get("name")
+                        .and_then(Json::as_str)
+                        .map(From::from),
+                    projection: read_camera(json)?,
+                    camera_to_world: node_to_world,
+                });
+            }
+            let light = node
+                .get("extensions")
+                .and_then(|e| e.get("KHR_lights_punctual"));
+            if let Some(light) = light {
+                let index = required_index(light, "light")?;
+                let mut light = light_definitions
+                    .get(index)
+                    .ok_or_else(|| GltfError::BadIndex("lights".into(), index))?
+                    .clone();
+                light.light_to_world = node_to_world;
+                scene.lights.push(light);
+            }
+
+            let children = optional(node, "children", Json::as_array)?;
+            for child in children.unwrap_or(&[]).iter().rev() {
+                let child = child
+                    .as_usize()
+                    .ok_or_else(|| GltfError::BadProperty("children".into()))?;
+                stack.push((child, node_to_world));
+            }
+        }
+
+        Ok(scene)
+    }
+
+    /// The material of mesh `mesh`, if it has one.
+    pub fn material(&self, mesh: usize) -> Option<&PbrMaterial> {
+        self.meshes[mesh].material.map(|m| &self.materials[m])
+    }
+
+    fn is_emissive(&self, mesh: usize) -> bool {
+        self.material(mesh).is_some_and(PbrMaterial::is_emissive)
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.instances
+            .iter()
+            .fold(Aabb::empty(), |bounds, instance| {
+                let transform = Transform::new(instance.object_to_world);
+                let mesh = &self.meshes[instance.mesh].mesh;
+                bounds.union(&mesh.bounds().transformed(&transform))
+            })
+    }
+
+    /// The instances of meshes that don't give off light. Their hits carry
+    /// the index of the mesh's material into `materials`.
+    pub fn objects(&self) -> Vec<Instance<Triangle>> {
+        self.instances
+            .iter()
+            .filter(|instance| !self.is_emissive(instance.mesh))
+            .map(|instance| {
+                let mesh = Arc::clone(&self.meshes[instance.mesh].triangles);
+                Transformed::new(mesh, instance.object_to_world)
+            })
+            .collect()
+    }
+
+    /// Area lights for the triangles of the instances of emissive meshes,
+    /// moved into world space. They emit their material's emissive factor;
+    /// emissive textures can't be represented by the renderer's lights.
+    pub fn area_lights(&self) -> Vec<Arc<AreaLight<Triangle>>> {
+        let mut lights = Vec::new();
+        for instance in self.instances.iter() {
+            let material = match self.material(instance.mesh) {
+                Some(material) if material.is_emissive() => material,
+                _ => continue,
+            };
+            let mut mesh = self.meshes[instance.mesh].mesh.clone();
+            mesh.transform(&Transform::new(instance.object_to_world));
+            lights.extend(area_lights(
+                mesh.triangles(),
+                material.emissive,
+                material.double_sided,
+            ));
+        }
+        lights
+    }
+
+    /// The renderer's lights for the punctual lights in the scene.
+    pub fn punctual_lights(&self) -> Vec<Box<dyn Light>> {
+        let (_, radius) = self.bounds().bounding_sphere();
+        self.lights.iter().map(|l| l.to_light(radius)).collect()
+    }
+}
+
+/// The root nodes of the scene the file picks, or of every node if the
+/// file doesn't have any scenes.
+fn scene_roots(document: &Document) -> Result<Vec<usize>, GltfError> {
+    let scenes = document.items("scenes");
+    if scenes.is_empty() {
+        let nodes = document.items("nodes");
+        let mut is_child = vec![false; nodes.len()];
+        for node in nodes {
+            let children = optional(node, "children", Json::as_array)?;
+            for child in children.unwrap_or(&[]).iter() {
+                match child.as_usize() {
+                    Some(child) if child < nodes.len() => {
+                        is_child[child] = true
+                    }
+                    _ => return Err(GltfError::BadProperty("children".into())),
+                }
+            }
+        }
+        return Ok((0..nodes.len()).filter(|&i| !is_child[i]).collect());
+    }
+
+    let scene = optional_index(&document.json, "scene")?.unwrap_or(0);
+    let scene = document.item("scenes", scene)?;
+    let roots = optional(scene, "nodes", Json::as_array)?.unwrap_or(&[]);
+    roots
+        .iter()
+        .map(|node| {
+            node.as_usize()
+                .ok_or_else(|| GltfError::BadProperty("nodes".into()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::math::test_util::assert_eps_eq;
+    use super::super::math::{rotation_y, scaling, translation, Ray};
+    use super::super::primitive::Scene;
+    use super::buffer::tests::{encode_base64, glb};
+    use super::material::Wrap;
+    use super::*;
+    use image::png::PNGEncoder;
+    use image::ColorType;
+    use std::env;
+
+    /// A scene with two copies of a square, a camera and two lights. BUFFER
+    /// stands for the buffer's URI and IMAGE for the texture's.
+    const SCENE: &str = r#"{
+        "asset": {"version": "2.0"},
+        "scene": 0,
+        "scenes": [{"nodes": [0, 3]}],
+        "nodes": [
+            {"translation": [0, 0, -5], "children": [1, 2]},
+            {
+                "mesh": 0,
+                "rotation": [0, 0.7071067811865476, 0, 0.7071067811865476],
+                "scale": [2, 2, 2]
+            },
+            {
+                "mesh": 0,
+                "translation": [3, 0, 0],
+                "extensions": {"KHR_lights_punctual": {"light": 1}}
+            },
+            {
+                "camera": 0,
+                "matrix": [1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 1, 2, 1],
+                "children": [4]
+            },
+            {"extensions": {"KHR_lights_punctual": {"light": 0}}},
+            {"mesh": 0}
+        ],
+        "meshes": [{"primitives": [
+            {
+                "attributes": {"POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2},
+                "indices": 3,
+                "material": 0
+            },
+            {"attributes": {"POSITION": 0}, "mode": 1}
+        ]}],
+        "materials": [
+            {
+                "name": "red",
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [1, 0, 0, 1],
+                    "metallicFactor": 0.25,
+                    "roughnessFactor": 0.5
+                },
+                "doubleSided": true
+            },
+            {
+                "pbrMetallicRoughness": {
+                    "baseColorTexture": {"index": 0},
+                    "metallicRoughnessTexture": {"index": 0, "texCoord": 1}
+                }
+            }
+        ],
+        "textures": [{"source": 0, "sampler": 0}],
+        "samplers": [{"wrapS": 33071}],
+        "images": [{"uri": "IMAGE"}],
+        "cameras": [
+            {"type": "perspective", "perspective": {"yfov": 0.8, "znear": 0.1}}
+        ],
+        "extensions": {"KHR_lights_punctual": {"lights": [
+            {"type": "point", "color": [1, 0.5, 0.25], "intensity": 10},
+            {"type": "spot", "intensity": 2, "spot": {"outerConeAngle": 0.5}}
+        ]}},
+        "buffers": [{BUFFER "byteLength": 140}],
+        "bufferViews": [
+            {"buffer": 0, "byteOffset": 0, "byteLength": 48},
+            {"buffer": 0, "byteOffset": 48, "byteLength": 48},
+            {"buffer": 0, "byteOffset": 96, "byteLength": 32},
+            {"buffer": 0, "byteOffset": 128, "byteLength": 12}
+        ],
+        "accessors": [
+            {
+                "bufferView": 0, "componentType": 5126, "count": 4,
+                "type": "VEC3"
+            },
+            {
+                "bufferView": 1, "componentType": 5126, "count": 4,
+                "type": "VEC3"
+            },
+            {
+                "bufferView": 2, "componentType": 5126, "count": 4,
+                "type": "VEC2"
+            },
+            {
+                "bufferView": 3, "componentType": 5123, "count": 6,
+                "type": "SCALAR"
+            }
+        ]
+    }"#;
+
+    /// A unit square in the xy plane facing +z, with normals, (u, v) and
+    /// 16 bit indices.
+    fn square_buffer() -> Vec<u8> {
+        let positions: &[f32] = &[
+            -0.5, -0.5, 0.0, 0.5, -0.5, 0.0, 0.5, 0.5, 0.0, -0.5, 0.5, 0.0,
+        ];
+        let normals: &[f32] =
+            &[0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0];
+        let uvs: &[f32] = &[0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0];
+        let mut bytes: Vec<u8> = [positions, normals, uvs]
+            .concat()
+            .iter()
+            .flat_map(|f| f.to_le_bytes().to_vec())
+            .collect();
+        for &i in [0u16, 1, 2, 0, 2, 3].iter() {
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// A PNG with a red pixel and a dark green one.
+    fn image_uri() -> String {
+        let mut png = Vec::new();
+        PNGEncoder::new(&mut png)
+            .encode(&[255, 0, 0, 0, 128, 0], 2, 1, ColorType::RGB(8))
+            .unwrap();
+        format!("data:image/png;base64,{}", encode_base64(&png))
+    }
+
+    /// The scene with its buffer in a data URI.
+    fn scene_json() -> String {
+        let buffer = format!(
+            r#""uri": "data:application/octet-stream;base64,{}","#,
+            encode_base64(&square_buffer())
+        );
+        SCENE
+            .replace("BUFFER", &buffer)
+            .replace("IMAGE", &image_uri())
+    }
+
+    fn parse(json: &str) -> Result<GltfScene, GltfError> {
+        GltfScene::parse(json.as_bytes(), Path::new("/nonexistent"))
+    }
+
+    fn check_scene(scene: &GltfScene) {
+        // the lines primitive is left out
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.meshes[0].mesh.len(), 2);
+        assert_eq!(scene.meshes[0].material, Some(0));
+        assert_eq!(scene.instances.len(), 2);
+        let placement = translation(0.0, 0.0, -5.0)
+            * rotation_y(0.5 * PI)
+            * scaling(2.0, 2.0, 2.0);
+        let instance = scene.instances[0].object_to_world;
+        for (a, b) in instance.cols.iter().zip(placement.cols.iter()) {
+            assert!((*a - *b).mag() < 1.0e-12);
+        }
+        assert_eq!(
+            scene.instances[1].object_to_world,
+            translation(3.0, 0.0, -5.0)
+        );
+
+        let objects = scene.objects();
+        // the first copy is turned to face +x and doubled in size
+        let ray = Ray::new(point(5.0, 0.3, -5.25), vector(-1.0, 0.0, 0.0));
+        let hit = objects.intersect(&ray).unwrap();
+        assert_eq!(hit.primitive, 0);
+        assert!((hit.t - 5.0).abs() < 1.0e-9);
+        assert_eps_eq(&hit.normal, &Normal3::new(1.0, 0.0, 0.0), 1.0e-9);
+        let ray = Ray::new(point(3.2, 0.1, 0.0), vector(0.0, 0.0, -1.0));
+        let hit = objects.intersect(&ray).unwrap();
+        assert_eq!(hit.primitive, 1);
+        assert_eq!(hit.material, Some(0));
+        assert!((hit.t - 5.0).abs() < 1.0e-9);
+        assert_eps_eq(&hit.uv, &DVec2::new(0.7, 0.4), 1.0e-6);
+
+        let bounds = scene.bounds();
+        assert_eps_eq(&bounds.min, &point(-0.0, -1.0, -6.0), 1.0e-9);
+        assert_eps_eq(&bounds.max, &point(3.5, 1.0, -4.0), 1.0e-9);
+
+        assert_eq!(scene.cameras.len(), 1);
+        assert_eq!(
+            scene.cameras[0].camera_to_world,
+            translation(0.0, 1.0, 2.0)
+        );
+        assert_eq!(
+            scene.cameras[0].projection,
+            Projection::Perspective {
+                yfov: 0.8,
+                aspect_ratio: None,
+                znear: 0.1,
+                zfar: None,
+            }
+        );
+
+        // lights come in the order the hierarchy is walked
+        assert_eq!(scene.lights.len(), 2);
+        let spot = &scene.lights[0];
+        assert_eq!(
+            spot.kind,
+            PunctualKind::Spot {
+                inner_cone_angle: 0.0,
+                outer_cone_angle: 0.5,
+            }
+        );
+        assert_eq!(spot.position(), point(3.0, 0.0, -5.0));
+        assert_eq!(spot.direction(), vector(0.0, 0.0, -1.0));
+        let light = &scene.lights[1];
+        assert_eq!(light.kind, PunctualKind::Point);
+        assert_eq!(light.color, Color::new(1.0, 0.5, 0.25));
+        assert_eq!(light.intensity, 10.0);
+        assert_eq!(light.position(), point(0.0, 1.0, 2.0));
+    }
+
+    #[test]
+    fn test_parse_gltf() {
+        let scene = parse(&scene_json()).unwrap();
+        check_scene(&scene);
+
+        let red = &scene.materials[0];
+        assert_eq!(red.name.as_deref(), Some("red"));
+        assert_eq!(red.base_color, Color::new(1.0, 0.0, 0.0));
+        assert_eq!((red.metallic, red.roughness), (0.25, 0.5));
+        assert!(red.double_sided);
+        assert!(!red.is_emissive());
+        assert_eq!(scene.material(0), Some(red));
+    }
+
+    #[test]
+    fn test_parse_glb() {
+        let json = SCENE.replace("BUFFER", "").replace("IMAGE", &image_uri());
+        let bytes = glb(&json, Some(&square_buffer()));
+        check_scene(&GltfScene::parse(&bytes, Path::new("")).unwrap());
+
+        let bytes = glb(&json, None);
+        match GltfScene::parse(&bytes, Path::new("")) {
+            Err(GltfError::BadProperty(s)) => assert_eq!(s, "uri"),
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_load_external_buffer() {
+        let directory = env::temp_dir();
+        let bin = directory.join("raytracer test square.bin");
+        fs::write(&bin, square_buffer()).unwrap();
+        let json = SCENE
+            .replace("BUFFER", r#""uri": "raytracer%20test%20square.bin","#)
+            .replace("IMAGE", &image_uri());
+        let path = directory.join("raytracer_test_square.gltf");
+        fs::write(&path, json).unwrap();
+
+        let scene = GltfScene::load(&path).unwrap();
+        fs::remove_file(&bin).unwrap();
+        check_scene(&scene);
+        match GltfScene::load(&path) {
+            Err(GltfError::Io(_)) => (),
+            other => panic!("unexpected {:?}", other.err()),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_textures() {
+        let scene = parse(&scene_json()).unwrap();
+        let material = &scene.materials[1];
+        let texture = material.base_color_texture.as_ref().unwrap();
+        assert_eq!(texture.wrap, (Wrap::ClampToEdge, Wrap::Repeat));
+        assert_eq!((texture.image.width(), texture.image.height()), (2, 1));
+
+        // color textures are sRGB encoded, and clamped here in u
+        let red = Color::new(1.0, 0.0, 0.0);
+        assert_eq!(material.base_color_at(DVec2::new(0.25, 0.5)), red);
+        let green = material.base_color_at(DVec2::new(1.5, 0.5));
+        assert!((green.g() - 0.2158605).abs() < 1.0e-6);
+
+        // but metalness and roughness aren't
+        let (metallic, roughness) =
+            material.metallic_roughness_at(DVec2::new(0.75, 0.5));
+        assert_eq!((metallic, roughness), (0.0, 128.0 / 255.0));
+        let texture = material.metallic_roughness_texture.as_ref().unwrap();
+        assert_eq!(texture.tex_coord, 1);
+    }
+
+    #[test]
+    fn test_emissive_meshes_become_lights() {
+        let json = scene_json().replace(
+            r#""doubleSided": true"#,
+            r#""emissiveFactor": [1, 1, 0.5],
+               "extensions": {
+                   "KHR_materials_emissive_strength": {"emissiveStrength": 4}
+               }"#,
+        );
+        let scene = parse(&json).unwrap();
+        assert_eq!(scene.materials[0].emissive, Color::new(4.0, 4.0, 2.0));
+        assert!(scene.objects().is_empty());
+        let lights = scene.area_lights();
+        assert_eq!(lights.len(), 4);
+
+        // the second copy faces +z, and is one-sided
+        let ray = Ray::new(point(3.2, 0.1, 0.0), vector(0.0, 0.0, -1.0));
+        let hit = lights.intersect(&ray).unwrap();
+        assert_eq!(hit.emitted, Color::new(4.0, 4.0, 2.0));
+        let ray = Ray::new(point(3.2, 0.1, -10.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(lights.intersect(&ray).unwrap().emitted, Color::black());
+    }
+
+    #[test]
+    fn test_punctual_lights() {
+        let scene = parse(&scene_json()).unwrap();
+        let lights = scene.punctual_lights();
+
+        // straight down the spot light's axis, and outside its cone
+        let sample = lights[0].sample_li(point(3.0, 0.0, -7.0), (0.5, 0.5));
+        assert_eq!(sample.unwrap().radiance, Color::new(0.5, 0.5, 0.5));
+        let outside = lights[0].sample_li(point(5.0, 0.0, -7.0), (0.5, 0.5));
+        assert!(outside.is_none());
+
+        let sample = lights[1].sample_li(point(0.0, -1.0, 2.0), (0.5, 0.5));
+        assert_eq!(sample.unwrap().radiance, Color::new(2.5, 1.25, 0.625));
+    }
+
+    #[test]
+    fn test_triangulate() {
+        let indices = [0, 1, 2, 3];
+        assert_eq!(triangulate(4, &indices), vec![[0, 1, 2]]);
+        assert_eq!(triangulate(5, &indices), vec![[0, 1, 2], [2, 1, 3]]);
+        assert_eq!(triangulate(6, &indices), vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn test_scene_without_scenes() {
+        let json = r#"{
+            "asset": {"version": "2.0"},
+            "nodes": [
+                {"camera": 0},
+                {"translation": [1, 0, 0], "children": [0]}
+            ],
+            "cameras": [{
+                "type": "orthographic",
+                "orthographic": {"xmag": 1, "ymag": 2, "znear": 0, "zfar": 9}
+            }]
+        }"#;
+        let scene = parse(json).unwrap();
+        assert_eq!(scene.cameras.len(), 1);
+        assert_eq!(
+            scene.cameras[0].camera_to_world,
+            translation(1.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            scene.cameras[0].projection,
+            Projection::Orthographic {
+                xmag: 1.0,
+                ymag: 2.0,
+                znear: 0.0,
+                zfar: 9.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let json = scene_json();
+        let error = |json: &str| match parse(json) {
+            Err(err) => err,
+            Ok(_) => panic!("{} parsed", json),
+        };
+
+        match error(&json.replace("\"2.0\"", "\"1.0\"")) {
+            GltfError::Unsupported(s) => assert_eq!(s, "glTF version 1.0"),
+            other => panic!("unexpected {:?}", other),
+        }
+        let required = json.replace(
+            "\"scene\": 0,",
+            r#""scene": 0,
+               "extensionsRequired": ["KHR_draco_mesh_compression"],"#,
+        );
+        match error(&required) {
+            GltfError::Unsupported(s) => {
+                assert_eq!(s, "extension KHR_draco_mesh_compression")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match error(&json.replace("\"indices\": 3", "\"indices\": 9")) {
+            GltfError::BadIndex(s, 9) => assert_eq!(s, "accessors"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match error(&json.replace(
+            "\"scale\": [2, 2, 2]",
+            "\"scale\": [2, 2, 2], \"children\": [0]",
+        )) {
+            GltfError::BadHierarchy(0) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match error(&json.replace("\"byteLength\": 140", "\"byteLength\": 141"))
+        {
+            GltfError::UnexpectedEnd => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match error(&json.replace("\"count\": 6", "\"count\": 7")) {
+            GltfError::UnexpectedEnd => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        let positions =
+            r#""bufferView": 0, "componentType": 5126, "count": 4"#;
+        match error(&json.replace(positions, &positions.replace('4', "6e18")))
+        {
+            GltfError::UnexpectedEnd => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        let zeros = r#""componentType": 5126, "count": 1e30"#;
+        match error(&json.replace(positions, zeros)) {
+            GltfError::BadProperty(s) => assert_eq!(s, "count"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match error(&json.replace("{\"POSITION\": 0, ", "{")) {
+            GltfError::BadProperty(s) => assert_eq!(s, "POSITION"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match error(&json.replace("\"scale\": [2, 2, 2]", "\"scale\": [2, 2]"))
+        {
+            GltfError::BadProperty(s) => assert_eq!(s, "scale"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match error(&json.replace("\"mesh\": 0,", "\"mesh\": 2,")) {
+            GltfError::BadIndex(s, 2) => assert_eq!(s, "meshes"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match error("{\"asset\": ") {
+            GltfError::Json(JsonError::UnexpectedEnd) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match error(&json.replace("base64,", "base64,*")) {
+            GltfError::BadUri(_) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use super::color::Color;
+use image::hdr::{HDRDecoder, HDREncoder};
+use image::{ImageResult, Rgb, RgbImage};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+/// A high dynamic range image whose pixels hold linear radiance values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrImage {
+    width: usize,
+    height: usize,
+    /// Row by row, starting at the top.
+    pixels: Vec<Color>,
+}
+
+impl HdrImage {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> HdrImage {
+        assert_eq!(pixels.len(), width * height);
+        HdrImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Decode an 8 bit image, undoing the sRGB transfer function if `srgb`
+    /// is set.
+    pub fn from_rgb(rgb: &RgbImage, srgb: bool) -> HdrImage {
+        let decode = |c: u8| {
+            let c = c as f64 / 255.0;
+            if srgb {
+                srgb_to_linear(c)
+            } else {
+                c
+            }
+        };
+        let pixels = rgb
+            .pixels()
+            .map(|p| Color::new(decode(p[0]), decode(p[1]), decode(p[2])))
+            .collect();
+        let (width, height) = rgb.dimensions();
+        HdrImage::new(width as usize, height as usize, pixels)
+    }
+
+    /// Read a Radiance RGBE (.hdr) file.
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<HdrImage> {
+        let reader = BufReader::new(File::open(path)?);
+        let decoder = HDRDecoder::new(reader)?;
+        let metadata = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()?
+            .into_iter()
+            .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+
+        Ok(HdrImage::new(
+            metadata.width as usize,
+            metadata.height as usize,
+            pixels,
+        ))
+    }
+
+    /// Write the image as a Radiance RGBE (.hdr) file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        let pixels: Vec<_> = self
+            .pixels
+            .iter()
+            .map(|c| Rgb([c.r() as f32, c.g() as f32, c.b() as f32]))
+            .collect();
+        HDREncoder::new(writer).encode(&pixels, self.width, self.height)?;
+        Ok(())
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    /// The pixel containing the point (u, v) of the unit square, with (0, 0)
+    /// at the top left corner.
+    pub fn lookup(&self, u: f64, v: f64) -> Color {
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.get(x, y)
+    }
+}
+
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_lookup() {
+        let image = HdrImage::new(
+            2,
+            1,
+            vec![Color::new(1.0, 0.0, 0.0), Color::new(0.0, 5.0, 0.0)],
+        );
+        assert_eq!(image.lookup(0.25, 0.5), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(image.lookup(0.75, 0.0), Color::new(0.0, 5.0, 0.0));
+        assert_eq!(image.lookup(1.0, 1.0), Color::new(0.0, 5.0, 0.0));
+    }
+
+    #[test]
+    fn test_load() {
+        // two uncompressed RGBE pixels
+        let mut data =
+            b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+        data.extend_from_slice(&[128, 64, 32, 129, 0, 0, 0, 0]);
+        let path = env::temp_dir().join("raytracer_test_load.hdr");
+        fs::write(&path, data).unwrap();
+
+        let image = HdrImage::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.get(0, 0), Color::new(1.0, 0.5, 0.25));
+        assert_eq!(image.get(1, 0), Color::black());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let pixels = vec![
+            Color::new(1.0, 2.0, 4.0),
+            Color::new(0.5, 0.25, 0.125),
+            Color::black(),
+            Color::new(8.0, 8.0, 8.0),
+        ];
+        let image = HdrImage::new(2, 2, pixels);
+        let path = env::temp_dir().join("raytracer_test_save.hdr");
+        image.save(&path).unwrap();
+
+        let loaded = HdrImage::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, image);
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        assert!(HdrImage::load("does/not/exist.hdr").is_err());
+    }
+
+    #[test]
+    fn test_srgb_to_linear() {
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1.0e-12);
+        assert!((srgb_to_linear(0.5) - 0.2140).abs() < 1.0e-4);
+    }
+}
@@ -0,0 +1,347 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Something wrong with an IES file.
+#[derive(Debug)]
+pub enum IesError {
+    Io(io::Error),
+    /// The file ended before all of the photometric data was read.
+    UnexpectedEnd,
+    /// There is no `TILT=` line ending the header.
+    MissingTilt,
+    /// A value that should be a number isn't one.
+    BadNumber(String),
+    /// Only type C photometry, used by nearly all architectural lighting,
+    /// is supported.
+    UnsupportedPhotometricType(u32),
+    /// The angles aren't in increasing order, or there are none.
+    BadAngles,
+}
+
+impl fmt::Display for IesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IesError::Io(err) => write!(f, "{}", err),
+            IesError::UnexpectedEnd => write!(f, "unexpected end of file"),
+            IesError::MissingTilt => write!(f, "missing TILT= line"),
+            IesError::BadNumber(s) => {
+                write!(f, "expected a number, found '{}'", s)
+            }
+            IesError::UnsupportedPhotometricType(t) => {
+                write!(f, "unsupported photometric type {}", t)
+            }
+            IesError::BadAngles => write!(f, "angles must be increasing"),
+        }
+    }
+}
+
+impl Error for IesError {}
+
+impl From<io::Error> for IesError {
+    fn from(err: io::Error) -> IesError {
+        IesError::Io(err)
+    }
+}
+
+/// The candela distribution of a luminaire, read from an IESNA LM-63 file.
+///
+/// Angles are in degrees, in type C photometry: vertical angles go from 0
+/// straight down to 180 straight up, and horizontal angles go around the
+/// vertical axis starting from the luminaire's length.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IesProfile {
+    vertical_angles: Vec<f64>,
+    horizontal_angles: Vec<f64>,
+    /// One row of values per horizontal angle, with one value per vertical
+    /// angle, already scaled by the candela multiplier.
+    candela: Vec<Vec<f64>>,
+    /// The keyword lines of the header, like `[MANUFAC]`, with the keyword
+    /// and its value.
+    keywords: Vec<(String, String)>,
+}
+
+impl IesProfile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<IesProfile, IesError> {
+        IesProfile::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<IesProfile, IesError> {
+        let mut lines = text.lines();
+
+        // the header is free form up to the TILT line, apart from keywords
+        let mut keywords = Vec::new();
+        let tilt = loop {
+            let line = lines.next().ok_or(IesError::MissingTilt)?.trim();
+            if let Some(tilt) = line.strip_prefix("TILT=") {
+                break tilt.trim();
+            }
+            if line.starts_with('[') {
+                if let Some(end) = line.find(']') {
+                    let keyword = line[1..end].to_string();
+                    let value = line[end + 1..].trim().to_string();
+                    keywords.push((keyword, value));
+                }
+            }
+        };
+
+        // everything after is whitespace separated numbers, wrapped onto
+        // lines however the writer liked
+        let rest: Vec<&str> = lines.collect();
+        let mut values = rest
+            .iter()
+            .flat_map(|line| {
+                line.split(|c: char| c.is_whitespace() || c == ',')
+            })
+            .filter(|s| !s.is_empty());
+        let mut next = || -> Result<f64, IesError> {
+            let s = values.next().ok_or(IesError::UnexpectedEnd)?;
+            s.parse().map_err(|_| IesError::BadNumber(s.to_string()))
+        };
+
+        // lamp tilt data only matters for lamps that change output when
+        // tilted, and is skipped
+        if tilt == "INCLUDE" {
+            let _lamp_to_luminaire_geometry = next()?;
+            let count = next()? as usize;
+            for _ in 0..2 * count {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()? as u32;
+        let _units = next()?;
+        let _width = next()?;
+        let _length = next()?;
+        let _height = next()?;
+        let _ballast_factor = next()?;
+        let _future_use = next()?;
+        let _input_watts = next()?;
+
+        if photometric_type != 1 {
+            return Err(IesError::UnsupportedPhotometricType(photometric_type));
+        }
+
+        let mut read =
+            |n: usize| (0..n).map(|_| next()).collect::<Result<Vec<_>, _>>();
+        let vertical_angles = read(vertical_count)?;
+        let horizontal_angles = read(horizontal_count)?;
+        let mut candela = Vec::with_capacity(horizontal_count);
+        for _ in 0..horizontal_count {
+            let row = read(vertical_count)?;
+            candela.push(row.iter().map(|c| c * multiplier).collect());
+        }
+
+        for angles in [&vertical_angles, &horizontal_angles].iter() {
+            if angles.is_empty() || angles.windows(2).any(|w| w[0] >= w[1]) {
+                return Err(IesError::BadAngles);
+            }
+        }
+
+        Ok(IesProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+            keywords,
+        })
+    }
+
+    /// The value of a header keyword, such as "MANUFAC".
+    pub fn keyword(&self, keyword: &str) -> Option<&str> {
+        self.keywords
+            .iter()
+            .find(|(k, _)| k == keyword)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The luminous intensity in candela at the vertical angle `theta` and
+    /// the horizontal angle `phi`, in degrees, interpolated between the
+    /// measured angles. Horizontal angles the file leaves out are filled in
+    /// by the symmetry its range implies.
+    pub fn candela(&self, theta: f64, phi: f64) -> f64 {
+        let phi = self.fold_horizontal(phi);
+
+        let (i, s) = match bracket(&self.horizontal_angles, phi) {
+            Some(b) => b,
+            None => return 0.0,
+        };
+        let at = |row: usize| match bracket(&self.vertical_angles, theta) {
+            Some((j, t)) => {
+                let values = &self.candela[row];
+                let next = values[(j + 1).min(values.len() - 1)];
+                values[j] * (1.0 - t) + next * t
+            }
+            None => 0.0,
+        };
+
+        let next = (i + 1).min(self.horizontal_angles.len() - 1);
+        at(i) * (1.0 - s) + at(next) * s
+    }
+
+    /// The largest intensity anywhere in the distribution.
+    pub fn max_candela(&self) -> f64 {
+        self.candela.iter().flatten().cloned().fold(0.0, f64::max)
+    }
+
+    /// Map `phi` into the range of horizontal angles the file covers.
+    fn fold_horizontal(&self, phi: f64) -> f64 {
+        let first = self.horizontal_angles[0];
+        let last = *self.horizontal_angles.last().unwrap();
+        let phi = phi.rem_euclid(360.0);
+        if self.horizontal_angles.len() == 1 {
+            // the same in every direction
+            first
+        } else if first == 0.0 && last == 90.0 {
+            // symmetric in each quadrant
+            let phi = if phi > 180.0 { 360.0 - phi } else { phi };
+            if phi > 90.0 {
+                180.0 - phi
+            } else {
+                phi
+            }
+        } else if first == 0.0 && last == 180.0 {
+            // symmetric about the 0-180 degree plane
+            if phi > 180.0 {
+                360.0 - phi
+            } else {
+                phi
+            }
+        } else if first == 90.0 && last == 270.0 {
+            // symmetric about the 90-270 degree plane
+            if phi < 90.0 {
+                180.0 - phi
+            } else if phi > 270.0 {
+                540.0 - phi
+            } else {
+                phi
+            }
+        } else {
+            phi
+        }
+    }
+}
+
+/// Find the interval of the increasing `angles` that holds `x`, returning
+/// the index of its start and how far along it `x` lies. A single angle
+/// only holds itself.
+fn bracket(angles: &[f64], x: f64) -> Option<(usize, f64)> {
+    const EPS: f64 = 1.0e-9;
+    let first = angles[0];
+    let last = angles[angles.len() - 1];
+    if x < first - EPS || x > last + EPS {
+        return None;
+    }
+    if angles.len() == 1 {
+        return Some((0, 0.0));
+    }
+
+    let i = angles[..angles.len() - 1]
+        .iter()
+        .rposition(|&a| a <= x)
+        .unwrap_or(0);
+    let t = (x - angles[i]) / (angles[i + 1] - angles[i]);
+    Some((i, t.clamp(0.0, 1.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::math::test_util::assert_eps_eq;
+
+    const EPS: f64 = 1.0e-9;
+    const ISOTROPIC: &str = include_str!("../data/ies/isotropic.ies");
+    const DOWNLIGHT: &str = include_str!("../data/ies/downlight.ies");
+    const WALLWASHER: &str = include_str!("../data/ies/wallwasher.ies");
+
+    #[test]
+    fn test_isotropic() {
+        let profile = IesProfile::parse(ISOTROPIC).unwrap();
+        for &(theta, phi) in [(0.0, 0.0), (45.0, 123.0), (180.0, 300.0)].iter()
+        {
+            assert_eps_eq(&profile.candela(theta, phi), &1000.0, EPS);
+        }
+        assert_eq!(profile.keyword("TEST"), Some("isotropic"));
+    }
+
+    #[test]
+    fn test_downlight() {
+        let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+        assert_eq!(profile.keyword("LUMCAT"), Some("DL-10"));
+        assert_eq!(profile.keyword("WATTAGE"), None);
+
+        // measured angles, and halfway between two of them
+        assert_eps_eq(&profile.candela(0.0, 0.0), &500.0, EPS);
+        assert_eps_eq(&profile.candela(30.0, 77.0), &330.0, EPS);
+        assert_eps_eq(&profile.candela(35.0, 200.0), &270.0, EPS);
+
+        // nothing above the horizon
+        assert_eq!(profile.candela(90.0, 0.0), 0.0);
+        assert_eq!(profile.candela(120.0, 0.0), 0.0);
+        assert_eq!(profile.max_candela(), 500.0);
+    }
+
+    #[test]
+    fn test_quadrant_symmetry_and_tilt() {
+        let profile = IesProfile::parse(WALLWASHER).unwrap();
+
+        // the candela multiplier of 2 applies to every value
+        assert_eps_eq(&profile.candela(0.0, 0.0), &200.0, EPS);
+        assert_eps_eq(&profile.candela(45.0, 0.0), &160.0, EPS);
+        assert_eps_eq(&profile.candela(45.0, 90.0), &40.0, EPS);
+        assert_eps_eq(&profile.candela(45.0, 67.5), &80.0, EPS);
+
+        // the other quadrants mirror the first
+        for &phi in [90.0, 270.0].iter() {
+            assert_eps_eq(&profile.candela(45.0, phi), &40.0, EPS);
+        }
+        for &phi in [135.0, 225.0, 315.0, -45.0].iter() {
+            assert_eps_eq(&profile.candela(45.0, phi), &120.0, EPS);
+        }
+    }
+
+    #[test]
+    fn test_load() {
+        let path =
+            concat!(env!("CARGO_MANIFEST_DIR"), "/data/ies/downlight.ies");
+        let profile = IesProfile::load(path).unwrap();
+        assert_eq!(profile, IesProfile::parse(DOWNLIGHT).unwrap());
+
+        match IesProfile::load("data/ies/missing.ies") {
+            Err(IesError::Io(_)) => (),
+            other => panic!("expected an io error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        match IesProfile::parse("IESNA:LM-63-2002\n[TEST] nothing\n") {
+            Err(IesError::MissingTilt) => (),
+            other => panic!("expected a missing tilt, got {:?}", other),
+        }
+
+        let truncated = &DOWNLIGHT[..DOWNLIGHT.len() - 12];
+        match IesProfile::parse(truncated) {
+            Err(IesError::UnexpectedEnd) => (),
+            other => panic!("expected the end of the file, got {:?}", other),
+        }
+
+        let garbled = DOWNLIGHT.replace("330", "3e0x");
+        match IesProfile::parse(&garbled) {
+            Err(IesError::BadNumber(s)) => assert_eq!(s, "3e0x"),
+            other => panic!("expected a bad number, got {:?}", other),
+        }
+
+        let type_b = ISOTROPIC.replace("1 -1 1 3 1 1 2", "1 -1 1 3 1 2 2");
+        match IesProfile::parse(&type_b) {
+            Err(IesError::UnsupportedPhotometricType(2)) => (),
+            other => panic!("expected an unsupported type, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use super::color::Color;
+use super::math::{Normal3, Ray};
+use super::primitive::{node_visits, Intersection, Scene};
+use super::sampling;
+use rand::Rng;
+
+/// Diagnostic renderers that visualize the geometry of a scene instead of
+/// lighting it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    /// The fraction of the hemisphere above each hit point that is not
+    /// blocked by geometry within `radius`, estimated with `samples` cosine
+    /// weighted rays.
+    AmbientOcclusion {
+        radius: f64,
+        samples: u32,
+    },
+    ShadingNormal,
+    GeometricNormal,
+    /// Distance to the hit point, fading from white at the camera to black at
+    /// `max_depth`.
+    Depth {
+        max_depth: f64,
+    },
+    Uv,
+    Barycentric,
+    PrimitiveId,
+    /// How many bounding volume hierarchy nodes the ray was tested against,
+    /// as a heatmap from blue for none to red for `max_nodes` or more.
+    BvhCost {
+        max_nodes: u32,
+    },
+}
+
+impl Integrator {
+    /// Return the color seen along `ray`.
+    pub fn li<S, R>(&self, ray: &Ray, scene: &S, rng: &mut R) -> Color
+    where
+        S: Scene + ?Sized,
+        R: Rng + ?Sized,
+    {
+        // rays cost the same whether they hit anything or not
+        if let Integrator::BvhCost { max_nodes } = *self {
+            let before = node_visits();
+            scene.intersect(ray);
+            let visits = node_visits() - before;
+            return heat_color(visits as f64 / max_nodes.max(1) as f64);
+        }
+
+        let hit = match scene.intersect(ray) {
+            Some(hit) => hit,
+            None => return Color::black(),
+        };
+
+        match *self {
+            Integrator::AmbientOcclusion { radius, samples } => {
+                let visible =
+                    ambient_occlusion(ray, &hit, scene, radius, samples, rng);
+                Color::new(visible, visible, visible)
+            }
+            Integrator::ShadingNormal => normal_color(hit.shading_normal),
+            Integrator::GeometricNormal => normal_color(hit.normal),
+            Integrator::Depth { max_depth } => {
+                let depth = hit.t * ray.direction.mag();
+                let shade = f64::max(0.0, 1.0 - depth / max_depth);
+                Color::new(shade, shade, shade)
+            }
+            Integrator::Uv => Color::new(hit.uv.x, hit.uv.y, 0.0),
+            Integrator::Barycentric => match hit.barycentric {
+                Some(b) => Color::new(b.x, b.y, b.z),
+                None => Color::black(),
+            },
+            Integrator::PrimitiveId => false_color(hit.primitive),
+            Integrator::BvhCost { .. } => unreachable!(),
+        }
+    }
+}
+
+fn ambient_occlusion<S, R>(
+    ray: &Ray,
+    hit: &Intersection,
+    scene: &S,
+    radius: f64,
+    samples: u32,
+    rng: &mut R,
+) -> f64
+where
+    S: Scene + ?Sized,
+    R: Rng + ?Sized,
+{
+    if samples == 0 {
+        return 0.0;
+    }
+
+    // sample the hemisphere on the side of the surface the ray came from
+    let normal = if hit.normal.dot(ray.direction) > 0.0 {
+        -hit.normal
+    } else {
+        hit.normal
+    };
+
+    let mut visible = 0;
+    for _ in 0..samples {
+        let local = sampling::cosine_hemisphere(rng.gen(), rng.gen());
+        let direction = sampling::to_world(local, normal.into());
+        let occlusion_ray = Ray::new(hit.point, direction);
+        match scene.intersect(&occlusion_ray) {
+            Some(occluder) if occluder.t < radius => (),
+            _ => visible += 1,
+        }
+    }
+
+    visible as f64 / samples as f64
+}
+
+/// Map each component of a unit normal from [-1, 1] to [0, 1].
+fn normal_color(normal: Normal3) -> Color {
+    Color::new(
+        0.5 * (normal.x + 1.0),
+        0.5 * (normal.y + 1.0),
+        0.5 * (normal.z + 1.0),
+    )
+}
+
+/// Pick a distinct, saturated color for `id`. Consecutive ids step around
+/// the hue circle by the golden ratio so neighbors never look alike.
+fn false_color(id: usize) -> Color {
+    const GOLDEN_RATIO_CONJUGATE: f64 = 0.618_033_988_749_895;
+    let hue = (id as f64 * GOLDEN_RATIO_CONJUGATE).fract() * 6.0;
+    let (saturation, value) = (0.75, 0.95);
+
+    // hsv to rgb
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - ((hue % 2.0) - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    Color::new(r + m, g + m, b + m)
+}
+
+/// Map `t` in [0, 1] from blue through cyan, green and yellow to red.
+/// Values outside of the range are clamped.
+fn heat_color(t: f64) -> Color {
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    let x = t.fract();
+    match t as u32 {
+        0 => Color::new(0.0, x, 1.0),
+        1 => Color::new(0.0, 1.0, 1.0 - x),
+        2 => Color::new(x, 1.0, 0.0),
+        3 => Color::new(1.0, 1.0 - x, 0.0),
+        _ => Color::new(1.0, 0.0, 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::math::{point, vector};
+    use super::super::primitive::{Bvh, Sphere, Triangle};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn make_floor() -> Vec<Triangle> {
+        // a large square in the y = 0 plane with its normal facing up
+        let a = point(-100.0, 0.0, -100.0);
+        let b = point(100.0, 0.0, -100.0);
+        let c = point(100.0, 0.0, 100.0);
+        let d = point(-100.0, 0.0, 100.0);
+        vec![Triangle::new(a, b, c), Triangle::new(a, c, d)]
+    }
+
+    #[test]
+    fn test_ambient_occlusion_open_floor() {
+        let scene = make_floor();
+        let mut rng = StdRng::seed_from_u64(0);
+        let ao = Integrator::AmbientOcclusion {
+            radius: 10.0,
+            samples: 64,
+        };
+
+        let ray = Ray::new(point(0.3, 1.0, 0.2), vector(0.0, -1.0, 0.0));
+        assert_eq!(ao.li(&ray, &scene, &mut rng), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_ambient_occlusion_inside_sphere() {
+        let scene = Sphere::new(point(0.0, 0.0, 0.0), 5.0);
+        let mut rng = StdRng::seed_from_u64(0);
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, -1.0));
+
+        // every occlusion ray hits the other side of the sphere, which is
+        // further away than a small radius but closer than a large one
+        let near = Integrator::AmbientOcclusion {
+            radius: 1.0e-3,
+            samples: 64,
+        };
+        let far = Integrator::AmbientOcclusion {
+            radius: 100.0,
+            samples: 64,
+        };
+        assert_eq!(near.li(&ray, &scene, &mut rng), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(far.li(&ray, &scene, &mut rng), Color::black());
+    }
+
+    #[test]
+    fn test_depth() {
+        let scene = Sphere::new(point(0.0, 0.0, -5.0), 1.0);
+        let mut rng = StdRng::seed_from_u64(0);
+        let depth = Integrator::Depth { max_depth: 8.0 };
+
+        // the direction isn't normalized, but depth is still a distance
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, -2.0));
+        assert_eq!(depth.li(&ray, &scene, &mut rng), Color::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_normal_views() {
+        let scene = Sphere::new(point(0.0, 0.0, -5.0), 1.0);
+        let mut rng = StdRng::seed_from_u64(0);
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, -1.0));
+
+        let expected = Color::new(0.5, 0.5, 1.0);
+        let geometric = Integrator::GeometricNormal;
+        let shading = Integrator::ShadingNormal;
+        assert_eq!(geometric.li(&ray, &scene, &mut rng), expected);
+        assert_eq!(shading.li(&ray, &scene, &mut rng), expected);
+    }
+
+    #[test]
+    fn test_primitive_id() {
+        let scene = vec![
+            Sphere::new(point(-3.0, 0.0, -5.0), 1.0),
+            Sphere::new(point(3.0, 0.0, -5.0), 1.0),
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        let ids = Integrator::PrimitiveId;
+
+        let left = Ray::new(point(-3.0, 0.0, 0.0), vector(0.0, 0.0, -1.0));
+        let right = Ray::new(point(3.0, 0.0, 0.0), vector(0.0, 0.0, -1.0));
+        let miss = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, -1.0));
+        assert_eq!(ids.li(&left, &scene, &mut rng), false_color(0));
+        assert_eq!(ids.li(&right, &scene, &mut rng), false_color(1));
+        assert_eq!(ids.li(&miss, &scene, &mut rng), Color::black());
+        assert_ne!(false_color(0), false_color(1));
+    }
+
+    #[test]
+    fn test_barycentric() {
+        let scene = make_floor();
+        let mut rng = StdRng::seed_from_u64(0);
+        let ray = Ray::new(point(100.0, 1.0, -100.0), vector(0.0, -1.0, 0.0));
+
+        // straight down onto the shared corner b of the first triangle
+        let color = Integrator::Barycentric.li(&ray, &scene, &mut rng);
+        assert!((color.g() - 1.0).abs() < 1.0e-6);
+
+        let sphere = Sphere::new(point(0.0, 0.0, -5.0), 1.0);
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, -1.0));
+        let color = Integrator::Barycentric.li(&ray, &sphere, &mut rng);
+        assert_eq!(color, Color::black());
+    }
+
+    #[test]
+    fn test_bvh_cost() {
+        // a row of spheres, too many for one leaf
+        let scene = Bvh::new(
+            (0..8)
+                .map(|i| Sphere::new(point(4.0 * i as f64, 0.0, -5.0), 1.0))
+                .collect(),
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        let cost = Integrator::BvhCost { max_nodes: 4 };
+
+        // a ray that misses the root costs one node, a quarter of the way
+        // from blue to red
+        let miss = Ray::new(point(0.0, 5.0, 0.0), vector(0.0, 0.0, -1.0));
+        assert_eq!(cost.li(&miss, &scene, &mut rng), Color::new(0.0, 1.0, 1.0));
+        // but a hit needs a leaf too
+        let hit = Ray::new(point(4.0, 0.0, 0.0), vector(0.0, 0.0, -1.0));
+        assert_ne!(cost.li(&hit, &scene, &mut rng), Color::new(0.0, 1.0, 1.0));
+
+        assert_eq!(heat_color(-1.0), Color::new(0.0, 0.0, 1.0));
+        assert_eq!(heat_color(0.5), Color::new(0.0, 1.0, 0.0));
+        assert_eq!(heat_color(2.0), Color::new(1.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,339 @@
+use std::error::Error;
+use std::fmt;
+
+/// Something wrong with a JSON document.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonError {
+    /// The document ended in the middle of a value.
+    UnexpectedEnd,
+    /// A character that can't be where it is, and its byte offset.
+    Unexpected(char, usize),
+    /// A number that doesn't parse.
+    BadNumber(String),
+    /// A `\u` escape that isn't four hex digits of a character.
+    BadEscape(String),
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonError::UnexpectedEnd => write!(f, "unexpected end of JSON"),
+            JsonError::Unexpected(c, offset) => {
+                write!(f, "unexpected '{}' at byte {}", c, offset)
+            }
+            JsonError::BadNumber(s) => write!(f, "bad number '{}'", s),
+            JsonError::BadEscape(s) => write!(f, "bad escape '\\u{}'", s),
+        }
+    }
+}
+
+impl Error for JsonError {}
+
+/// A parsed JSON value. Objects keep their members in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, JsonError> {
+        let mut parser = Parser { text, offset: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(value),
+            Some(c) => Err(JsonError::Unexpected(c, parser.offset)),
+        }
+    }
+
+    /// The member `key` of an object, if this is an object and has one.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => {
+                members.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The value as an index or count, if it's a whole number that isn't
+    /// negative.
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 => {
+                Some(*n as usize)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// The items of an array of numbers.
+    pub fn as_f64_array(&self) -> Option<Vec<f64>> {
+        self.as_array()?.iter().map(Json::as_f64).collect()
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.offset..].chars().next()
+    }
+
+    fn next(&mut self) -> Result<char, JsonError> {
+        let c = self.peek().ok_or(JsonError::UnexpectedEnd)?;
+        self.offset += c.len_utf8();
+        Ok(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = self.peek()
+        {
+            self.offset += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        let offset = self.offset;
+        match self.next()? {
+            c if c == expected => Ok(()),
+            c => Err(JsonError::Unexpected(c, offset)),
+        }
+    }
+
+    /// The rest of a keyword whose first letter has been checked.
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        match self.peek().ok_or(JsonError::UnexpectedEnd)? {
+            '{' => self.object(),
+            '[' => self.array(),
+            '"' => Ok(Json::String(self.string()?)),
+            't' => self.keyword("true", Json::Bool(true)),
+            'f' => self.keyword("false", Json::Bool(false)),
+            'n' => self.keyword("null", Json::Null),
+            '-' | '0'..='9' => self.number(),
+            c => Err(JsonError::Unexpected(c, self.offset)),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, JsonError> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.offset += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            let offset = self.offset;
+            match self.next()? {
+                ',' => (),
+                '}' => return Ok(Json::Object(members)),
+                c => return Err(JsonError::Unexpected(c, offset)),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, JsonError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.offset += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            let offset = self.offset;
+            match self.next()? {
+                ',' => (),
+                ']' => return Ok(Json::Array(items)),
+                c => return Err(JsonError::Unexpected(c, offset)),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.offset;
+        while let Some('-') | Some('+') | Some('.') | Some('e') | Some('E')
+        | Some('0'..='9') = self.peek()
+        {
+            self.offset += 1;
+        }
+        let s = &self.text[start..self.offset];
+        s.parse()
+            .map(Json::Number)
+            .map_err(|_| JsonError::BadNumber(s.to_string()))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let start = self.offset;
+        for _ in 0..4 {
+            self.next()?;
+        }
+        let s = &self.text[start..self.offset];
+        u32::from_str_radix(s, 16).map_err(|_| JsonError::BadEscape(s.into()))
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let offset = self.offset;
+            match self.next()? {
+                '"' => return Ok(s),
+                '\\' => {
+                    let offset = self.offset;
+                    let c = match self.next()? {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            // characters outside the basic plane are
+                            // written as a surrogate pair
+                            if (0xd800..0xdc00).contains(&code) {
+                                self.expect('\\')?;
+                                self.expect('u')?;
+                                let low = self.hex4()?;
+                                code = 0x10000
+                                    + ((code - 0xd800) << 10)
+                                    + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            std::char::from_u32(code).ok_or_else(|| {
+                                JsonError::BadEscape(format!("{:x}", code))
+                            })?
+                        }
+                        c => return Err(JsonError::Unexpected(c, offset)),
+                    };
+                    s.push(c);
+                }
+                c if (c as u32) < 0x20 => {
+                    return Err(JsonError::Unexpected(c, offset))
+                }
+                c => s.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_values() {
+        assert_eq!(Json::parse("null"), Ok(Json::Null));
+        assert_eq!(Json::parse(" true "), Ok(Json::Bool(true)));
+        assert_eq!(Json::parse("false"), Ok(Json::Bool(false)));
+        assert_eq!(Json::parse("-1.5e2"), Ok(Json::Number(-150.0)));
+        assert_eq!(Json::parse("0"), Ok(Json::Number(0.0)));
+        assert_eq!(
+            Json::parse(r#""a\"b\\c\né😀""#),
+            Ok(Json::String("a\"b\\c\né😀".to_string()))
+        );
+        assert_eq!(Json::parse("[]"), Ok(Json::Array(Vec::new())));
+        assert_eq!(Json::parse("{ }"), Ok(Json::Object(Vec::new())));
+    }
+
+    #[test]
+    fn test_parse_nested() {
+        let json = Json::parse(
+            r#"{
+                "asset": {"version": "2.0"},
+                "nodes": [{"mesh": 0, "translation": [1, 2.5, -3]}, {}],
+                "flag": false
+            }"#,
+        )
+        .unwrap();
+        let version = json.get("asset").and_then(|a| a.get("version"));
+        assert_eq!(version.and_then(Json::as_str), Some("2.0"));
+        let nodes = json.get("nodes").and_then(Json::as_array).unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].get("mesh").and_then(Json::as_usize), Some(0));
+        assert_eq!(
+            nodes[0].get("translation").and_then(Json::as_f64_array),
+            Some(vec![1.0, 2.5, -3.0])
+        );
+        assert_eq!(nodes[1].get("mesh"), None);
+        assert_eq!(json.get("flag").and_then(Json::as_bool), Some(false));
+        assert_eq!(Json::Number(1.5).as_usize(), None);
+        assert_eq!(Json::Number(-1.0).as_usize(), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Json::parse(""), Err(JsonError::UnexpectedEnd));
+        assert_eq!(Json::parse("[1, 2"), Err(JsonError::UnexpectedEnd));
+        assert_eq!(Json::parse("[1 2]"), Err(JsonError::Unexpected('2', 3)));
+        assert_eq!(
+            Json::parse(r#"{"a" 1}"#),
+            Err(JsonError::Unexpected('1', 5))
+        );
+        assert_eq!(Json::parse("tru"), Err(JsonError::UnexpectedEnd));
+        assert_eq!(Json::parse("1 1"), Err(JsonError::Unexpected('1', 2)));
+        assert_eq!(
+            Json::parse("1.2.3"),
+            Err(JsonError::BadNumber("1.2.3".to_string()))
+        );
+        assert_eq!(
+            Json::parse(r#""\uzzzz""#),
+            Err(JsonError::BadEscape("zzzz".to_string()))
+        );
+    }
+}
@@ -0,0 +1,1264 @@
+use super::color::Color;
+use super::hdr::HdrImage;
+use super::ies::IesProfile;
+use super::math::{Normal3, Point3, Ray, Vector3};
+use super::primitive::{Aabb, DirectionCone, Intersection, Scene, Shape};
+use super::sampling::{self, Distribution2D};
+use std::f64::consts::PI;
+use std::sync::{Arc, OnceLock};
+use ultraviolet::mat::DMat4;
+
+/// Fraction of the distance to a light that a shadow ray leaves unchecked at
+/// its far end, so that a light's own surface doesn't count as an occluder.
+pub const SHADOW_EPSILON: f64 = 1.0e-6;
+
+/// Illumination arriving at a point from a sampled direction on a light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightSample {
+    /// Unit vector pointing from the illuminated point towards the light.
+    pub wi: Vector3,
+    /// Radiance arriving along `wi`, not accounting for anything in between.
+    pub radiance: Color,
+    /// Probability density of having sampled `wi`, with respect to solid
+    /// angle. Lights that can only be sampled one way report 1.
+    pub pdf: f64,
+    /// Distance to the light along `wi`, so that shadow rays know where to
+    /// stop looking for occluders. Infinite for lights at infinity.
+    pub distance: f64,
+    /// The light's surface normal at the sampled point, for lights with a
+    /// surface.
+    pub normal: Option<Normal3>,
+}
+
+impl LightSample {
+    /// Whether nothing in `scene` blocks the light on its way to `point`.
+    pub fn unoccluded<S: Scene + ?Sized>(
+        &self,
+        scene: &S,
+        point: Point3,
+    ) -> bool {
+        match scene.intersect(&Ray::new(point, self.wi)) {
+            Some(occluder) => {
+                occluder.t >= self.distance * (1.0 - SHADOW_EPSILON)
+            }
+            None => true,
+        }
+    }
+}
+
+/// Light leaving a light, sampled to start a path from it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmissionSample {
+    /// The sampled point on the light.
+    pub point: Point3,
+    /// The sampled unit direction in which the light leaves it.
+    pub w: Vector3,
+    /// The light's surface normal at the sampled point, for lights with a
+    /// surface.
+    pub normal: Option<Normal3>,
+    /// Radiance leaving along the ray, or intensity for point lights.
+    pub radiance: Color,
+    /// Probability density of the point, with respect to area on the light.
+    /// Lights at a single point report 1.
+    pub pdf_pos: f64,
+    /// Probability density of the direction, with respect to solid angle.
+    pub pdf_dir: f64,
+}
+
+pub trait Light {
+    /// Sample a direction from `point` towards the light using the point `u`
+    /// in the unit square. Returns `None` if no light arrives at `point`.
+    fn sample_li(&self, point: Point3, u: (f64, f64)) -> Option<LightSample>;
+
+    /// The density with which `sample_li` picks the unit direction `wi` from
+    /// `point`. Lights described by a delta distribution can never be hit by
+    /// chance, so they return 0.
+    fn pdf(&self, point: Point3, wi: Vector3) -> f64;
+
+    /// Total power emitted by the light.
+    fn power(&self) -> Color;
+
+    /// Radiance arriving along a ray that escapes the scene without hitting
+    /// anything. Only lights surrounding the scene contribute.
+    fn le(&self, _ray: &Ray) -> Color {
+        Color::black()
+    }
+
+    /// Where the light is and which way it shines. `None` for lights that
+    /// surround the scene.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
+    /// Whether the light is a delta distribution in position or direction,
+    /// meaning that `sample_li` always returns the same direction.
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    /// Sample a ray of light leaving the light, picking the point with
+    /// `u_pos` and the direction with `u_dir`. Returns `None` for lights
+    /// that can't start paths, such as those surrounding the scene.
+    fn sample_le(
+        &self,
+        _u_pos: (f64, f64),
+        _u_dir: (f64, f64),
+    ) -> Option<EmissionSample> {
+        None
+    }
+
+    /// The densities with which `sample_le` picks a point with the normal
+    /// `normal` and the unit direction `w` leaving it, as `(pdf_pos,
+    /// pdf_dir)`. Both are 0 for lights that can't start paths.
+    fn pdf_le(&self, _normal: Option<Normal3>, _w: Vector3) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+
+    /// Record that the light is at `index` in the scene's list of lights.
+    /// Lights that are also geometry report it on their hits, so that
+    /// integrators can tell which light they found; others ignore it.
+    fn set_index(&self, _index: usize) {}
+}
+
+/// Tell every light in `lights` where it is in the list. Call this once the
+/// scene's lights are all collected, before rendering.
+pub fn number_lights(lights: &[Box<dyn Light>]) {
+    for (index, light) in lights.iter().enumerate() {
+        light.set_index(index);
+    }
+}
+
+/// A conservative summary of where a light is and which way it shines, used
+/// to estimate how much it could contribute to a point without sampling it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    /// Luminance scaled so that `phi` over the squared distance estimates
+    /// the irradiance the light delivers.
+    pub phi: f64,
+    /// Contains the surface normals of the light, or its axis of emission.
+    pub normals: DirectionCone,
+    /// Cosine of how far beyond `normals` the light still emits.
+    pub cos_theta_e: f64,
+    /// Whether the light also emits on the side opposite its normals.
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    pub fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.phi == 0.0 {
+            return *other;
+        }
+        if other.phi == 0.0 {
+            return *self;
+        }
+        LightBounds {
+            bounds: self.bounds.union(&other.bounds),
+            phi: self.phi + other.phi,
+            normals: self.normals.union(&other.normals),
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    /// An estimate of the light arriving from within the bounds at `point`
+    /// on a surface with the unit normal `normal`. Every angle is widened by
+    /// the angle the bounds subtend, so that the estimate is only zero when
+    /// no light can possibly arrive (pbrt-v4's light BVH importance).
+    pub fn importance(&self, point: Point3, normal: Normal3) -> f64 {
+        let (center, radius) = self.bounds.bounding_sphere();
+        let to_light = center - point;
+        let dist_sq = to_light.mag_sq();
+        let to_light = to_light / dist_sq.sqrt();
+
+        // stay finite for points within or right next to the bounds
+        let dist_sq = dist_sq.max(self.bounds.diagonal().mag() / 2.0);
+
+        let (sin_b, cos_b) = if dist_sq < radius * radius {
+            (0.0, -1.0)
+        } else {
+            let sin_sq = radius * radius / dist_sq;
+            (sin_sq.sqrt(), (1.0 - sin_sq).max(0.0).sqrt())
+        };
+
+        // angle from the emission cone to the point
+        let mut cos_w = self.normals.w.dot(-to_light);
+        if self.two_sided {
+            cos_w = cos_w.abs();
+        }
+        let sin_w = (1.0 - cos_w * cos_w).max(0.0).sqrt();
+        let cos_o = self.normals.cos_theta;
+        let sin_o = (1.0 - cos_o * cos_o).max(0.0).sqrt();
+        let (sin_x, cos_x) = angle_difference((sin_w, cos_w), (sin_o, cos_o));
+        let (_, cos_emitted) = angle_difference((sin_x, cos_x), (sin_b, cos_b));
+        if cos_emitted <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        // angle from the surface normal to the light
+        let cos_i = normal.dot(to_light);
+        let sin_i = (1.0 - cos_i * cos_i).max(0.0).sqrt();
+        let (_, cos_received) =
+            angle_difference((sin_i, cos_i), (sin_b, cos_b));
+
+        f64::max(
+            0.0,
+            self.phi * cos_emitted * cos_received.max(0.0) / dist_sq,
+        )
+    }
+}
+
+/// The sine and cosine of the angle a - b, clamped to zero if b is larger,
+/// from the sines and cosines of a and b.
+fn angle_difference(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    let ((sin_a, cos_a), (sin_b, cos_b)) = (a, b);
+    if cos_a > cos_b {
+        (0.0, 1.0)
+    } else {
+        (sin_a * cos_b - cos_a * sin_b, cos_a * cos_b + sin_a * sin_b)
+    }
+}
+
+impl<L: Light + ?Sized> Light for Box<L> {
+    fn sample_li(&self, point: Point3, u: (f64, f64)) -> Option<LightSample> {
+        (**self).sample_li(point, u)
+    }
+
+    fn pdf(&self, point: Point3, wi: Vector3) -> f64 {
+        (**self).pdf(point, wi)
+    }
+
+    fn power(&self) -> Color {
+        (**self).power()
+    }
+
+    fn le(&self, ray: &Ray) -> Color {
+        (**self).le(ray)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        (**self).bounds()
+    }
+
+    fn is_delta(&self) -> bool {
+        (**self).is_delta()
+    }
+
+    fn sample_le(
+        &self,
+        u_pos: (f64, f64),
+        u_dir: (f64, f64),
+    ) -> Option<EmissionSample> {
+        (**self).sample_le(u_pos, u_dir)
+    }
+
+    fn pdf_le(&self, normal: Option<Normal3>, w: Vector3) -> (f64, f64) {
+        (**self).pdf_le(normal, w)
+    }
+
+    fn set_index(&self, index: usize) {
+        (**self).set_index(index)
+    }
+}
+
+impl<L: Light + ?Sized> Light for Arc<L> {
+    fn sample_li(&self, point: Point3, u: (f64, f64)) -> Option<LightSample> {
+        (**self).sample_li(point, u)
+    }
+
+    fn pdf(&self, point: Point3, wi: Vector3) -> f64 {
+        (**self).pdf(point, wi)
+    }
+
+    fn power(&self) -> Color {
+        (**self).power()
+    }
+
+    fn le(&self, ray: &Ray) -> Color {
+        (**self).le(ray)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        (**self).bounds()
+    }
+
+    fn is_delta(&self) -> bool {
+        (**self).is_delta()
+    }
+
+    fn sample_le(
+        &self,
+        u_pos: (f64, f64),
+        u_dir: (f64, f64),
+    ) -> Option<EmissionSample> {
+        (**self).sample_le(u_pos, u_dir)
+    }
+
+    fn pdf_le(&self, normal: Option<Normal3>, w: Vector3) -> (f64, f64) {
+        (**self).pdf_le(normal, w)
+    }
+
+    fn set_index(&self, index: usize) {
+        (**self).set_index(index)
+    }
+}
+
+/// A light that shines equally in all directions from a single point.
+pub struct PointLight {
+    position: Point3,
+    /// Radiant intensity, in watts per steradian.
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, point: Point3, _u: (f64, f64)) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let dist_sq = to_light.mag_sq();
+        let distance = dist_sq.sqrt();
+        Some(LightSample {
+            wi: to_light / distance,
+            radiance: self.intensity * (1.0 / dist_sq),
+            pdf: 1.0,
+            distance,
+            normal: None,
+        })
+    }
+
+    fn pdf(&self, _point: Point3, _wi: Vector3) -> f64 {
+        0.0
+    }
+
+    fn power(&self) -> Color {
+        self.intensity * (4.0 * PI)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: Aabb::new(self.position, self.position),
+            phi: self.intensity.luminance(),
+            normals: DirectionCone::entire_sphere(),
+            cos_theta_e: -1.0,
+            two_sided: false,
+        })
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_le(
+        &self,
+        _u_pos: (f64, f64),
+        u_dir: (f64, f64),
+    ) -> Option<EmissionSample> {
+        let w = sampling::uniform_cone(u_dir.0, u_dir.1, -1.0);
+        Some(EmissionSample {
+            point: self.position,
+            w,
+            normal: None,
+            radiance: self.intensity,
+            pdf_pos: 1.0,
+            pdf_dir: sampling::uniform_cone_pdf(-1.0),
+        })
+    }
+
+    fn pdf_le(&self, _normal: Option<Normal3>, _w: Vector3) -> (f64, f64) {
+        (1.0, sampling::uniform_cone_pdf(-1.0))
+    }
+}
+
+/// A light so far away that its rays all arrive parallel to each other, like
+/// sunlight.
+pub struct DirectionalLight {
+    /// Unit vector pointing in the direction the light travels.
+    direction: Vector3,
+    radiance: Color,
+    scene_radius: f64,
+}
+
+impl DirectionalLight {
+    /// `scene_radius` is the radius of a sphere bounding the scene, which
+    /// determines how much power the light delivers to it.
+    pub fn new(
+        direction: Vector3,
+        radiance: Color,
+        scene_radius: f64,
+    ) -> DirectionalLight {
+        DirectionalLight {
+            direction: direction.normalized(),
+            radiance,
+            scene_radius,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _point: Point3, _u: (f64, f64)) -> Option<LightSample> {
+        Some(LightSample {
+            wi: -self.direction,
+            radiance: self.radiance,
+            pdf: 1.0,
+            distance: f64::INFINITY,
+            normal: None,
+        })
+    }
+
+    fn pdf(&self, _point: Point3, _wi: Vector3) -> f64 {
+        0.0
+    }
+
+    fn power(&self) -> Color {
+        // everything crossing a disk the size of the scene
+        self.radiance * (PI * self.scene_radius * self.scene_radius)
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+/// A point light that only shines within a cone. The intensity is constant
+/// within `falloff_start` of the axis and then drops smoothly to zero at
+/// `total_width`.
+pub struct SpotLight {
+    position: Point3,
+    /// Unit vector along the axis of the cone.
+    direction: Vector3,
+    /// Radiant intensity along the axis, in watts per steradian.
+    intensity: Color,
+    cos_total_width: f64,
+    cos_falloff_start: f64,
+}
+
+impl SpotLight {
+    /// Angles are measured from the axis of the cone, in radians.
+    pub fn new(
+        position: Point3,
+        direction: Vector3,
+        intensity: Color,
+        total_width: f64,
+        falloff_start: f64,
+    ) -> SpotLight {
+        SpotLight {
+            position,
+            direction: direction.normalized(),
+            intensity,
+            cos_total_width: total_width.cos(),
+            cos_falloff_start: falloff_start.min(total_width).cos(),
+        }
+    }
+
+    /// Fraction of the axial intensity emitted in the unit direction `w`
+    /// leaving the light.
+    fn falloff(&self, w: Vector3) -> f64 {
+        let cos_theta = w.dot(self.direction);
+        if cos_theta < self.cos_total_width {
+            0.0
+        } else if cos_theta >= self.cos_falloff_start {
+            1.0
+        } else {
+            let delta = (cos_theta - self.cos_total_width)
+                / (self.cos_falloff_start - self.cos_total_width);
+            delta.powi(4)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, point: Point3, _u: (f64, f64)) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let dist_sq = to_light.mag_sq();
+        let distance = dist_sq.sqrt();
+        let wi = to_light / distance;
+
+        let falloff = self.falloff(-wi);
+        if falloff == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            radiance: self.intensity * (falloff / dist_sq),
+            pdf: 1.0,
+            distance,
+            normal: None,
+        })
+    }
+
+    fn pdf(&self, _point: Point3, _wi: Vector3) -> f64 {
+        0.0
+    }
+
+    fn power(&self) -> Color {
+        // the solid angle of the cone, counting the falloff region as half
+        let cos_average = 0.5 * (self.cos_falloff_start + self.cos_total_width);
+        self.intensity * (2.0 * PI * (1.0 - cos_average))
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // the intensity is only constant within the falloff angle, and then
+        // fades out over the rest of the cone
+        let theta_e =
+            self.cos_total_width.acos() - self.cos_falloff_start.acos();
+        Some(LightBounds {
+            bounds: Aabb::new(self.position, self.position),
+            phi: self.intensity.luminance(),
+            normals: DirectionCone::new(self.direction, self.cos_falloff_start),
+            cos_theta_e: theta_e.cos(),
+            two_sided: false,
+        })
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_le(
+        &self,
+        _u_pos: (f64, f64),
+        u_dir: (f64, f64),
+    ) -> Option<EmissionSample> {
+        let local =
+            sampling::uniform_cone(u_dir.0, u_dir.1, self.cos_total_width);
+        let w = sampling::to_world(local, self.direction);
+        Some(EmissionSample {
+            point: self.position,
+            w,
+            normal: None,
+            radiance: self.intensity * self.falloff(w),
+            pdf_pos: 1.0,
+            pdf_dir: sampling::uniform_cone_pdf(self.cos_total_width),
+        })
+    }
+
+    fn pdf_le(&self, _normal: Option<Normal3>, w: Vector3) -> (f64, f64) {
+        if w.dot(self.direction) < self.cos_total_width {
+            (1.0, 0.0)
+        } else {
+            (1.0, sampling::uniform_cone_pdf(self.cos_total_width))
+        }
+    }
+}
+
+/// A point light whose intensity varies with direction according to a
+/// measured IES profile, like a real luminaire.
+///
+/// In the light's own space the profile's vertical angle 0 points down -y
+/// and horizontal angles go around the y axis from +x towards +z.
+pub struct GoniometricLight {
+    position: Point3,
+    world_to_light: DMat4,
+    profile: IesProfile,
+    /// Converts candela to the scene's units, and tints the light.
+    scale: Color,
+}
+
+impl GoniometricLight {
+    /// `light_to_world` orients the luminaire and should be a rotation.
+    pub fn new(
+        position: Point3,
+        light_to_world: DMat4,
+        profile: IesProfile,
+        scale: Color,
+    ) -> GoniometricLight {
+        GoniometricLight {
+            position,
+            world_to_light: light_to_world.inversed(),
+            profile,
+            scale,
+        }
+    }
+
+    /// Intensity emitted in the world space unit direction `w`.
+    fn intensity(&self, w: Vector3) -> Color {
+        let w = (self.world_to_light * w).normalized();
+        let theta = (-w.y).clamp(-1.0, 1.0).acos().to_degrees();
+        let phi = w.z.atan2(w.x).to_degrees();
+        self.scale * self.profile.candela(theta, phi)
+    }
+}
+
+impl Light for GoniometricLight {
+    fn sample_li(&self, point: Point3, _u: (f64, f64)) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let dist_sq = to_light.mag_sq();
+        let distance = dist_sq.sqrt();
+        let wi = to_light / distance;
+
+        let intensity = self.intensity(-wi);
+        if intensity == Color::black() {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            radiance: intensity * (1.0 / dist_sq),
+            pdf: 1.0,
+            distance,
+            normal: None,
+        })
+    }
+
+    fn pdf(&self, _point: Point3, _wi: Vector3) -> f64 {
+        0.0
+    }
+
+    fn power(&self) -> Color {
+        // integrate the intensity over the sphere with the midpoint rule
+        let (n_theta, n_phi) = (90, 180);
+        let mut power = Color::black();
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) / n_theta as f64 * 180.0;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) / n_phi as f64 * 360.0;
+                let sin_theta = theta.to_radians().sin();
+                power +=
+                    self.scale * (self.profile.candela(theta, phi) * sin_theta);
+            }
+        }
+        let d_theta = PI / n_theta as f64;
+        let d_phi = 2.0 * PI / n_phi as f64;
+        power * (d_theta * d_phi)
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: Aabb::new(self.position, self.position),
+            phi: self.scale.luminance() * self.profile.max_candela(),
+            normals: DirectionCone::entire_sphere(),
+            cos_theta_e: -1.0,
+            two_sided: false,
+        })
+    }
+}
+
+/// A shape that emits the same radiance from every point of its surface. A
+/// one-sided light only emits on the side its normals face.
+///
+/// Area lights are also part of the scene's geometry: hits on them report
+/// the emitted radiance, so that they show up when seen directly.
+pub struct AreaLight<S> {
+    shape: S,
+    radiance: Color,
+    two_sided: bool,
+    /// Where the light is in the scene's list of lights, once it's been put
+    /// in one. A light keeps the first index it's given.
+    index: OnceLock<usize>,
+}
+
+impl<S: Shape> AreaLight<S> {
+    pub fn new(shape: S, radiance: Color, two_sided: bool) -> AreaLight<S> {
+        AreaLight {
+            shape,
+            radiance,
+            two_sided,
+            index: OnceLock::new(),
+        }
+    }
+
+    /// Radiance leaving a point with the normal `normal` in the direction
+    /// `w`.
+    fn emitted(&self, normal: Normal3, w: Vector3) -> Color {
+        if self.two_sided || normal.dot(w) > 0.0 {
+            self.radiance
+        } else {
+            Color::black()
+        }
+    }
+}
+
+/// Turn every shape in `shapes`, such as the triangles of a mesh, into its
+/// own area light.
+pub fn area_lights<S: Shape>(
+    shapes: Vec<S>,
+    radiance: Color,
+    two_sided: bool,
+) -> Vec<Arc<AreaLight<S>>> {
+    shapes
+        .into_iter()
+        .map(|shape| Arc::new(AreaLight::new(shape, radiance, two_sided)))
+        .collect()
+}
+
+impl<S: Shape> Scene for AreaLight<S> {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let mut hit = self.shape.intersect(ray)?;
+        hit.emitted = self.emitted(hit.normal, -ray.direction);
+        hit.light = self.index.get().copied();
+        Some(hit)
+    }
+}
+
+impl<S: Shape> Light for AreaLight<S> {
+    fn sample_li(&self, point: Point3, u: (f64, f64)) -> Option<LightSample> {
+        let sample = self.shape.sample_from(point, u)?;
+        let to_light = sample.point - point;
+        let distance = to_light.mag();
+        let wi = to_light / distance;
+
+        let radiance = self.emitted(sample.normal, -wi);
+        if radiance == Color::black() || sample.pdf == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            radiance,
+            pdf: sample.pdf,
+            distance,
+            normal: Some(sample.normal),
+        })
+    }
+
+    fn pdf(&self, point: Point3, wi: Vector3) -> f64 {
+        self.shape.pdf_from(point, wi)
+    }
+
+    fn power(&self) -> Color {
+        let sides = if self.two_sided { 2.0 } else { 1.0 };
+        self.radiance * (sides * PI * self.shape.area())
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let sides = if self.two_sided { 2.0 } else { 1.0 };
+        Some(LightBounds {
+            bounds: self.shape.bounds(),
+            phi: self.radiance.luminance() * sides * self.shape.area(),
+            normals: self.shape.normal_bounds(),
+            // emits over the whole hemisphere around each normal
+            cos_theta_e: 0.0,
+            two_sided: self.two_sided,
+        })
+    }
+
+    fn sample_le(
+        &self,
+        u_pos: (f64, f64),
+        u_dir: (f64, f64),
+    ) -> Option<EmissionSample> {
+        let sample = self.shape.sample(u_pos);
+
+        // two sided lights pick a side with the first half of the range
+        let (u, normal, side_pdf) = if !self.two_sided {
+            (u_dir.0, sample.normal, 1.0)
+        } else if u_dir.0 < 0.5 {
+            (2.0 * u_dir.0, sample.normal, 0.5)
+        } else {
+            (2.0 * u_dir.0 - 1.0, -sample.normal, 0.5)
+        };
+        let local = sampling::cosine_hemisphere(u, u_dir.1);
+        let w = sampling::to_world(local, normal.into());
+
+        Some(EmissionSample {
+            point: sample.point,
+            w,
+            normal: Some(sample.normal),
+            radiance: self.emitted(sample.normal, w),
+            pdf_pos: sample.pdf,
+            pdf_dir: side_pdf * sampling::cosine_hemisphere_pdf(local.z),
+        })
+    }
+
+    fn pdf_le(&self, normal: Option<Normal3>, w: Vector3) -> (f64, f64) {
+        let cos = match normal {
+            Some(normal) => normal.dot(w),
+            None => return (0.0, 0.0),
+        };
+        let pdf_pos = 1.0 / self.shape.area();
+        if self.two_sided {
+            (pdf_pos, 0.5 * sampling::cosine_hemisphere_pdf(cos.abs()))
+        } else {
+            (pdf_pos, sampling::cosine_hemisphere_pdf(cos.max(0.0)))
+        }
+    }
+
+    fn set_index(&self, index: usize) {
+        let _ = self.index.set(index);
+    }
+}
+
+/// Light arriving from infinitely far away in every direction, given by a
+/// latitude-longitude map laid out as in `sampling::latlong_to_direction`.
+pub struct EnvironmentLight {
+    map: HdrImage,
+    light_to_world: DMat4,
+    world_to_light: DMat4,
+    distribution: Distribution2D,
+    scene_radius: f64,
+}
+
+impl EnvironmentLight {
+    /// `light_to_world` orients the map in the scene and should be a
+    /// rotation. `scene_radius` is the radius of a sphere bounding the scene,
+    /// which determines how much power the light delivers to it.
+    pub fn new(
+        map: HdrImage,
+        light_to_world: DMat4,
+        scene_radius: f64,
+    ) -> EnvironmentLight {
+        // weight each pixel by the solid angle it covers, which shrinks
+        // towards the poles
+        let (width, height) = (map.width(), map.height());
+        let mut func = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let theta = PI * (y as f64 + 0.5) / height as f64;
+            for x in 0..width {
+                func.push(map.get(x, y).luminance() * theta.sin());
+            }
+        }
+
+        EnvironmentLight {
+            distribution: Distribution2D::new(&func, width, height),
+            map,
+            light_to_world,
+            world_to_light: light_to_world.inversed(),
+            scene_radius,
+        }
+    }
+
+    /// The map coordinates seen in the world space direction `w`.
+    fn direction_to_uv(&self, w: Vector3) -> (f64, f64) {
+        sampling::direction_to_latlong((self.world_to_light * w).normalized())
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn sample_li(&self, _point: Point3, u: (f64, f64)) -> Option<LightSample> {
+        let ((u, v), map_pdf) = self.distribution.sample_continuous(u);
+        let sin_theta = (PI * v).sin();
+        if map_pdf == 0.0 || sin_theta == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            wi: self.light_to_world * sampling::latlong_to_direction(u, v),
+            radiance: self.map.lookup(u, v),
+            // from the unit square to the sphere of directions
+            pdf: map_pdf / (2.0 * PI * PI * sin_theta),
+            distance: f64::INFINITY,
+            normal: None,
+        })
+    }
+
+    fn pdf(&self, _point: Point3, wi: Vector3) -> f64 {
+        let uv = self.direction_to_uv(wi);
+        let sin_theta = (PI * uv.1).sin();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(uv) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn power(&self) -> Color {
+        // everything crossing a disk the size of the scene, with the average
+        // radiance of the map coming from every direction
+        let (width, height) = (self.map.width(), self.map.height());
+        let mut sum = Color::black();
+        for y in 0..height {
+            for x in 0..width {
+                sum += self.map.get(x, y);
+            }
+        }
+        let average = sum * (1.0 / (width * height) as f64);
+        average * (PI * self.scene_radius * self.scene_radius)
+    }
+
+    fn le(&self, ray: &Ray) -> Color {
+        let (u, v) = self.direction_to_uv(ray.direction);
+        self.map.lookup(u, v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::math::{point, vector};
+    use super::super::math::test_util::assert_eps_eq;
+    use super::super::primitive::{Parallelogram, Sphere, Triangle};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const EPS: f64 = 1.0e-9;
+
+    fn make_ceiling_light(two_sided: bool) -> AreaLight<Parallelogram> {
+        // a 2 by 2 square at y = 1 facing down
+        let quad = Parallelogram::new(
+            point(-1.0, 1.0, 1.0),
+            vector(0.0, 0.0, -2.0),
+            vector(2.0, 0.0, 0.0),
+        );
+        AreaLight::new(quad, Color::new(2.0, 2.0, 2.0), two_sided)
+    }
+
+    #[test]
+    fn test_point_light_inverse_square() {
+        let light =
+            PointLight::new(point(0.0, 4.0, 0.0), Color::new(16.0, 8.0, 4.0));
+        let sample = light.sample_li(point(0.0, 0.0, 0.0), (0.5, 0.5)).unwrap();
+
+        assert_eps_eq(&sample.wi, &vector(0.0, 1.0, 0.0), EPS);
+        assert_eps_eq(&sample.distance, &4.0, EPS);
+        assert_eq!(sample.radiance, Color::new(1.0, 0.5, 0.25));
+        assert_eq!(light.pdf(point(0.0, 0.0, 0.0), sample.wi), 0.0);
+        assert!(light.is_delta());
+    }
+
+    #[test]
+    fn test_point_light_power() {
+        let light =
+            PointLight::new(point(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0));
+        assert_eps_eq(&light.power().r(), &(4.0 * PI), EPS);
+    }
+
+    #[test]
+    fn test_directional_light() {
+        let light = DirectionalLight::new(
+            vector(0.0, -2.0, 0.0),
+            Color::new(1.0, 0.9, 0.8),
+            2.0,
+        );
+
+        // the same light arrives everywhere
+        for &p in [point(0.0, 0.0, 0.0), point(100.0, -3.0, 7.0)].iter() {
+            let sample = light.sample_li(p, (0.5, 0.5)).unwrap();
+            assert_eps_eq(&sample.wi, &vector(0.0, 1.0, 0.0), EPS);
+            assert_eq!(sample.radiance, Color::new(1.0, 0.9, 0.8));
+            assert_eq!(sample.distance, f64::INFINITY);
+        }
+        assert_eps_eq(&light.power().r(), &(4.0 * PI), EPS);
+    }
+
+    #[test]
+    fn test_spot_light_cone() {
+        let light = SpotLight::new(
+            point(0.0, 1.0, 0.0),
+            vector(0.0, -1.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            f64::to_radians(45.0),
+            f64::to_radians(30.0),
+        );
+
+        // straight down the axis
+        let center = light.sample_li(point(0.0, 0.0, 0.0), (0.5, 0.5)).unwrap();
+        assert_eq!(center.radiance, Color::new(1.0, 1.0, 1.0));
+
+        // outside of the cone
+        assert_eq!(light.sample_li(point(2.0, 0.0, 0.0), (0.5, 0.5)), None);
+
+        // in the falloff region, between 30 and 45 degrees off the axis
+        let x = f64::to_radians(40.0).tan();
+        let edge = light.sample_li(point(x, 0.0, 0.0), (0.5, 0.5)).unwrap();
+        let dist_sq = 1.0 + x * x;
+        let falloff = edge.radiance.r() * dist_sq;
+        assert!(falloff > 0.0 && falloff < 1.0);
+    }
+
+    #[test]
+    fn test_spot_light_power() {
+        // with no falloff region the power is the intensity times the solid
+        // angle of the cone
+        let angle = f64::to_radians(60.0);
+        let light = SpotLight::new(
+            point(0.0, 0.0, 0.0),
+            vector(0.0, 0.0, -1.0),
+            Color::new(1.0, 1.0, 1.0),
+            angle,
+            angle,
+        );
+        assert_eps_eq(&light.power().r(), &(2.0 * PI * 0.5), EPS);
+    }
+
+    #[test]
+    fn test_area_light_seen_directly() {
+        let one_sided = make_ceiling_light(false);
+        let two_sided = make_ceiling_light(true);
+
+        let from_below = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 1.0, 0.0));
+        let from_above = Ray::new(point(0.0, 2.0, 0.0), vector(0.0, -1.0, 0.0));
+        let emitted =
+            |light: &AreaLight<_>, ray| light.intersect(ray).unwrap().emitted;
+
+        assert_eq!(emitted(&one_sided, &from_below), Color::new(2.0, 2.0, 2.0));
+        assert_eq!(emitted(&one_sided, &from_above), Color::black());
+        assert_eq!(emitted(&two_sided, &from_above), Color::new(2.0, 2.0, 2.0));
+        assert_eq!(one_sided.intersect(&from_below).unwrap().light, None);
+    }
+
+    #[test]
+    fn test_area_light_one_sided_sampling() {
+        let light = make_ceiling_light(false);
+        assert!(light.sample_li(point(0.0, 0.0, 0.0), (0.5, 0.5)).is_some());
+        assert_eq!(light.sample_li(point(0.0, 2.0, 0.0), (0.5, 0.5)), None);
+        assert_eps_eq(&light.power().r(), &(2.0 * PI * 4.0), EPS);
+
+        let light = make_ceiling_light(true);
+        assert!(light.sample_li(point(0.0, 2.0, 0.0), (0.5, 0.5)).is_some());
+        assert_eps_eq(&light.power().r(), &(2.0 * 2.0 * PI * 4.0), EPS);
+    }
+
+    #[test]
+    fn test_area_light_pdf_matches_sample() {
+        let light = make_ceiling_light(false);
+        let p = point(0.3, -0.5, 0.2);
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..16 {
+            let sample = light.sample_li(p, (rng.gen(), rng.gen())).unwrap();
+            assert_eps_eq(&light.pdf(p, sample.wi), &sample.pdf, 1.0e-6);
+        }
+    }
+
+    #[test]
+    fn test_sample_le() {
+        // every sampled ray carries the light's power on average
+        let spot = SpotLight::new(
+            point(0.0, 0.0, 0.0),
+            vector(1.0, 1.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            0.5,
+            0.5,
+        );
+        let lights: Vec<Box<dyn Light>> = vec![
+            Box::new(make_ceiling_light(false)),
+            Box::new(make_ceiling_light(true)),
+            Box::new(PointLight::new(
+                point(1.0, 2.0, 3.0),
+                Color::new(2.0, 2.0, 2.0),
+            )),
+            Box::new(spot),
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        for light in lights.iter() {
+            let mut power = 0.0;
+            let count = 1000;
+            for _ in 0..count {
+                let u_pos = (rng.gen(), rng.gen());
+                let sample = light.sample_le(u_pos, (rng.gen(), rng.gen()));
+                let sample = sample.unwrap();
+                let (pdf_pos, pdf_dir) = light.pdf_le(sample.normal, sample.w);
+                assert_eps_eq(&pdf_pos, &sample.pdf_pos, EPS);
+                assert_eps_eq(&pdf_dir, &sample.pdf_dir, EPS);
+
+                let cos = sample.normal.map_or(1.0, |n| n.dot(sample.w).abs());
+                power += sample.radiance.r() * cos
+                    / (sample.pdf_pos * sample.pdf_dir);
+            }
+            assert_eps_eq(&(power / count as f64), &light.power().r(), 1.0e-6);
+        }
+
+        // lights around the scene don't start paths
+        let sun = DirectionalLight::new(
+            vector(0.0, -1.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            1.0,
+        );
+        assert_eq!(sun.sample_le((0.5, 0.5), (0.5, 0.5)), None);
+    }
+
+    #[test]
+    fn test_sphere_light_irradiance() {
+        // a point facing a sphere that emits L receives pi * L * sin^2 of the
+        // sphere's angular radius
+        let light = AreaLight::new(
+            Sphere::new(point(0.0, 4.0, 0.0), 2.0),
+            Color::new(1.0, 1.0, 1.0),
+            false,
+        );
+        let p = point(0.0, 0.0, 0.0);
+        let normal = vector(0.0, 1.0, 0.0);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let n = 10_000;
+        let mut irradiance = 0.0;
+        for _ in 0..n {
+            let sample = light.sample_li(p, (rng.gen(), rng.gen())).unwrap();
+            irradiance +=
+                sample.radiance.r() * normal.dot(sample.wi) / sample.pdf;
+        }
+        irradiance /= n as f64;
+
+        let expected = PI * 0.25;
+        assert!((irradiance - expected).abs() < 0.01 * expected);
+    }
+
+    #[test]
+    fn test_number_lights() {
+        // hits on an area light tell which light in the list they're on
+        let light = Arc::new(make_ceiling_light(false));
+        let lights: Vec<Box<dyn Light>> = vec![
+            Box::new(PointLight::new(
+                point(0.0, 0.0, 0.0),
+                Color::new(1.0, 1.0, 1.0),
+            )),
+            Box::new(Arc::clone(&light)),
+        ];
+        number_lights(&lights);
+
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 1.0, 0.0));
+        assert_eq!(light.intersect(&ray).unwrap().light, Some(1));
+    }
+
+    #[test]
+    fn test_mesh_lights() {
+        // a glowing square made of two triangles, lit from both sides
+        let (a, b) = (point(-1.0, 1.0, -1.0), point(1.0, 1.0, -1.0));
+        let (c, d) = (point(1.0, 1.0, 1.0), point(-1.0, 1.0, 1.0));
+        let triangles = vec![Triangle::new(a, b, c), Triangle::new(a, c, d)];
+        let mesh = area_lights(triangles, Color::new(1.0, 1.0, 1.0), true);
+        let lights: Vec<Box<dyn Light>> =
+            mesh.iter().map(|l| Box::new(Arc::clone(l)) as _).collect();
+        number_lights(&lights);
+
+        let total: f64 = lights.iter().map(|l| l.power().r()).sum();
+        assert_eps_eq(&total, &(2.0 * PI * 4.0), EPS);
+
+        // each triangle is its own light, seen from either side
+        let up = Ray::new(point(0.5, 0.0, -0.5), vector(0.0, 1.0, 0.0));
+        let down = Ray::new(point(-0.5, 2.0, 0.5), vector(0.0, -1.0, 0.0));
+        let hit = mesh.intersect(&up).unwrap();
+        assert_eq!(hit.emitted, Color::new(1.0, 1.0, 1.0));
+        assert_eq!(hit.light, Some(0));
+        let hit = mesh.intersect(&down).unwrap();
+        assert_eq!(hit.emitted, Color::new(1.0, 1.0, 1.0));
+        assert_eq!(hit.light, Some(1));
+    }
+
+    fn make_environment(light_to_world: DMat4) -> EnvironmentLight {
+        // dark everywhere except for one bright pixel in the center of the
+        // map, just above the horizon
+        let (width, height) = (16, 8);
+        let mut pixels = vec![Color::new(0.01, 0.01, 0.01); width * height];
+        pixels[3 * width + 8] = Color::new(100.0, 100.0, 100.0);
+        let map = HdrImage::new(width, height, pixels);
+        EnvironmentLight::new(map, light_to_world, 1.0)
+    }
+
+    #[test]
+    fn test_environment_lookup() {
+        let light = make_environment(DMat4::identity());
+        let bright = Color::new(100.0, 100.0, 100.0);
+
+        // the bright pixel spans theta in [67.5, 90] degrees around -z
+        let theta = f64::to_radians(80.0);
+        let ray = Ray::new(
+            point(0.0, 0.0, 0.0),
+            vector(0.01, theta.cos(), -theta.sin()),
+        );
+        assert_eq!(light.le(&ray), bright);
+        let up = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 1.0, 0.0));
+        assert_eq!(light.le(&up), Color::new(0.01, 0.01, 0.01));
+
+        // turning the map around makes the pixel appear behind
+        let turned = make_environment(DMat4::from_rotation_y(PI));
+        let behind = Ray::new(
+            point(0.0, 0.0, 0.0),
+            vector(-0.01, theta.cos(), theta.sin()),
+        );
+        assert_eq!(turned.le(&behind), bright);
+        assert_ne!(turned.le(&ray), bright);
+    }
+
+    #[test]
+    fn test_environment_importance_sampling() {
+        let light = make_environment(DMat4::from_rotation_y(1.0));
+        let p = point(0.0, 0.0, 0.0);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // most samples go towards the bright pixel, and the pdf agrees with
+        // the one reported for the sampled direction
+        let bright = Color::new(100.0, 100.0, 100.0);
+        let mut hits = 0;
+        for _ in 0..100 {
+            let sample = light.sample_li(p, (rng.gen(), rng.gen())).unwrap();
+            let ray = Ray::new(p, sample.wi);
+            assert_eq!(light.le(&ray), sample.radiance);
+            assert_eps_eq(&light.pdf(p, sample.wi), &sample.pdf, 1.0e-6);
+            if sample.radiance == bright {
+                hits += 1;
+            }
+        }
+        assert!(hits > 90);
+    }
+
+    #[test]
+    fn test_environment_irradiance() {
+        // a constant map delivers pi * L to a surface facing any way
+        let map = HdrImage::new(4, 2, vec![Color::new(1.0, 1.0, 1.0); 8]);
+        let light = EnvironmentLight::new(map, DMat4::identity(), 1.0);
+        let p = point(0.0, 0.0, 0.0);
+        let normal = vector(1.0, 1.0, 0.0).normalized();
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let n = 20_000;
+        let mut irradiance = 0.0;
+        for _ in 0..n {
+            let sample = light.sample_li(p, (rng.gen(), rng.gen())).unwrap();
+            let cos = normal.dot(sample.wi).max(0.0);
+            irradiance += sample.radiance.r() * cos / sample.pdf;
+        }
+        irradiance /= n as f64;
+        assert!((irradiance - PI).abs() < 0.02 * PI);
+    }
+
+    #[test]
+    fn test_goniometric_light() {
+        let profile =
+            IesProfile::parse(include_str!("../data/ies/downlight.ies"))
+                .unwrap();
+        let light = GoniometricLight::new(
+            point(0.0, 2.0, 0.0),
+            DMat4::identity(),
+            profile.clone(),
+            Color::new(1.0, 1.0, 1.0),
+        );
+
+        // straight below, and 30 degrees off of straight below
+        let below = light.sample_li(point(0.0, 0.0, 0.0), (0.5, 0.5)).unwrap();
+        assert_eps_eq(&below.radiance.r(), &(500.0 / 4.0), EPS);
+        let x = 2.0 * f64::to_radians(30.0).tan();
+        let off = light.sample_li(point(x, 0.0, 0.0), (0.5, 0.5)).unwrap();
+        assert_eps_eq(&off.radiance.r(), &(330.0 / (4.0 + x * x)), EPS);
+
+        // nothing above
+        assert_eq!(light.sample_li(point(0.0, 3.0, 0.0), (0.5, 0.5)), None);
+
+        // turned upside down it lights the ceiling instead
+        let flipped = GoniometricLight::new(
+            point(0.0, 2.0, 0.0),
+            DMat4::from_rotation_x(PI),
+            profile,
+            Color::new(1.0, 1.0, 1.0),
+        );
+        assert_eq!(flipped.sample_li(point(0.0, 0.0, 0.0), (0.5, 0.5)), None);
+        let above = flipped.sample_li(point(0.0, 4.0, 0.0), (0.5, 0.5));
+        assert_eps_eq(&above.unwrap().radiance.r(), &(500.0 / 4.0), 1.0e-6);
+    }
+
+    #[test]
+    fn test_goniometric_light_power() {
+        // a uniform 1000 cd source emits 4 pi 1000 watts
+        let profile =
+            IesProfile::parse(include_str!("../data/ies/isotropic.ies"))
+                .unwrap();
+        let light = GoniometricLight::new(
+            point(0.0, 0.0, 0.0),
+            DMat4::identity(),
+            profile,
+            Color::new(1.0, 1.0, 1.0),
+        );
+        let expected = 4.0 * PI * 1000.0;
+        assert!((light.power().r() - expected).abs() < 1.0e-3 * expected);
+    }
+}
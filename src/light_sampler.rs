@@ -0,0 +1,547 @@
+use super::light::{Light, LightBounds};
+use super::math::{Normal3, Point3};
+use super::primitive::Aabb;
+use super::sampling::Distribution1D;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// How to pick which light to sample for direct lighting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightSampling {
+    /// Every light equally often.
+    Uniform,
+    /// Lights in proportion to the power they emit.
+    Power,
+    /// Lights in proportion to an estimate of how much they contribute to
+    /// the shading point, using a hierarchy of light bounds.
+    Bvh,
+}
+
+impl FromStr for LightSampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LightSampling, String> {
+        match s {
+            "uniform" => Ok(LightSampling::Uniform),
+            "power" => Ok(LightSampling::Power),
+            "bvh" => Ok(LightSampling::Bvh),
+            _ => Err(format!("unknown light sampling strategy '{}'", s)),
+        }
+    }
+}
+
+/// Picks one light out of a list for a shading point.
+pub trait LightSampler {
+    /// Pick a light for the point `point` with the unit normal `normal`
+    /// using `u` in [0, 1). Returns the index of the light and the
+    /// probability of having picked it.
+    fn sample(
+        &self,
+        point: Point3,
+        normal: Normal3,
+        u: f64,
+    ) -> Option<(usize, f64)>;
+
+    /// The probability that `sample` picks the light at `index`.
+    fn pmf(&self, point: Point3, normal: Normal3, index: usize) -> f64;
+}
+
+/// Build a sampler for `lights` using the given strategy.
+pub fn light_sampler<L: Light>(
+    strategy: LightSampling,
+    lights: &[L],
+) -> Box<dyn LightSampler> {
+    match strategy {
+        LightSampling::Uniform => Box::new(UniformLightSampler {
+            count: lights.len(),
+        }),
+        LightSampling::Power => Box::new(PowerLightSampler::new(lights)),
+        LightSampling::Bvh => Box::new(BvhLightSampler::new(lights)),
+    }
+}
+
+pub struct UniformLightSampler {
+    count: usize,
+}
+
+impl LightSampler for UniformLightSampler {
+    fn sample(&self, _: Point3, _: Normal3, u: f64) -> Option<(usize, f64)> {
+        if self.count == 0 {
+            return None;
+        }
+        let index = ((u * self.count as f64) as usize).min(self.count - 1);
+        Some((index, 1.0 / self.count as f64))
+    }
+
+    fn pmf(&self, _: Point3, _: Normal3, index: usize) -> f64 {
+        if index < self.count {
+            1.0 / self.count as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+pub struct PowerLightSampler {
+    distribution: Option<Distribution1D>,
+}
+
+impl PowerLightSampler {
+    pub fn new<L: Light>(lights: &[L]) -> PowerLightSampler {
+        if lights.is_empty() {
+            return PowerLightSampler { distribution: None };
+        }
+        let power = lights.iter().map(|l| l.power().luminance()).collect();
+        PowerLightSampler {
+            distribution: Some(Distribution1D::new(power)),
+        }
+    }
+}
+
+impl LightSampler for PowerLightSampler {
+    fn sample(&self, _: Point3, _: Normal3, u: f64) -> Option<(usize, f64)> {
+        let distribution = self.distribution.as_ref()?;
+        let (_, pdf, index) = distribution.sample_continuous(u);
+        let pmf = pdf / distribution.count() as f64;
+        if pmf == 0.0 {
+            // all of the lights are dark
+            return Some((index, 1.0 / distribution.count() as f64));
+        }
+        Some((index, pmf))
+    }
+
+    fn pmf(&self, _: Point3, _: Normal3, index: usize) -> f64 {
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => return 0.0,
+        };
+        let n = distribution.count();
+        if index >= n {
+            return 0.0;
+        }
+        if distribution.integral() == 0.0 {
+            return 1.0 / n as f64;
+        }
+        distribution.pdf((index as f64 + 0.5) / n as f64) / n as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NodeKind {
+    /// Holds the index of a light.
+    Leaf(usize),
+    /// The first child directly follows its parent in the node list; this
+    /// is the index of the second.
+    Interior(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LightBvhNode {
+    bounds: LightBounds,
+    kind: NodeKind,
+    /// `None` for the root.
+    parent: Option<usize>,
+}
+
+/// Buckets per axis when looking for the best place to split a node.
+const SPLIT_BUCKETS: usize = 12;
+
+/// A bounding volume hierarchy over the lights that have bounds. Sampling
+/// walks down from the root, choosing between the two children of each node
+/// in proportion to their importance for the shading point. Each light
+/// without bounds, like the environment, is picked as often as the whole
+/// hierarchy.
+pub struct BvhLightSampler {
+    nodes: Vec<LightBvhNode>,
+    infinite: Vec<usize>,
+    /// The leaf node holding each light, if it's in the hierarchy.
+    leaves: Vec<Option<usize>>,
+}
+
+impl BvhLightSampler {
+    pub fn new<L: Light>(lights: &[L]) -> BvhLightSampler {
+        let mut infinite = Vec::new();
+        let mut bounded = Vec::new();
+        for (i, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) if bounds.phi > 0.0 => bounded.push((i, bounds)),
+                Some(_) => (),
+                None => infinite.push(i),
+            }
+        }
+
+        let mut sampler = BvhLightSampler {
+            nodes: Vec::new(),
+            infinite,
+            leaves: vec![None; lights.len()],
+        };
+        if !bounded.is_empty() {
+            sampler.build(&mut bounded, None);
+        }
+        sampler
+    }
+
+    /// Add the nodes for `lights` to the node list, returning their bounds.
+    fn build(
+        &mut self,
+        lights: &mut [(usize, LightBounds)],
+        parent: Option<usize>,
+    ) -> LightBounds {
+        if lights.len() == 1 {
+            let (index, bounds) = lights[0];
+            self.leaves[index] = Some(self.nodes.len());
+            self.nodes.push(LightBvhNode {
+                bounds,
+                kind: NodeKind::Leaf(index),
+                parent,
+            });
+            return bounds;
+        }
+
+        let split = split_lights(lights);
+        let (first, second) = lights.split_at_mut(split);
+
+        let node = self.nodes.len();
+        self.nodes.push(LightBvhNode {
+            bounds: first[0].1,
+            kind: NodeKind::Interior(0),
+            parent,
+        });
+        let first_bounds = self.build(first, Some(node));
+        let second_index = self.nodes.len();
+        let second_bounds = self.build(second, Some(node));
+
+        let bounds = first_bounds.union(&second_bounds);
+        self.nodes[node].bounds = bounds;
+        self.nodes[node].kind = NodeKind::Interior(second_index);
+        bounds
+    }
+
+    /// Probability of sampling the hierarchy instead of a light without
+    /// bounds.
+    fn bvh_probability(&self) -> f64 {
+        let bvh = if self.nodes.is_empty() { 0.0 } else { 1.0 };
+        bvh / (bvh + self.infinite.len() as f64)
+    }
+}
+
+impl LightSampler for BvhLightSampler {
+    fn sample(
+        &self,
+        point: Point3,
+        normal: Normal3,
+        u: f64,
+    ) -> Option<(usize, f64)> {
+        if self.nodes.is_empty() && self.infinite.is_empty() {
+            return None;
+        }
+
+        let p_bvh = self.bvh_probability();
+        if u >= p_bvh {
+            let n = self.infinite.len();
+            let u = (u - p_bvh) / (1.0 - p_bvh);
+            let i = ((u * n as f64) as usize).min(n - 1);
+            return Some((self.infinite[i], (1.0 - p_bvh) / n as f64));
+        }
+
+        let mut u = u / p_bvh;
+        let mut pmf = p_bvh;
+        let mut node = 0;
+        loop {
+            match self.nodes[node].kind {
+                NodeKind::Leaf(index) => {
+                    // the importance of the other nodes has been checked on
+                    // the way down, but a lone root hasn't been
+                    let root = &self.nodes[0].bounds;
+                    if node == 0 && root.importance(point, normal) == 0.0 {
+                        return None;
+                    }
+                    return Some((index, pmf));
+                }
+                NodeKind::Interior(second) => {
+                    let first = node + 1;
+                    let c0 = self.nodes[first].bounds.importance(point, normal);
+                    let c1 =
+                        self.nodes[second].bounds.importance(point, normal);
+                    if c0 == 0.0 && c1 == 0.0 {
+                        return None;
+                    }
+
+                    let p0 = c0 / (c0 + c1);
+                    if u < p0 {
+                        node = first;
+                        u = (u / p0).min(1.0 - f64::EPSILON);
+                        pmf *= p0;
+                    } else {
+                        node = second;
+                        u = ((u - p0) / (1.0 - p0)).min(1.0 - f64::EPSILON);
+                        pmf *= 1.0 - p0;
+                    }
+                }
+            }
+        }
+    }
+
+    fn pmf(&self, point: Point3, normal: Normal3, index: usize) -> f64 {
+        let p_bvh = self.bvh_probability();
+        if self.infinite.contains(&index) {
+            return (1.0 - p_bvh) / self.infinite.len() as f64;
+        }
+        let mut node = match self.leaves.get(index) {
+            Some(Some(leaf)) => *leaf,
+            _ => return 0.0,
+        };
+
+        if node == 0 && self.nodes[0].bounds.importance(point, normal) == 0.0 {
+            return 0.0;
+        }
+
+        // walk up to the root, multiplying in the probability of each choice
+        // on the way down
+        let mut pmf = p_bvh;
+        while let Some(parent) = self.nodes[node].parent {
+            let second = match self.nodes[parent].kind {
+                NodeKind::Interior(second) => second,
+                NodeKind::Leaf(_) => unreachable!("leaves have no children"),
+            };
+            let first = parent + 1;
+            let c0 = self.nodes[first].bounds.importance(point, normal);
+            let c1 = self.nodes[second].bounds.importance(point, normal);
+            if c0 == 0.0 && c1 == 0.0 {
+                return 0.0;
+            }
+
+            let chosen = if node == first { c0 } else { c1 };
+            pmf *= chosen / (c0 + c1);
+            node = parent;
+        }
+        pmf
+    }
+}
+
+/// Reorder `lights` and return where to split them into two nodes, choosing
+/// the split that minimizes pbrt-v4's surface area and orientation
+/// heuristic.
+fn split_lights(lights: &mut [(usize, LightBounds)]) -> usize {
+    let mut centroid_bounds = Aabb::empty();
+    for (_, b) in lights.iter() {
+        centroid_bounds = centroid_bounds.union_point(b.bounds.centroid());
+    }
+
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..3 {
+        if centroid_bounds.max[axis] == centroid_bounds.min[axis] {
+            continue;
+        }
+
+        let mut buckets: [Option<LightBounds>; SPLIT_BUCKETS] =
+            [None; SPLIT_BUCKETS];
+        for (_, b) in lights.iter() {
+            let i = bucket(&centroid_bounds, b, axis);
+            buckets[i] = Some(match buckets[i] {
+                Some(bucket) => bucket.union(b),
+                None => *b,
+            });
+        }
+
+        for split in 1..SPLIT_BUCKETS {
+            let below = merge(&buckets[..split]);
+            let above = merge(&buckets[split..]);
+            let cost = split_cost(below, &centroid_bounds, axis)
+                + split_cost(above, &centroid_bounds, axis);
+            match best {
+                Some((best_cost, _, _)) if best_cost <= cost => (),
+                _ => best = Some((cost, axis, split)),
+            }
+        }
+    }
+
+    let mid = match best {
+        Some((_, axis, split)) => {
+            partition(lights, |b| bucket(&centroid_bounds, b, axis) < split)
+        }
+        None => 0,
+    };
+
+    // all centroids in the same place, or every light on one side
+    if mid == 0 || mid == lights.len() {
+        lights.len() / 2
+    } else {
+        mid
+    }
+}
+
+fn bucket(centroid_bounds: &Aabb, b: &LightBounds, axis: usize) -> usize {
+    let offset = centroid_bounds.offset(b.bounds.centroid())[axis];
+    ((offset * SPLIT_BUCKETS as f64) as usize).min(SPLIT_BUCKETS - 1)
+}
+
+fn merge(buckets: &[Option<LightBounds>]) -> Option<LightBounds> {
+    buckets.iter().flatten().fold(None, |merged, b| {
+        Some(match merged {
+            Some(m) => m.union(b),
+            None => *b,
+        })
+    })
+}
+
+/// The cost of a node with the given bounds: its power, times the solid
+/// angle its emission covers, times its surface area. Boxes that are thin
+/// along the split axis are penalized relative to the centroid bounds.
+fn split_cost(
+    b: Option<LightBounds>,
+    centroid_bounds: &Aabb,
+    axis: usize,
+) -> f64 {
+    let b = match b {
+        Some(b) => b,
+        None => return 0.0,
+    };
+
+    let theta_o = b.normals.cos_theta.clamp(-1.0, 1.0).acos();
+    let theta_e = b.cos_theta_e.clamp(-1.0, 1.0).acos();
+    let theta_w = f64::min(theta_o + theta_e, PI);
+    let sin_o = theta_o.sin();
+    let m_omega = 2.0 * PI * (1.0 - theta_o.cos())
+        + PI / 2.0
+            * (2.0 * theta_w * sin_o
+                - (theta_o - 2.0 * theta_w).cos()
+                - 2.0 * theta_o * sin_o
+                + theta_o.cos());
+
+    let d = centroid_bounds.diagonal();
+    let max_extent = d.x.max(d.y).max(d.z);
+    let regularization = if d[axis] > 0.0 {
+        max_extent / d[axis]
+    } else {
+        1.0
+    };
+
+    // a single point light has no area, but still costs something
+    let area = b.bounds.surface_area().max(f64::EPSILON);
+    b.phi * m_omega * area * regularization
+}
+
+/// Move the elements for which `pred` holds to the front, returning how many
+/// there are.
+fn partition<F>(lights: &mut [(usize, LightBounds)], pred: F) -> usize
+where
+    F: Fn(&LightBounds) -> bool,
+{
+    let mut mid = 0;
+    for i in 0..lights.len() {
+        if pred(&lights[i].1) {
+            lights.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::color::Color;
+    use super::super::light::{DirectionalLight, PointLight};
+    use super::super::math::{normal, point, vector};
+    use super::super::math::test_util::assert_eps_eq;
+
+    const EPS: f64 = 1.0e-9;
+
+    fn make_lights() -> Vec<Box<dyn Light>> {
+        let mut lights: Vec<Box<dyn Light>> = Vec::new();
+        // a row of dim lights along x
+        for i in 0..20 {
+            lights.push(Box::new(PointLight::new(
+                point(i as f64 * 2.0, 1.0, 0.0),
+                Color::new(1.0, 1.0, 1.0),
+            )));
+        }
+        // one bright light far away
+        lights.push(Box::new(PointLight::new(
+            point(0.0, 100.0, 0.0),
+            Color::new(1000.0, 1000.0, 1000.0),
+        )));
+        lights.push(Box::new(DirectionalLight::new(
+            vector(0.0, -1.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            10.0,
+        )));
+        lights
+    }
+
+    /// The pmf reported for every light sums to one and agrees with what
+    /// `sample` reports.
+    fn check_consistency(sampler: &dyn LightSampler, count: usize) {
+        let (p, n) = (point(13.0, 0.0, 0.5), normal(0.0, 1.0, 0.0));
+        let total: f64 = (0..count).map(|i| sampler.pmf(p, n, i)).sum();
+        assert_eps_eq(&total, &1.0, 1.0e-6);
+
+        for i in 0..100 {
+            let u = (i as f64 + 0.5) / 100.0;
+            let (index, pmf) = sampler.sample(p, n, u).unwrap();
+            assert_eps_eq(&sampler.pmf(p, n, index), &pmf, 1.0e-9);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("bvh".parse(), Ok(LightSampling::Bvh));
+        assert_eq!("power".parse(), Ok(LightSampling::Power));
+        assert!("best".parse::<LightSampling>().is_err());
+    }
+
+    #[test]
+    fn test_uniform() {
+        let lights = make_lights();
+        let sampler = light_sampler(LightSampling::Uniform, &lights);
+        check_consistency(&*sampler, lights.len());
+        let (o, n) = (point(0.0, 0.0, 0.0), normal(0.0, 1.0, 0.0));
+        assert_eq!(sampler.sample(o, n, 0.0), Some((0, 1.0 / 22.0)));
+    }
+
+    #[test]
+    fn test_power() {
+        let lights = make_lights();
+        let sampler = light_sampler(LightSampling::Power, &lights);
+        check_consistency(&*sampler, lights.len());
+
+        // the bright light has more power than all of the others together
+        let (p, n) = (point(0.0, 0.0, 0.0), normal(0.0, 1.0, 0.0));
+        assert!(sampler.pmf(p, n, 20) > 0.5);
+        assert!(sampler.pmf(p, n, 20) > 100.0 * sampler.pmf(p, n, 3));
+    }
+
+    #[test]
+    fn test_bvh() {
+        let lights = make_lights();
+        let sampler = light_sampler(LightSampling::Bvh, &lights);
+        check_consistency(&*sampler, lights.len());
+
+        // right below one of the dim lights, that light is the most likely
+        // of the bounded ones
+        let (p, n) = (point(14.0, 0.0, 0.0), normal(0.0, 1.0, 0.0));
+        let pmfs: Vec<f64> = (0..21).map(|i| sampler.pmf(p, n, i)).collect();
+        let best = (0..21)
+            .max_by(|&a, &b| pmfs[a].partial_cmp(&pmfs[b]).unwrap())
+            .unwrap();
+        assert_eq!(best, 7);
+        assert!(pmfs[7] > 10.0 * pmfs[0]);
+
+        // half of the samples go to the directional light
+        assert_eps_eq(&sampler.pmf(p, n, 21), &0.5, EPS);
+    }
+
+    #[test]
+    fn test_bvh_facing_away() {
+        // lights below the surface can't contribute
+        let lights = vec![PointLight::new(
+            point(0.0, -1.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+        )];
+        let sampler = light_sampler(LightSampling::Bvh, &lights);
+        let (p, n) = (point(0.0, 0.0, 0.0), normal(0.0, 1.0, 0.0));
+        assert_eq!(sampler.sample(p, n, 0.5), None);
+        assert_eq!(sampler.pmf(p, n, 0), 0.0);
+    }
+}
@@ -1,61 +1,1153 @@
 extern crate image;
 
-use ultraviolet::vec::DVec4;
-use image::{ImageBuffer, Rgb, RgbImage};
+use image::{ImageBuffer, RgbImage};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::env;
 use std::f64::consts::PI;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use ultraviolet::mat::DMat4;
 
+mod bdpt;
+mod bsdf;
 mod camera;
 mod color;
+mod film;
+mod gltf;
+mod hdr;
+mod ies;
+mod integrator;
+mod json;
+mod light;
+mod light_sampler;
+mod material;
 mod math;
+mod mesh;
+mod mlt;
+mod path;
+mod pbrt;
+mod photon;
+mod polynomial;
 mod primitive;
-use math::{Ray, point, vector};
-use primitive::{Scene, Sphere, Triangle};
+mod sampling;
+mod sky;
+use bdpt::BidirectionalPathTracer;
+use camera::Camera;
+use color::Color;
+use film::SplatBuffer;
+use gltf::{GltfCamera, GltfScene, Projection};
+use hdr::HdrImage;
+use ies::IesProfile;
+use integrator::Integrator;
+use light::{
+    area_lights, AreaLight, DirectionalLight, EnvironmentLight,
+    GoniometricLight, Light, PointLight, SpotLight,
+};
+use light_sampler::{light_sampler, LightSampler, LightSampling};
+use material::{Material, MaterialList};
+use math::{point, rotation_x, rotation_y, rotation_z, scaling, translation};
+use math::{vector, Normal3, Point3, Ray};
+use mesh::TriangleMesh;
+use mlt::MetropolisSettings;
+use path::PathTracer;
+use pbrt::PbrtScene;
+use photon::{PhotonMapper, PhotonMapping, Sppm};
+use primitive::{Bvh, CornellBox, Instance, Scene, Sphere, Transformed};
+use primitive::{Cone, Cylinder, Disk, Hyperboloid, Paraboloid, Plane};
+use primitive::{Aabb, AxisAlignedBox, Csg, Sdf, SignedDistance, Torus};
+use primitive::{load_patches, BezierSurface, Triangle};
+use sky::{Sky, SkyLight, SunLight};
+
+const USAGE: &str = "usage: raytracer [MODE] [OPTIONS]
+
+modes:
+    shaded              direct lighting from the scene's lights (default)
+    path                light bouncing between surfaces, traced from the
+                        camera
+    bdpt                light bouncing between surfaces, traced from both
+                        the camera and the lights
+    photon              light bouncing between surfaces, from the density
+                        of photons shot from the lights
+    sppm                progressive photon mapping, which shoots photons
+                        once for each of --pixel-samples iterations
+    mlt                 Metropolis light transport, which explores paths
+                        from the camera near the brightest ones it finds,
+                        mutating --pixel-samples paths per pixel
+    ao                  ambient occlusion
+    normal              shading normals
+    geometric-normal    geometric normals
+    depth               distance from the camera
+    uv                  surface (u, v) coordinates
+    barycentric         triangle barycentric coordinates
+    primitive-id        a different color for each object
+    bvh-cost            bounding volume hierarchy nodes each camera ray
+                        visits, from blue for none to red for --max-nodes
+
+options:
+    --scene NAME        demo, cornell, neon, instances, shapes or teapot, a
+                        .ply or .stl mesh to render on a floor, a .gltf or
+                        .glb file to render from its first camera, or a
+                        .pbrt file to render as its camera and film
+                        describe (default demo)
+    --patches FILE      Bézier patches, in the format of Newell's teapot,
+                        to render in place of the teapot scene's teapot
+    --ies FILE          hang a luminaire with this IES profile above the
+                        middle of the scene, facing down
+    --environment FILE  light the scene with a latitude-longitude .hdr map
+    --rotate-environment DEGREES
+                        turn the environment map about the vertical axis
+    --sun-elevation DEGREES
+                        light the scene with a sun and sky at this elevation
+    --sun-azimuth DEGREES
+                        angle of the sun around the vertical axis from -z
+    --turbidity T       haziness of the sky, from 2 to 10 (default 3)
+    --bake-sky FILE     also write the sky to a latitude-longitude .hdr map
+    --light-sampling S  sample every light at each point (all, the default),
+                        or pick lights by uniform, power or bvh (path
+                        and mlt modes always pick one, by power unless
+                        told, and bdpt and photon mapping modes always
+                        pick by power)
+    --light-samples N   lights picked at each point (default 1)
+    --pixel-samples N   camera rays per pixel (default 1, or the sampler's
+                        for .pbrt files)
+    --radius R          ambient occlusion distance (default 1.0)
+    --samples N         ambient occlusion rays per pixel (default 16)
+    --max-depth D       distance that renders black in depth mode (default 10.0)
+    --max-nodes N       node visits that render red in bvh-cost mode
+                        (default 100)
+    --max-bounces N     times light may scatter in path, bdpt, photon
+                        mapping and mlt modes (default 5)
+    --photons N         photons shot from the lights, each iteration in
+                        sppm mode (default 100000)
+    --nearest N         photons each density estimate in photon mode is
+                        made from (default 50)
+    --photon-radius R   furthest photons are gathered from in photon mode,
+                        and at first in sppm mode (default 0.1)
+    --final-gather N    rays traced from each point seen to find the light
+                        it reflects in photon mode (default 0, estimating
+                        it from the photons at the point itself)
+    --bootstrap N       paths traced to find how bright the image is in
+                        mlt mode (default 100000)
+    --chains N          Markov chains of mutated paths in mlt mode
+                        (default 64)
+    --large-steps P     probability that a mutation in mlt mode replaces
+                        the whole path (default 0.3)";
+
+/// How the light arriving at the camera is found.
+enum Mode {
+    /// Direct lighting only.
+    Shaded,
+    /// A view of the geometry rather than its lighting.
+    Diagnostic(Integrator),
+    Path { max_bounces: u32 },
+    Bdpt { max_bounces: u32 },
+    Photon { max_bounces: u32, mapping: PhotonMapping },
+    Sppm { max_bounces: u32, photons: usize, radius: f64 },
+    Mlt {
+        max_bounces: u32,
+        bootstrap: usize,
+        chains: usize,
+        large_steps: f64,
+    },
+}
+
+struct Options {
+    mode: Mode,
+    scene: String,
+    patches: Option<String>,
+    ies: Option<String>,
+    environment: Option<String>,
+    /// Rotation of the environment map about the y axis, in radians.
+    environment_rotation: f64,
+    /// Elevation and azimuth of the sun in radians, if there is a sky.
+    sun: Option<(f64, f64)>,
+    turbidity: f64,
+    bake_sky: Option<String>,
+    /// `None` means sampling every light.
+    light_sampling: Option<LightSampling>,
+    light_samples: u32,
+    /// `None` means the scene's own number of samples.
+    pixel_samples: Option<u32>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut scene = String::from("demo");
+    let mut patches = None;
+    let mut ies = None;
+    let mut environment = None;
+    let mut environment_rotation = 0.0;
+    let mut sun_elevation = None;
+    let mut sun_azimuth = 0.0;
+    let mut turbidity = 3.0;
+    let mut bake_sky = None;
+    let mut light_sampling = None;
+    let mut light_samples = 1;
+    let mut pixel_samples = None;
+    let mut radius = 1.0;
+    let mut samples = 16;
+    let mut max_depth = 10.0;
+    let mut max_nodes = 100;
+    let mut max_bounces = 5;
+    let mut photons = 100_000;
+    let mut nearest = 50;
+    let mut photon_radius = 0.1;
+    let mut final_gather = 0;
+    let mut bootstrap = 100_000;
+    let mut chains = 64;
+    let mut large_steps = 0.3;
+    let mut mode = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value", name))
+                .cloned()
+        };
+
+        match arg.as_str() {
+            "--scene" => scene = value(arg)?,
+            "--patches" => patches = Some(value(arg)?),
+            "--ies" => ies = Some(value(arg)?),
+            "--environment" => environment = Some(value(arg)?),
+            "--rotate-environment" => {
+                let degrees: f64 = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --rotate-environment: {}", e))?;
+                environment_rotation = degrees.to_radians();
+            }
+            "--sun-elevation" => {
+                let degrees: f64 = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --sun-elevation: {}", e))?;
+                sun_elevation = Some(degrees.to_radians());
+            }
+            "--sun-azimuth" => {
+                let degrees: f64 = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --sun-azimuth: {}", e))?;
+                sun_azimuth = degrees.to_radians();
+            }
+            "--turbidity" => {
+                turbidity = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --turbidity: {}", e))?
+            }
+            "--bake-sky" => bake_sky = Some(value(arg)?),
+            "--light-sampling" => {
+                light_sampling = match value(arg)?.as_str() {
+                    "all" => None,
+                    strategy => Some(strategy.parse()?),
+                }
+            }
+            "--light-samples" => {
+                light_samples = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --light-samples: {}", e))?
+            }
+            "--pixel-samples" => {
+                let samples = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --pixel-samples: {}", e))?;
+                pixel_samples = Some(samples);
+            }
+            "--radius" => {
+                radius = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --radius: {}", e))?
+            }
+            "--samples" => {
+                samples = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --samples: {}", e))?
+            }
+            "--max-depth" => {
+                max_depth = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --max-depth: {}", e))?
+            }
+            "--max-nodes" => {
+                max_nodes = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --max-nodes: {}", e))?
+            }
+            "--max-bounces" => {
+                max_bounces = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --max-bounces: {}", e))?
+            }
+            "--photons" => {
+                photons = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --photons: {}", e))?
+            }
+            "--nearest" => {
+                nearest = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --nearest: {}", e))?
+            }
+            "--photon-radius" => {
+                photon_radius = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --photon-radius: {}", e))?
+            }
+            "--final-gather" => {
+                final_gather = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --final-gather: {}", e))?
+            }
+            "--bootstrap" => {
+                bootstrap = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --bootstrap: {}", e))?
+            }
+            "--chains" => {
+                chains = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --chains: {}", e))?
+            }
+            "--large-steps" => {
+                large_steps = value(arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --large-steps: {}", e))?
+            }
+            _ if mode.is_none() => mode = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    let mode = match mode.as_deref() {
+        None | Some("shaded") => Mode::Shaded,
+        Some("path") => Mode::Path { max_bounces },
+        Some("bdpt") => Mode::Bdpt { max_bounces },
+        Some("photon") => Mode::Photon {
+            max_bounces,
+            mapping: PhotonMapping {
+                photons: photons.max(1),
+                nearest,
+                max_radius: photon_radius,
+                final_gather,
+            },
+        },
+        Some("sppm") => Mode::Sppm {
+            max_bounces,
+            photons: photons.max(1),
+            radius: photon_radius,
+        },
+        Some("mlt") => Mode::Mlt {
+            max_bounces,
+            bootstrap,
+            chains: chains.max(1),
+            large_steps,
+        },
+        Some(name) => Mode::Diagnostic(match name {
+            "ao" => Integrator::AmbientOcclusion { radius, samples },
+            "normal" => Integrator::ShadingNormal,
+            "geometric-normal" => Integrator::GeometricNormal,
+            "depth" => Integrator::Depth { max_depth },
+            "uv" => Integrator::Uv,
+            "barycentric" => Integrator::Barycentric,
+            "primitive-id" => Integrator::PrimitiveId,
+            "bvh-cost" => Integrator::BvhCost { max_nodes },
+            other => return Err(format!("unknown mode '{}'", other)),
+        }),
+    };
+
+    Ok(Options {
+        mode,
+        scene,
+        patches,
+        ies,
+        environment,
+        environment_rotation,
+        sun: sun_elevation.map(|elevation| (elevation, sun_azimuth)),
+        turbidity,
+        bake_sky,
+        light_sampling,
+        light_samples,
+        pixel_samples,
+    })
+}
+
+type SceneList = Vec<Box<dyn Scene>>;
+type LightList = Vec<Box<dyn Light>>;
+
+fn demo_scene() -> (SceneList, LightList) {
+    // a small glowing ball, which is both geometry and a light
+    let lamp = Arc::new(AreaLight::new(
+        Sphere::new(point(-1.0, -1.0, -2.5), 0.25),
+        Color::new(8.0, 8.0, 6.0),
+        false,
+    ));
+
+    let scene: SceneList = vec![
+        // a unit sphere squashed into a wide, flat ellipsoid
+        Box::new(Transformed::new(
+            Sphere::new(point(0.0, 0.0, 0.0), 1.0),
+            translation(1.5, -0.5, -4.0) * scaling(1.3, 0.8, 1.0),
+        )),
+        Box::new(Triangle::new(
+            point(-1.0, 0.0, -3.0),
+            point(1.0, 0.0, -1.0),
+            point(0.0, 1.0, -3.0),
+        )),
+        Box::new(Arc::clone(&lamp)),
+    ];
+    let lights: LightList = vec![
+        // warm key light above and to the left
+        Box::new(PointLight::new(
+            point(-2.0, 2.0, -1.0),
+            Color::new(24.0, 20.0, 16.0),
+        )),
+        // dim blue fill light from the upper right
+        Box::new(DirectionalLight::new(
+            vector(-1.0, -1.0, -0.5),
+            Color::new(0.3, 0.4, 0.7),
+            5.0,
+        )),
+        // narrow spot light aimed at the sphere
+        Box::new(SpotLight::new(
+            point(1.5, 3.0, -2.0),
+            vector(0.0, -3.5, -2.0),
+            Color::new(40.0, 10.0, 10.0),
+            f64::to_radians(20.0),
+            f64::to_radians(15.0),
+        )),
+        Box::new(lamp),
+    ];
+
+    (scene, lights)
+}
+
+/// A floor lit by a sign made of thousands of small glowing triangles, which
+/// is hopeless to light by sampling every light or picking them uniformly.
+fn neon_scene() -> (SceneList, LightList) {
+    let mut scene: SceneList = vec![
+        Box::new(Triangle::new(
+            point(-20.0, -1.0, 10.0),
+            point(20.0, -1.0, -30.0),
+            point(-20.0, -1.0, -30.0),
+        )),
+        Box::new(Triangle::new(
+            point(-20.0, -1.0, 10.0),
+            point(20.0, -1.0, 10.0),
+            point(20.0, -1.0, -30.0),
+        )),
+    ];
+
+    // a wavy tube of light along x, split into short segments
+    let segments = 2000;
+    let mut tube = Vec::with_capacity(2 * segments);
+    for i in 0..segments {
+        let x0 = -4.0 + 8.0 * i as f64 / segments as f64;
+        let x1 = -4.0 + 8.0 * (i + 1) as f64 / segments as f64;
+        let y0 = 0.5 + 0.4 * (3.0 * x0).sin();
+        let y1 = 0.5 + 0.4 * (3.0 * x1).sin();
+        let (a, b) = (point(x0, y0, -4.0), point(x1, y1, -4.0));
+        let (c, d) = (point(x1, y1 + 0.05, -4.0), point(x0, y0 + 0.05, -4.0));
+        tube.push(Triangle::new(a, b, c));
+        tube.push(Triangle::new(a, c, d));
+    }
+
+    let mut lights: LightList = Vec::new();
+    for light in area_lights(tube, Color::new(32.0, 4.0, 16.0), true) {
+        scene.push(Box::new(Arc::clone(&light)));
+        lights.push(Box::new(light));
+    }
+
+    (scene, lights)
+}
+
+/// Ten thousand pyramids, all copies of one small mesh, each placed by its
+/// own transform in a top-level hierarchy over the copies.
+fn instances_scene() -> (SceneList, LightList) {
+    let apex = point(0.0, 1.0, 0.0);
+    let base = [
+        point(-0.5, 0.0, -0.5),
+        point(0.5, 0.0, -0.5),
+        point(0.5, 0.0, 0.5),
+        point(-0.5, 0.0, 0.5),
+    ];
+    let sides = (0..4)
+        .map(|i| Triangle::new(base[i], base[(i + 1) % 4], apex))
+        .collect();
+    let pyramid = Arc::new(Bvh::new(sides));
+
+    let mut instances = Vec::with_capacity(100 * 100);
+    for i in 0..100 {
+        for j in 0..100 {
+            // vary the copies' turns and heights in a repeating pattern
+            let turn = ((7 * i + 13 * j) % 10) as f64 * 0.05 * PI;
+            let height = 0.5 + ((3 * i + 11 * j) % 10) as f64 * 0.1;
+            let x = 1.5 * (i as f64 - 49.5);
+            let z = -4.0 - 1.5 * j as f64;
+            let placement = translation(x, -1.0, z)
+                * rotation_y(turn)
+                * scaling(1.0, height, 1.0);
+            instances.push(Instance::new(Arc::clone(&pyramid), placement));
+        }
+    }
+
+    let scene: SceneList = vec![
+        Box::new(Triangle::new(
+            point(-80.0, -1.0, 10.0),
+            point(80.0, -1.0, -160.0),
+            point(-80.0, -1.0, -160.0),
+        )),
+        Box::new(Triangle::new(
+            point(-80.0, -1.0, 10.0),
+            point(80.0, -1.0, 10.0),
+            point(80.0, -1.0, -160.0),
+        )),
+        Box::new(Bvh::new(instances)),
+    ];
+    let lights: LightList = vec![Box::new(DirectionalLight::new(
+        vector(-1.0, -2.0, -1.0),
+        Color::new(2.5, 2.4, 2.2),
+        120.0,
+    ))];
+
+    (scene, lights)
+}
+
+/// The surfaces the renderer can intersect directly, standing on an endless
+/// floor.
+fn shapes_scene() -> (SceneList, LightList) {
+    // the classic solid modelling example: where a cube and a ball overlap,
+    // drilled through along each axis
+    let drill = || Cylinder::capped(0.2, -0.6, 0.6);
+    let drills = Csg::union(
+        Csg::union(
+            drill(),
+            Transformed::new(drill(), rotation_x(0.5 * PI)),
+        ),
+        Transformed::new(drill(), rotation_z(0.5 * PI)),
+    );
+    let block = Csg::difference(
+        Csg::intersection(
+            AxisAlignedBox::new(point(-0.4, -0.4, -0.4), point(0.4, 0.4, 0.4)),
+            Sphere::new(point(0.0, 0.0, 0.0), 0.52),
+        ),
+        drills,
+    );
+
+    // a twisted column ringed every so often, with a ball melted onto its
+    // top, which only a distance field can do
+    let rings = Sdf::torus(0.36, 0.03)
+        .repeat(vector(0.0, 0.25, 0.0))
+        .intersection(Sdf::cylinder(0.4, 0.4));
+    let column = Sdf::rounded_cuboid(vector(0.25, 0.5, 0.25), 0.05)
+        .twist(1.5)
+        .union(rings)
+        .smooth_union(Sdf::sphere(0.25).translate(vector(0.0, 0.6, 0.0)), 0.1);
+    let bounds = Aabb::new(point(-0.4, -0.5, -0.4), point(0.4, 0.9, 0.4));
+    // twists overestimate distances, so step more carefully
+    let column = SignedDistance::new(column, bounds).with_step_scale(0.5);
+
+    // a Mandelbulb with its front quarter cut away to show the inside
+    let bulb = Sdf::mandelbulb(8.0, 8)
+        .difference(Sdf::cuboid(vector(0.7, 0.7, 0.7)).translate(vector(
+            0.7, 0.0, 0.7,
+        )))
+        .transform(rotation_y(0.25 * PI))
+        .scale(0.4);
+    let bounds = Aabb::new(point(-0.5, -0.5, -0.5), point(0.5, 0.5, 0.5));
+    let bulb = SignedDistance::new(bulb, bounds);
+
+    let scene: SceneList = vec![
+        Box::new(Transformed::new(Plane::new(), translation(0.0, -1.0, 0.0))),
+        // the quadrics along the back, all standing on the floor
+        Box::new(Transformed::new(
+            Cylinder::capped(0.5, 0.0, 1.2),
+            translation(-3.0, -1.0, -4.0),
+        )),
+        Box::new(Transformed::new(
+            Cone::new(0.5, 1.2),
+            translation(-1.5, -1.0, -4.0),
+        )),
+        Box::new(Transformed::new(
+            Paraboloid::new(0.6, 0.0, 1.2),
+            translation(0.0, -1.0, -4.0),
+        )),
+        Box::new(Transformed::new(
+            Hyperboloid::new(0.3, 0.6, -0.6, 0.6),
+            translation(1.5, -0.4, -4.0),
+        )),
+        // a washer on its edge, facing the camera
+        Box::new(Transformed::new(
+            Disk::new(0.6, 0.3),
+            translation(3.0, -0.4, -4.0) * rotation_x(0.5 * PI),
+        )),
+        // and the other shapes in front of them
+        Box::new(Transformed::new(
+            Torus::new(0.4, 0.15),
+            translation(-1.5, -0.52, -2.5) * rotation_x(0.3 * PI),
+        )),
+        Box::new(Transformed::new(
+            block,
+            translation(0.0, -0.6, -2.5) * rotation_y(0.2 * PI),
+        )),
+        Box::new(Transformed::new(column, translation(1.5, -0.5, -2.5))),
+        Box::new(Transformed::new(bulb, translation(3.0, -0.5, -2.5))),
+    ];
+    let lights: LightList = vec![
+        Box::new(PointLight::new(
+            point(-2.0, 3.0, -1.0),
+            Color::new(30.0, 28.0, 24.0),
+        )),
+        Box::new(DirectionalLight::new(
+            vector(1.0, -1.0, -0.5),
+            Color::new(0.3, 0.4, 0.7),
+            10.0,
+        )),
+    ];
+
+    (scene, lights)
+}
+
+/// The Utah teapot, from Newell's original Bézier patches, on a floor, or the
+/// patches in the file `patches` in its place.
+fn teapot_scene(patches: Option<&str>) -> (SceneList, LightList) {
+    let patches = match patches {
+        Some(path) => load_patches(path).unwrap_or_else(|err| {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(1);
+        }),
+        None => primitive::teapot(),
+    };
+    let teapot = BezierSurface::new(patches, 1.0e-3);
+    // the patches are z up, with the teapot 3 units across its body
+    let placement = translation(0.0, -1.0, -3.0)
+        * rotation_y(0.15 * PI)
+        * rotation_x(-0.5 * PI)
+        * scaling(0.6, 0.6, 0.6);
+
+    let scene: SceneList = vec![
+        Box::new(Triangle::new(
+            point(-20.0, -1.0, 10.0),
+            point(20.0, -1.0, -30.0),
+            point(-20.0, -1.0, -30.0),
+        )),
+        Box::new(Triangle::new(
+            point(-20.0, -1.0, 10.0),
+            point(20.0, -1.0, 10.0),
+            point(20.0, -1.0, -30.0),
+        )),
+        Box::new(Transformed::new(teapot, placement)),
+    ];
+    let lights: LightList = vec![
+        Box::new(PointLight::new(
+            point(-2.0, 2.0, -1.0),
+            Color::new(24.0, 20.0, 16.0),
+        )),
+        Box::new(DirectionalLight::new(
+            vector(1.0, -1.0, -0.5),
+            Color::new(0.3, 0.4, 0.7),
+            5.0,
+        )),
+    ];
+
+    (scene, lights)
+}
+
+/// The mesh in the file at `path`, scaled to about the size of the teapot
+/// and standing on a floor in the same place.
+fn mesh_scene(path: &str) -> (SceneList, LightList) {
+    let mesh = TriangleMesh::load(path).unwrap_or_else(|err| {
+        eprintln!("failed to read {}: {}", path, err);
+        process::exit(1);
+    });
+    if mesh.is_empty() {
+        eprintln!("{} has no triangles", path);
+        process::exit(1);
+    }
+
+    let bounds = mesh.bounds();
+    let center = bounds.min + bounds.diagonal() * 0.5;
+    let size = bounds.diagonal();
+    let scale = 2.0 / size.x.max(size.y).max(size.z);
+    let placement = translation(0.0, -1.0 + 0.5 * scale * size.y, -3.0)
+        * scaling(scale, scale, scale)
+        * translation(-center.x, -center.y, -center.z);
+    let mesh = Bvh::new(mesh.triangles());
+
+    let scene: SceneList = vec![
+        Box::new(Triangle::new(
+            point(-20.0, -1.0, 10.0),
+            point(20.0, -1.0, -30.0),
+            point(-20.0, -1.0, -30.0),
+        )),
+        Box::new(Triangle::new(
+            point(-20.0, -1.0, 10.0),
+            point(20.0, -1.0, 10.0),
+            point(20.0, -1.0, -30.0),
+        )),
+        Box::new(Transformed::new(mesh, placement)),
+    ];
+    let lights: LightList = vec![
+        Box::new(PointLight::new(
+            point(-2.0, 2.0, -1.0),
+            Color::new(24.0, 20.0, 16.0),
+        )),
+        Box::new(DirectionalLight::new(
+            vector(1.0, -1.0, -0.5),
+            Color::new(0.3, 0.4, 0.7),
+            5.0,
+        )),
+    ];
+
+    (scene, lights)
+}
+
+/// The meshes, lights and materials of a glTF file, and its first camera if
+/// it has one.
+fn gltf_scene(
+    path: &str,
+) -> (SceneList, LightList, MaterialList, Option<GltfCamera>) {
+    let gltf = GltfScene::load(path).unwrap_or_else(|err| {
+        eprintln!("failed to read {}: {}", path, err);
+        process::exit(1);
+    });
+
+    let mut scene: SceneList = vec![Box::new(Bvh::new(gltf.objects()))];
+    let mut lights = gltf.punctual_lights();
+    for light in gltf.area_lights() {
+        scene.push(Box::new(Arc::clone(&light)));
+        lights.push(Box::new(light));
+    }
+    // most files leave the lighting to whatever displays them
+    if lights.is_empty() {
+        let (_, radius) = gltf.bounds().bounding_sphere();
+        lights.push(Box::new(DirectionalLight::new(
+            vector(-1.0, -2.0, -1.0),
+            Color::new(1.0, 1.0, 1.0),
+            radius,
+        )));
+    }
+
+    let materials = gltf
+        .materials
+        .iter()
+        .map(|material| Box::new(material.clone()) as Box<dyn Material>)
+        .collect();
+    (scene, lights, materials, gltf.cameras.first().cloned())
+}
+
+/// The shapes, lights and materials of a pbrt file, and the view its
+/// camera, film and sampler describe.
+fn pbrt_scene(path: &str) -> (SceneList, LightList, MaterialList, View) {
+    let pbrt = PbrtScene::load(path).unwrap_or_else(|err| {
+        eprintln!("failed to read {}: {}", path, err);
+        process::exit(1);
+    });
+    for warning in pbrt.warnings.iter() {
+        eprintln!("{}: {}", path, warning);
+    }
+
+    let (width, height) = (pbrt.film.width, pbrt.film.height);
+    let view = View {
+        camera_to_world: pbrt.camera.camera_to_world,
+        fov: pbrt.camera.yfov(width, height),
+        width,
+        height,
+        pixel_samples: pbrt.pixel_samples,
+    };
+    let (scene, lights) = pbrt.build();
+    let materials = pbrt
+        .materials
+        .iter()
+        .map(|material| Box::new(material.clone()) as Box<dyn Material>)
+        .collect();
+    (scene, lights, materials, view)
+}
+
+fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+fn is_mesh(path: &str) -> bool {
+    let extension = extension(path);
+    extension == "ply" || extension == "stl"
+}
+
+fn is_gltf(path: &str) -> bool {
+    let extension = extension(path);
+    extension == "gltf" || extension == "glb"
+}
+
+fn is_pbrt(path: &str) -> bool {
+    extension(path) == "pbrt"
+}
+
+fn cornell_scene() -> (SceneList, LightList, MaterialList) {
+    let cornell = CornellBox::new();
+    let lights: LightList = vec![Box::new(cornell.light())];
+    let materials = cornell.materials();
+    (vec![Box::new(cornell)], lights, materials)
+}
+
+/// Where the image is seen from, and its size and sampling.
+struct View {
+    /// The camera looks down its -z axis with y up.
+    camera_to_world: DMat4,
+    /// The vertical field of view, in radians.
+    fov: f64,
+    width: u32,
+    height: u32,
+    pixel_samples: u32,
+}
+
+impl Default for View {
+    fn default() -> View {
+        View {
+            camera_to_world: DMat4::identity(),
+            fov: f64::to_radians(100.0),
+            width: 800,
+            height: 600,
+            pixel_samples: 1,
+        }
+    }
+}
 
 fn main() {
-    const IMAGE_WIDTH: u32 = 800;
-    const IMAGE_HEIGHT: u32 = 600;
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = parse_args(&args).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        process::exit(1);
+    });
+    let mut view = View::default();
+    let mut materials = MaterialList::new();
+    let (scene, mut lights) = match options.scene.as_str() {
+        "demo" => demo_scene(),
+        "cornell" => {
+            let (scene, lights, cornell_materials) = cornell_scene();
+            materials = cornell_materials;
+            (scene, lights)
+        }
+        "neon" => neon_scene(),
+        "instances" => instances_scene(),
+        "shapes" => shapes_scene(),
+        "teapot" => teapot_scene(options.patches.as_deref()),
+        path if is_mesh(path) => mesh_scene(path),
+        path if is_gltf(path) => {
+            let (scene, lights, gltf_materials, camera) = gltf_scene(path);
+            materials = gltf_materials;
+            // only cameras with perspective lend their field of view
+            if let Some(camera) = camera {
+                view.camera_to_world = camera.camera_to_world;
+                if let Projection::Perspective { yfov, .. } = camera.projection
+                {
+                    view.fov = yfov;
+                }
+            }
+            (scene, lights)
+        }
+        path if is_pbrt(path) => {
+            let (scene, lights, pbrt_materials, pbrt_view) =
+                pbrt_scene(path);
+            materials = pbrt_materials;
+            view = pbrt_view;
+            (scene, lights)
+        }
+        other => {
+            eprintln!("unknown scene '{}'\n\n{}", other, USAGE);
+            process::exit(1);
+        }
+    };
+    if let Some(path) = &options.ies {
+        let profile = IesProfile::load(path).unwrap_or_else(|err| {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(1);
+        });
+        let name = profile.keyword("LUMINAIRE").unwrap_or(path.as_str());
+        eprintln!("hanging luminaire {}", name);
+        // as bright in its brightest direction as the demo's key light
+        let max = profile.max_candela();
+        let scale = if max > 0.0 { 20.0 / max } else { 0.0 };
+        lights.push(Box::new(GoniometricLight::new(
+            point(0.0, 0.9, -2.0),
+            DMat4::identity(),
+            profile,
+            Color::new(scale, scale, scale),
+        )));
+    }
+    if let Some(path) = &options.environment {
+        let map = HdrImage::load(path).unwrap_or_else(|err| {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(1);
+        });
+        let rotation = DMat4::from_rotation_y(options.environment_rotation);
+        lights.push(Box::new(EnvironmentLight::new(map, rotation, 5.0)));
+    }
+    if let Some((elevation, azimuth)) = options.sun {
+        // the sky is in kcd/m^2, which would be far too bright unscaled
+        let sky = Sky::new(elevation, azimuth, options.turbidity)
+            .with_scale(0.02);
+        if let Some(path) = &options.bake_sky {
+            sky.bake(512, 256).save(path).unwrap_or_else(|err| {
+                eprintln!("failed to write {}: {}", path, err);
+                process::exit(1);
+            });
+        }
+        lights.push(Box::new(SunLight::new(&sky, 5.0)));
+        lights.push(Box::new(SkyLight::new(sky, 5.0)));
+    }
+    light::number_lights(&lights);
 
-    let mut image: RgbImage = ImageBuffer::new(IMAGE_WIDTH, IMAGE_HEIGHT);
+    // camera is facing in the -z direction, unless the scene places its own
+    let camera = camera::projection_matrix(view.fov, view.width, view.height);
+    let origin = view.camera_to_world * point(0.0, 0.0, 0.0);
+    let pixel_samples = options
+        .pixel_samples
+        .unwrap_or(view.pixel_samples)
+        .max(1);
 
-    // camera is facing in the -z direction
-    let fov = f64::to_radians(100.0);
-    let camera = camera::projection_matrix(fov, IMAGE_WIDTH, IMAGE_HEIGHT);
-    let origin = point(0.0, 0.0, 0.0);
+    // light tracing lands anywhere on the film, so the image can only be
+    // finished once every pixel has been sampled
+    let mut pixels = Vec::with_capacity((view.width * view.height) as usize);
+    let film = SplatBuffer::new(view.width, view.height);
+    let pinhole =
+        Camera::new(view.camera_to_world, view.fov, view.width, view.height);
 
-    //let scene = Sphere::new(point(0.0, 0.0, -3.0), 1.0);
-    let scene = Triangle::new(point(-1.0, 0.0, -3.0), point(1.0, 0.0, -1.0), point(0.0, 1.0, -3.0));
+    let picker = options.light_sampling.map(|strategy| LightPicker {
+        sampler: light_sampler(strategy, &lights),
+        samples: options.light_samples,
+    });
+    let path_tracer = match options.mode {
+        Mode::Path { max_bounces } | Mode::Mlt { max_bounces, .. } => {
+            // the path tracer always picks one light to sample
+            let strategy =
+                options.light_sampling.unwrap_or(LightSampling::Power);
+            Some(PathTracer::new(
+                &scene,
+                &lights,
+                &materials,
+                light_sampler(strategy, &lights),
+                max_bounces,
+            ))
+        }
+        _ => None,
+    };
+    let bdpt = match options.mode {
+        Mode::Bdpt { max_bounces } => Some(BidirectionalPathTracer::new(
+            &scene,
+            &lights,
+            &materials,
+            &pinhole,
+            max_bounces,
+        )),
+        _ => None,
+    };
+    let mut rng = StdRng::seed_from_u64(0);
+    let photon_mapper = match options.mode {
+        Mode::Photon {
+            max_bounces,
+            mapping,
+        } => Some(PhotonMapper::new(
+            &scene,
+            &lights,
+            &materials,
+            max_bounces,
+            mapping,
+            &mut rng,
+        )),
+        _ => None,
+    };
+    let sppm = match options.mode {
+        Mode::Sppm {
+            max_bounces,
+            photons,
+            radius,
+        } => Some(Sppm::new(
+            &scene,
+            &lights,
+            &materials,
+            max_bounces,
+            photons,
+            radius,
+        )),
+        _ => None,
+    };
 
-    for j in 0..IMAGE_HEIGHT {
-        for i in 0..IMAGE_WIDTH {
-            let dir = camera * vector(i as f64, j as f64, 1.0);
-            let ray = Ray::new(origin, dir);
-            let light_intensity = trace(ray, &scene);
-            let I = f64::floor(light_intensity * 255.0) as u8;
-            image[(i, j)] = Rgb([I, I, I]);
+    // progressive photon mapping and Metropolis light transport work on the
+    // whole image at once rather than one pixel at a time
+    let scale = 1.0 / pixel_samples as f64;
+    if let Some(sppm) = &sppm {
+        pixels = sppm.render(&pinhole, pixel_samples, &mut rng);
+    } else if let Mode::Mlt {
+        bootstrap,
+        chains,
+        large_steps,
+        ..
+    } = options.mode
+    {
+        let count = view.width as u64 * view.height as u64;
+        let mutations = pixel_samples as u64 * count;
+        let settings = MetropolisSettings {
+            bootstrap_samples: bootstrap,
+            chains,
+            mutations_per_chain: mutations / chains as u64,
+            large_step_probability: large_steps,
+            seed: 0,
+        };
+        let tracer = path_tracer.as_ref().unwrap();
+        let (image, stats) = mlt::render_image(&settings, &pinhole, tracer);
+        eprintln!(
+            "accepted {:.1}% of proposals: {:.1}% of small steps and {:.1}% \
+             of large steps",
+            100.0 * stats.acceptance_rate(),
+            100.0 * stats.small_step_acceptance_rate(),
+            100.0 * stats.large_step_acceptance_rate()
+        );
+        pixels = image;
+    } else {
+        for j in 0..view.height {
+            for i in 0..view.width {
+                let mut color = Color::black();
+                for _ in 0..pixel_samples {
+                    // a single sample goes through the middle of the pixel,
+                    // and more are jittered across it
+                    let (dx, dy) = if pixel_samples == 1 {
+                        (0.0, 0.0)
+                    } else {
+                        (rng.gen::<f64>() - 0.5, rng.gen::<f64>() - 0.5)
+                    };
+                    let pixel = vector(i as f64 + dx, j as f64 + dy, 1.0);
+                    let dir = view.camera_to_world * (camera * pixel);
+                    let ray = Ray::new(origin, dir);
+                    color += match &options.mode {
+                        Mode::Shaded => trace(
+                            &ray, &scene, &lights, &materials, &picker,
+                            &mut rng,
+                        ),
+                        Mode::Diagnostic(integrator) => {
+                            integrator.li(&ray, &scene, &mut rng)
+                        }
+                        Mode::Path { .. } => {
+                            path_tracer.as_ref().unwrap().li(&ray, &mut rng)
+                        }
+                        Mode::Bdpt { .. } => {
+                            let x = i as f64 + 0.5 + dx;
+                            let y = j as f64 + 0.5 + dy;
+                            bdpt.as_ref().unwrap().li(x, y, &film, &mut rng)
+                        }
+                        Mode::Photon { .. } => {
+                            photon_mapper.as_ref().unwrap().li(&ray, &mut rng)
+                        }
+                        Mode::Sppm { .. } | Mode::Mlt { .. } => {
+                            unreachable!()
+                        }
+                    };
+                }
+                pixels.push(color * scale);
+            }
         }
     }
 
+    let image: RgbImage = ImageBuffer::from_fn(view.width, view.height, |i, j| {
+        let color = pixels[(j * view.width + i) as usize];
+        (color + film.get(i, j) * scale).to_rgb()
+    });
     image.save("render.png").expect("Failed to write image");
 }
 
-const LIGHT: DVec4 = point(-1.0, -1.0, 0.0);
-const LIGHT_POWER: f64 = 200.0;
-const AMBIENT_LIGHT: f64 = 0.01;
+/// Chooses which lights to sample instead of sampling all of them.
+struct LightPicker {
+    sampler: Box<dyn LightSampler>,
+    samples: u32,
+}
+
+/// Light reflected towards the camera from the first surface `ray` hits,
+/// lit directly by every light in `lights`, or by those `picker` picks.
+fn trace<S, R>(
+    ray: &Ray,
+    scene: &S,
+    lights: &[Box<dyn Light>],
+    materials: &[Box<dyn Material>],
+    picker: &Option<LightPicker>,
+    rng: &mut R,
+) -> Color
+where
+    S: Scene + ?Sized,
+    R: Rng + ?Sized,
+{
+    let hit = match scene.intersect(ray) {
+        Some(hit) => hit,
+        None => {
+            let mut background = Color::black();
+            for light in lights {
+                background += light.le(ray);
+            }
+            return background;
+        }
+    };
+
+    // shade whichever side of the surface is facing the camera
+    let normal = if hit.shading_normal.dot(ray.direction) > 0.0 {
+        -hit.shading_normal
+    } else {
+        hit.shading_normal
+    };
+
+    let mut radiance = Color::black();
+    match picker {
+        None => {
+            for light in lights {
+                radiance += direct(&**light, hit.point, normal, scene, rng);
+            }
+        }
+        Some(picker) => {
+            for _ in 0..picker.samples {
+                let u = rng.gen();
+                let (index, pmf) =
+                    match picker.sampler.sample(hit.point, normal, u) {
+                        Some(picked) => picked,
+                        None => continue,
+                    };
+                let light = &*lights[index];
+                let weight = 1.0 / (pmf * picker.samples as f64);
+                radiance +=
+                    direct(light, hit.point, normal, scene, rng) * weight;
+            }
+        }
+    }
+
+    hit.emitted + radiance * material::albedo(materials, &hit)
+}
+
+/// Light from one sample of `light` reflected by a white diffuse surface at
+/// `point` with the unit normal `normal`.
+fn direct<S, R>(
+    light: &dyn Light,
+    point: Point3,
+    normal: Normal3,
+    scene: &S,
+    rng: &mut R,
+) -> Color
+where
+    S: Scene + ?Sized,
+    R: Rng + ?Sized,
+{
+    let sample = match light.sample_li(point, (rng.gen(), rng.gen())) {
+        Some(sample) => sample,
+        None => return Color::black(),
+    };
 
-// TODO: color encoding problem; light intensity is not bound to the range
-// [0.0, 1.0]. The image generation code assumes this in order to convert to the
-// range [0, 256) for image encoding, causing overflow errors in the final image
-// when the value goes above 255.
-fn trace(ray: Ray, scene: &impl Scene) -> f64 {
-    if let Some(normal) = scene.intersect(&ray) {
-        let light_vec = normal.origin - LIGHT; // point - point is a vector
-        let light_mag_sq = light_vec.mag_sq();
+    let cos = normal.dot(sample.wi);
+    if cos <= 0.0 || sample.pdf == 0.0 {
+        return Color::black();
+    }
 
-        let cos = normal.direction.dot(light_vec)
-            / (normal.direction.mag() * f64::sqrt(light_mag_sq));
-        let intensity = LIGHT_POWER * cos / (4.0 * PI * light_mag_sq);
-        return f64::max(intensity, AMBIENT_LIGHT);
+    // check for anything between the surface and the light
+    if !sample.unoccluded(scene, point) {
+        return Color::black();
     }
 
-    0.0
+    sample.radiance * (cos / (PI * sample.pdf))
 }
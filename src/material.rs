@@ -0,0 +1,71 @@
+use super::color::Color;
+use super::gltf::PbrMaterial;
+use super::pbrt::PbrtMaterial;
+use super::primitive::Intersection;
+use ultraviolet::DVec2;
+
+/// How a surface scatters light. Every surface is a diffuse reflector for
+/// now, so a material only decides how much of each color it reflects.
+pub trait Material {
+    /// The fraction of light reflected at the texture coordinates `uv`.
+    fn albedo(&self, uv: DVec2) -> Color;
+}
+
+/// The same albedo everywhere.
+impl Material for Color {
+    fn albedo(&self, _uv: DVec2) -> Color {
+        *self
+    }
+}
+
+/// glTF materials reflect their base color, as if they weren't metallic.
+impl Material for PbrMaterial {
+    fn albedo(&self, uv: DVec2) -> Color {
+        self.base_color_at(uv)
+    }
+}
+
+/// pbrt materials reflect their diffuse reflectance, whatever their type.
+impl Material for PbrtMaterial {
+    fn albedo(&self, uv: DVec2) -> Color {
+        self.reflectance.at(uv)
+    }
+}
+
+pub type MaterialList = Vec<Box<dyn Material>>;
+
+/// The albedo at `hit`, which is white for surfaces without a material.
+pub fn albedo(materials: &[Box<dyn Material>], hit: &Intersection) -> Color {
+    match hit.material {
+        Some(index) => materials[index].albedo(hit.uv),
+        None => Color::new(1.0, 1.0, 1.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::math::{normal, point};
+    use super::*;
+
+    #[test]
+    fn test_albedo() {
+        let materials: MaterialList = vec![
+            Box::new(Color::new(0.5, 0.25, 0.0)),
+            Box::new(PbrMaterial {
+                base_color: Color::new(0.0, 0.75, 1.0),
+                ..PbrMaterial::default()
+            }),
+        ];
+        let mut hit = Intersection::new(
+            1.0,
+            point(0.0, 0.0, 0.0),
+            normal(0.0, 0.0, 1.0),
+            DVec2::new(0.5, 0.5),
+        );
+        assert_eq!(albedo(&materials, &hit), Color::new(1.0, 1.0, 1.0));
+        hit.material = Some(0);
+        assert_eq!(albedo(&materials, &hit), Color::new(0.5, 0.25, 0.0));
+        hit.material = Some(1);
+        assert_eq!(albedo(&materials, &hit), Color::new(0.0, 0.75, 1.0));
+    }
+}
@@ -1,13 +1,378 @@
 use ultraviolet::vec::DVec4;
 use ultraviolet::mat::DMat4;
-use std::ops::{Add, Mul};
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul};
+use std::ops::{MulAssign, Neg, Sub, SubAssign};
 
-pub const fn vector(x: f64, y: f64, z: f64) -> DVec4 {
-    DVec4::new(x, y, z, 0.0)
+/// A position in space. Points can be moved by vectors, and subtracting two
+/// of them gives the vector between them, but they can't be added together
+/// or scaled.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Point3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
 }
 
-pub const fn point(x: f64, y: f64, z: f64) -> DVec4 {
-    DVec4::new(x, y, z, 1.0)
+/// A direction and length, such as the direction of a ray.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vector3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+/// A surface normal. Normals are kept apart from vectors because they
+/// transform differently, by the inverse transpose of the transform.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Normal3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+pub const fn point(x: f64, y: f64, z: f64) -> Point3 {
+    Point3 { x, y, z }
+}
+
+pub const fn vector(x: f64, y: f64, z: f64) -> Vector3 {
+    Vector3 { x, y, z }
+}
+
+pub const fn normal(x: f64, y: f64, z: f64) -> Normal3 {
+    Normal3 { x, y, z }
+}
+
+/// What all three kinds of triple have in common.
+macro_rules! impl_triple {
+    ($t:ident) => {
+        impl $t {
+            pub const fn new(x: f64, y: f64, z: f64) -> $t {
+                $t { x, y, z }
+            }
+
+            pub fn min_by_component(self, other: $t) -> $t {
+                $t::new(
+                    self.x.min(other.x),
+                    self.y.min(other.y),
+                    self.z.min(other.z),
+                )
+            }
+
+            pub fn max_by_component(self, other: $t) -> $t {
+                $t::new(
+                    self.x.max(other.x),
+                    self.y.max(other.y),
+                    self.z.max(other.z),
+                )
+            }
+
+            pub fn component_max(self) -> f64 {
+                self.x.max(self.y).max(self.z)
+            }
+
+            pub fn abs(self) -> $t {
+                $t::new(self.x.abs(), self.y.abs(), self.z.abs())
+            }
+        }
+
+        impl Index<usize> for $t {
+            type Output = f64;
+
+            fn index(&self, axis: usize) -> &f64 {
+                match axis {
+                    0 => &self.x,
+                    1 => &self.y,
+                    2 => &self.z,
+                    _ => panic!("axis {} out of range", axis),
+                }
+            }
+        }
+
+        impl IndexMut<usize> for $t {
+            fn index_mut(&mut self, axis: usize) -> &mut f64 {
+                match axis {
+                    0 => &mut self.x,
+                    1 => &mut self.y,
+                    2 => &mut self.z,
+                    _ => panic!("axis {} out of range", axis),
+                }
+            }
+        }
+    };
+}
+
+/// The operations of a direction, which vectors and normals share.
+macro_rules! impl_direction {
+    ($t:ident) => {
+        impl $t {
+            pub fn dot<V: Into<Vector3>>(self, other: V) -> f64 {
+                let other = other.into();
+                self.x * other.x + self.y * other.y + self.z * other.z
+            }
+
+            pub fn mag_sq(self) -> f64 {
+                self.x * self.x + self.y * self.y + self.z * self.z
+            }
+
+            pub fn mag(self) -> f64 {
+                self.mag_sq().sqrt()
+            }
+
+            pub fn normalized(self) -> $t {
+                self / self.mag()
+            }
+
+            /// `self` or its negation, whichever is on the same side as `v`.
+            pub fn face_forward<V: Into<Vector3>>(self, v: V) -> $t {
+                if self.dot(v) < 0.0 {
+                    -self
+                } else {
+                    self
+                }
+            }
+        }
+
+        impl Neg for $t {
+            type Output = $t;
+
+            fn neg(self) -> $t {
+                $t::new(-self.x, -self.y, -self.z)
+            }
+        }
+
+        impl Add for $t {
+            type Output = $t;
+
+            fn add(self, rhs: $t) -> $t {
+                $t::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+            }
+        }
+
+        impl Sub for $t {
+            type Output = $t;
+
+            fn sub(self, rhs: $t) -> $t {
+                $t::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+            }
+        }
+
+        impl AddAssign for $t {
+            fn add_assign(&mut self, rhs: $t) {
+                *self = *self + rhs;
+            }
+        }
+
+        impl SubAssign for $t {
+            fn sub_assign(&mut self, rhs: $t) {
+                *self = *self - rhs;
+            }
+        }
+
+        impl Mul<f64> for $t {
+            type Output = $t;
+
+            fn mul(self, rhs: f64) -> $t {
+                $t::new(self.x * rhs, self.y * rhs, self.z * rhs)
+            }
+        }
+
+        impl Mul<$t> for f64 {
+            type Output = $t;
+
+            fn mul(self, rhs: $t) -> $t {
+                rhs * self
+            }
+        }
+
+        impl MulAssign<f64> for $t {
+            fn mul_assign(&mut self, rhs: f64) {
+                *self = *self * rhs;
+            }
+        }
+
+        impl Div<f64> for $t {
+            type Output = $t;
+
+            fn div(self, rhs: f64) -> $t {
+                $t::new(self.x / rhs, self.y / rhs, self.z / rhs)
+            }
+        }
+
+        impl DivAssign<f64> for $t {
+            fn div_assign(&mut self, rhs: f64) {
+                *self = *self / rhs;
+            }
+        }
+    };
+}
+
+impl_triple!(Point3);
+impl_triple!(Vector3);
+impl_triple!(Normal3);
+impl_direction!(Vector3);
+impl_direction!(Normal3);
+
+impl Point3 {
+    pub const fn origin() -> Point3 {
+        Point3::new(0.0, 0.0, 0.0)
+    }
+
+    pub fn distance(self, other: Point3) -> f64 {
+        (self - other).mag()
+    }
+
+    pub fn distance_sq(self, other: Point3) -> f64 {
+        (self - other).mag_sq()
+    }
+
+    /// The point a fraction `t` of the way from `self` to `other`.
+    pub fn lerp(self, other: Point3, t: f64) -> Point3 {
+        self + (other - self) * t
+    }
+}
+
+impl Vector3 {
+    pub fn cross(self, other: Vector3) -> Vector3 {
+        Vector3::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    /// Multiply component by component.
+    pub fn mul_by_component(self, other: Vector3) -> Vector3 {
+        Vector3::new(self.x * other.x, self.y * other.y, self.z * other.z)
+    }
+}
+
+impl From<Normal3> for Vector3 {
+    fn from(n: Normal3) -> Vector3 {
+        Vector3::new(n.x, n.y, n.z)
+    }
+}
+
+impl From<Vector3> for Normal3 {
+    fn from(v: Vector3) -> Normal3 {
+        Normal3::new(v.x, v.y, v.z)
+    }
+}
+
+impl Add<Vector3> for Point3 {
+    type Output = Point3;
+
+    fn add(self, rhs: Vector3) -> Point3 {
+        Point3::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
+impl AddAssign<Vector3> for Point3 {
+    fn add_assign(&mut self, rhs: Vector3) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Vector3> for Point3 {
+    type Output = Point3;
+
+    fn sub(self, rhs: Vector3) -> Point3 {
+        Point3::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+impl Sub for Point3 {
+    type Output = Vector3;
+
+    fn sub(self, rhs: Point3) -> Vector3 {
+        Vector3::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+/// Points are affected by translation.
+impl Mul<Point3> for DMat4 {
+    type Output = Point3;
+
+    fn mul(self, p: Point3) -> Point3 {
+        let h = self * DVec4::new(p.x, p.y, p.z, 1.0);
+        if h.w == 1.0 {
+            Point3::new(h.x, h.y, h.z)
+        } else {
+            Point3::new(h.x / h.w, h.y / h.w, h.z / h.w)
+        }
+    }
+}
+
+/// Vectors aren't affected by translation.
+impl Mul<Vector3> for DMat4 {
+    type Output = Vector3;
+
+    fn mul(self, v: Vector3) -> Vector3 {
+        let h = self * DVec4::new(v.x, v.y, v.z, 0.0);
+        Vector3::new(h.x, h.y, h.z)
+    }
+}
+
+/// A transform together with its inverse, which is what it takes to apply it
+/// to normals as well as to points and vectors. There is deliberately no way
+/// to multiply a matrix by a normal directly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    matrix: DMat4,
+    inverse: DMat4,
+}
+
+impl Transform {
+    pub fn new(matrix: DMat4) -> Transform {
+        Transform {
+            matrix,
+            inverse: matrix.inversed(),
+        }
+    }
+
+    pub fn matrix(&self) -> DMat4 {
+        self.matrix
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn point(&self, p: Point3) -> Point3 {
+        self.matrix * p
+    }
+
+    pub fn vector(&self, v: Vector3) -> Vector3 {
+        self.matrix * v
+    }
+
+    /// Transform a normal by the inverse transpose, which keeps it
+    /// perpendicular to the surface under non-uniform scaling and shear. The
+    /// result isn't normalized.
+    pub fn normal(&self, n: Normal3) -> Normal3 {
+        let h = self.inverse.transposed() * DVec4::new(n.x, n.y, n.z, 0.0);
+        Normal3::new(h.x, h.y, h.z)
+    }
+
+    /// Transform a ray without normalizing its direction, so that distances
+    /// along it stay the same.
+    pub fn ray(&self, ray: &Ray) -> Ray {
+        Ray::new(self.point(ray.origin), self.vector(ray.direction))
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    /// The transform that applies `rhs` and then `self`.
+    fn mul(self, rhs: Transform) -> Transform {
+        Transform {
+            matrix: self.matrix * rhs.matrix,
+            inverse: rhs.inverse * self.inverse,
+        }
+    }
 }
 
 pub const fn translation(x: f64, y: f64, z: f64) -> DMat4 {
@@ -26,18 +391,403 @@ pub const fn scaling(x: f64, y: f64, z: f64) -> DMat4 {
     DMat4::new(c0, c1, c2, c3)
 }
 
+/// Rotate by `r` radians around the x axis, turning y towards z.
+pub fn rotation_x(r: f64) -> DMat4 {
+    let (sin, cos) = r.sin_cos();
+    let c0 = DVec4::new(1.0, 0.0, 0.0, 0.0);
+    let c1 = DVec4::new(0.0, cos, sin, 0.0);
+    let c2 = DVec4::new(0.0, -sin, cos, 0.0);
+    let c3 = DVec4::new(0.0, 0.0, 0.0, 1.0);
+    DMat4::new(c0, c1, c2, c3)
+}
+
+/// Rotate by `r` radians around the y axis, turning z towards x.
+pub fn rotation_y(r: f64) -> DMat4 {
+    let (sin, cos) = r.sin_cos();
+    let c0 = DVec4::new(cos, 0.0, -sin, 0.0);
+    let c1 = DVec4::new(0.0, 1.0, 0.0, 0.0);
+    let c2 = DVec4::new(sin, 0.0, cos, 0.0);
+    let c3 = DVec4::new(0.0, 0.0, 0.0, 1.0);
+    DMat4::new(c0, c1, c2, c3)
+}
+
+/// Rotate by `r` radians around the z axis, turning x towards y.
+pub fn rotation_z(r: f64) -> DMat4 {
+    let (sin, cos) = r.sin_cos();
+    let c0 = DVec4::new(cos, sin, 0.0, 0.0);
+    let c1 = DVec4::new(-sin, cos, 0.0, 0.0);
+    let c2 = DVec4::new(0.0, 0.0, 1.0, 0.0);
+    let c3 = DVec4::new(0.0, 0.0, 0.0, 1.0);
+    DMat4::new(c0, c1, c2, c3)
+}
+
+/// Rotate by `r` radians around `axis`, counterclockwise when looking down
+/// the axis towards the origin. The axis doesn't need to be normalized.
+pub fn rotation(axis: Vector3, r: f64) -> DMat4 {
+    Quaternion::from_axis_angle(axis, r).to_matrix()
+}
+
+/// Move each coordinate in proportion to the other two: `xy` is how much x
+/// moves in proportion to y, and so on.
+#[cfg(test)]
+pub const fn shearing(
+    xy: f64,
+    xz: f64,
+    yx: f64,
+    yz: f64,
+    zx: f64,
+    zy: f64,
+) -> DMat4 {
+    let c0 = DVec4::new(1.0, yx, zx, 0.0);
+    let c1 = DVec4::new(xy, 1.0, zy, 0.0);
+    let c2 = DVec4::new(xz, yz, 1.0, 0.0);
+    let c3 = DVec4::new(0.0, 0.0, 0.0, 1.0);
+    DMat4::new(c0, c1, c2, c3)
+}
+
+/// The world to camera transform for a camera at `from` looking at `to`.
+/// The camera looks down its negative z axis with `up` roughly along its
+/// positive y axis, like the one in `camera.rs`.
+#[cfg(test)]
+pub fn view_transform(from: Point3, to: Point3, up: Vector3) -> DMat4 {
+    let forward = (to - from).normalized();
+    let right = forward.cross(up.normalized());
+    let true_up = right.cross(forward);
+
+    // the rows are the camera's axes in world space
+    let c0 = DVec4::new(right.x, true_up.x, -forward.x, 0.0);
+    let c1 = DVec4::new(right.y, true_up.y, -forward.y, 0.0);
+    let c2 = DVec4::new(right.z, true_up.z, -forward.z, 0.0);
+    let c3 = DVec4::new(0.0, 0.0, 0.0, 1.0);
+    DMat4::new(c0, c1, c2, c3) * translation(-from.x, -from.y, -from.z)
+}
+
+/// The camera to world transform for a camera at `from` looking at `to`,
+/// which is the inverse of `view_transform`. This is what places an object
+/// at `from` facing `to`.
+#[cfg(test)]
+pub fn look_at(from: Point3, to: Point3, up: Vector3) -> DMat4 {
+    view_transform(from, to, up).inversed()
+}
+
+/// A rotation, stored as a unit quaternion w + xi + yj + zk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub const fn new(w: f64, x: f64, y: f64, z: f64) -> Quaternion {
+        Quaternion { w, x, y, z }
+    }
+
+    pub const fn identity() -> Quaternion {
+        Quaternion::new(1.0, 0.0, 0.0, 0.0)
+    }
+
+    /// The rotation by `r` radians around `axis`.
+    pub fn from_axis_angle(axis: Vector3, r: f64) -> Quaternion {
+        let axis = axis.normalized();
+        let (sin, cos) = (r / 2.0).sin_cos();
+        Quaternion::new(cos, axis.x * sin, axis.y * sin, axis.z * sin)
+    }
+
+    /// The rotation held in the upper left 3x3 part of `m`, which must be a
+    /// rotation matrix (Shepperd's method).
+    #[cfg(test)]
+    pub fn from_matrix(m: DMat4) -> Quaternion {
+        let e = |row: usize, col: usize| m.cols[col][row];
+        let trace = e(0, 0) + e(1, 1) + e(2, 2);
+
+        // divide by the largest of the four components to stay accurate
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            Quaternion::new(
+                s / 4.0,
+                (e(2, 1) - e(1, 2)) / s,
+                (e(0, 2) - e(2, 0)) / s,
+                (e(1, 0) - e(0, 1)) / s,
+            )
+        } else if e(0, 0) > e(1, 1) && e(0, 0) > e(2, 2) {
+            let s = (1.0 + e(0, 0) - e(1, 1) - e(2, 2)).sqrt() * 2.0;
+            Quaternion::new(
+                (e(2, 1) - e(1, 2)) / s,
+                s / 4.0,
+                (e(0, 1) + e(1, 0)) / s,
+                (e(0, 2) + e(2, 0)) / s,
+            )
+        } else if e(1, 1) > e(2, 2) {
+            let s = (1.0 + e(1, 1) - e(0, 0) - e(2, 2)).sqrt() * 2.0;
+            Quaternion::new(
+                (e(0, 2) - e(2, 0)) / s,
+                (e(0, 1) + e(1, 0)) / s,
+                s / 4.0,
+                (e(1, 2) + e(2, 1)) / s,
+            )
+        } else {
+            let s = (1.0 + e(2, 2) - e(0, 0) - e(1, 1)).sqrt() * 2.0;
+            Quaternion::new(
+                (e(1, 0) - e(0, 1)) / s,
+                (e(0, 2) + e(2, 0)) / s,
+                (e(1, 2) + e(2, 1)) / s,
+                s / 4.0,
+            )
+        };
+        q.normalized()
+    }
+
+    pub fn to_matrix(self) -> DMat4 {
+        let Quaternion { w, x, y, z } = self;
+        let c0 = DVec4::new(
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y + w * z),
+            2.0 * (x * z - w * y),
+            0.0,
+        );
+        let c1 = DVec4::new(
+            2.0 * (x * y - w * z),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z + w * x),
+            0.0,
+        );
+        let c2 = DVec4::new(
+            2.0 * (x * z + w * y),
+            2.0 * (y * z - w * x),
+            1.0 - 2.0 * (x * x + y * y),
+            0.0,
+        );
+        let c3 = DVec4::new(0.0, 0.0, 0.0, 1.0);
+        DMat4::new(c0, c1, c2, c3)
+    }
+
+    pub fn dot(&self, other: Quaternion) -> f64 {
+        self.w * other.w
+            + self.x * other.x
+            + self.y * other.y
+            + self.z * other.z
+    }
+
+    pub fn normalized(&self) -> Quaternion {
+        let mag = self.dot(*self).sqrt();
+        Quaternion::new(self.w / mag, self.x / mag, self.y / mag, self.z / mag)
+    }
+
+    /// The opposite rotation.
+    #[cfg(test)]
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    /// Rotate the vector `v`.
+    #[cfg(test)]
+    pub fn rotate(&self, v: Vector3) -> Vector3 {
+        let p = Quaternion::new(0.0, v.x, v.y, v.z);
+        let r = *self * p * self.conjugate();
+        Vector3::new(r.x, r.y, r.z)
+    }
+
+    /// Spherical linear interpolation, turning at a constant rate from
+    /// `self` at t = 0 to `other` at t = 1 the short way around.
+    #[cfg(test)]
+    pub fn slerp(&self, other: Quaternion, t: f64) -> Quaternion {
+        let mut cos = self.dot(other);
+        let mut other = other;
+        if cos < 0.0 {
+            // q and -q are the same rotation, but only one is the short way
+            other = Quaternion::new(-other.w, -other.x, -other.y, -other.z);
+            cos = -cos;
+        }
+
+        let (a, b) = if cos > 0.9995 {
+            // nearly parallel, where linear interpolation is just as good and
+            // dividing by sin(theta) isn't
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        Quaternion::new(
+            a * self.w + b * other.w,
+            a * self.x + b * other.x,
+            a * self.y + b * other.y,
+            a * self.z + b * other.z,
+        )
+        .normalized()
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    /// The rotation by `rhs` followed by the rotation by `self`.
+    fn mul(self, rhs: Quaternion) -> Quaternion {
+        let (a, b) = (self, rhs);
+        Quaternion::new(
+            a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        )
+    }
+}
+
+/// A transform split into a scale, followed by a rotation, followed by a
+/// translation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trs {
+    pub translation: Vector3,
+    pub rotation: Quaternion,
+    pub scale: Vector3,
+}
+
+impl Trs {
+    pub fn to_matrix(self) -> DMat4 {
+        let t = self.translation;
+        let s = self.scale;
+        translation(t.x, t.y, t.z)
+            * self.rotation.to_matrix()
+            * scaling(s.x, s.y, s.z)
+    }
+
+    /// Blend two transforms, interpolating translation and scale linearly
+    /// and rotation with `Quaternion::slerp`, as for animation keyframes.
+    #[cfg(test)]
+    pub fn interpolate(&self, other: &Trs, t: f64) -> Trs {
+        Trs {
+            translation: self.translation * (1.0 - t) + other.translation * t,
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale * (1.0 - t) + other.scale * t,
+        }
+    }
+}
+
+/// Split an affine transform into translation, rotation and scale. Any shear
+/// is lost, and `None` is returned if the transform flattens space.
+#[cfg(test)]
+pub fn decompose(m: DMat4) -> Option<Trs> {
+    let column = |i: usize| Vector3::new(m.cols[i].x, m.cols[i].y, m.cols[i].z);
+    let translation = column(3);
+    let axes = [column(0), column(1), column(2)];
+    let mut scale =
+        Vector3::new(axes[0].mag(), axes[1].mag(), axes[2].mag());
+    if scale.x == 0.0 || scale.y == 0.0 || scale.z == 0.0 {
+        return None;
+    }
+
+    // a mirror image can't be a rotation, so put it in the scale instead
+    if axes[0].cross(axes[1]).dot(axes[2]) < 0.0 {
+        scale.x = -scale.x;
+    }
+
+    let mut rotation = DMat4::identity();
+    for axis in 0..3 {
+        let a = axes[axis] / scale[axis];
+        rotation.cols[axis] = DVec4::new(a.x, a.y, a.z, 0.0);
+    }
+
+    Some(Trs {
+        translation,
+        rotation: Quaternion::from_matrix(rotation),
+        scale,
+    })
+}
+
+/// Builds up a transform one step at a time, with each step applied after
+/// the ones before it:
+///
+/// ```ignore
+/// let m = TransformBuilder::new()
+///     .rotate_y(PI / 4.0)
+///     .translate(0.0, 0.0, -5.0)
+///     .build();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransformBuilder {
+    matrix: DMat4,
+}
+
+impl TransformBuilder {
+    pub fn new() -> TransformBuilder {
+        TransformBuilder {
+            matrix: DMat4::identity(),
+        }
+    }
+
+    /// Apply `m` after the transform built so far.
+    pub fn transform(self, m: DMat4) -> TransformBuilder {
+        TransformBuilder {
+            matrix: m * self.matrix,
+        }
+    }
+
+    pub fn translate(self, x: f64, y: f64, z: f64) -> TransformBuilder {
+        self.transform(translation(x, y, z))
+    }
+
+    #[cfg(test)]
+    pub fn scale(self, x: f64, y: f64, z: f64) -> TransformBuilder {
+        self.transform(scaling(x, y, z))
+    }
+
+    #[cfg(test)]
+    pub fn rotate_x(self, r: f64) -> TransformBuilder {
+        self.transform(rotation_x(r))
+    }
+
+    pub fn rotate_y(self, r: f64) -> TransformBuilder {
+        self.transform(rotation_y(r))
+    }
+
+    #[cfg(test)]
+    pub fn rotate_z(self, r: f64) -> TransformBuilder {
+        self.transform(rotation_z(r))
+    }
+
+    #[cfg(test)]
+    pub fn rotate(self, axis: Vector3, r: f64) -> TransformBuilder {
+        self.transform(rotation(axis, r))
+    }
+
+    #[cfg(test)]
+    pub fn shear(
+        self,
+        xy: f64,
+        xz: f64,
+        yx: f64,
+        yz: f64,
+        zx: f64,
+        zy: f64,
+    ) -> TransformBuilder {
+        self.transform(shearing(xy, xz, yx, yz, zx, zy))
+    }
+
+    pub fn build(self) -> DMat4 {
+        self.matrix
+    }
+}
+
+impl Default for TransformBuilder {
+    fn default() -> TransformBuilder {
+        TransformBuilder::new()
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Ray {
-    pub origin: DVec4,
-    pub direction: DVec4,
+    pub origin: Point3,
+    pub direction: Vector3,
 }
 
 impl Ray {
-    pub fn new(origin: DVec4, direction: DVec4) -> Ray {
+    pub fn new(origin: Point3, direction: Vector3) -> Ray {
         Ray { origin, direction }
     }
 
-    pub fn position(&self, t: f64) -> DVec4 {
+    pub fn position(&self, t: f64) -> Point3 {
         self.origin + self.direction * t
     }
 }
@@ -45,12 +795,16 @@ impl Ray {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::test_util::assert_eps_eq;
+    use std::f64::consts::PI;
+
+    const EPS: f64 = 1.0e-9;
 
     #[test]
     fn test_create_ray() {
         let origin = point(1.0, 2.0, 3.0);
         let direction = vector(4.0, 5.0, 6.0);
-        let ray = Ray::new(origin.clone(), direction.clone());
+        let ray = Ray::new(origin, direction);
         assert_eq!(ray.origin, origin);
         assert_eq!(ray.direction, direction);
     }
@@ -83,7 +837,7 @@ mod tests {
 
     #[test]
     fn test_translation_vector_unchanged() {
-        let mut transform = translation(5.0, -3.0, 2.0);
+        let transform = translation(5.0, -3.0, 2.0);
         let v = vector(-3.0, 4.0, 5.0);
         assert_eq!(transform * v, v);
     }
@@ -109,12 +863,288 @@ mod tests {
         let v = vector(-4.0, 6.0, 8.0);
         assert_eq!(transform * v, vector(-2.0, 2.0, 2.0));
     }
+
+    #[test]
+    fn test_point_and_vector_arithmetic() {
+        let p = point(3.0, 2.0, 1.0);
+        let q = point(5.0, 6.0, 7.0);
+        assert_eq!(p - q, vector(-2.0, -4.0, -6.0));
+        assert_eq!(p + vector(1.0, 1.0, 1.0), point(4.0, 3.0, 2.0));
+        assert_eq!(p - vector(1.0, 1.0, 1.0), point(2.0, 1.0, 0.0));
+        assert_eq!(p.lerp(q, 0.5), point(4.0, 4.0, 4.0));
+        assert_eq!(q.distance_sq(p), 56.0);
+
+        let v = vector(1.0, 2.0, 3.0);
+        let w = vector(2.0, 3.0, 4.0);
+        assert_eq!(v.dot(w), 20.0);
+        assert_eq!(v.cross(w), vector(-1.0, 2.0, -1.0));
+        assert_eq!(w.cross(v), vector(1.0, -2.0, 1.0));
+        assert_eq!(-v * 2.0, vector(-2.0, -4.0, -6.0));
+        assert_eq!(vector(0.0, 3.0, 4.0).mag(), 5.0);
+        assert_eq!(v[1], 2.0);
+
+        let n = normal(0.0, 0.0, 1.0);
+        assert_eq!(n.dot(v), 3.0);
+        assert_eq!(n.face_forward(vector(0.0, 0.0, -1.0)), -n);
+        assert_eq!(Vector3::from(n), vector(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_transform_normal() {
+        // a plane tilted 45 degrees, then squashed along x, tilts further
+        let transform = Transform::new(scaling(0.5, 1.0, 1.0));
+        let n = normal(1.0, 1.0, 0.0);
+        let transformed = transform.normal(n).normalized();
+        assert_eps_eq(&transformed, &normal(2.0, 1.0, 0.0).normalized(), EPS);
+
+        // the normal stays perpendicular to vectors along the surface
+        let along = vector(1.0, -1.0, 0.0);
+        assert_eps_eq(&transformed.dot(transform.vector(along)), &0.0, EPS);
+    }
+
+    #[test]
+    fn test_transform_inverse_and_ray() {
+        let transform =
+            Transform::new(translation(1.0, 2.0, 3.0) * scaling(2.0, 2.0, 2.0));
+        let p = point(1.0, 1.0, 1.0);
+        assert_eps_eq(&transform.inverse().point(transform.point(p)), &p, EPS);
+        let both = transform * transform.inverse();
+        assert_eps_eq(&both.matrix(), &DMat4::identity(), EPS);
+
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, 1.0));
+        let moved = transform.ray(&ray);
+        assert_eq!(moved.origin, point(1.0, 2.0, 3.0));
+        assert_eq!(moved.direction, vector(0.0, 0.0, 2.0));
+    }
+
+    #[test]
+    fn test_rotation_x_point() {
+        let p = point(0.0, 1.0, 0.0);
+        let half_quarter = rotation_x(PI / 4.0);
+        let full_quarter = rotation_x(PI / 2.0);
+        let s = f64::sqrt(2.0) / 2.0;
+        assert_eps_eq(&(half_quarter * p), &point(0.0, s, s), EPS);
+        assert_eps_eq(&(full_quarter * p), &point(0.0, 0.0, 1.0), EPS);
+    }
+
+    #[test]
+    fn test_rotation_x_inverse() {
+        let mut transform = rotation_x(PI / 4.0);
+        transform.inverse();
+        let p = point(0.0, 1.0, 0.0);
+        let s = f64::sqrt(2.0) / 2.0;
+        assert_eps_eq(&(transform * p), &point(0.0, s, -s), EPS);
+    }
+
+    #[test]
+    fn test_rotation_y_point() {
+        let p = point(0.0, 0.0, 1.0);
+        let half_quarter = rotation_y(PI / 4.0);
+        let full_quarter = rotation_y(PI / 2.0);
+        let s = f64::sqrt(2.0) / 2.0;
+        assert_eps_eq(&(half_quarter * p), &point(s, 0.0, s), EPS);
+        assert_eps_eq(&(full_quarter * p), &point(1.0, 0.0, 0.0), EPS);
+    }
+
+    #[test]
+    fn test_rotation_z_point() {
+        let p = point(0.0, 1.0, 0.0);
+        let half_quarter = rotation_z(PI / 4.0);
+        let full_quarter = rotation_z(PI / 2.0);
+        let s = f64::sqrt(2.0) / 2.0;
+        assert_eps_eq(&(half_quarter * p), &point(-s, s, 0.0), EPS);
+        assert_eps_eq(&(full_quarter * p), &point(-1.0, 0.0, 0.0), EPS);
+    }
+
+    #[test]
+    fn test_rotation_axis() {
+        let r = 0.7;
+        assert_eps_eq(&rotation(vector(2.0, 0.0, 0.0), r), &rotation_x(r), EPS);
+        assert_eps_eq(&rotation(vector(0.0, 1.0, 0.0), r), &rotation_y(r), EPS);
+        assert_eps_eq(&rotation(vector(0.0, 0.0, 1.0), r), &rotation_z(r), EPS);
+
+        // a third of a turn around the diagonal cycles the axes
+        let transform = rotation(vector(1.0, 1.0, 1.0), 2.0 * PI / 3.0);
+        let v = vector(1.0, 0.0, 0.0);
+        assert_eps_eq(&(transform * v), &vector(0.0, 1.0, 0.0), EPS);
+    }
+
+    #[test]
+    fn test_shearing_point() {
+        let p = point(2.0, 3.0, 4.0);
+        let cases = [
+            (shearing(1.0, 0.0, 0.0, 0.0, 0.0, 0.0), point(5.0, 3.0, 4.0)),
+            (shearing(0.0, 1.0, 0.0, 0.0, 0.0, 0.0), point(6.0, 3.0, 4.0)),
+            (shearing(0.0, 0.0, 1.0, 0.0, 0.0, 0.0), point(2.0, 5.0, 4.0)),
+            (shearing(0.0, 0.0, 0.0, 1.0, 0.0, 0.0), point(2.0, 7.0, 4.0)),
+            (shearing(0.0, 0.0, 0.0, 0.0, 1.0, 0.0), point(2.0, 3.0, 6.0)),
+            (shearing(0.0, 0.0, 0.0, 0.0, 0.0, 1.0), point(2.0, 3.0, 7.0)),
+        ];
+        for (transform, expected) in cases.iter() {
+            assert_eq!(*transform * p, *expected);
+        }
+    }
+
+    #[test]
+    fn test_view_transform_default() {
+        let from = point(0.0, 0.0, 0.0);
+        let to = point(0.0, 0.0, -1.0);
+        let up = vector(0.0, 1.0, 0.0);
+        assert_eps_eq(&view_transform(from, to, up), &DMat4::identity(), EPS);
+    }
+
+    #[test]
+    fn test_view_transform_positive_z() {
+        let from = point(0.0, 0.0, 0.0);
+        let to = point(0.0, 0.0, 1.0);
+        let up = vector(0.0, 1.0, 0.0);
+        let expected = scaling(-1.0, 1.0, -1.0);
+        assert_eps_eq(&view_transform(from, to, up), &expected, EPS);
+    }
+
+    #[test]
+    fn test_view_transform_moves_world() {
+        let from = point(0.0, 0.0, 8.0);
+        let to = point(0.0, 0.0, 0.0);
+        let up = vector(0.0, 1.0, 0.0);
+        let expected = translation(0.0, 0.0, -8.0);
+        assert_eps_eq(&view_transform(from, to, up), &expected, EPS);
+    }
+
+    #[test]
+    fn test_view_transform_arbitrary() {
+        let from = point(1.0, 3.0, 2.0);
+        let to = point(4.0, -2.0, 8.0);
+        let up = vector(1.0, 1.0, 0.0);
+        let expected = DMat4::new(
+            DVec4::new(-0.50709, 0.76772, -0.35857, 0.0),
+            DVec4::new(0.50709, 0.60609, 0.59761, 0.0),
+            DVec4::new(0.67612, 0.12122, -0.71714, 0.0),
+            DVec4::new(-2.36643, -2.82843, 0.0, 1.0),
+        );
+        assert_eps_eq(&view_transform(from, to, up), &expected, 1.0e-5);
+    }
+
+    #[test]
+    fn test_look_at() {
+        let from = point(1.0, 3.0, 2.0);
+        let to = point(4.0, -2.0, 8.0);
+        let transform = look_at(from, to, vector(1.0, 1.0, 0.0));
+        assert_eps_eq(&(transform * point(0.0, 0.0, 0.0)), &from, EPS);
+        let forward = (to - from).normalized();
+        assert_eps_eq(&(transform * vector(0.0, 0.0, -1.0)), &forward, EPS);
+    }
+
+    #[test]
+    fn test_quaternion_rotation() {
+        let q = Quaternion::from_axis_angle(vector(0.0, 0.0, 1.0), PI / 2.0);
+        let p = vector(1.0, 2.0, 3.0);
+        assert_eps_eq(&q.rotate(p), &vector(-2.0, 1.0, 3.0), EPS);
+        assert_eps_eq(&q.to_matrix(), &rotation_z(PI / 2.0), EPS);
+
+        // composing rotations multiplies them
+        let r = Quaternion::from_axis_angle(vector(1.0, 0.0, 0.0), 0.3);
+        let expected = q.to_matrix() * r.to_matrix();
+        assert_eps_eq(&(q * r).to_matrix(), &expected, EPS);
+        assert_eps_eq(&q.conjugate().rotate(q.rotate(p)), &p, EPS);
+    }
+
+    #[test]
+    fn test_quaternion_from_matrix() {
+        let axes = [
+            vector(1.0, 0.0, 0.0),
+            vector(0.0, -1.0, 0.0),
+            vector(0.0, 0.0, 1.0),
+            vector(1.0, 2.0, -3.0),
+        ];
+        // include angles near a half turn, where the trace is negative
+        for &axis in axes.iter() {
+            for &r in [0.0, 0.5, 2.0, 3.1].iter() {
+                let m = rotation(axis, r);
+                let q = Quaternion::from_matrix(m);
+                assert_eps_eq(&q.to_matrix(), &m, EPS);
+            }
+        }
+    }
+
+    #[test]
+    fn test_slerp() {
+        let a = Quaternion::identity();
+        let b = Quaternion::from_axis_angle(vector(0.0, 1.0, 0.0), PI / 2.0);
+        let half = a.slerp(b, 0.5);
+        assert_eps_eq(&half.to_matrix(), &rotation_y(PI / 4.0), EPS);
+        assert_eps_eq(&a.slerp(b, 0.0).to_matrix(), &a.to_matrix(), EPS);
+        assert_eps_eq(&a.slerp(b, 1.0).to_matrix(), &b.to_matrix(), EPS);
+
+        // -b is the same rotation, and slerp still takes the short way
+        let minus_b = Quaternion::new(-b.w, -b.x, -b.y, -b.z);
+        let half = a.slerp(minus_b, 0.5);
+        assert_eps_eq(&half.to_matrix(), &rotation_y(PI / 4.0), EPS);
+    }
+
+    #[test]
+    fn test_decompose() {
+        let m = translation(1.0, 2.0, 3.0)
+            * rotation(vector(1.0, 1.0, 0.0), 1.2)
+            * scaling(2.0, 3.0, 4.0);
+        let trs = decompose(m).unwrap();
+        assert_eps_eq(&trs.translation, &vector(1.0, 2.0, 3.0), EPS);
+        assert_eps_eq(&trs.scale, &vector(2.0, 3.0, 4.0), EPS);
+        assert_eps_eq(&trs.to_matrix(), &m, EPS);
+
+        // mirror images keep the mirroring in the scale
+        let mirrored = rotation_z(0.4) * scaling(1.0, -1.0, 1.0);
+        let trs = decompose(mirrored).unwrap();
+        assert_eps_eq(&trs.to_matrix(), &mirrored, EPS);
+
+        assert_eq!(decompose(scaling(1.0, 0.0, 1.0)), None);
+    }
+
+    #[test]
+    fn test_interpolate_trs() {
+        let a = decompose(translation(0.0, 0.0, 0.0)).unwrap();
+        let b = decompose(translation(2.0, 0.0, 0.0) * rotation_y(PI / 2.0))
+            .unwrap();
+        let half = a.interpolate(&b, 0.5);
+        let expected = translation(1.0, 0.0, 0.0) * rotation_y(PI / 4.0);
+        assert_eps_eq(&half.to_matrix(), &expected, EPS);
+    }
+
+    #[test]
+    fn test_transform_builder() {
+        let p = point(1.0, 0.0, 1.0);
+        let transform = TransformBuilder::new()
+            .rotate_x(PI / 2.0)
+            .scale(5.0, 5.0, 5.0)
+            .translate(10.0, 5.0, 7.0)
+            .build();
+        assert_eps_eq(&(transform * p), &point(15.0, 0.0, 7.0), EPS);
+
+        let expected = translation(10.0, 5.0, 7.0)
+            * scaling(5.0, 5.0, 5.0)
+            * rotation_x(PI / 2.0);
+        assert_eps_eq(&transform, &expected, EPS);
+        assert_eq!(TransformBuilder::default().build(), DMat4::identity());
+
+        let transform = TransformBuilder::new()
+            .shear(1.0, 0.0, 0.0, 0.0, 0.0, 0.0)
+            .rotate_y(PI / 3.0)
+            .rotate_z(PI / 5.0)
+            .rotate(vector(1.0, 1.0, 0.0), PI / 7.0)
+            .build();
+        let expected = rotation(vector(1.0, 1.0, 0.0), PI / 7.0)
+            * rotation_z(PI / 5.0)
+            * rotation_y(PI / 3.0)
+            * shearing(1.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+        assert_eps_eq(&transform, &expected, EPS);
+    }
 }
 
 #[cfg(test)]
 pub mod test_util {
     // floating point comparison utilities
-    use ultraviolet::{DVec3, DVec4};
+    use super::{Normal3, Point3, Vector3};
+    use ultraviolet::{DMat4, DVec2, DVec3, DVec4};
     use std::fmt::Debug;
 
     pub trait EpsEq<Rhs = Self> {
@@ -129,6 +1159,37 @@ pub mod test_util {
         }
     }
 
+    macro_rules! impl_eps_eq_triple {
+        ($t:ident) => {
+            impl EpsEq for $t {
+                type Rhs = Self;
+                fn eps_eq(&self, rhs: &Self::Rhs, eps: f64) -> bool {
+                    (self.x - rhs.x).abs() < eps
+                        && (self.y - rhs.y).abs() < eps
+                        && (self.z - rhs.z).abs() < eps
+                }
+            }
+        };
+    }
+
+    impl_eps_eq_triple!(Point3);
+    impl_eps_eq_triple!(Vector3);
+    impl_eps_eq_triple!(Normal3);
+
+    impl EpsEq for DMat4 {
+        type Rhs = Self;
+        fn eps_eq(&self, rhs: &Self::Rhs, eps: f64) -> bool {
+            (0..4).all(|i| self.cols[i].eps_eq(&rhs.cols[i], eps))
+        }
+    }
+
+    impl EpsEq for DVec2 {
+        type Rhs = Self;
+        fn eps_eq(&self, rhs: &Self::Rhs, eps: f64) -> bool {
+            (*self - *rhs).abs().component_max() < eps
+        }
+    }
+
     impl EpsEq for DVec3 {
         type Rhs = Self;
         fn eps_eq(&self, rhs: &Self::Rhs, eps: f64) -> bool {
@@ -160,7 +1221,7 @@ pub mod test_util {
         type Rhs = Self;
         fn eps_eq(&self, rhs: &Self::Rhs, eps: f64) -> bool {
             match (self, rhs) {
-                (Some(a), Some(b)) => a.eps_eq(&b, eps),
+                (Some(a), Some(b)) => a.eps_eq(b, eps),
                 (None, None) => true,
                 _ => false,
             }
@@ -0,0 +1,285 @@
+mod ply;
+pub use ply::parse_ply;
+
+mod stl;
+pub use stl::parse_stl;
+
+use super::color::Color;
+use super::math::{Normal3, Point3, Transform, Vector3};
+use super::primitive::{Aabb, IntersectMethod, Triangle};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use ultraviolet::DVec2;
+
+/// Something wrong with a mesh file.
+#[derive(Debug)]
+pub enum MeshError {
+    Io(io::Error),
+    /// The file doesn't start the way files of its format do.
+    NotRecognized,
+    /// The file ended before all of the mesh was read.
+    UnexpectedEnd,
+    /// A value that should be a number isn't one.
+    BadNumber(String),
+    /// A line or keyword that the format doesn't allow where it is.
+    Unexpected(String),
+    /// Part of the format that isn't supported, like a PLY property type.
+    Unsupported(String),
+    /// A face refers to a vertex that isn't in the file.
+    BadIndex(usize),
+    /// The file's extension isn't one of the mesh formats.
+    UnknownFormat(String),
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshError::Io(err) => write!(f, "{}", err),
+            MeshError::NotRecognized => write!(f, "not a mesh file"),
+            MeshError::UnexpectedEnd => write!(f, "unexpected end of file"),
+            MeshError::BadNumber(s) => {
+                write!(f, "expected a number, found '{}'", s)
+            }
+            MeshError::Unexpected(s) => write!(f, "unexpected '{}'", s),
+            MeshError::Unsupported(s) => write!(f, "unsupported {}", s),
+            MeshError::BadIndex(i) => write!(f, "vertex {} doesn't exist", i),
+            MeshError::UnknownFormat(s) => {
+                write!(f, "unknown mesh format '{}'", s)
+            }
+        }
+    }
+}
+
+impl Error for MeshError {}
+
+impl From<io::Error> for MeshError {
+    fn from(err: io::Error) -> MeshError {
+        MeshError::Io(err)
+    }
+}
+
+/// Triangles sharing a list of vertices, which is what every mesh importer
+/// produces.
+///
+/// The optional attributes have one entry per vertex when present.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TriangleMesh {
+    pub positions: Vec<Point3>,
+    pub normals: Option<Vec<Normal3>>,
+    pub uvs: Option<Vec<DVec2>>,
+    pub colors: Option<Vec<Color>>,
+    /// Indices into the vertex lists, three per triangle.
+    pub indices: Vec<[usize; 3]>,
+}
+
+impl TriangleMesh {
+    /// Read a mesh, picking the format from the file's extension: `.ply`
+    /// or `.stl`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<TriangleMesh, MeshError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "ply" => parse_ply(&fs::read(path)?),
+            "stl" => parse_stl(&fs::read(path)?),
+            _ => Err(MeshError::UnknownFormat(extension)),
+        }
+    }
+
+    /// The number of triangles.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.positions
+            .iter()
+            .fold(Aabb::empty(), |bounds, &p| bounds.union_point(p))
+    }
+
+    /// Move the mesh by `transform`, as when placing an instance of it in
+    /// the world. Transforms that mirror the mesh also reverse the order of
+    /// each triangle's vertices, so that the triangles keep facing the way
+    /// their normals do.
+    pub fn transform(&mut self, transform: &Transform) {
+        for p in self.positions.iter_mut() {
+            *p = transform.point(*p);
+        }
+        if let Some(normals) = &mut self.normals {
+            for n in normals.iter_mut() {
+                *n = transform.normal(*n).normalized();
+            }
+        }
+
+        let m = transform.matrix();
+        let column =
+            |i: usize| Vector3::new(m.cols[i].x, m.cols[i].y, m.cols[i].z);
+        if column(0).cross(column(1)).dot(column(2)) < 0.0 {
+            for triangle in self.indices.iter_mut() {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+
+    /// Make sure every index refers to a vertex, as importers do before
+    /// returning a mesh.
+    fn check_indices(&self) -> Result<(), MeshError> {
+        let count = self.positions.len();
+        match self.indices.iter().flatten().find(|&&i| i >= count) {
+            Some(&i) => Err(MeshError::BadIndex(i)),
+            None => Ok(()),
+        }
+    }
+
+    /// The mesh as separate triangles, with the vertex normals and (u, v)
+    /// if there are any, and watertight intersection so that rays can't
+    /// slip between them. Triangles with no area are left out.
+    pub fn triangles(&self) -> Vec<Triangle> {
+        let mut triangles = Vec::with_capacity(self.indices.len());
+        for &[a, b, c] in self.indices.iter() {
+            let (p0, p1, p2) =
+                (self.positions[a], self.positions[b], self.positions[c]);
+            if (p1 - p0).cross(p2 - p0).mag_sq() == 0.0 {
+                continue;
+            }
+
+            let mut triangle = Triangle::new(p0, p1, p2)
+                .with_method(IntersectMethod::Watertight);
+            if let Some(normals) = &self.normals {
+                triangle =
+                    triangle.with_normals([normals[a], normals[b], normals[c]]);
+            }
+            if let Some(uvs) = &self.uvs {
+                triangle = triangle.with_uvs([uvs[a], uvs[b], uvs[c]]);
+            }
+            triangles.push(triangle);
+        }
+        triangles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::math::test_util::assert_eps_eq;
+    use super::super::math::{normal, point, vector};
+    use super::super::math::{rotation_x, scaling, translation};
+    use super::super::math::Ray;
+    use super::super::primitive::Scene;
+    use super::*;
+    use std::env;
+    use std::f64::consts::PI;
+
+    fn square() -> TriangleMesh {
+        TriangleMesh {
+            positions: vec![
+                point(0.0, 0.0, 0.0),
+                point(1.0, 0.0, 0.0),
+                point(1.0, 1.0, 0.0),
+                point(0.0, 1.0, 0.0),
+                point(2.0, 2.0, 0.0),
+            ],
+            normals: Some(vec![normal(0.0, 0.0, 1.0); 5]),
+            uvs: Some(vec![
+                DVec2::new(0.0, 0.0),
+                DVec2::new(1.0, 0.0),
+                DVec2::new(1.0, 1.0),
+                DVec2::new(0.0, 1.0),
+                DVec2::new(2.0, 2.0),
+            ]),
+            colors: None,
+            // the last triangle is a sliver with no area
+            indices: vec![[0, 1, 2], [0, 2, 3], [0, 2, 4]],
+        }
+    }
+
+    #[test]
+    fn test_mesh_triangles() {
+        let mesh = square();
+        assert_eq!(mesh.len(), 3);
+        assert!(mesh.check_indices().is_ok());
+        let bounds = mesh.bounds();
+        assert_eq!(bounds.min, point(0.0, 0.0, 0.0));
+        assert_eq!(bounds.max, point(2.0, 2.0, 0.0));
+
+        let triangles = mesh.triangles();
+        assert_eq!(triangles.len(), 2);
+        let ray = Ray::new(point(0.25, 0.75, 1.0), vector(0.0, 0.0, -1.0));
+        let hit = triangles.intersect(&ray).unwrap();
+        assert_eq!(hit.primitive, 1);
+        assert_eps_eq(&hit.uv, &DVec2::new(0.25, 0.75), 1.0e-9);
+        assert_eps_eq(&hit.shading_normal, &normal(0.0, 0.0, 1.0), 1.0e-9);
+    }
+
+    #[test]
+    fn test_mesh_transform() {
+        let mut mesh = square();
+        mesh.normals = None;
+        let ray = Ray::new(point(0.25, 0.75, 1.0), vector(0.0, 0.0, -1.0));
+        let before = mesh.triangles().intersect(&ray).unwrap().normal;
+
+        let mirror = translation(0.0, 0.0, -1.0) * scaling(-2.0, 1.0, 1.0);
+        mesh.transform(&Transform::new(mirror));
+        assert_eq!(mesh.positions[2], point(-2.0, 1.0, -1.0));
+        assert_eq!(mesh.indices[0], [0, 2, 1]);
+
+        // the triangles still face the same way
+        let ray = Ray::new(point(-0.5, 0.75, 1.0), vector(0.0, 0.0, -1.0));
+        let hit = mesh.triangles().intersect(&ray).unwrap();
+        assert_eq!(hit.t, 2.0);
+        assert_eq!(hit.normal, before);
+
+        let mut mesh = square();
+        mesh.transform(&Transform::new(rotation_x(0.5 * PI)));
+        let normals = mesh.normals.unwrap();
+        assert_eps_eq(&normals[0], &normal(0.0, -1.0, 0.0), 1.0e-9);
+    }
+
+    #[test]
+    fn test_mesh_bad_index() {
+        let mut mesh = square();
+        mesh.indices.push([0, 1, 5]);
+        match mesh.check_indices() {
+            Err(MeshError::BadIndex(5)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_mesh_load() {
+        let path = env::temp_dir().join("raytracer_test_load.stl");
+        let text = "solid t\n\
+                    facet normal 0 0 1\n\
+                    outer loop\n\
+                    vertex 0 0 0\n\
+                    vertex 1 0 0\n\
+                    vertex 0 1 0\n\
+                    endloop\n\
+                    endfacet\n\
+                    endsolid t\n";
+        fs::write(&path, text).unwrap();
+        let mesh = TriangleMesh::load(&path).unwrap();
+        assert_eq!(mesh.len(), 1);
+        assert_eq!(mesh.positions.len(), 3);
+
+        match TriangleMesh::load("mesh.xyz") {
+            Err(MeshError::UnknownFormat(s)) => assert_eq!(s, "xyz"),
+            other => panic!("unexpected {:?}", other),
+        }
+        let missing = env::temp_dir().join("raytracer_test_missing.ply");
+        match TriangleMesh::load(missing) {
+            Err(MeshError::Io(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
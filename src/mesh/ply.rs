@@ -0,0 +1,524 @@
+use super::super::color::Color;
+use super::super::math::{Normal3, Point3};
+use super::{MeshError, TriangleMesh};
+use std::borrow::Cow;
+use std::str::SplitWhitespace;
+use ultraviolet::DVec2;
+
+/// How the body of a PLY file after the header is stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    /// Both the original names and the ones with sizes are in use.
+    fn parse(name: &str) -> Result<ScalarType, MeshError> {
+        match name {
+            "char" | "int8" => Ok(ScalarType::I8),
+            "uchar" | "uint8" => Ok(ScalarType::U8),
+            "short" | "int16" => Ok(ScalarType::I16),
+            "ushort" | "uint16" => Ok(ScalarType::U16),
+            "int" | "int32" => Ok(ScalarType::I32),
+            "uint" | "uint32" => Ok(ScalarType::U32),
+            "float" | "float32" => Ok(ScalarType::F32),
+            "double" | "float64" => Ok(ScalarType::F64),
+            _ => Err(MeshError::Unsupported(format!("type '{}'", name))),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    /// What a color component of this type is divided by to get into
+    /// [0, 1]. Floating point colors already are.
+    fn color_scale(self) -> f64 {
+        match self {
+            ScalarType::I8 => i8::MAX as f64,
+            ScalarType::U8 => u8::MAX as f64,
+            ScalarType::I16 => i16::MAX as f64,
+            ScalarType::U16 => u16::MAX as f64,
+            ScalarType::I32 => i32::MAX as f64,
+            ScalarType::U32 => u32::MAX as f64,
+            ScalarType::F32 | ScalarType::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PropertyType {
+    Scalar(ScalarType),
+    /// A count of the given type followed by that many items.
+    List(ScalarType, ScalarType),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Property {
+    name: String,
+    kind: PropertyType,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|p| names.contains(&p.name.as_str()))
+    }
+}
+
+/// Where values are read from in the body of the file.
+enum Body<'a> {
+    Ascii(SplitWhitespace<'a>),
+    Binary { bytes: &'a [u8], big_endian: bool },
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, kind: ScalarType) -> Result<f64, MeshError> {
+        match self {
+            Body::Ascii(tokens) => {
+                let s = tokens.next().ok_or(MeshError::UnexpectedEnd)?;
+                s.parse().map_err(|_| MeshError::BadNumber(s.to_string()))
+            }
+            Body::Binary { bytes, big_endian } => {
+                let size = kind.size();
+                if bytes.len() < size {
+                    return Err(MeshError::UnexpectedEnd);
+                }
+                let mut b = [0; 8];
+                b[..size].copy_from_slice(&bytes[..size]);
+                if *big_endian {
+                    b[..size].reverse();
+                }
+                *bytes = &bytes[size..];
+
+                // the bytes are now little endian
+                let (b2, b4) = ([b[0], b[1]], [b[0], b[1], b[2], b[3]]);
+                Ok(match kind {
+                    ScalarType::I8 => b[0] as i8 as f64,
+                    ScalarType::U8 => b[0] as f64,
+                    ScalarType::I16 => i16::from_le_bytes(b2) as f64,
+                    ScalarType::U16 => u16::from_le_bytes(b2) as f64,
+                    ScalarType::I32 => i32::from_le_bytes(b4) as f64,
+                    ScalarType::U32 => u32::from_le_bytes(b4) as f64,
+                    ScalarType::F32 => f32::from_le_bytes(b4) as f64,
+                    ScalarType::F64 => f64::from_le_bytes(b),
+                })
+            }
+        }
+    }
+
+    /// The values of one element, with every item of a list property.
+    fn read_element(
+        &mut self,
+        element: &Element,
+    ) -> Result<Vec<Vec<f64>>, MeshError> {
+        let mut values = Vec::with_capacity(element.properties.len());
+        for property in element.properties.iter() {
+            match property.kind {
+                PropertyType::Scalar(kind) => {
+                    values.push(vec![self.read(kind)?])
+                }
+                PropertyType::List(count, item) => {
+                    let count = self.read(count)?;
+                    let list = (0..count as usize)
+                        .map(|_| self.read(item))
+                        .collect::<Result<_, _>>()?;
+                    values.push(list);
+                }
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// The format, the elements, and where the body starts.
+fn parse_header(
+    bytes: &[u8],
+) -> Result<(Format, Vec<Element>, usize), MeshError> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let body = loop {
+        let end = bytes[start..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or(MeshError::UnexpectedEnd)?;
+        let line = String::from_utf8_lossy(&bytes[start..start + end]);
+        start += end + 1;
+        if line.trim() == "end_header" {
+            break start;
+        }
+        lines.push(line.trim().to_string());
+    };
+
+    let mut lines = lines.iter();
+    if lines.next().map(String::as_str) != Some("ply") {
+        return Err(MeshError::NotRecognized);
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let number = |s: &str| -> Result<usize, MeshError> {
+        s.parse().map_err(|_| MeshError::BadNumber(s.to_string()))
+    };
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] | ["comment", ..] | ["obj_info", ..] => (),
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => {
+                        let name = format!("format '{}'", name);
+                        return Err(MeshError::Unsupported(name));
+                    }
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: number(count)?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| MeshError::Unexpected(line.clone()))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind: PropertyType::List(
+                        ScalarType::parse(count)?,
+                        ScalarType::parse(item)?,
+                    ),
+                });
+            }
+            ["property", kind, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| MeshError::Unexpected(line.clone()))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind: PropertyType::Scalar(ScalarType::parse(kind)?),
+                });
+            }
+            _ => return Err(MeshError::Unexpected(line.clone())),
+        }
+    }
+
+    let format = format.ok_or(MeshError::NotRecognized)?;
+    Ok((format, elements, body))
+}
+
+/// Read a Stanford PLY file, in ASCII or either binary byte order.
+///
+/// Vertices can have normals (`nx`, `ny`, `nz`), texture coordinates (`u`
+/// and `v`, or `s` and `t`) and colors (`red`, `green`, `blue`), and faces
+/// are lists of `vertex_indices`. Faces with more than three vertices are
+/// split into a fan of triangles. Other elements and properties are
+/// skipped.
+pub fn parse_ply(bytes: &[u8]) -> Result<TriangleMesh, MeshError> {
+    if !bytes.starts_with(b"ply") {
+        return Err(MeshError::NotRecognized);
+    }
+    let (format, elements, start) = parse_header(bytes)?;
+    let text: Cow<str>;
+    let mut body = match format {
+        Format::Ascii => {
+            text = String::from_utf8_lossy(&bytes[start..]);
+            Body::Ascii(text.split_whitespace())
+        }
+        Format::BinaryLittleEndian | Format::BinaryBigEndian => Body::Binary {
+            bytes: &bytes[start..],
+            big_endian: format == Format::BinaryBigEndian,
+        },
+    };
+
+    let mut mesh = TriangleMesh::default();
+    for element in elements.iter() {
+        match element.name.as_str() {
+            "vertex" => read_vertices(&mut body, element, &mut mesh)?,
+            "face" => read_faces(&mut body, element, &mut mesh)?,
+            _ => {
+                for _ in 0..element.count {
+                    body.read_element(element)?;
+                }
+            }
+        }
+    }
+
+    mesh.check_indices()?;
+    Ok(mesh)
+}
+
+fn read_vertices(
+    body: &mut Body,
+    element: &Element,
+    mesh: &mut TriangleMesh,
+) -> Result<(), MeshError> {
+    let find_all = |names: &[&[&str]]| -> Option<Vec<usize>> {
+        names.iter().map(|n| element.find(n)).collect()
+    };
+    let position = find_all(&[&["x"], &["y"], &["z"]]).ok_or_else(|| {
+        MeshError::Unsupported("vertices without x, y and z".into())
+    })?;
+    let normal = find_all(&[&["nx"], &["ny"], &["nz"]]);
+    let uv = find_all(&[
+        &["u", "s", "texture_u", "texture_s"],
+        &["v", "t", "texture_v", "texture_t"],
+    ]);
+    let color = find_all(&[&["red"], &["green"], &["blue"]]);
+    let color_scale: Vec<f64> = match &color {
+        Some(color) => color
+            .iter()
+            .map(|&i| match element.properties[i].kind {
+                PropertyType::Scalar(kind) => kind.color_scale(),
+                PropertyType::List(..) => 1.0,
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    for _ in 0..element.count {
+        let values = body.read_element(element)?;
+        // list properties hold their items, so take the first of each
+        let get = |i: usize| values[i].first().copied().unwrap_or(0.0);
+        let p = &position;
+        mesh.positions
+            .push(Point3::new(get(p[0]), get(p[1]), get(p[2])));
+        if let Some(n) = &normal {
+            normals.push(Normal3::new(get(n[0]), get(n[1]), get(n[2])));
+        }
+        if let Some(uv) = &uv {
+            uvs.push(DVec2::new(get(uv[0]), get(uv[1])));
+        }
+        if let Some(c) = &color {
+            let s = &color_scale;
+            colors.push(Color::new(
+                get(c[0]) / s[0],
+                get(c[1]) / s[1],
+                get(c[2]) / s[2],
+            ));
+        }
+    }
+
+    mesh.normals = normal.map(|_| normals);
+    mesh.uvs = uv.map(|_| uvs);
+    mesh.colors = color.map(|_| colors);
+    Ok(())
+}
+
+fn read_faces(
+    body: &mut Body,
+    element: &Element,
+    mesh: &mut TriangleMesh,
+) -> Result<(), MeshError> {
+    let indices = element
+        .find(&["vertex_indices", "vertex_index"])
+        .ok_or_else(|| {
+            MeshError::Unsupported("faces without vertex_indices".into())
+        })?;
+
+    for _ in 0..element.count {
+        let values = body.read_element(element)?;
+        let face = values[indices]
+            .iter()
+            .map(|&i| {
+                if i >= 0.0 && i.fract() == 0.0 {
+                    Ok(i as usize)
+                } else {
+                    Err(MeshError::BadNumber(i.to_string()))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for k in 2..face.len() {
+            mesh.indices.push([face[0], face[k - 1], face[k]]);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::math::point;
+    use super::*;
+
+    const ASCII: &str = "ply
+format ascii 1.0
+comment a unit square on the xy plane
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property float s
+property float t
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+element edge 1
+property int vertex1
+property int vertex2
+end_header
+0 0 0 0 0 1 0 0 255 0 0
+1 0 0 0 0 1 1 0 0 255 0
+1 1 0 0 0 1 1 1 0 0 255
+0 1 0 0 0 1 0 1 51 51 51
+4 0 1 2 3
+0 2
+";
+
+    /// A header for vertices with only a position and faces with only
+    /// their indices, in `format`.
+    fn header(format: &str, vertices: usize, faces: usize) -> Vec<u8> {
+        format!(
+            "ply\nformat {} 1.0\n\
+             element vertex {}\n\
+             property float x\nproperty float y\nproperty float z\n\
+             element face {}\n\
+             property list uchar int vertex_indices\n\
+             end_header\n",
+            format, vertices, faces
+        )
+        .into_bytes()
+    }
+
+    fn triangle_positions() -> Vec<Point3> {
+        vec![
+            point(0.0, 0.0, 0.0),
+            point(1.5, 0.0, 0.0),
+            point(0.0, -2.0, 0.25),
+        ]
+    }
+
+    #[test]
+    fn test_parse_ascii_ply() {
+        let mesh = parse_ply(ASCII.as_bytes()).unwrap();
+        assert_eq!(
+            mesh.positions,
+            vec![
+                point(0.0, 0.0, 0.0),
+                point(1.0, 0.0, 0.0),
+                point(1.0, 1.0, 0.0),
+                point(0.0, 1.0, 0.0),
+            ]
+        );
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        let normals = mesh.normals.unwrap();
+        assert!(normals.iter().all(|&n| n == Normal3::new(0.0, 0.0, 1.0)));
+        assert_eq!(mesh.uvs.unwrap()[2], DVec2::new(1.0, 1.0));
+        let colors = mesh.colors.unwrap();
+        assert_eq!(colors[0], Color::new(1.0, 0.0, 0.0));
+        assert_eq!(colors[3], Color::new(0.2, 0.2, 0.2));
+    }
+
+    #[test]
+    fn test_parse_binary_ply() {
+        for &(format, big_endian) in
+            [("binary_little_endian", false), ("binary_big_endian", true)]
+                .iter()
+        {
+            let mut bytes = header(format, 3, 1);
+            for p in triangle_positions() {
+                for &c in [p.x, p.y, p.z].iter() {
+                    let c = c as f32;
+                    if big_endian {
+                        bytes.extend_from_slice(&c.to_be_bytes());
+                    } else {
+                        bytes.extend_from_slice(&c.to_le_bytes());
+                    }
+                }
+            }
+            bytes.push(3);
+            for &i in [0i32, 2, 1].iter() {
+                if big_endian {
+                    bytes.extend_from_slice(&i.to_be_bytes());
+                } else {
+                    bytes.extend_from_slice(&i.to_le_bytes());
+                }
+            }
+
+            let mesh = parse_ply(&bytes).unwrap();
+            assert_eq!(mesh.positions, triangle_positions());
+            assert_eq!(mesh.indices, vec![[0, 2, 1]]);
+            assert_eq!(mesh.normals, None);
+            assert_eq!(mesh.uvs, None);
+            assert_eq!(mesh.colors, None);
+
+            // cut off in the middle of the face
+            bytes.truncate(bytes.len() - 2);
+            match parse_ply(&bytes) {
+                Err(MeshError::UnexpectedEnd) => (),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_ply_errors() {
+        match parse_ply(b"solid cube\n") {
+            Err(MeshError::NotRecognized) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_ply(&header("binary_middle_endian", 0, 0)) {
+            Err(MeshError::Unsupported(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_ply(b"ply\nformat ascii 1.0\nelement vertex 1\n") {
+            Err(MeshError::UnexpectedEnd) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let ascii = |body: &str| {
+            let mut bytes = header("ascii", 3, 1);
+            bytes.extend_from_slice(body.as_bytes());
+            parse_ply(&bytes)
+        };
+        assert!(ascii("0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n").is_ok());
+        match ascii("0 0 0\n1 0 0\n0 1 zero\n3 0 1 2\n") {
+            Err(MeshError::BadNumber(s)) => assert_eq!(s, "zero"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match ascii("0 0 0\n1 0 0\n0 1 0\n3 0 1 3\n") {
+            Err(MeshError::BadIndex(3)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match ascii("0 0 0\n1 0 0\n0 1 0\n3 0 1\n") {
+            Err(MeshError::UnexpectedEnd) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use super::super::math::Point3;
+use super::{MeshError, TriangleMesh};
+use std::collections::HashMap;
+use std::str::SplitWhitespace;
+
+/// Binary files start with an 80 byte header and a 4 byte triangle count.
+const BINARY_HEADER: usize = 84;
+
+/// Each triangle of a binary file is a normal and three vertices as 32 bit
+/// floats, then 2 bytes of attributes.
+const BINARY_TRIANGLE: usize = 50;
+
+/// Builds an indexed mesh out of separate triangles, merging vertices with
+/// exactly the same position.
+#[derive(Default)]
+struct Welder {
+    mesh: TriangleMesh,
+    vertices: HashMap<(u64, u64, u64), usize>,
+}
+
+impl Welder {
+    fn vertex(&mut self, p: Point3) -> usize {
+        let mesh = &mut self.mesh;
+        // -0.0 and 0.0 are the same position
+        let key = |c: f64| (c + 0.0).to_bits();
+        *self
+            .vertices
+            .entry((key(p.x), key(p.y), key(p.z)))
+            .or_insert_with(|| {
+                mesh.positions.push(p);
+                mesh.positions.len() - 1
+            })
+    }
+
+    fn triangle(&mut self, p: [Point3; 3]) {
+        let indices = [self.vertex(p[0]), self.vertex(p[1]), self.vertex(p[2])];
+        self.mesh.indices.push(indices);
+    }
+}
+
+/// Read an STL file, in either its ASCII or its binary form.
+///
+/// STL files are separate triangles, so vertices in the same place are
+/// merged to make them an indexed mesh. The facet normals are ignored: the
+/// triangles are flat and the order of their vertices gives their normals
+/// anyway.
+pub fn parse_stl(bytes: &[u8]) -> Result<TriangleMesh, MeshError> {
+    // binary files are allowed to start with "solid" too, but only they
+    // have exactly the size their triangle count says
+    let binary_size = bytes.get(80..BINARY_HEADER).map(|count| {
+        let count = [count[0], count[1], count[2], count[3]];
+        BINARY_HEADER + BINARY_TRIANGLE * u32::from_le_bytes(count) as usize
+    });
+    if bytes.starts_with(b"solid") && binary_size != Some(bytes.len()) {
+        parse_ascii(&String::from_utf8_lossy(bytes))
+    } else {
+        parse_binary(bytes)
+    }
+}
+
+fn parse_binary(bytes: &[u8]) -> Result<TriangleMesh, MeshError> {
+    if bytes.len() < BINARY_HEADER {
+        return Err(MeshError::UnexpectedEnd);
+    }
+    let count = [bytes[80], bytes[81], bytes[82], bytes[83]];
+    let count = u32::from_le_bytes(count) as usize;
+    if bytes.len() < BINARY_HEADER + BINARY_TRIANGLE * count {
+        return Err(MeshError::UnexpectedEnd);
+    }
+
+    let mut welder = Welder::default();
+    let float = |b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64;
+    for triangle in bytes[BINARY_HEADER..]
+        .chunks_exact(BINARY_TRIANGLE)
+        .take(count)
+    {
+        // skip the normal
+        let vertex = |k: usize| {
+            let b = &triangle[12 * (k + 1)..];
+            Point3::new(float(&b[0..]), float(&b[4..]), float(&b[8..]))
+        };
+        welder.triangle([vertex(0), vertex(1), vertex(2)]);
+    }
+    Ok(welder.mesh)
+}
+
+fn next<'a>(tokens: &mut SplitWhitespace<'a>) -> Result<&'a str, MeshError> {
+    tokens.next().ok_or(MeshError::UnexpectedEnd)
+}
+
+fn expect(
+    tokens: &mut SplitWhitespace,
+    keyword: &str,
+) -> Result<(), MeshError> {
+    match next(tokens)? {
+        t if t == keyword => Ok(()),
+        t => Err(MeshError::Unexpected(t.to_string())),
+    }
+}
+
+fn parse_ascii(text: &str) -> Result<TriangleMesh, MeshError> {
+    let tokens = &mut text.split_whitespace();
+    let mut welder = Welder::default();
+
+    // the name after "solid" can be several words, or none
+    expect(tokens, "solid")?;
+    let mut token = next(tokens)?;
+    while token != "facet" && token != "endsolid" {
+        token = next(tokens)?;
+    }
+
+    while token == "facet" {
+        expect(tokens, "normal")?;
+        for _ in 0..3 {
+            next(tokens)?;
+        }
+        expect(tokens, "outer")?;
+        expect(tokens, "loop")?;
+
+        let mut vertices = [Point3::origin(); 3];
+        for vertex in vertices.iter_mut() {
+            expect(tokens, "vertex")?;
+            let mut c = [0.0; 3];
+            for c in c.iter_mut() {
+                let s = next(tokens)?;
+                *c = s.parse().map_err(|_| MeshError::BadNumber(s.into()))?;
+            }
+            *vertex = Point3::new(c[0], c[1], c[2]);
+        }
+        welder.triangle(vertices);
+
+        expect(tokens, "endloop")?;
+        expect(tokens, "endfacet")?;
+        token = next(tokens)?;
+    }
+
+    match token {
+        "endsolid" => Ok(welder.mesh),
+        t => Err(MeshError::Unexpected(t.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::math::point;
+    use super::*;
+
+    /// A tetrahedron, with its faces wound counterclockwise from outside.
+    fn tetrahedron() -> Vec<[Point3; 3]> {
+        let (o, x, y, z) = (
+            point(0.0, 0.0, 0.0),
+            point(1.0, 0.0, 0.0),
+            point(0.0, 1.0, 0.0),
+            point(0.0, 0.0, 1.0),
+        );
+        vec![[o, y, x], [o, x, z], [o, z, y], [x, y, z]]
+    }
+
+    fn ascii(faces: &[[Point3; 3]]) -> String {
+        let mut text = String::from("solid my part\n");
+        for face in faces.iter() {
+            text += "  facet normal 0 0 0\n    outer loop\n";
+            for p in face.iter() {
+                text += &format!("      vertex {} {} {}\n", p.x, p.y, p.z);
+            }
+            text += "    endloop\n  endfacet\n";
+        }
+        text + "endsolid my part\n"
+    }
+
+    fn binary(header: &[u8], faces: &[[Point3; 3]]) -> Vec<u8> {
+        let mut bytes = header.to_vec();
+        bytes.resize(80, b' ');
+        bytes.extend_from_slice(&(faces.len() as u32).to_le_bytes());
+        for face in faces.iter() {
+            bytes.extend_from_slice(&[0; 12]);
+            for p in face.iter() {
+                for &c in [p.x, p.y, p.z].iter() {
+                    bytes.extend_from_slice(&(c as f32).to_le_bytes());
+                }
+            }
+            bytes.extend_from_slice(&[0; 2]);
+        }
+        bytes
+    }
+
+    fn check_tetrahedron(mesh: &TriangleMesh) {
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.len(), 4);
+        assert_eq!(mesh.normals, None);
+        for (face, expected) in mesh.indices.iter().zip(tetrahedron()) {
+            for (&i, &p) in face.iter().zip(expected.iter()) {
+                assert_eq!(mesh.positions[i], p);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_ascii_stl() {
+        let mesh = parse_stl(ascii(&tetrahedron()).as_bytes()).unwrap();
+        check_tetrahedron(&mesh);
+
+        let empty = parse_stl(b"solid\nendsolid\n").unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_parse_binary_stl() {
+        let mesh = parse_stl(&binary(b"binary", &tetrahedron())).unwrap();
+        check_tetrahedron(&mesh);
+
+        // plenty of exporters start binary files with "solid" too
+        let bytes = binary(b"solid exported", &tetrahedron());
+        check_tetrahedron(&parse_stl(&bytes).unwrap());
+
+        let mut bytes = binary(b"binary", &tetrahedron());
+        bytes.truncate(bytes.len() - 10);
+        match parse_stl(&bytes) {
+            Err(MeshError::UnexpectedEnd) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_stl(b"binary") {
+            Err(MeshError::UnexpectedEnd) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_stl_errors() {
+        let text = ascii(&tetrahedron());
+        match parse_stl(text.replacen("outer loop", "outer", 1).as_bytes()) {
+            Err(MeshError::Unexpected(s)) => assert_eq!(s, "vertex"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_stl(
+            text.replacen("vertex 0 0 0", "vertex 0 0 x", 1).as_bytes(),
+        ) {
+            Err(MeshError::BadNumber(s)) => assert_eq!(s, "x"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_stl(text.replace("endsolid my part\n", "").as_bytes()) {
+            Err(MeshError::UnexpectedEnd) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
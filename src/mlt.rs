@@ -0,0 +1,546 @@
+use super::camera::Camera;
+use super::color::Color;
+use super::film::SplatBuffer;
+use super::path::PathTracer;
+use super::primitive::Scene;
+use rand::rngs::StdRng;
+use rand::{Error, Rng, RngCore, SeedableRng};
+
+/// Bounds on the size of a small step mutation, from Kelemen et al. 2002.
+const MUTATION_MIN: f64 = 1.0 / 1024.0;
+const MUTATION_MAX: f64 = 1.0 / 64.0;
+
+/// One coordinate of a point in primary sample space, along with the value it
+/// had before the current iteration so that a rejected proposal can be undone.
+#[derive(Debug, Clone, Copy)]
+struct Coordinate {
+    value: f64,
+    modified: u64,
+    backup: f64,
+    backup_modified: u64,
+}
+
+/// A sampler that hands out the coordinates of a point in primary sample
+/// space, the unit hypercube of random numbers that a path tracer consumes to
+/// build a path. Rather than drawing fresh numbers every time, each iteration
+/// either perturbs the previous point slightly (a small step) or replaces it
+/// with a new uniformly distributed one (a large step).
+///
+/// Coordinates are mutated lazily, only once a path actually asks for them,
+/// so paths of any length can be explored without knowing their dimension up
+/// front.
+pub struct PrimarySample {
+    rng: StdRng,
+    coordinates: Vec<Coordinate>,
+    index: usize,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    large_step_probability: f64,
+}
+
+impl PrimarySample {
+    /// Create a sampler whose first point is uniformly distributed. Two
+    /// samplers created with the same `seed` produce the same sequence of
+    /// points.
+    pub fn new(seed: u64, large_step_probability: f64) -> PrimarySample {
+        PrimarySample {
+            rng: StdRng::seed_from_u64(seed),
+            coordinates: Vec::new(),
+            index: 0,
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            large_step_probability,
+        }
+    }
+
+    /// Begin proposing a new point, mutated from the last accepted one.
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.index = 0;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+    }
+
+    pub fn is_large_step(&self) -> bool {
+        self.large_step
+    }
+
+    /// Return the next coordinate of the current point, in [0, 1).
+    pub fn next(&mut self) -> f64 {
+        let index = self.index;
+        self.index += 1;
+
+        if index >= self.coordinates.len() {
+            // a dimension that no earlier path used; it is independent of
+            // everything sampled so far, so any uniform value will do
+            let value = self.rng.gen();
+            self.coordinates.push(Coordinate {
+                value,
+                modified: self.iteration,
+                backup: value,
+                backup_modified: self.iteration,
+            });
+            return value;
+        }
+
+        let mut coordinate = self.coordinates[index];
+
+        // catch up on a large step that happened while this coordinate was
+        // unused
+        if coordinate.modified < self.last_large_step {
+            coordinate.value = self.rng.gen();
+            coordinate.modified = self.last_large_step;
+        }
+
+        coordinate.backup = coordinate.value;
+        coordinate.backup_modified = coordinate.modified;
+
+        if self.large_step {
+            coordinate.value = self.rng.gen();
+        } else {
+            // apply every small step this coordinate missed as well
+            for _ in coordinate.modified..self.iteration {
+                coordinate.value = self.mutate(coordinate.value);
+            }
+        }
+        coordinate.modified = self.iteration;
+
+        self.coordinates[index] = coordinate;
+        coordinate.value
+    }
+
+    /// Keep the proposed point.
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// Throw away the proposed point and go back to the last accepted one.
+    pub fn reject(&mut self) {
+        for coordinate in &mut self.coordinates {
+            if coordinate.modified == self.iteration {
+                coordinate.value = coordinate.backup;
+                coordinate.modified = coordinate.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    /// Kelemen's small step: move the value up or down by an amount that is
+    /// exponentially distributed between `MUTATION_MIN` and `MUTATION_MAX`,
+    /// wrapping around the edges of the unit interval.
+    fn mutate(&mut self, value: f64) -> f64 {
+        let u: f64 = self.rng.gen();
+        let delta =
+            MUTATION_MAX * f64::exp(-(MUTATION_MAX / MUTATION_MIN).ln() * u);
+        let value = if self.rng.gen() {
+            value + delta
+        } else {
+            value - delta
+        };
+        value - value.floor()
+    }
+}
+
+/// Hands out the coordinates of the current point as random numbers, so that
+/// anything drawing from an `Rng` can be driven by the Metropolis sampler.
+impl RngCore for PrimarySample {
+    fn next_u32(&mut self) -> u32 {
+        (self.next() * (1u64 << 32) as f64) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        // `gen::<f64>()` keeps only the top 53 bits
+        ((self.next() * (1u64 << 53) as f64) as u64) << 11
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// The result of tracing one path: where it landed on the film, in raster
+/// coordinates, and how much radiance it carries.
+#[derive(Debug, Clone, Copy)]
+pub struct PathSample {
+    pub x: f64,
+    pub y: f64,
+    pub radiance: Color,
+}
+
+pub struct MetropolisSettings {
+    /// Number of independent paths used to estimate the image's overall
+    /// brightness and to pick the starting points of the chains.
+    pub bootstrap_samples: usize,
+    pub chains: usize,
+    pub mutations_per_chain: u64,
+    pub large_step_probability: f64,
+    pub seed: u64,
+}
+
+/// How many proposals of each kind were made and how many were kept.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MetropolisStats {
+    pub small_steps_proposed: u64,
+    pub small_steps_accepted: u64,
+    pub large_steps_proposed: u64,
+    pub large_steps_accepted: u64,
+}
+
+impl MetropolisStats {
+    pub fn acceptance_rate(&self) -> f64 {
+        let proposed = self.small_steps_proposed + self.large_steps_proposed;
+        let accepted = self.small_steps_accepted + self.large_steps_accepted;
+        ratio(accepted, proposed)
+    }
+
+    pub fn small_step_acceptance_rate(&self) -> f64 {
+        ratio(self.small_steps_accepted, self.small_steps_proposed)
+    }
+
+    pub fn large_step_acceptance_rate(&self) -> f64 {
+        ratio(self.large_steps_accepted, self.large_steps_proposed)
+    }
+}
+
+fn ratio(a: u64, b: u64) -> f64 {
+    if b == 0 {
+        0.0
+    } else {
+        a as f64 / b as f64
+    }
+}
+
+/// Render with primary sample space Metropolis light transport (Kelemen et
+/// al. 2002). `path` traces a single path using the random numbers handed out
+/// by the sampler; the chains explore primary sample space in proportion to
+/// the luminance of the paths they find and splat them into `film`.
+///
+/// Returns the scale that converts the film into pixel values along with the
+/// acceptance statistics of the chains. The scale folds in the normalization
+/// constant estimated during the bootstrap phase, so that the image agrees in
+/// expectation with averaging `path` over uniformly distributed points.
+pub fn render<F>(
+    settings: &MetropolisSettings,
+    film: &SplatBuffer,
+    mut path: F,
+) -> (f64, MetropolisStats)
+where
+    F: FnMut(&mut PrimarySample) -> PathSample,
+{
+    let mut stats = MetropolisStats::default();
+    let p_large = settings.large_step_probability;
+
+    // bootstrap: estimate the integral of the luminance over primary sample
+    // space, remembering each path's weight so the chains can start on them
+    let mut cdf = Vec::with_capacity(settings.bootstrap_samples);
+    let mut total = 0.0;
+    for i in 0..settings.bootstrap_samples {
+        let mut sampler =
+            PrimarySample::new(bootstrap_seed(settings, i), p_large);
+        total += path(&mut sampler).radiance.luminance();
+        cdf.push(total);
+    }
+
+    if total <= 0.0 || settings.chains == 0 {
+        return (0.0, stats);
+    }
+    let normalization = total / settings.bootstrap_samples as f64;
+
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    for _ in 0..settings.chains {
+        // start each chain on a bootstrap path chosen in proportion to its
+        // weight; reseeding the sampler replays the path exactly
+        let u = rng.gen::<f64>() * total;
+        let start = cdf.iter().position(|&c| c > u).unwrap_or(cdf.len() - 1);
+        let mut sampler =
+            PrimarySample::new(bootstrap_seed(settings, start), p_large);
+        let mut current = path(&mut sampler);
+
+        for _ in 0..settings.mutations_per_chain {
+            sampler.start_iteration();
+            let large_step = sampler.is_large_step();
+            let proposed = path(&mut sampler);
+
+            let current_weight = current.radiance.luminance();
+            let proposed_weight = proposed.radiance.luminance();
+            let accept = if current_weight > 0.0 {
+                f64::min(1.0, proposed_weight / current_weight)
+            } else {
+                1.0
+            };
+
+            // splat both paths weighted by how likely each is to be the
+            // chain's next state rather than only the one that wins
+            if proposed_weight > 0.0 {
+                let weight = accept / proposed_weight;
+                film.splat(proposed.x, proposed.y, proposed.radiance * weight);
+            }
+            if current_weight > 0.0 {
+                let weight = (1.0 - accept) / current_weight;
+                film.splat(current.x, current.y, current.radiance * weight);
+            }
+
+            let accepted = rng.gen::<f64>() < accept;
+            if accepted {
+                current = proposed;
+                sampler.accept();
+            } else {
+                sampler.reject();
+            }
+
+            if large_step {
+                stats.large_steps_proposed += 1;
+                stats.large_steps_accepted += accepted as u64;
+            } else {
+                stats.small_steps_proposed += 1;
+                stats.small_steps_accepted += accepted as u64;
+            }
+        }
+    }
+
+    let pixels = film.width() as f64 * film.height() as f64;
+    let mutations =
+        settings.chains as f64 * settings.mutations_per_chain as f64;
+    (normalization * pixels / mutations, stats)
+}
+
+/// Render the image `camera` sees with Metropolis light transport over the
+/// paths `tracer` traces, returning its pixels row by row along with the
+/// acceptance statistics of the chains.
+pub fn render_image<S: Scene + ?Sized>(
+    settings: &MetropolisSettings,
+    camera: &Camera,
+    tracer: &PathTracer<S>,
+) -> (Vec<Color>, MetropolisStats) {
+    let (width, height) = (camera.width(), camera.height());
+    let film = SplatBuffer::new(width, height);
+    let (scale, stats) = render(settings, &film, |sampler| {
+        let x = sampler.next() * width as f64;
+        let y = sampler.next() * height as f64;
+        let radiance = tracer.li(&camera.ray(x, y), sampler);
+        PathSample { x, y, radiance }
+    });
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for j in 0..height {
+        for i in 0..width {
+            pixels.push(film.get(i, j) * scale);
+        }
+    }
+    (pixels, stats)
+}
+
+fn bootstrap_seed(settings: &MetropolisSettings, index: usize) -> u64 {
+    settings
+        .seed
+        .wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        .wrapping_add(index as u64 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::light::Light;
+    use super::super::light_sampler::{light_sampler, LightSampling};
+    use super::super::math::test_util::assert_eps_eq;
+    use super::super::primitive::CornellBox;
+    use ultraviolet::mat::DMat4;
+
+    #[test]
+    fn test_primary_sample_replays_seed() {
+        let mut a = PrimarySample::new(7, 0.3);
+        let mut b = PrimarySample::new(7, 0.3);
+        for _ in 0..10 {
+            assert_eq!(a.next(), b.next());
+        }
+    }
+
+    #[test]
+    fn test_primary_sample_small_step() {
+        let mut sampler = PrimarySample::new(1, 0.0);
+        let first: Vec<f64> = (0..4).map(|_| sampler.next()).collect();
+
+        sampler.start_iteration();
+        assert!(!sampler.is_large_step());
+        for &value in &first {
+            let mutated = sampler.next();
+            // the mutation wraps around the unit interval
+            let delta = (mutated - value).abs();
+            let delta = delta.min(1.0 - delta);
+            assert!(delta > 0.0 && delta <= MUTATION_MAX);
+        }
+    }
+
+    #[test]
+    fn test_primary_sample_reject() {
+        let mut sampler = PrimarySample::new(3, 0.5);
+        let first: Vec<f64> = (0..5).map(|_| sampler.next()).collect();
+
+        sampler.start_iteration();
+        for _ in 0..5 {
+            sampler.next();
+        }
+        sampler.reject();
+
+        // rejecting restores the point from before the iteration
+        let restored: Vec<f64> =
+            sampler.coordinates.iter().map(|c| c.value).collect();
+        assert_eq!(restored, first);
+    }
+
+    #[test]
+    fn test_primary_sample_accept() {
+        let mut sampler = PrimarySample::new(5, 1.0);
+        (0..3).for_each(|_| {
+            sampler.next();
+        });
+
+        sampler.start_iteration();
+        assert!(sampler.is_large_step());
+        let proposed: Vec<f64> = (0..3).map(|_| sampler.next()).collect();
+        sampler.accept();
+
+        let kept: Vec<f64> =
+            sampler.coordinates.iter().map(|c| c.value).collect();
+        assert_eq!(kept, proposed);
+    }
+
+    #[test]
+    fn test_render_matches_expectation() {
+        // a 4x1 image whose brightness ramps up from left to right, with some
+        // noise from a second random number so paths have more than one
+        // dimension; pixel i should come out to i + 0.5
+        let film = SplatBuffer::new(4, 1);
+        let settings = MetropolisSettings {
+            bootstrap_samples: 10_000,
+            chains: 16,
+            mutations_per_chain: 20_000,
+            large_step_probability: 0.3,
+            seed: 42,
+        };
+
+        let (scale, stats) = render(&settings, &film, |sampler| {
+            let x = sampler.next() * 4.0;
+            let y = sampler.next();
+            let noise = 2.0 * sampler.next();
+            PathSample {
+                x,
+                y,
+                radiance: Color::new(x, x, x) * noise,
+            }
+        });
+
+        for i in 0..4 {
+            let pixel = film.get(i, 0) * scale;
+            assert_eps_eq(&pixel.g(), &(i as f64 + 0.5), 0.15);
+        }
+
+        let rate = stats.acceptance_rate();
+        assert!(rate > 0.0 && rate < 1.0);
+        assert!(
+            stats.large_steps_proposed > 0 && stats.small_steps_proposed > 0
+        );
+    }
+
+    #[test]
+    fn test_render_black_image() {
+        let film = SplatBuffer::new(2, 2);
+        let settings = MetropolisSettings {
+            bootstrap_samples: 100,
+            chains: 4,
+            mutations_per_chain: 100,
+            large_step_probability: 0.3,
+            seed: 1,
+        };
+
+        let (scale, stats) = render(&settings, &film, |sampler| PathSample {
+            x: sampler.next() * 2.0,
+            y: sampler.next() * 2.0,
+            radiance: Color::black(),
+        });
+
+        assert_eq!(scale, 0.0);
+        assert_eq!(stats.acceptance_rate(), 0.0);
+    }
+
+    #[test]
+    fn test_primary_sample_as_rng() {
+        // drawing through `Rng` gives back the coordinates themselves
+        let mut a = PrimarySample::new(9, 0.3);
+        let mut b = PrimarySample::new(9, 0.3);
+        for _ in 0..10 {
+            assert_eq!(a.gen::<f64>(), b.next());
+        }
+    }
+
+    #[test]
+    fn test_agrees_with_path_tracer() {
+        // the light reaching each quarter of a small view of the Cornell
+        // box, tilted down so the lamp is out of sight
+        let cornell = CornellBox::new();
+        let lights: Vec<Box<dyn Light>> = vec![Box::new(cornell.light())];
+        let materials = cornell.materials();
+        let (width, height) = (16, 16);
+        let fov = f64::to_radians(40.0);
+        let tilt = DMat4::from_rotation_x(f64::to_radians(-10.0));
+        let camera = Camera::new(tilt, fov, width, height);
+        let sampler = light_sampler(LightSampling::Power, &lights);
+        let tracer = PathTracer::new(&cornell, &lights, &materials, sampler, 3);
+        let samples = 256;
+
+        let settings = MetropolisSettings {
+            bootstrap_samples: 10_000,
+            chains: 64,
+            mutations_per_chain: samples * (width * height) as u64 / 64,
+            large_step_probability: 0.3,
+            seed: 1,
+        };
+        let (pixels, stats) = render_image(&settings, &camera, &tracer);
+
+        let mut rng = StdRng::seed_from_u64(settings.seed);
+        let mut expected = [0.0; 4];
+        let mut quarters = [0.0; 4];
+        let quarter = |i, j| (2 * j / height + 2 * (2 * i / width)) as usize;
+        for j in 0..height {
+            for i in 0..width {
+                for _ in 0..samples {
+                    let x = i as f64 + rng.gen::<f64>();
+                    let y = j as f64 + rng.gen::<f64>();
+                    let radiance = tracer.li(&camera.ray(x, y), &mut rng);
+                    expected[quarter(i, j)] +=
+                        radiance.luminance() / samples as f64;
+                }
+                let pixel = pixels[(j * width + i) as usize];
+                quarters[quarter(i, j)] += pixel.luminance();
+            }
+        }
+
+        // the chains wander, so the light is only spread across the image
+        // as it should be on average, while the bootstrap fixes the total
+        let total: f64 = quarters.iter().sum();
+        let expected_total: f64 = expected.iter().sum();
+        assert!(
+            (total - expected_total).abs() < 0.03 * expected_total,
+            "{} where path tracing gave {}",
+            total,
+            expected_total
+        );
+        for (a, b) in quarters.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 0.12 * b, "{:?} {:?}", quarters, expected);
+        }
+
+        let rate = stats.acceptance_rate();
+        assert!(rate > 0.0 && rate < 1.0);
+    }
+}
@@ -0,0 +1,221 @@
+use super::bsdf::Lambertian;
+use super::color::Color;
+use super::light::Light;
+use super::light_sampler::LightSampler;
+use super::material::{self, Material};
+use super::math::{Normal3, Point3, Ray, Vector3};
+use super::primitive::{Intersection, Scene};
+use rand::Rng;
+
+/// Bounces after which paths may be ended early by Russian roulette.
+const MIN_BOUNCES: u32 = 3;
+
+/// A unidirectional path tracer for diffuse surfaces.
+///
+/// Light reaching each vertex of a path is found by sampling one light, so
+/// emission is only counted where the camera sees it directly; a path that
+/// later happens to hit a light has already accounted for it.
+pub struct PathTracer<'a, S: ?Sized> {
+    scene: &'a S,
+    lights: &'a [Box<dyn Light>],
+    materials: &'a [Box<dyn Material>],
+    light_sampler: Box<dyn LightSampler>,
+    max_bounces: u32,
+}
+
+impl<'a, S: Scene + ?Sized> PathTracer<'a, S> {
+    /// Trace paths through `scene`, scattering at most `max_bounces` times
+    /// and picking the light to sample at each vertex with `light_sampler`.
+    pub fn new(
+        scene: &'a S,
+        lights: &'a [Box<dyn Light>],
+        materials: &'a [Box<dyn Material>],
+        light_sampler: Box<dyn LightSampler>,
+        max_bounces: u32,
+    ) -> PathTracer<'a, S> {
+        PathTracer {
+            scene,
+            lights,
+            materials,
+            light_sampler,
+            max_bounces,
+        }
+    }
+
+    /// Return the radiance arriving along `ray`.
+    pub fn li<R: Rng + ?Sized>(&self, ray: &Ray, rng: &mut R) -> Color {
+        let mut radiance = Color::black();
+        let mut beta = Color::new(1.0, 1.0, 1.0);
+        let mut ray = Ray::new(ray.origin, ray.direction.normalized());
+
+        for bounces in 0.. {
+            let hit = match self.scene.intersect(&ray) {
+                Some(hit) => hit,
+                None => {
+                    if bounces == 0 {
+                        for light in self.lights {
+                            radiance += light.le(&ray);
+                        }
+                    }
+                    break;
+                }
+            };
+            if bounces == 0 {
+                radiance += hit.emitted;
+            }
+            if bounces == self.max_bounces {
+                break;
+            }
+
+            let wo = -ray.direction;
+            let bsdf = self.bsdf(&hit, wo);
+            radiance += beta * self.sample_light(&bsdf, hit.point, wo, rng);
+
+            let sample = match bsdf.sample_f(wo, (rng.gen(), rng.gen())) {
+                Some(sample) => sample,
+                None => break,
+            };
+            let cos = bsdf.normal().dot(sample.wi);
+            beta = beta * sample.f * (cos / sample.pdf);
+
+            if bounces + 1 >= MIN_BOUNCES {
+                let q = f64::max(0.05, 1.0 - max_component(beta));
+                if rng.gen::<f64>() < q {
+                    break;
+                }
+                beta = beta * (1.0 / (1.0 - q));
+            }
+            ray = Ray::new(hit.point, sample.wi);
+        }
+
+        radiance
+    }
+
+    /// The BSDF at `hit` for light leaving along `wo`.
+    pub fn bsdf(&self, hit: &Intersection, wo: Vector3) -> Lambertian {
+        let albedo = material::albedo(self.materials, hit);
+        Lambertian::new(albedo, hit.shading_normal.face_forward(wo))
+    }
+
+    /// Light from one sample of one light reflected along `wo` by `bsdf` at
+    /// `point`.
+    pub fn sample_light<R: Rng + ?Sized>(
+        &self,
+        bsdf: &Lambertian,
+        point: Point3,
+        wo: Vector3,
+        rng: &mut R,
+    ) -> Color {
+        let normal: Normal3 = bsdf.normal();
+        let (index, pmf) =
+            match self.light_sampler.sample(point, normal, rng.gen()) {
+                Some(picked) => picked,
+                None => return Color::black(),
+            };
+        let light = &self.lights[index];
+        let sample = match light.sample_li(point, (rng.gen(), rng.gen())) {
+            Some(sample) => sample,
+            None => return Color::black(),
+        };
+
+        let f = bsdf.f(wo, sample.wi);
+        if f == Color::black() || sample.pdf == 0.0 {
+            return Color::black();
+        }
+        if !sample.unoccluded(self.scene, point) {
+            return Color::black();
+        }
+        let cos = normal.dot(sample.wi);
+        sample.radiance * f * (cos / (sample.pdf * pmf))
+    }
+}
+
+pub fn max_component(color: Color) -> f64 {
+    color.r().max(color.g()).max(color.b())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::light::AreaLight;
+    use super::super::light_sampler::{light_sampler, LightSampling};
+    use super::super::material::MaterialList;
+    use super::super::math::{point, vector};
+    use super::super::primitive::Parallelogram;
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_inside_glowing_box() {
+        // every bounce inside a box that glows evenly and reflects half of
+        // the light reaching it adds half as much again as the last
+        let glow = Color::new(1.0, 2.0, 4.0);
+        let face = |p, u, v| {
+            let quad = Parallelogram::new(p, u, v).with_material(0);
+            Arc::new(AreaLight::new(quad, glow, false))
+        };
+        // the cube [-1, 1]^3 with every face facing in
+        let (x, y, z) = (
+            vector(2.0, 0.0, 0.0),
+            vector(0.0, 2.0, 0.0),
+            vector(0.0, 0.0, 2.0),
+        );
+        let (low, high) = (point(-1.0, -1.0, -1.0), point(1.0, 1.0, 1.0));
+        let scene = vec![
+            face(low, z, x),
+            face(low, y, z),
+            face(low, x, y),
+            face(high, -x, -z),
+            face(high, -z, -y),
+            face(high, -y, -x),
+        ];
+        let lights: Vec<Box<dyn Light>> = scene
+            .iter()
+            .map(|face| Box::new(face.clone()) as Box<dyn Light>)
+            .collect();
+        let materials: MaterialList =
+            vec![Box::new(Color::new(0.5, 0.5, 0.5))];
+
+        let mut rng = StdRng::seed_from_u64(1);
+        for &max_bounces in [0, 1, 3].iter() {
+            let sampler = light_sampler(LightSampling::Power, &lights);
+            let tracer = PathTracer::new(
+                &scene,
+                &lights,
+                &materials,
+                sampler,
+                max_bounces,
+            );
+            let count = 4000;
+            let mut radiance = Color::black();
+            for i in 0..count {
+                let angle = i as f64;
+                let direction = vector(angle.cos(), 0.3, angle.sin());
+                let ray = Ray::new(point(0.0, 0.2, 0.1), direction);
+                radiance += tracer.li(&ray, &mut rng);
+            }
+            let radiance = radiance * (1.0 / count as f64);
+            let expected = 2.0 - 0.5f64.powi(max_bounces as i32);
+            assert!(
+                (radiance.g() / 2.0 - expected).abs() < 0.03 * expected,
+                "{} bounces gave {:?}",
+                max_bounces,
+                radiance
+            );
+        }
+    }
+
+    #[test]
+    fn test_background() {
+        // an empty scene shows only the lights around it
+        let scene: Vec<Parallelogram> = Vec::new();
+        let lights: Vec<Box<dyn Light>> = Vec::new();
+        let materials: MaterialList = Vec::new();
+        let sampler = light_sampler(LightSampling::Uniform, &lights);
+        let tracer = PathTracer::new(&scene, &lights, &materials, sampler, 5);
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, -1.0));
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(tracer.li(&ray, &mut rng), Color::black());
+    }
+}
@@ -0,0 +1,1899 @@
+mod params;
+pub use params::ParamSet;
+
+mod tokens;
+
+use super::color::Color;
+use super::hdr::HdrImage;
+use super::light::{AreaLight, DirectionalLight, EnvironmentLight, Light};
+use super::light::{area_lights, PointLight, SpotLight};
+use super::math::{point, rotation, scaling, translation, vector};
+use super::math::{Normal3, Point3, Transform, Vector3};
+use super::mesh::{MeshError, TriangleMesh};
+use super::primitive::{Aabb, Bounded, Bvh, Instance, Scene, Sphere};
+use super::primitive::{Transformed, Triangle};
+use image::ImageError;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokens::{tokenize, Token, Tokens};
+use ultraviolet::mat::DMat4;
+use ultraviolet::vec::{DVec2, DVec4};
+
+/// How deeply Include and Import can nest, which stops files that include
+/// themselves.
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+pub type SceneList = Vec<Box<dyn Scene>>;
+pub type LightList = Vec<Box<dyn Light>>;
+
+/// Something wrong with a pbrt scene file.
+#[derive(Debug)]
+pub enum PbrtError {
+    Io(io::Error),
+    /// A `plymesh` file that can't be read.
+    Mesh(MeshError),
+    /// An environment map that can't be decoded.
+    Image(ImageError),
+    /// The file ended in the middle of a directive or string.
+    UnexpectedEnd,
+    /// A token that doesn't belong where it is, and the line it's on.
+    Unexpected(String, usize),
+    /// A value that should be a number isn't one.
+    BadNumber(String),
+    /// A parameter with the wrong type or number of values, or one that is
+    /// required but missing.
+    BadParameter(String),
+    /// A named material, texture, object or coordinate system that hasn't
+    /// been defined.
+    Undefined(String),
+    /// Part of the format that isn't supported, like Include files nested
+    /// too deeply.
+    Unsupported(String),
+}
+
+impl fmt::Display for PbrtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PbrtError::Io(err) => write!(f, "{}", err),
+            PbrtError::Mesh(err) => write!(f, "{}", err),
+            PbrtError::Image(err) => write!(f, "{}", err),
+            PbrtError::UnexpectedEnd => write!(f, "unexpected end of file"),
+            PbrtError::Unexpected(s, line) => {
+                write!(f, "unexpected '{}' on line {}", s, line)
+            }
+            PbrtError::BadNumber(s) => {
+                write!(f, "expected a number, found '{}'", s)
+            }
+            PbrtError::BadParameter(s) => {
+                write!(f, "missing or invalid parameter '{}'", s)
+            }
+            PbrtError::Undefined(s) => write!(f, "'{}' isn't defined", s),
+            PbrtError::Unsupported(s) => write!(f, "unsupported {}", s),
+        }
+    }
+}
+
+impl Error for PbrtError {}
+
+impl From<io::Error> for PbrtError {
+    fn from(err: io::Error) -> PbrtError {
+        PbrtError::Io(err)
+    }
+}
+
+impl From<MeshError> for PbrtError {
+    fn from(err: MeshError) -> PbrtError {
+        PbrtError::Mesh(err)
+    }
+}
+
+impl From<ImageError> for PbrtError {
+    fn from(err: ImageError) -> PbrtError {
+        PbrtError::Image(err)
+    }
+}
+
+/// A camera looking down its own -z axis with y up, just as the renderer's
+/// camera does.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PbrtCamera {
+    pub camera_to_world: DMat4,
+    /// pbrt's field of view in radians, which spans the shorter side of
+    /// the image.
+    pub fov: f64,
+}
+
+impl Default for PbrtCamera {
+    /// The camera pbrt uses when a file doesn't place one, at the origin
+    /// looking down +z.
+    fn default() -> PbrtCamera {
+        PbrtCamera {
+            camera_to_world: scaling(1.0, 1.0, -1.0),
+            fov: f64::to_radians(90.0),
+        }
+    }
+}
+
+impl PbrtCamera {
+    /// The vertical field of view of a `width` by `height` image.
+    pub fn yfov(&self, width: u32, height: u32) -> f64 {
+        if width >= height {
+            self.fov
+        } else {
+            let ratio = height as f64 / width as f64;
+            2.0 * ((self.fov / 2.0).tan() * ratio).atan()
+        }
+    }
+}
+
+/// The image the scene asks to be rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PbrtFilm {
+    pub width: u32,
+    pub height: u32,
+    pub filename: Option<String>,
+}
+
+impl Default for PbrtFilm {
+    /// The film pbrt uses when a file doesn't describe one.
+    fn default() -> PbrtFilm {
+        PbrtFilm {
+            width: 1280,
+            height: 720,
+            filename: None,
+        }
+    }
+}
+
+/// Light given off by a shape, from the AreaLightSource in effect when the
+/// shape was defined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Emission {
+    pub radiance: Color,
+    pub two_sided: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PbrtShape {
+    /// Triangles in world space, wound so that they face the same way as
+    /// they do in pbrt.
+    Mesh(TriangleMesh),
+    /// A sphere around the origin of its object space.
+    Sphere { radius: f64, object_to_world: DMat4 },
+}
+
+impl PbrtShape {
+    pub fn bounds(&self) -> Aabb {
+        match self {
+            PbrtShape::Mesh(mesh) => mesh.bounds(),
+            PbrtShape::Sphere {
+                radius,
+                object_to_world,
+            } => Aabb::new(
+                point(-radius, -radius, -radius),
+                point(*radius, *radius, *radius),
+            )
+            .transformed(&Transform::new(*object_to_world)),
+        }
+    }
+}
+
+/// A shape with the material and area light that applied to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PbrtObject {
+    pub shape: PbrtShape,
+    /// Index into the scene's materials, or `None` for pbrt's default.
+    pub material: Option<usize>,
+    pub emission: Option<Emission>,
+}
+
+impl PbrtObject {
+    /// The triangles of `mesh`, which should be the object's shape, with
+    /// the object's material.
+    fn triangles(&self, mesh: &TriangleMesh) -> Vec<Triangle> {
+        let triangles = mesh.triangles();
+        match self.material {
+            Some(material) => triangles
+                .into_iter()
+                .map(|triangle| triangle.with_material(material))
+                .collect(),
+            None => triangles,
+        }
+    }
+
+    /// A sphere with the object's material.
+    fn sphere(&self, center: Point3, radius: f64) -> Sphere {
+        let sphere = Sphere::new(center, radius);
+        match self.material {
+            Some(material) => sphere.with_material(material),
+            None => sphere,
+        }
+    }
+}
+
+/// The shapes between an ObjectBegin and its ObjectEnd, which ObjectInstance
+/// can place any number of times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PbrtPrototype {
+    pub name: String,
+    /// In the object's space, without emission: pbrt doesn't support area
+    /// lights in instances.
+    pub objects: Vec<PbrtObject>,
+}
+
+/// A copy of a prototype placed by ObjectInstance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PbrtInstance {
+    /// Index into the scene's prototypes.
+    pub prototype: usize,
+    pub object_to_world: DMat4,
+}
+
+/// A material as the file describes it, and the diffuse reflectance the
+/// renderer shades its surfaces with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PbrtMaterial {
+    /// The name given by MakeNamedMaterial, if it has one.
+    pub name: Option<String>,
+    /// The type of material, like "diffuse" or "conductor".
+    pub kind: String,
+    pub params: ParamSet,
+    pub reflectance: PbrtReflectance,
+}
+
+/// The fraction of light a material reflects diffusely.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PbrtReflectance {
+    Constant(Color),
+    /// An `imagemap` texture. Texture coordinates are scaled and offset by
+    /// the texture's `uscale`, `vscale`, `udelta` and `vdelta`, and the
+    /// image repeats.
+    Image {
+        image: Arc<HdrImage>,
+        scale: f64,
+        uv_scale: DVec2,
+        uv_offset: DVec2,
+    },
+}
+
+impl PbrtReflectance {
+    /// The reflectance at the texture coordinates `uv`.
+    pub fn at(&self, uv: DVec2) -> Color {
+        match self {
+            PbrtReflectance::Constant(color) => *color,
+            PbrtReflectance::Image {
+                image,
+                scale,
+                uv_scale,
+                uv_offset,
+            } => {
+                let st = uv * *uv_scale + *uv_offset;
+                // pbrt puts t = 0 at the bottom of the image
+                let (s, t) = (st.x, 1.0 - st.y);
+                image.lookup(s - s.floor(), t - t.floor()) * *scale
+            }
+        }
+    }
+}
+
+/// A texture as the file describes it. Materials only use `imagemap`
+/// textures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PbrtTexture {
+    pub name: String,
+    /// What the texture holds: "spectrum" or "float".
+    pub kind: String,
+    /// How it's made, like "imagemap" or "checkerboard".
+    pub class: String,
+    pub params: ParamSet,
+}
+
+/// A light from LightSource, in world space. Angles are in radians.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PbrtLight {
+    Point {
+        position: Point3,
+        intensity: Color,
+    },
+    /// Full intensity within `cone_angle - cone_delta` of the axis, fading
+    /// out to nothing at `cone_angle`.
+    Spot {
+        position: Point3,
+        direction: Vector3,
+        intensity: Color,
+        cone_angle: f64,
+        cone_delta: f64,
+    },
+    Distant {
+        /// The direction the light travels in.
+        direction: Vector3,
+        radiance: Color,
+    },
+    /// A latitude-longitude map, already oriented for `EnvironmentLight`.
+    Infinite {
+        map: HdrImage,
+        light_to_world: DMat4,
+    },
+}
+
+impl PbrtLight {
+    pub fn to_light(&self, scene_radius: f64) -> Box<dyn Light> {
+        match self {
+            PbrtLight::Point {
+                position,
+                intensity,
+            } => Box::new(PointLight::new(*position, *intensity)),
+            PbrtLight::Spot {
+                position,
+                direction,
+                intensity,
+                cone_angle,
+                cone_delta,
+            } => Box::new(SpotLight::new(
+                *position,
+                *direction,
+                *intensity,
+                *cone_angle,
+                cone_angle - cone_delta,
+            )),
+            PbrtLight::Distant {
+                direction,
+                radiance,
+            } => Box::new(DirectionalLight::new(
+                *direction,
+                *radiance,
+                scene_radius,
+            )),
+            PbrtLight::Infinite {
+                map,
+                light_to_world,
+            } => Box::new(EnvironmentLight::new(
+                map.clone(),
+                *light_to_world,
+                scene_radius,
+            )),
+        }
+    }
+}
+
+/// A scene read from a pbrt-v3 or pbrt-v4 file.
+///
+/// Only a subset of the format is understood: the camera's placement and
+/// field of view, the film's resolution, the sampler's pixel samples,
+/// triangle meshes, PLY meshes and spheres, point, spot, distant and
+/// infinite lights, diffuse area lights, and the transform and attribute
+/// directives. Every material is rendered as diffuse, with its
+/// `reflectance` or `Kd` as a constant or an `imagemap` texture. Anything
+/// else is skipped or approximated, with a note in `warnings`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PbrtScene {
+    pub camera: PbrtCamera,
+    pub film: PbrtFilm,
+    pub pixel_samples: u32,
+    pub objects: Vec<PbrtObject>,
+    pub prototypes: Vec<PbrtPrototype>,
+    pub instances: Vec<PbrtInstance>,
+    pub lights: Vec<PbrtLight>,
+    pub materials: Vec<PbrtMaterial>,
+    pub textures: Vec<PbrtTexture>,
+    /// What was skipped or approximated, each mentioned once.
+    pub warnings: Vec<String>,
+}
+
+impl PbrtScene {
+    /// Read a `.pbrt` file. Included files, PLY meshes and environment
+    /// maps are relative to the file's directory.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<PbrtScene, PbrtError> {
+        let path = path.as_ref();
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        PbrtScene::parse(&fs::read_to_string(path)?, base)
+    }
+
+    /// Read a scene description, with the files it refers to relative to
+    /// the directory `base`.
+    pub fn parse(text: &str, base: &Path) -> Result<PbrtScene, PbrtError> {
+        let mut parser = Parser::new(base);
+        parser.run(&tokenize(text)?, 0)?;
+        if !parser.stack.is_empty() {
+            parser.warn("AttributeBegin without an AttributeEnd".into());
+        }
+
+        let mut scene = parser.scene;
+        let mut seen = HashSet::new();
+        scene
+            .warnings
+            .retain(|warning| seen.insert(warning.clone()));
+        Ok(scene)
+    }
+
+    /// The material of object `object`, if it has one.
+    #[cfg(test)]
+    pub fn material(&self, object: usize) -> Option<&PbrtMaterial> {
+        self.objects[object].material.map(|m| &self.materials[m])
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let objects = self.objects.iter().fold(Aabb::empty(), |b, object| {
+            b.union(&object.shape.bounds())
+        });
+        self.instances.iter().fold(objects, |b, instance| {
+            let prototype = &self.prototypes[instance.prototype];
+            let transform = Transform::new(instance.object_to_world);
+            prototype.objects.iter().fold(b, |b, object| {
+                b.union(&object.shape.bounds().transformed(&transform))
+            })
+        })
+    }
+
+    /// The renderer's geometry and lights for the scene. Shapes that give
+    /// off light are area lights, which are in both lists.
+    pub fn build(&self) -> (SceneList, LightList) {
+        let mut scene: SceneList = Vec::new();
+        let mut lights: LightList = Vec::new();
+        let mut triangles = Vec::new();
+        let mut spheres = Vec::new();
+        let mut ellipsoids = Vec::new();
+
+        for object in self.objects.iter() {
+            match (&object.shape, object.emission) {
+                (PbrtShape::Mesh(mesh), None) => {
+                    triangles.extend(object.triangles(mesh))
+                }
+                (PbrtShape::Mesh(mesh), Some(emission)) => {
+                    for light in area_lights(
+                        mesh.triangles(),
+                        emission.radiance,
+                        emission.two_sided,
+                    ) {
+                        scene.push(Box::new(Arc::clone(&light)));
+                        lights.push(Box::new(light));
+                    }
+                }
+                (
+                    PbrtShape::Sphere {
+                        radius,
+                        object_to_world,
+                    },
+                    emission,
+                ) => match uniform_scale(object_to_world) {
+                    Some(scale) => {
+                        let center = *object_to_world * point(0.0, 0.0, 0.0);
+                        let sphere = object.sphere(center, radius * scale);
+                        match emission {
+                            Some(emission) => {
+                                let light = Arc::new(AreaLight::new(
+                                    sphere,
+                                    emission.radiance,
+                                    emission.two_sided,
+                                ));
+                                scene.push(Box::new(Arc::clone(&light)));
+                                lights.push(Box::new(light));
+                            }
+                            None => spheres.push(sphere),
+                        }
+                    }
+                    // the emission of these was dropped when they were read
+                    None => ellipsoids.push(Transformed::new(
+                        object.sphere(point(0.0, 0.0, 0.0), *radius),
+                        *object_to_world,
+                    )),
+                },
+            }
+        }
+
+        if !triangles.is_empty() {
+            scene.push(Box::new(Bvh::new(triangles)));
+        }
+        if !spheres.is_empty() {
+            scene.push(Box::new(Bvh::new(spheres)));
+        }
+        if !ellipsoids.is_empty() {
+            scene.push(Box::new(Bvh::new(ellipsoids)));
+        }
+        self.build_instances(&mut scene);
+        let (_, radius) = self.bounds().bounding_sphere();
+        lights.extend(self.lights.iter().map(|light| light.to_light(radius)));
+        (scene, lights)
+    }
+
+    /// Add a top-level hierarchy over the placed copies of each prototype,
+    /// which all share one bottom-level hierarchy per prototype.
+    fn build_instances(&self, scene: &mut SceneList) {
+        let mut meshes = Vec::new();
+        let mut spheres = Vec::new();
+        for prototype in self.prototypes.iter() {
+            let mut triangles: Vec<Triangle> = Vec::new();
+            let mut ellipsoids = Vec::new();
+            for object in prototype.objects.iter() {
+                match &object.shape {
+                    PbrtShape::Mesh(mesh) => {
+                        triangles.extend(object.triangles(mesh))
+                    }
+                    PbrtShape::Sphere {
+                        radius,
+                        object_to_world,
+                    } => ellipsoids.push(Transformed::new(
+                        object.sphere(point(0.0, 0.0, 0.0), *radius),
+                        *object_to_world,
+                    )),
+                }
+            }
+            meshes.push(shared(triangles));
+            spheres.push(shared(ellipsoids));
+        }
+
+        let mut mesh_instances: Vec<Instance<Triangle>> = Vec::new();
+        let mut sphere_instances: Vec<Instance<_>> = Vec::new();
+        for instance in self.instances.iter() {
+            let m = instance.object_to_world;
+            if let Some(bvh) = &meshes[instance.prototype] {
+                mesh_instances.push(Transformed::new(Arc::clone(bvh), m));
+            }
+            if let Some(bvh) = &spheres[instance.prototype] {
+                sphere_instances.push(Transformed::new(Arc::clone(bvh), m));
+            }
+        }
+        if !mesh_instances.is_empty() {
+            scene.push(Box::new(Bvh::new(mesh_instances)));
+        }
+        if !sphere_instances.is_empty() {
+            scene.push(Box::new(Bvh::new(sphere_instances)));
+        }
+    }
+}
+
+/// A hierarchy over `primitives` for instances to share, unless there are
+/// none.
+fn shared<T: Bounded>(primitives: Vec<T>) -> Option<Arc<Bvh<T>>> {
+    if primitives.is_empty() {
+        None
+    } else {
+        Some(Arc::new(Bvh::new(primitives)))
+    }
+}
+
+/// The scale of `m` if it only rotates, reflects, scales uniformly and
+/// translates, which keeps spheres spheres.
+fn uniform_scale(m: &DMat4) -> Option<f64> {
+    let column = |i: usize| vector(m.cols[i].x, m.cols[i].y, m.cols[i].z);
+    let (x, y, z) = (column(0), column(1), column(2));
+    let scale_sq = x.mag_sq();
+    let eps = 1.0e-9 * scale_sq;
+    let similar = scale_sq > 0.0
+        && (y.mag_sq() - scale_sq).abs() <= eps
+        && (z.mag_sq() - scale_sq).abs() <= eps
+        && x.dot(y).abs() <= eps
+        && y.dot(z).abs() <= eps
+        && z.dot(x).abs() <= eps;
+    if similar {
+        Some(scale_sq.sqrt())
+    } else {
+        None
+    }
+}
+
+/// pbrt's LookAt, which gives the world to camera transform of a camera
+/// looking down its +z axis. pbrt's space is left-handed, so the camera's
+/// +x axis is `up` crossed with the viewing direction.
+fn look_at(v: &[f64]) -> Result<DMat4, PbrtError> {
+    let eye = point(v[0], v[1], v[2]);
+    let dir = (point(v[3], v[4], v[5]) - eye).normalized();
+    let right = vector(v[6], v[7], v[8]).normalized().cross(dir);
+    if right.mag_sq() == 0.0 || right.mag_sq().is_nan() {
+        return Err(PbrtError::BadParameter("LookAt".into()));
+    }
+    let right = right.normalized();
+    let up = dir.cross(right);
+    let camera_to_world = DMat4::new(
+        DVec4::new(right.x, right.y, right.z, 0.0),
+        DVec4::new(up.x, up.y, up.z, 0.0),
+        DVec4::new(dir.x, dir.y, dir.z, 0.0),
+        DVec4::new(eye.x, eye.y, eye.z, 1.0),
+    );
+    Ok(camera_to_world.inversed())
+}
+
+/// A matrix from the 16 numbers of Transform or ConcatTransform, which are
+/// column major like DMat4.
+fn matrix(m: &[f64]) -> DMat4 {
+    let column = |i: usize| {
+        DVec4::new(m[4 * i], m[4 * i + 1], m[4 * i + 2], m[4 * i + 3])
+    };
+    DMat4::new(column(0), column(1), column(2), column(3))
+}
+
+/// Maps the renderer's latitude-longitude directions onto pbrt's, which
+/// have the poles along z and the middle of the map along -x.
+fn latlong_axes() -> DMat4 {
+    DMat4::new(
+        DVec4::new(0.0, -1.0, 0.0, 0.0),
+        DVec4::new(0.0, 0.0, 1.0, 0.0),
+        DVec4::new(1.0, 0.0, 0.0, 0.0),
+        DVec4::new(0.0, 0.0, 0.0, 1.0),
+    )
+}
+
+/// Wind the triangles of `mesh` so that the renderer's normals, which face
+/// the side the vertices go clockwise from, face the way pbrt's do: the
+/// side the vertex normals are on if there are any, and otherwise the side
+/// the vertices go counterclockwise from, or the other side if `reverse`
+/// is set.
+fn orient(mesh: &mut TriangleMesh, reverse: bool) {
+    let TriangleMesh {
+        positions,
+        normals,
+        indices,
+        ..
+    } = mesh;
+    for triangle in indices.iter_mut() {
+        let [a, b, c] = *triangle;
+        let (p0, p1, p2) = (positions[a], positions[b], positions[c]);
+        let counterclockwise = (p1 - p0).cross(p2 - p0);
+        let facing = match normals {
+            Some(n) => {
+                Vector3::from(n[a]) + Vector3::from(n[b]) + Vector3::from(n[c])
+            }
+            None if reverse => -counterclockwise,
+            None => counterclockwise,
+        };
+        if counterclockwise.dot(facing) > 0.0 {
+            triangle.swap(1, 2);
+        }
+    }
+}
+
+/// The mesh of a `trianglemesh` shape, in its object space.
+fn triangle_mesh(params: &ParamSet) -> Result<TriangleMesh, PbrtError> {
+    let bad = |name: &str| PbrtError::BadParameter(name.into());
+    let positions = params.points("P")?.ok_or_else(|| bad("P"))?;
+    let count = positions.len();
+    let indices = match params.indices("indices")? {
+        Some(indices) => indices,
+        // a single triangle can leave out its indices
+        None if count == 3 => vec![0, 1, 2],
+        None => return Err(bad("indices")),
+    };
+    if indices.len() % 3 != 0 || indices.iter().any(|&i| i >= count) {
+        return Err(bad("indices"));
+    }
+
+    let normals = match params.points("N")? {
+        Some(normals) if normals.len() == count => Some(
+            normals
+                .iter()
+                .map(|n| Normal3::new(n.x, n.y, n.z))
+                .collect(),
+        ),
+        Some(_) => return Err(bad("N")),
+        None => None,
+    };
+    // pbrt-v3 also accepts "st" for the texture coordinates
+    let uv_name = if params.get("st").is_some() {
+        "st"
+    } else {
+        "uv"
+    };
+    let uvs = match params.floats(uv_name)? {
+        Some(uvs) if uvs.len() == 2 * count => Some(
+            uvs.chunks_exact(2)
+                .map(|uv| DVec2::new(uv[0], uv[1]))
+                .collect(),
+        ),
+        Some(_) => return Err(bad(uv_name)),
+        None => None,
+    };
+
+    Ok(TriangleMesh {
+        positions,
+        normals,
+        uvs,
+        colors: None,
+        indices: indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect(),
+    })
+}
+
+/// The parts of the graphics state that AttributeBegin saves.
+#[derive(Debug, Clone)]
+struct GraphicsState {
+    /// The current transformation matrix, from the object space of shapes
+    /// and lights to the world.
+    ctm: DMat4,
+    reverse_orientation: bool,
+    material: Option<usize>,
+    emission: Option<Emission>,
+}
+
+/// What started a block, which decides what ending it restores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Block {
+    Attribute,
+    Transform,
+    Object,
+}
+
+impl Block {
+    fn end(self) -> &'static str {
+        match self {
+            Block::Attribute => "AttributeEnd",
+            Block::Transform => "TransformEnd",
+            Block::Object => "ObjectEnd",
+        }
+    }
+}
+
+struct Parser {
+    scene: PbrtScene,
+    /// The directory that file names are relative to.
+    base: PathBuf,
+    state: GraphicsState,
+    stack: Vec<(Block, GraphicsState)>,
+    named_materials: HashMap<String, usize>,
+    coordinate_systems: HashMap<String, DMat4>,
+    /// Index into the scene's prototypes of each object defined by
+    /// ObjectBegin.
+    prototypes: HashMap<String, usize>,
+    /// The object being defined.
+    object: Option<PbrtPrototype>,
+    /// Images decoded for textures, by file name and whether they're sRGB
+    /// encoded.
+    images: HashMap<(String, bool), Arc<HdrImage>>,
+}
+
+impl Parser {
+    fn new(base: &Path) -> Parser {
+        Parser {
+            scene: PbrtScene {
+                pixel_samples: 16,
+                ..PbrtScene::default()
+            },
+            base: base.to_path_buf(),
+            state: GraphicsState {
+                ctm: DMat4::identity(),
+                reverse_orientation: false,
+                material: None,
+                emission: None,
+            },
+            stack: Vec::new(),
+            named_materials: HashMap::new(),
+            coordinate_systems: HashMap::new(),
+            prototypes: HashMap::new(),
+            object: None,
+            images: HashMap::new(),
+        }
+    }
+
+    fn warn(&mut self, warning: String) {
+        self.scene.warnings.push(warning);
+    }
+
+    fn color(
+        &mut self,
+        params: &ParamSet,
+        name: &str,
+        default: Color,
+    ) -> Result<Color, PbrtError> {
+        params.color(name, default, &mut self.scene.warnings)
+    }
+
+    /// Apply `m` to shapes and lights before the current transform.
+    fn concat(&mut self, m: DMat4) {
+        self.state.ctm = self.state.ctm * m;
+    }
+
+    fn begin(&mut self, block: Block) {
+        self.stack.push((block, self.state.clone()));
+    }
+
+    fn end(&mut self, block: Block, line: usize) -> Result<(), PbrtError> {
+        let state = match self.stack.pop() {
+            Some((begun, state)) if begun == block => state,
+            _ => return Err(PbrtError::Unexpected(block.end().into(), line)),
+        };
+        if block == Block::Transform {
+            self.state.ctm = state.ctm;
+        } else {
+            self.state = state;
+        }
+        Ok(())
+    }
+
+    fn run(
+        &mut self,
+        tokens: &[(Token, usize)],
+        depth: usize,
+    ) -> Result<(), PbrtError> {
+        let mut tokens = Tokens::new(tokens);
+        while let Some(token) = tokens.next() {
+            let directive = match token {
+                Token::Word(word) => word.as_str(),
+                token => return Err(tokens.unexpected(token)),
+            };
+            match directive {
+                "Identity" => self.state.ctm = DMat4::identity(),
+                "Translate" => {
+                    let v = tokens.numbers(3)?;
+                    self.concat(translation(v[0], v[1], v[2]));
+                }
+                "Scale" => {
+                    let v = tokens.numbers(3)?;
+                    self.concat(scaling(v[0], v[1], v[2]));
+                }
+                "Rotate" => {
+                    let v = tokens.numbers(4)?;
+                    let axis = vector(v[1], v[2], v[3]);
+                    self.concat(rotation(axis, v[0].to_radians()));
+                }
+                "LookAt" => self.concat(look_at(&tokens.numbers(9)?)?),
+                "Transform" => self.state.ctm = matrix(&tokens.numbers(16)?),
+                "ConcatTransform" => self.concat(matrix(&tokens.numbers(16)?)),
+                "CoordinateSystem" => {
+                    let name = tokens.string()?;
+                    self.coordinate_systems.insert(name, self.state.ctm);
+                }
+                "CoordSysTransform" => {
+                    let name = tokens.string()?;
+                    self.state.ctm = *self
+                        .coordinate_systems
+                        .get(&name)
+                        .ok_or(PbrtError::Undefined(name))?;
+                }
+                "ReverseOrientation" => {
+                    self.state.reverse_orientation =
+                        !self.state.reverse_orientation
+                }
+                "ActiveTransform" => {
+                    tokens.next();
+                    self.warn("motion blur isn't supported".into());
+                }
+                "TransformTimes" => {
+                    tokens.numbers(2)?;
+                }
+                "AttributeBegin" => self.begin(Block::Attribute),
+                "AttributeEnd" => self.end(Block::Attribute, tokens.line())?,
+                "TransformBegin" => self.begin(Block::Transform),
+                "TransformEnd" => self.end(Block::Transform, tokens.line())?,
+                "Attribute" => {
+                    tokens.string()?;
+                    tokens.params()?;
+                    self.warn("Attribute defaults are ignored".into());
+                }
+                "ObjectBegin" => {
+                    let name = tokens.string()?;
+                    self.begin(Block::Object);
+                    self.object = Some(PbrtPrototype {
+                        name,
+                        objects: Vec::new(),
+                    });
+                }
+                "ObjectEnd" => {
+                    self.end(Block::Object, tokens.line())?;
+                    if let Some(prototype) = self.object.take() {
+                        let index = self.scene.prototypes.len();
+                        self.prototypes.insert(prototype.name.clone(), index);
+                        self.scene.prototypes.push(prototype);
+                    }
+                }
+                "ObjectInstance" => {
+                    let name = tokens.string()?;
+                    self.instance(name)?;
+                }
+                "WorldBegin" => {
+                    self.state.ctm = DMat4::identity();
+                    let world = DMat4::identity();
+                    self.coordinate_systems.insert("world".into(), world);
+                }
+                "WorldEnd" => (),
+                "Camera" => {
+                    let kind = tokens.string()?;
+                    self.camera(&kind, &tokens.params()?)?;
+                }
+                "Film" => {
+                    tokens.string()?;
+                    let params = tokens.params()?;
+                    let film = &mut self.scene.film;
+                    film.width = params.count("xresolution", 1280)?;
+                    film.height = params.count("yresolution", 720)?;
+                    film.filename = params.string("filename")?.map(From::from);
+                    if params.get("cropwindow").is_some() {
+                        self.warn("the film's crop window is ignored".into());
+                    }
+                }
+                "Sampler" => {
+                    tokens.string()?;
+                    let params = tokens.params()?;
+                    self.scene.pixel_samples =
+                        params.count("pixelsamples", 16)?;
+                }
+                // these choose how pbrt renders rather than what the scene
+                // is, so they're left to the renderer
+                "Integrator" | "PixelFilter" | "Accelerator"
+                | "SurfaceIntegrator" | "VolumeIntegrator" => {
+                    tokens.string()?;
+                    tokens.params()?;
+                }
+                "ColorSpace" => {
+                    tokens.string()?;
+                }
+                "Option" => {
+                    tokens.params()?;
+                }
+                "MakeNamedMedium" | "MediumInterface" => {
+                    tokens.string()?;
+                    if directive == "MakeNamedMedium" {
+                        tokens.params()?;
+                    } else if let Some(Token::Str(_)) = tokens.peek() {
+                        tokens.next();
+                    }
+                    self.warn("participating media are ignored".into());
+                }
+                "Material" => {
+                    let kind = tokens.string()?;
+                    let params = tokens.params()?;
+                    self.state.material =
+                        Some(self.material(None, kind, params)?);
+                }
+                "MakeNamedMaterial" => {
+                    let name = tokens.string()?;
+                    let params = tokens.params()?;
+                    let kind = params.string("type")?.unwrap_or("").to_string();
+                    let material =
+                        self.material(Some(name.clone()), kind, params)?;
+                    self.named_materials.insert(name, material);
+                }
+                "NamedMaterial" => {
+                    let name = tokens.string()?;
+                    let material = *self
+                        .named_materials
+                        .get(&name)
+                        .ok_or(PbrtError::Undefined(name))?;
+                    self.state.material = Some(material);
+                }
+                "Texture" => {
+                    let texture = PbrtTexture {
+                        name: tokens.string()?,
+                        kind: tokens.string()?,
+                        class: tokens.string()?,
+                        params: tokens.params()?,
+                    };
+                    self.scene.textures.push(texture);
+                }
+                "LightSource" => {
+                    let kind = tokens.string()?;
+                    self.light_source(&kind, &tokens.params()?)?;
+                }
+                "AreaLightSource" => {
+                    let kind = tokens.string()?;
+                    let params = tokens.params()?;
+                    if kind != "diffuse" {
+                        let warning = format!("'{}' area lights", kind);
+                        self.warn(warning + " are treated as diffuse");
+                    }
+                    let white = Color::new(1.0, 1.0, 1.0);
+                    let radiance = self.color(&params, "L", white)?
+                        * self.color(&params, "scale", white)?;
+                    self.state.emission = Some(Emission {
+                        radiance,
+                        two_sided: params.bool("twosided", false)?,
+                    });
+                }
+                "Shape" => {
+                    let kind = tokens.string()?;
+                    self.shape(&kind, &tokens.params()?)?;
+                }
+                "Include" | "Import" => {
+                    let filename = tokens.string()?;
+                    self.include(&filename, depth)?;
+                }
+                other => {
+                    let warning = format!("unknown directive '{}'", other);
+                    self.warn(warning + " skipped");
+                    // directives are the only capitalized words
+                    while let Some(token) = tokens.peek() {
+                        if let Token::Word(word) = token {
+                            if word.starts_with(|c: char| c.is_uppercase()) {
+                                break;
+                            }
+                        }
+                        tokens.next();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn include(
+        &mut self,
+        filename: &str,
+        depth: usize,
+    ) -> Result<(), PbrtError> {
+        if depth >= MAX_INCLUDE_DEPTH {
+            let what =
+                format!("Include nested over {} deep", MAX_INCLUDE_DEPTH);
+            return Err(PbrtError::Unsupported(what));
+        }
+        let text = fs::read_to_string(self.base.join(filename))?;
+        self.run(&tokenize(&text)?, depth + 1)
+    }
+
+    fn material(
+        &mut self,
+        name: Option<String>,
+        kind: String,
+        params: ParamSet,
+    ) -> Result<usize, PbrtError> {
+        let reflectance = self.reflectance(&kind, &params)?;
+        self.scene.materials.push(PbrtMaterial {
+            name,
+            kind,
+            params,
+            reflectance,
+        });
+        Ok(self.scene.materials.len() - 1)
+    }
+
+    /// The diffuse reflectance of a material of type `kind`. Materials
+    /// with a diffuse layer keep just that, and the rest are gray.
+    fn reflectance(
+        &mut self,
+        kind: &str,
+        params: &ParamSet,
+    ) -> Result<PbrtReflectance, PbrtError> {
+        let gray = Color::new(0.5, 0.5, 0.5);
+        // pbrt-v3 calls the diffuse reflectance Kd
+        let name = match kind {
+            "diffuse" | "coateddiffuse" | "diffusetransmission" => {
+                "reflectance"
+            }
+            "matte" | "plastic" | "substrate" | "translucent" | "uber" => "Kd",
+            // shapes with these aren't rendered at all
+            "" | "interface" | "none" => {
+                return Ok(PbrtReflectance::Constant(gray))
+            }
+            _ => {
+                let warning = format!("'{}' materials are rendered", kind);
+                self.warn(warning + " as gray diffuse");
+                return Ok(PbrtReflectance::Constant(gray));
+            }
+        };
+        if kind != "diffuse" && kind != "matte" {
+            let warning = format!("'{}' materials are rendered", kind);
+            self.warn(warning + " as just their diffuse part");
+        }
+
+        if params.get(name).is_some_and(|param| param.kind == "texture") {
+            match params.string(name)? {
+                Some(texture) => self.texture(texture),
+                None => Err(PbrtError::BadParameter(name.into())),
+            }
+        } else {
+            let color = self.color(params, name, gray)?;
+            Ok(PbrtReflectance::Constant(color))
+        }
+    }
+
+    /// The reflectance given by the texture called `name`. Textures other
+    /// than image maps are rendered as gray.
+    fn texture(&mut self, name: &str) -> Result<PbrtReflectance, PbrtError> {
+        // a texture can be redefined, and the latest definition counts
+        let texture = self
+            .scene
+            .textures
+            .iter()
+            .rfind(|texture| texture.name == name)
+            .cloned()
+            .ok_or_else(|| PbrtError::Undefined(name.into()))?;
+        let gray = PbrtReflectance::Constant(Color::new(0.5, 0.5, 0.5));
+        if texture.class != "imagemap" {
+            let warning = format!("'{}' textures are rendered", texture.class);
+            self.warn(warning + " as gray");
+            return Ok(gray);
+        }
+
+        let params = &texture.params;
+        let filename = params
+            .string("filename")?
+            .ok_or_else(|| PbrtError::BadParameter("filename".into()))?;
+        // pbrt-v4 names the encoding, and pbrt-v3 says whether it's gamma
+        // corrected
+        let srgb = match params.string("encoding")? {
+            Some(encoding) => encoding == "sRGB",
+            None => params.bool("gamma", true)?,
+        };
+        let image = match self.image(filename, srgb)? {
+            Some(image) => image,
+            None => return Ok(gray),
+        };
+        if params.string("wrap")?.is_some_and(|wrap| wrap != "repeat") {
+            let warning = "texture wrap modes other than repeat";
+            self.warn(format!("{} are ignored", warning));
+        }
+        Ok(PbrtReflectance::Image {
+            image,
+            scale: params.float("scale", 1.0)?,
+            uv_scale: DVec2::new(
+                params.float("uscale", 1.0)?,
+                params.float("vscale", 1.0)?,
+            ),
+            uv_offset: DVec2::new(
+                params.float("udelta", 0.0)?,
+                params.float("vdelta", 0.0)?,
+            ),
+        })
+    }
+
+    /// The texture image in `filename`, or `None` if it isn't in a format
+    /// that can be read. 8 bit images are sRGB encoded if `srgb` is set.
+    fn image(
+        &mut self,
+        filename: &str,
+        srgb: bool,
+    ) -> Result<Option<Arc<HdrImage>>, PbrtError> {
+        let key = (filename.to_string(), srgb);
+        if let Some(image) = self.images.get(&key) {
+            return Ok(Some(Arc::clone(image)));
+        }
+
+        let path = self.base.join(filename);
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        let image = match extension.as_str() {
+            "hdr" => HdrImage::load(path)?,
+            "png" | "jpg" | "jpeg" | "tga" | "bmp" | "tif" | "tiff" => {
+                HdrImage::from_rgb(&image::open(path)?.to_rgb(), srgb)
+            }
+            _ => {
+                let warning = format!("'{}' can't be read", filename);
+                self.warn(warning + ", so its texture is gray");
+                return Ok(None);
+            }
+        };
+        let image = Arc::new(image);
+        self.images.insert(key, Arc::clone(&image));
+        Ok(Some(image))
+    }
+
+    fn camera(
+        &mut self,
+        kind: &str,
+        params: &ParamSet,
+    ) -> Result<(), PbrtError> {
+        if kind != "perspective" {
+            let warning = format!("'{}' cameras are rendered", kind);
+            self.warn(warning + " with perspective");
+        }
+        // pbrt's camera looks down +z, and the renderer's down -z
+        let camera_to_world = self.state.ctm.inversed();
+        self.coordinate_systems
+            .insert("camera".into(), camera_to_world);
+        self.scene.camera = PbrtCamera {
+            camera_to_world: camera_to_world * scaling(1.0, 1.0, -1.0),
+            fov: params.float("fov", 90.0)?.to_radians(),
+        };
+        Ok(())
+    }
+
+    fn light_source(
+        &mut self,
+        kind: &str,
+        params: &ParamSet,
+    ) -> Result<(), PbrtError> {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let scale = self.color(params, "scale", white)?;
+        if params.get("power").is_some() || params.get("illuminance").is_some()
+        {
+            self.warn("light power and illuminance are ignored".into());
+        }
+        let ctm = self.state.ctm;
+        let origin = point(0.0, 0.0, 0.0);
+        let from = params.point("from", origin)?;
+        let to = params.point("to", point(0.0, 0.0, 1.0))?;
+
+        let light = match kind {
+            "point" => PbrtLight::Point {
+                position: ctm * from,
+                intensity: self.color(params, "I", white)? * scale,
+            },
+            "spot" => PbrtLight::Spot {
+                position: ctm * from,
+                direction: (ctm * (to - from)).normalized(),
+                intensity: self.color(params, "I", white)? * scale,
+                cone_angle: params.float("coneangle", 30.0)?.to_radians(),
+                cone_delta: params.float("conedelta", 5.0)?.to_radians(),
+            },
+            "distant" => PbrtLight::Distant {
+                direction: (ctm * (to - from)).normalized(),
+                radiance: self.color(params, "L", white)? * scale,
+            },
+            "infinite" => {
+                let radiance = self.color(params, "L", white)? * scale;
+                let map = match params.string("filename")? {
+                    Some(filename) => self.environment_map(filename)?,
+                    None => None,
+                };
+                let map =
+                    map.unwrap_or_else(|| HdrImage::new(1, 1, vec![white]));
+                let pixels = (0..map.height())
+                    .flat_map(|y| (0..map.width()).map(move |x| (x, y)))
+                    .map(|(x, y)| map.get(x, y) * radiance)
+                    .collect();
+                PbrtLight::Infinite {
+                    map: HdrImage::new(map.width(), map.height(), pixels),
+                    light_to_world: ctm * latlong_axes(),
+                }
+            }
+            other => {
+                self.warn(format!("'{}' lights aren't supported", other));
+                return Ok(());
+            }
+        };
+        self.scene.lights.push(light);
+        Ok(())
+    }
+
+    /// The environment map in `filename`, or `None` if it isn't in a
+    /// format that can be read.
+    fn environment_map(
+        &mut self,
+        filename: &str,
+    ) -> Result<Option<HdrImage>, PbrtError> {
+        let path = self.base.join(filename);
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        if extension != "hdr" {
+            let warning = format!("'{}' can't be read", filename);
+            self.warn(warning + ", so its light is uniform");
+            return Ok(None);
+        }
+        let map = HdrImage::load(path)?;
+        if map.width() == map.height() {
+            let warning = format!("'{}' is square, and may be", filename);
+            self.warn(warning + " an equal-area map, which isn't supported");
+        }
+        Ok(Some(map))
+    }
+
+    fn shape(
+        &mut self,
+        kind: &str,
+        params: &ParamSet,
+    ) -> Result<(), PbrtError> {
+        // shapes with the interface material only mark the boundaries of
+        // media, and aren't seen
+        if let Some(material) = self.state.material {
+            let kind = self.scene.materials[material].kind.as_str();
+            if kind.is_empty() || kind == "none" || kind == "interface" {
+                return Ok(());
+            }
+        }
+
+        let ctm = self.state.ctm;
+        let mut mesh = match kind {
+            "trianglemesh" => triangle_mesh(params)?,
+            "plymesh" => {
+                let filename = params.string("filename")?.ok_or_else(|| {
+                    PbrtError::BadParameter("filename".into())
+                })?;
+                TriangleMesh::load(self.base.join(filename))?
+            }
+            "sphere" => {
+                let partial = ["zmin", "zmax", "phimax"];
+                if partial.iter().any(|name| params.get(name).is_some()) {
+                    self.warn("partial spheres are rendered whole".into());
+                }
+                let mut emission = self.state.emission;
+                if emission.is_some() && uniform_scale(&ctm).is_none() {
+                    let warning = "spheres scaled unevenly can't be";
+                    self.warn(format!("{} area lights", warning));
+                    emission = None;
+                }
+                let shape = PbrtShape::Sphere {
+                    radius: params.float("radius", 1.0)?,
+                    object_to_world: ctm,
+                };
+                self.add(shape, emission);
+                return Ok(());
+            }
+            other => {
+                self.warn(format!("'{}' shapes aren't supported", other));
+                return Ok(());
+            }
+        };
+        orient(&mut mesh, self.state.reverse_orientation);
+        mesh.transform(&Transform::new(ctm));
+        self.add(PbrtShape::Mesh(mesh), self.state.emission);
+        Ok(())
+    }
+
+    fn add(&mut self, shape: PbrtShape, emission: Option<Emission>) {
+        let mut object = PbrtObject {
+            shape,
+            material: self.state.material,
+            emission,
+        };
+        match &mut self.object {
+            Some(PbrtPrototype { objects, .. }) => {
+                if object.emission.take().is_some() {
+                    let warning = "area lights in object instances";
+                    self.scene
+                        .warnings
+                        .push(format!("{} are ignored", warning));
+                }
+                objects.push(object);
+            }
+            None => self.scene.objects.push(object),
+        }
+    }
+
+    /// Place a copy of the object `name` with the current transform.
+    fn instance(&mut self, name: String) -> Result<(), PbrtError> {
+        let prototype = *self
+            .prototypes
+            .get(&name)
+            .ok_or(PbrtError::Undefined(name))?;
+        if self.object.is_some() {
+            self.warn("ObjectInstance inside ObjectBegin is ignored".into());
+            return Ok(());
+        }
+        self.scene.instances.push(PbrtInstance {
+            prototype,
+            object_to_world: self.state.ctm,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::math::test_util::assert_eps_eq;
+    use super::super::math::{normal, Ray};
+    use super::*;
+    use image::RgbImage;
+    use std::env;
+
+    const EPS: f64 = 1.0e-9;
+
+    fn parse(text: &str) -> PbrtScene {
+        PbrtScene::parse(text, Path::new("")).unwrap()
+    }
+
+    fn object_to_world(object: &PbrtObject) -> DMat4 {
+        match object.shape {
+            PbrtShape::Sphere {
+                object_to_world, ..
+            } => object_to_world,
+            _ => panic!("not a sphere"),
+        }
+    }
+
+    #[test]
+    fn test_transforms() {
+        let scene = parse(
+            "WorldBegin
+             AttributeBegin
+               Translate 1 2 3
+               Shape \"sphere\"
+               CoordinateSystem \"moved\"
+             AttributeEnd
+             TransformBegin
+               Scale 2 2 2
+               Rotate 90 0 0 1
+               Shape \"sphere\" \"float radius\" 0.5
+             TransformEnd
+             Transform [1 0 0 0  0 1 0 0  0 0 1 0  4 5 6 1]
+             ConcatTransform [2 0 0 0  0 2 0 0  0 0 2 0  0 0 0 1]
+             Shape \"sphere\"
+             CoordSysTransform \"moved\"
+             Shape \"sphere\"
+             Identity
+             Shape \"sphere\"",
+        );
+        let m: Vec<DMat4> = scene.objects.iter().map(object_to_world).collect();
+        assert_eq!(m[0], translation(1.0, 2.0, 3.0));
+        assert_eps_eq(
+            &(m[1] * vector(1.0, 0.0, 0.0)),
+            &vector(0.0, 2.0, 0.0),
+            EPS,
+        );
+        assert_eq!(m[2], translation(4.0, 5.0, 6.0) * scaling(2.0, 2.0, 2.0));
+        assert_eq!(m[3], translation(1.0, 2.0, 3.0));
+        assert_eq!(m[4], DMat4::identity());
+
+        let bounds = scene.objects[1].shape.bounds();
+        assert_eps_eq(&bounds.max, &point(1.0, 1.0, 1.0), EPS);
+    }
+
+    #[test]
+    fn test_camera() {
+        let scene = parse(
+            "LookAt 0 0 5  0 0 0  0 1 0
+             Camera \"perspective\" \"float fov\" [45]
+             Film \"rgb\" \"integer xresolution\" 400
+               \"integer yresolution\" 200 \"string filename\" \"out.exr\"
+             Sampler \"halton\" \"integer pixelsamples\" 4
+             WorldBegin",
+        );
+        let camera = scene.camera;
+        let m = camera.camera_to_world;
+        assert_eps_eq(&(m * point(0.0, 0.0, 0.0)), &point(0.0, 0.0, 5.0), EPS);
+        assert_eps_eq(
+            &(m * vector(0.0, 0.0, -1.0)),
+            &vector(0.0, 0.0, -1.0),
+            EPS,
+        );
+        assert_eps_eq(
+            &(m * vector(0.0, 1.0, 0.0)),
+            &vector(0.0, 1.0, 0.0),
+            EPS,
+        );
+        // pbrt is left-handed, so looking down -z puts -x on the right
+        assert_eps_eq(
+            &(m * vector(1.0, 0.0, 0.0)),
+            &vector(-1.0, 0.0, 0.0),
+            EPS,
+        );
+
+        assert_eq!(camera.yfov(400, 200), 45.0f64.to_radians());
+        let tall = 2.0 * (22.5f64.to_radians().tan() * 2.0).atan();
+        assert!((camera.yfov(200, 400) - tall).abs() < EPS);
+        assert_eq!(
+            scene.film,
+            PbrtFilm {
+                width: 400,
+                height: 200,
+                filename: Some("out.exr".into()),
+            }
+        );
+        assert_eq!(scene.pixel_samples, 4);
+
+        let scene = parse("WorldBegin");
+        assert_eq!(scene.camera, PbrtCamera::default());
+        assert_eq!(scene.film, PbrtFilm::default());
+        assert_eq!(scene.pixel_samples, 16);
+    }
+
+    #[test]
+    fn test_triangle_mesh() {
+        let quad = "Shape \"trianglemesh\" \"integer indices\" [0 1 2 0 2 3]
+                    \"point3 P\" [0 0 0  1 0 0  1 1 0  0 1 0]
+                    \"point2 uv\" [0 0 1 0 1 1 0 1]";
+        let down = Ray::new(point(0.25, 0.75, 1.0), vector(0.0, 0.0, -1.0));
+        let up = Ray::new(point(0.25, 0.75, -1.0), vector(0.0, 0.0, 1.0));
+
+        // counterclockwise from +z, so facing +z as it does in pbrt
+        let (scene, lights) = parse(quad).build();
+        assert!(lights.is_empty());
+        let hit = scene.intersect(&down).unwrap();
+        assert_eps_eq(&hit.point, &point(0.25, 0.75, 0.0), EPS);
+        assert_eps_eq(&hit.normal, &normal(0.0, 0.0, 1.0), EPS);
+        assert_eps_eq(&hit.uv, &DVec2::new(0.25, 0.75), EPS);
+
+        let text = format!("ReverseOrientation {}", quad);
+        let hit = parse(&text).build().0.intersect(&down).unwrap();
+        assert_eps_eq(&hit.normal, &normal(0.0, 0.0, -1.0), EPS);
+
+        // vertex normals decide which way the triangles face
+        let text =
+            format!("{} \"normal N\" [0 0 -1 0 0 -1 0 0 -1 0 0 -1]", quad);
+        let hit = parse(&text).build().0.intersect(&down).unwrap();
+        assert_eps_eq(&hit.normal, &normal(0.0, 0.0, -1.0), EPS);
+
+        // a mirrored mesh still faces the way pbrt's does
+        let text = format!("Translate 0 0 -1 Scale 1 1 -1 {}", quad);
+        let hit = parse(&text).build().0.intersect(&down).unwrap();
+        assert_eps_eq(&hit.normal, &normal(0.0, 0.0, -1.0), EPS);
+
+        // a one-sided area light only emits on its front
+        let text = format!(
+            "AttributeBegin AreaLightSource \"diffuse\" \"rgb L\" [1 2 3]
+             \"float scale\" 2 {} AttributeEnd",
+            quad
+        );
+        let (scene, lights) = parse(&text).build();
+        assert_eq!(lights.len(), 2);
+        let emitted = scene.intersect(&down).unwrap().emitted;
+        assert_eq!(emitted, Color::new(2.0, 4.0, 6.0));
+        assert_eq!(scene.intersect(&up).unwrap().emitted, Color::black());
+
+        let text = "Shape \"trianglemesh\" \"point3 P\" [0 0 0 1 0 0 0 1 0]";
+        assert_eq!(parse(text).objects.len(), 1);
+        let bad = [
+            "Shape \"trianglemesh\" \"point3 P\" [0 0 0 1 0 0 0 1 0 1 1 0]",
+            "Shape \"trianglemesh\" \"integer indices\" [0 1 3]
+             \"point3 P\" [0 0 0 1 0 0 0 1 0]",
+            "Shape \"trianglemesh\" \"point3 P\" [0 0 0 1 0 0 0 1 0]
+             \"normal N\" [0 0 1]",
+        ];
+        for text in bad.iter() {
+            match PbrtScene::parse(text, Path::new("")) {
+                Err(PbrtError::BadParameter(_)) => (),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_spheres() {
+        let scene = parse(
+            "AttributeBegin
+               AreaLightSource \"diffuse\" \"blackbody L\" 3000
+               Translate 0 0 -4 Scale 2 2 2
+               Shape \"sphere\" \"float radius\" 0.5
+             AttributeEnd
+             AttributeBegin
+               AreaLightSource \"diffuse\" \"rgb L\" [1 1 1]
+               Translate 0 5 0 Scale 3 1 1
+               Shape \"sphere\"
+             AttributeEnd
+             Shape \"sphere\" \"float zmax\" 0.5",
+        );
+        assert_eq!(
+            scene.objects[0].emission,
+            Some(Emission {
+                radiance: Color::new(1.0, 1.0, 1.0),
+                two_sided: false,
+            })
+        );
+        assert_eq!(scene.objects[1].emission, None);
+        assert_eq!(scene.objects[2].emission, None);
+        assert_eq!(scene.warnings.len(), 3);
+
+        let (world, lights) = scene.build();
+        assert_eq!(lights.len(), 1);
+        let ray = Ray::new(point(0.0, 0.0, -1.5), vector(0.0, 0.0, -1.0));
+        let hit = world.intersect(&ray).unwrap();
+        assert!((hit.t - 1.5).abs() < EPS);
+        assert_eq!(hit.emitted, Color::new(1.0, 1.0, 1.0));
+        let ray = Ray::new(point(-10.0, 5.0, 0.0), vector(1.0, 0.0, 0.0));
+        assert!((world.intersect(&ray).unwrap().t - 7.0).abs() < EPS);
+        let ray = Ray::new(point(0.0, -10.0, 0.0), vector(0.0, 1.0, 0.0));
+        assert!((world.intersect(&ray).unwrap().t - 9.0).abs() < EPS);
+    }
+
+    #[test]
+    fn test_lights() {
+        let scene = parse(
+            "WorldBegin
+             LightSource \"point\" \"rgb I\" [1 2 3] \"point3 from\" [0 1 0]
+             Translate 0 0 1
+             LightSource \"spot\" \"rgb I\" [4 4 4] \"float scale\" 0.5
+               \"point3 to\" [1 0 0] \"float coneangle\" 20
+             LightSource \"distant\" \"point3 from\" [0 1 0]
+               \"point3 to\" [0 0 0] \"spectrum L\" [400 1 700 1]
+             LightSource \"infinite\" \"rgb L\" [0.5 0.5 0.5]
+             LightSource \"goniometric\"",
+        );
+        assert_eq!(scene.lights.len(), 4);
+        assert_eq!(
+            scene.lights[0],
+            PbrtLight::Point {
+                position: point(0.0, 1.0, 0.0),
+                intensity: Color::new(1.0, 2.0, 3.0),
+            }
+        );
+        match &scene.lights[1] {
+            PbrtLight::Spot {
+                position,
+                direction,
+                intensity,
+                cone_angle,
+                cone_delta,
+            } => {
+                assert_eq!(*position, point(0.0, 0.0, 1.0));
+                assert_eps_eq(direction, &vector(1.0, 0.0, 0.0), EPS);
+                assert_eq!(*intensity, Color::new(2.0, 2.0, 2.0));
+                assert_eq!(*cone_angle, 20.0f64.to_radians());
+                assert_eq!(*cone_delta, 5.0f64.to_radians());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            scene.lights[2],
+            PbrtLight::Distant {
+                direction: vector(0.0, -1.0, 0.0),
+                radiance: Color::new(1.0, 1.0, 1.0),
+            }
+        );
+        match &scene.lights[3] {
+            PbrtLight::Infinite {
+                map,
+                light_to_world,
+            } => {
+                assert_eq!(map.get(0, 0), Color::new(0.5, 0.5, 0.5));
+                // the top of pbrt's maps is +z
+                assert_eps_eq(
+                    &(*light_to_world * vector(0.0, 1.0, 0.0)),
+                    &vector(0.0, 0.0, 1.0),
+                    EPS,
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(scene.warnings.len(), 2);
+        assert_eq!(scene.build().1.len(), 4);
+    }
+
+    #[test]
+    fn test_materials() {
+        let scene = parse(
+            "WorldBegin
+             Texture \"checks\" \"spectrum\" \"checkerboard\"
+               \"float uscale\" 4
+             MakeNamedMaterial \"red\" \"string type\" \"diffuse\"
+               \"rgb reflectance\" [0.8 0.1 0.1]
+             MakeNamedMaterial \"boundary\" \"string type\" \"interface\"
+             Shape \"sphere\"
+             AttributeBegin
+               Material \"diffuse\" \"texture reflectance\" \"checks\"
+               Shape \"sphere\"
+               NamedMaterial \"red\"
+               Shape \"sphere\"
+               NamedMaterial \"boundary\"
+               Shape \"sphere\"
+             AttributeEnd
+             Shape \"sphere\"",
+        );
+        assert_eq!(scene.textures.len(), 1);
+        assert_eq!(scene.textures[0].class, "checkerboard");
+        assert_eq!(scene.textures[0].params.float("uscale", 1.0).unwrap(), 4.0);
+
+        // the shape with the interface material isn't there
+        assert_eq!(scene.objects.len(), 4);
+        assert_eq!(scene.material(0), None);
+        assert_eq!(scene.material(1).unwrap().kind, "diffuse");
+        let red = scene.material(2).unwrap();
+        assert_eq!(red.name.as_deref(), Some("red"));
+        let mut warnings = Vec::new();
+        let reflectance =
+            red.params
+                .color("reflectance", Color::black(), &mut warnings);
+        assert_eq!(reflectance.unwrap(), Color::new(0.8, 0.1, 0.1));
+        assert_eq!(scene.material(3), None);
+
+        // which hits carry for the renderer to shade with
+        let red_color = Color::new(0.8, 0.1, 0.1);
+        assert_eq!(red.reflectance, PbrtReflectance::Constant(red_color));
+        let gray = PbrtReflectance::Constant(Color::new(0.5, 0.5, 0.5));
+        assert_eq!(scene.material(1).unwrap().reflectance, gray);
+        assert!(scene.warnings.contains(
+            &"'checkerboard' textures are rendered as gray".to_string()
+        ));
+
+        match PbrtScene::parse("NamedMaterial \"missing\"", Path::new("")) {
+            Err(PbrtError::Undefined(name)) => assert_eq!(name, "missing"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_material_kinds() {
+        let scene = parse(
+            "WorldBegin
+             Material \"matte\" \"rgb Kd\" [0.1 0.2 0.3]
+             Material \"coateddiffuse\" \"rgb reflectance\" [0.4 0.5 0.6]
+             Material \"conductor\" \"spectrum eta\" \"metal-Cu-eta\"
+             Shape \"sphere\"",
+        );
+        let reflectances: Vec<_> = scene
+            .materials
+            .iter()
+            .map(|material| material.reflectance.at(DVec2::zero()))
+            .collect();
+        assert_eq!(
+            reflectances,
+            vec![
+                Color::new(0.1, 0.2, 0.3),
+                Color::new(0.4, 0.5, 0.6),
+                Color::new(0.5, 0.5, 0.5),
+            ]
+        );
+        assert_eq!(
+            scene.warnings,
+            vec![
+                "'coateddiffuse' materials are rendered as just their \
+                 diffuse part",
+                "'conductor' materials are rendered as gray diffuse",
+            ]
+        );
+        let ray = Ray::new(point(0.0, 0.0, 5.0), vector(0.0, 0.0, -1.0));
+        let hit = scene.build().0.intersect(&ray).unwrap();
+        assert_eq!(hit.material, Some(2));
+
+        match PbrtScene::parse(
+            "Material \"diffuse\" \"texture reflectance\" \"missing\"",
+            Path::new(""),
+        ) {
+            Err(PbrtError::Undefined(name)) => assert_eq!(name, "missing"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_image_texture() {
+        let dir = env::temp_dir().join("raytracer_test_pbrt_texture");
+        fs::create_dir_all(&dir).unwrap();
+        // a red pixel and a dark green one
+        let pixels = vec![255, 0, 0, 0, 128, 0];
+        let image = RgbImage::from_raw(2, 1, pixels).unwrap();
+        image.save(dir.join("grid.png")).unwrap();
+        let scene = PbrtScene::parse(
+            "Texture \"grid\" \"spectrum\" \"imagemap\"
+               \"string filename\" \"grid.png\" \"float uscale\" 2
+             Texture \"linear\" \"spectrum\" \"imagemap\"
+               \"string filename\" \"grid.png\" \"string encoding\" \"linear\"
+               \"float scale\" 2
+             Material \"matte\" \"texture Kd\" \"grid\"
+             Material \"diffuse\" \"texture reflectance\" \"linear\"",
+            &dir,
+        )
+        .unwrap();
+
+        // u is doubled, so the image repeats twice across the surface
+        let grid = &scene.materials[0].reflectance;
+        assert_eq!(grid.at(DVec2::new(0.1, 0.5)), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(grid.at(DVec2::new(0.6, 0.5)), Color::new(1.0, 0.0, 0.0));
+        let green = grid.at(DVec2::new(0.3, 0.5));
+        assert!((green.g() - 0.2158605).abs() < 1.0e-6);
+        let linear = &scene.materials[1].reflectance;
+        let green = linear.at(DVec2::new(0.75, 0.5));
+        assert!((green.g() - 2.0 * 128.0 / 255.0).abs() < 1.0e-12);
+        assert!(scene.warnings.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_object_instances() {
+        let scene = parse(
+            "WorldBegin
+             ObjectBegin \"ball\"
+               AreaLightSource \"diffuse\"
+               Shape \"sphere\"
+               Shape \"trianglemesh\" \"point3 P\" [0 0 3 1 0 3 0 1 3]
+             ObjectEnd
+             Shape \"sphere\"
+             Translate 5 0 0
+             ObjectInstance \"ball\"
+             Translate 5 0 0
+             ObjectInstance \"ball\"",
+        );
+        assert_eq!(scene.objects.len(), 1);
+        assert_eq!(scene.prototypes.len(), 1);
+        let ball = &scene.prototypes[0];
+        assert_eq!(ball.name, "ball");
+        assert_eq!(ball.objects.len(), 2);
+        assert!(ball.objects.iter().all(|o| o.emission.is_none()));
+        assert_eq!(scene.warnings.len(), 1);
+
+        // the copies keep the shapes in object space and only add a
+        // transform
+        let placed: Vec<DMat4> =
+            scene.instances.iter().map(|i| i.object_to_world).collect();
+        assert_eq!(
+            placed,
+            vec![translation(5.0, 0.0, 0.0), translation(10.0, 0.0, 0.0)]
+        );
+        assert_eq!(object_to_world(&ball.objects[0]), DMat4::identity());
+        assert_eps_eq(&scene.bounds().max, &point(11.0, 1.0, 3.0), EPS);
+
+        let (world, _) = scene.build();
+        let down = Ray::new(point(10.0, 5.0, 0.0), vector(0.0, -1.0, 0.0));
+        assert_eps_eq(&world.intersect(&down).unwrap().t, &4.0, EPS);
+        let back = Ray::new(point(10.5, 0.25, 5.0), vector(0.0, 0.0, -1.0));
+        let hit = world.intersect(&back).unwrap();
+        assert_eps_eq(&hit.point, &point(10.5, 0.25, 3.0), EPS);
+
+        match PbrtScene::parse("ObjectInstance \"missing\"", Path::new("")) {
+            Err(PbrtError::Undefined(name)) => assert_eq!(name, "missing"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_include_and_plymesh() {
+        let dir = env::temp_dir().join("raytracer_test_pbrt");
+        fs::create_dir_all(&dir).unwrap();
+        let ply = "ply\n\
+                   format ascii 1.0\n\
+                   element vertex 3\n\
+                   property float x\n\
+                   property float y\n\
+                   property float z\n\
+                   element face 1\n\
+                   property list uchar int vertex_indices\n\
+                   end_header\n\
+                   0 0 0\n\
+                   1 0 0\n\
+                   0 1 0\n\
+                   3 0 1 2\n";
+        fs::write(dir.join("triangle.ply"), ply).unwrap();
+        fs::write(
+            dir.join("geometry.pbrt"),
+            "Shape \"plymesh\" \"string filename\" \"triangle.ply\"",
+        )
+        .unwrap();
+        let path = dir.join("scene.pbrt");
+        fs::write(
+            &path,
+            "WorldBegin\nTranslate 0 0 -2\nInclude \"geometry.pbrt\"",
+        )
+        .unwrap();
+
+        let scene = PbrtScene::load(&path).unwrap();
+        assert_eq!(scene.objects.len(), 1);
+        let ray = Ray::new(point(0.25, 0.25, 0.0), vector(0.0, 0.0, -1.0));
+        let hit = scene.build().0.intersect(&ray).unwrap();
+        assert!((hit.t - 2.0).abs() < EPS);
+        assert_eps_eq(&hit.normal, &normal(0.0, 0.0, 1.0), EPS);
+
+        let path = dir.join("loop.pbrt");
+        fs::write(&path, "Include \"loop.pbrt\"").unwrap();
+        match PbrtScene::load(&path) {
+            Err(PbrtError::Unsupported(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match PbrtScene::load(dir.join("missing.pbrt")) {
+            Err(PbrtError::Io(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let parse = |text: &str| PbrtScene::parse(text, Path::new(""));
+        match parse("AttributeBegin\nAttributeEnd\nAttributeEnd") {
+            Err(PbrtError::Unexpected(s, 3)) => assert_eq!(s, "AttributeEnd"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse("AttributeBegin\nTransformEnd") {
+            Err(PbrtError::Unexpected(s, 2)) => assert_eq!(s, "TransformEnd"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse("Translate 1 2") {
+            Err(PbrtError::UnexpectedEnd) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse("Translate 1 two 3") {
+            Err(PbrtError::BadNumber(s)) => assert_eq!(s, "two"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse("\"sphere\"") {
+            Err(PbrtError::Unexpected(s, 1)) => assert_eq!(s, "\"sphere\""),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse("LookAt 0 0 0  0 1 0  0 1 0") {
+            Err(PbrtError::BadParameter(s)) => assert_eq!(s, "LookAt"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse("Film \"rgb\" \"integer xresolution\" 0") {
+            Err(PbrtError::BadParameter(s)) => assert_eq!(s, "xresolution"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse("Sampler \"halton\" \"integer pixelsamples\" -4") {
+            Err(PbrtError::BadParameter(s)) => assert_eq!(s, "pixelsamples"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // unknown directives are skipped along with their arguments
+        let scene = parse(
+            "MakeNamedThing \"x\" \"float y\" [1 2] true
+             AttributeBegin Shape \"sphere\" Shape \"disk\"",
+        )
+        .unwrap();
+        assert_eq!(scene.objects.len(), 1);
+        assert_eq!(scene.warnings.len(), 3);
+    }
+}
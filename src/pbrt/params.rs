@@ -0,0 +1,260 @@
+use super::super::color::Color;
+use super::super::math::{point, Point3};
+use super::PbrtError;
+use std::convert::TryFrom;
+
+/// The values of a parameter, which all have the same type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamValue {
+    Numbers(Vec<f64>),
+    Strings(Vec<String>),
+    Bools(Vec<bool>),
+}
+
+/// One parameter of a directive, like `"float radius" [2]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    /// The declared type, like "float", "point3" or "rgb".
+    pub kind: String,
+    pub name: String,
+    pub value: ParamValue,
+}
+
+/// The parameter list of a directive.
+///
+/// Lookups by name fail with `BadParameter` when the parameter is there
+/// but holds the wrong type or number of values, and give `None` or the
+/// default when it isn't there at all.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ParamSet {
+    pub params: Vec<Param>,
+}
+
+impl ParamSet {
+    pub fn new(params: Vec<Param>) -> ParamSet {
+        ParamSet { params }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Param> {
+        self.params.iter().find(|param| param.name == name)
+    }
+
+    fn bad(name: &str) -> PbrtError {
+        PbrtError::BadParameter(name.into())
+    }
+
+    pub fn floats(&self, name: &str) -> Result<Option<&[f64]>, PbrtError> {
+        match self.get(name).map(|param| &param.value) {
+            Some(ParamValue::Numbers(numbers)) => Ok(Some(numbers)),
+            Some(_) => Err(ParamSet::bad(name)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn float(&self, name: &str, default: f64) -> Result<f64, PbrtError> {
+        match self.floats(name)? {
+            Some(&[x]) => Ok(x),
+            Some(_) => Err(ParamSet::bad(name)),
+            None => Ok(default),
+        }
+    }
+
+    /// Whole, non-negative numbers, like the indices of a mesh.
+    pub fn indices(&self, name: &str) -> Result<Option<Vec<usize>>, PbrtError> {
+        match self.floats(name)? {
+            Some(numbers) => numbers
+                .iter()
+                .map(|&x| {
+                    if x >= 0.0 && x.fract() == 0.0 {
+                        Ok(x as usize)
+                    } else {
+                        Err(ParamSet::bad(name))
+                    }
+                })
+                .collect::<Result<_, _>>()
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn integer(
+        &self,
+        name: &str,
+        default: usize,
+    ) -> Result<usize, PbrtError> {
+        match self.indices(name)?.as_deref() {
+            Some(&[n]) => Ok(n),
+            Some(_) => Err(ParamSet::bad(name)),
+            None => Ok(default),
+        }
+    }
+
+    /// A whole number of at least 1, like a resolution or a sample count.
+    pub fn count(&self, name: &str, default: u32) -> Result<u32, PbrtError> {
+        match self.integer(name, default as usize)? {
+            0 => Err(ParamSet::bad(name)),
+            n => u32::try_from(n).map_err(|_| ParamSet::bad(name)),
+        }
+    }
+
+    /// A list of points, three numbers each.
+    pub fn points(&self, name: &str) -> Result<Option<Vec<Point3>>, PbrtError> {
+        match self.floats(name)? {
+            Some(numbers) if numbers.len() % 3 == 0 => Ok(Some(
+                numbers
+                    .chunks_exact(3)
+                    .map(|p| point(p[0], p[1], p[2]))
+                    .collect(),
+            )),
+            Some(_) => Err(ParamSet::bad(name)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn point(
+        &self,
+        name: &str,
+        default: Point3,
+    ) -> Result<Point3, PbrtError> {
+        match self.points(name)?.as_deref() {
+            Some(&[p]) => Ok(p),
+            Some(_) => Err(ParamSet::bad(name)),
+            None => Ok(default),
+        }
+    }
+
+    pub fn string(&self, name: &str) -> Result<Option<&str>, PbrtError> {
+        match self.get(name).map(|param| &param.value) {
+            Some(ParamValue::Strings(strings)) if strings.len() == 1 => {
+                Ok(Some(&strings[0]))
+            }
+            Some(_) => Err(ParamSet::bad(name)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn bool(&self, name: &str, default: bool) -> Result<bool, PbrtError> {
+        match self.get(name).map(|param| &param.value) {
+            Some(ParamValue::Bools(bools)) if bools.len() == 1 => Ok(bools[0]),
+            Some(_) => Err(ParamSet::bad(name)),
+            None => Ok(default),
+        }
+    }
+
+    /// A color given as "rgb" or "color", or as a single "float" for gray.
+    ///
+    /// Spectral colors can't be converted exactly, so they come out as
+    /// approximations and add a note to `warnings`: sampled spectra as the
+    /// gray of their average value, and named spectra and blackbody
+    /// emitters as white.
+    pub fn color(
+        &self,
+        name: &str,
+        default: Color,
+        warnings: &mut Vec<String>,
+    ) -> Result<Color, PbrtError> {
+        let param = match self.get(name) {
+            Some(param) => param,
+            None => return Ok(default),
+        };
+        match (param.kind.as_str(), &param.value) {
+            ("rgb", ParamValue::Numbers(c))
+            | ("color", ParamValue::Numbers(c))
+                if c.len() == 3 =>
+            {
+                Ok(Color::new(c[0], c[1], c[2]))
+            }
+            ("float", ParamValue::Numbers(c)) if c.len() == 1 => {
+                Ok(Color::new(c[0], c[0], c[0]))
+            }
+            // pairs of wavelength and value
+            ("spectrum", ParamValue::Numbers(c))
+                if c.len() >= 2 && c.len() % 2 == 0 =>
+            {
+                warnings.push(format!("'{}' approximated as gray", name));
+                let values = c.iter().skip(1).step_by(2);
+                let gray = values.sum::<f64>() / (c.len() / 2) as f64;
+                Ok(Color::new(gray, gray, gray))
+            }
+            ("spectrum", ParamValue::Strings(_))
+            | ("blackbody", ParamValue::Numbers(_)) => {
+                warnings.push(format!("'{}' approximated as white", name));
+                Ok(Color::new(1.0, 1.0, 1.0))
+            }
+            _ => Err(ParamSet::bad(name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(kind: &str, name: &str, value: ParamValue) -> Param {
+        Param {
+            kind: kind.into(),
+            name: name.into(),
+            value,
+        }
+    }
+
+    #[test]
+    fn test_color() {
+        let params = ParamSet::new(vec![
+            param("rgb", "Kd", ParamValue::Numbers(vec![0.1, 0.2, 0.3])),
+            param("float", "scale", ParamValue::Numbers(vec![2.0])),
+            param(
+                "spectrum",
+                "L",
+                ParamValue::Numbers(vec![400.0, 1.0, 700.0, 3.0]),
+            ),
+            param("blackbody", "I", ParamValue::Numbers(vec![5500.0])),
+            param("rgb", "bad", ParamValue::Numbers(vec![1.0, 2.0])),
+        ]);
+        let mut warnings = Vec::new();
+        let white = Color::new(1.0, 1.0, 1.0);
+        let mut color =
+            |name| params.color(name, Color::black(), &mut warnings);
+        assert_eq!(color("Kd").unwrap(), Color::new(0.1, 0.2, 0.3));
+        assert_eq!(color("scale").unwrap(), Color::new(2.0, 2.0, 2.0));
+        assert_eq!(color("L").unwrap(), Color::new(2.0, 2.0, 2.0));
+        assert_eq!(color("I").unwrap(), white);
+        assert_eq!(color("missing").unwrap(), Color::black());
+        assert!(color("bad").is_err());
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn test_indices_and_points() {
+        let params = ParamSet::new(vec![
+            param("integer", "indices", ParamValue::Numbers(vec![0.0, 2.0])),
+            param("integer", "bad", ParamValue::Numbers(vec![-1.0])),
+            param("point3", "P", ParamValue::Numbers(vec![1.0, 2.0, 3.0])),
+        ]);
+        assert_eq!(params.indices("indices").unwrap(), Some(vec![0, 2]));
+        assert!(params.indices("bad").is_err());
+        assert_eq!(
+            params.point("P", point(0.0, 0.0, 0.0)).unwrap(),
+            point(1.0, 2.0, 3.0)
+        );
+        assert!(params.points("indices").is_err());
+    }
+
+    #[test]
+    fn test_count() {
+        let params = ParamSet::new(vec![
+            param("integer", "n", ParamValue::Numbers(vec![8.0])),
+            param("integer", "zero", ParamValue::Numbers(vec![0.0])),
+            param("integer", "negative", ParamValue::Numbers(vec![-4.0])),
+            param("integer", "huge", ParamValue::Numbers(vec![1.0e10])),
+        ]);
+        assert_eq!(params.count("n", 1).unwrap(), 8);
+        assert_eq!(params.count("missing", 16).unwrap(), 16);
+        for &name in ["zero", "negative", "huge"].iter() {
+            match params.count(name, 1) {
+                Err(PbrtError::BadParameter(s)) => assert_eq!(s, name),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use super::params::{Param, ParamSet, ParamValue};
+use super::PbrtError;
+
+/// A piece of a scene file: a bare word, which is a directive, a number or
+/// a bool, a quoted string, or one of the brackets around an array.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(String),
+    Str(String),
+    Open,
+    Close,
+}
+
+impl Token {
+    fn text(&self) -> String {
+        match self {
+            Token::Word(s) => s.clone(),
+            Token::Str(s) => format!("\"{}\"", s),
+            Token::Open => "[".into(),
+            Token::Close => "]".into(),
+        }
+    }
+}
+
+/// Split a scene file into tokens, each with the line it's on. Comments
+/// run from `#` to the end of the line.
+pub fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, PbrtError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => (),
+            '#' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '[' => tokens.push((Token::Open, line)),
+            ']' => tokens.push((Token::Close, line)),
+            '"' => {
+                let start = line;
+                let mut s = String::new();
+                loop {
+                    match chars.next().ok_or(PbrtError::UnexpectedEnd)? {
+                        '"' => break,
+                        '\\' => {
+                            match chars
+                                .next()
+                                .ok_or(PbrtError::UnexpectedEnd)?
+                            {
+                                'n' => s.push('\n'),
+                                't' => s.push('\t'),
+                                c => s.push(c),
+                            }
+                        }
+                        '\n' => {
+                            let what = "newline in string".to_string();
+                            return Err(PbrtError::Unexpected(what, line));
+                        }
+                        c => s.push(c),
+                    }
+                }
+                tokens.push((Token::Str(s), start));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "[]\"#".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push((Token::Word(word), line));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads the arguments of directives from a list of tokens.
+pub struct Tokens<'a> {
+    tokens: &'a [(Token, usize)],
+    position: usize,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(tokens: &'a [(Token, usize)]) -> Tokens<'a> {
+        Tokens {
+            tokens,
+            position: 0,
+        }
+    }
+
+    pub fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    /// The line of the last token read, for error messages.
+    pub fn line(&self) -> usize {
+        match self.position {
+            0 => 1,
+            p => self.tokens[p - 1].1,
+        }
+    }
+
+    pub fn next(&mut self) -> Option<&'a Token> {
+        let token = self.peek()?;
+        self.position += 1;
+        Some(token)
+    }
+
+    pub fn unexpected(&self, token: &Token) -> PbrtError {
+        PbrtError::Unexpected(token.text(), self.line())
+    }
+
+    pub fn string(&mut self) -> Result<String, PbrtError> {
+        match self.next().ok_or(PbrtError::UnexpectedEnd)? {
+            Token::Str(s) => Ok(s.clone()),
+            token => Err(self.unexpected(token)),
+        }
+    }
+
+    pub fn number(&mut self) -> Result<f64, PbrtError> {
+        match self.next().ok_or(PbrtError::UnexpectedEnd)? {
+            Token::Word(s) => {
+                s.parse().map_err(|_| PbrtError::BadNumber(s.clone()))
+            }
+            token => Err(self.unexpected(token)),
+        }
+    }
+
+    /// `n` numbers, which may be in brackets or not.
+    pub fn numbers(&mut self, n: usize) -> Result<Vec<f64>, PbrtError> {
+        let bracketed = self.peek() == Some(&Token::Open);
+        if bracketed {
+            self.next();
+        }
+        let numbers = (0..n)
+            .map(|_| self.number())
+            .collect::<Result<Vec<_>, _>>()?;
+        if bracketed {
+            match self.next().ok_or(PbrtError::UnexpectedEnd)? {
+                Token::Close => (),
+                token => return Err(self.unexpected(token)),
+            }
+        }
+        Ok(numbers)
+    }
+
+    /// The parameter list following a directive's fixed arguments: pairs of
+    /// a `"type name"` string and a value or a bracketed array of them.
+    pub fn params(&mut self) -> Result<ParamSet, PbrtError> {
+        let mut params = Vec::new();
+        while let Some(Token::Str(declaration)) = self.peek() {
+            self.next();
+            let mut words = declaration.split_whitespace();
+            let (kind, name) = match (words.next(), words.next(), words.next())
+            {
+                (Some(kind), Some(name), None) => (kind, name),
+                _ => return Err(PbrtError::BadParameter(declaration.clone())),
+            };
+
+            let mut values = Vec::new();
+            match self.next().ok_or(PbrtError::UnexpectedEnd)? {
+                Token::Open => loop {
+                    match self.next().ok_or(PbrtError::UnexpectedEnd)? {
+                        Token::Close => break,
+                        Token::Open => {
+                            return Err(self.unexpected(&Token::Open));
+                        }
+                        token => values.push(token),
+                    }
+                },
+                token @ Token::Word(_) | token @ Token::Str(_) => {
+                    values.push(token)
+                }
+                token => return Err(self.unexpected(token)),
+            }
+
+            params.push(Param {
+                kind: kind.to_string(),
+                name: name.to_string(),
+                value: parse_value(kind, &values)?,
+            });
+        }
+        Ok(ParamSet::new(params))
+    }
+}
+
+fn parse_value(kind: &str, values: &[&Token]) -> Result<ParamValue, PbrtError> {
+    let words = || {
+        values.iter().map(|token| match token {
+            Token::Word(s) | Token::Str(s) => s.as_str(),
+            _ => "",
+        })
+    };
+
+    if kind == "bool" {
+        // bools are bare words in pbrt-v4 and strings before that
+        return words()
+            .map(|s| match s {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                s => Err(PbrtError::BadParameter(s.into())),
+            })
+            .collect::<Result<_, _>>()
+            .map(ParamValue::Bools);
+    }
+    if values.iter().all(|token| matches!(token, Token::Str(_))) {
+        return Ok(ParamValue::Strings(words().map(String::from).collect()));
+    }
+    words()
+        .map(|s| s.parse().map_err(|_| PbrtError::BadNumber(s.into())))
+        .collect::<Result<_, _>>()
+        .map(ParamValue::Numbers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> Vec<Token> {
+        tokenize(text)
+            .unwrap()
+            .into_iter()
+            .map(|(t, _)| t)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let word = |s: &str| Token::Word(s.into());
+        assert_eq!(
+            words("Shape \"sphere\" # a comment\n\"float radius\" [2.5]"),
+            vec![
+                word("Shape"),
+                Token::Str("sphere".into()),
+                Token::Str("float radius".into()),
+                Token::Open,
+                word("2.5"),
+                Token::Close,
+            ]
+        );
+        assert_eq!(
+            words("[1 -2e3]\"a\\\"b\""),
+            vec![
+                Token::Open,
+                word("1"),
+                word("-2e3"),
+                Token::Close,
+                Token::Str("a\"b".into()),
+            ]
+        );
+
+        let tokens = tokenize("A\n\n\"b\"\nC").unwrap();
+        let lines: Vec<usize> = tokens.iter().map(|(_, line)| *line).collect();
+        assert_eq!(lines, vec![1, 3, 4]);
+        match tokenize("\"unterminated") {
+            Err(PbrtError::UnexpectedEnd) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_params() {
+        let tokens = tokenize(
+            "\"point3 P\" [0 0 0 1 0 0] \"float radius\" 2 \
+             \"string filename\" \"a.ply\" \"bool twosided\" true \
+             \"bool old\" \"false\" \"spectrum eta\" \"metal-Cu-eta\" \
+             WorldBegin",
+        )
+        .unwrap();
+        let mut tokens = Tokens::new(&tokens);
+        let params = tokens.params().unwrap();
+        assert_eq!(tokens.peek(), Some(&Token::Word("WorldBegin".into())));
+
+        assert_eq!(
+            params.floats("P").unwrap(),
+            Some(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0][..])
+        );
+        assert_eq!(params.float("radius", 1.0).unwrap(), 2.0);
+        assert_eq!(params.float("missing", 1.0).unwrap(), 1.0);
+        assert_eq!(params.string("filename").unwrap(), Some("a.ply"));
+        assert!(params.bool("twosided", false).unwrap());
+        assert!(!params.bool("old", true).unwrap());
+        assert_eq!(params.string("eta").unwrap(), Some("metal-Cu-eta"));
+        match params.float("filename", 0.0) {
+            Err(PbrtError::BadParameter(s)) => assert_eq!(s, "filename"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_param_errors() {
+        let parse = |text: &str| {
+            let tokens = tokenize(text).unwrap();
+            Tokens::new(&tokens).params()
+        };
+        match parse("\"float\" 1") {
+            Err(PbrtError::BadParameter(s)) => assert_eq!(s, "float"),
+            other => panic!("unexpected {:?}", other.err()),
+        }
+        match parse("\"float radius\" [1 x]") {
+            Err(PbrtError::BadNumber(s)) => assert_eq!(s, "x"),
+            other => panic!("unexpected {:?}", other.err()),
+        }
+        match parse("\"float radius\" [1 2") {
+            Err(PbrtError::UnexpectedEnd) => (),
+            other => panic!("unexpected {:?}", other.err()),
+        }
+        match parse("\"bool on\" maybe") {
+            Err(PbrtError::BadParameter(s)) => assert_eq!(s, "maybe"),
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,883 @@
+use super::bsdf::Lambertian;
+use super::camera::Camera;
+use super::color::Color;
+use super::light::Light;
+use super::light_sampler::{LightSampler, PowerLightSampler};
+use super::material::Material;
+use super::math::{Normal3, Point3, Ray, Vector3};
+use super::path::{self, PathTracer};
+use super::primitive::Scene;
+use rand::Rng;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f64::consts::PI;
+
+/// Bounces after which photons may be absorbed by Russian roulette.
+const MIN_BOUNCES: u32 = 3;
+
+/// How much of the photons a pixel gathers each iteration of progressive
+/// photon mapping it keeps.
+const ALPHA: f64 = 2.0 / 3.0;
+
+/// A packet of light deposited on a surface while tracing paths from the
+/// lights. `direction` points back towards where the photon came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Photon {
+    pub position: Point3,
+    pub direction: Vector3,
+    pub power: Color,
+}
+
+impl Photon {
+    pub fn new(position: Point3, direction: Vector3, power: Color) -> Photon {
+        Photon {
+            position,
+            direction,
+            power,
+        }
+    }
+}
+
+/// A balanced kd-tree of photons. The tree is stored implicitly: the photons
+/// in `photons[lo..hi]` are split by the median at `(lo + hi) / 2` along
+/// `axes[(lo + hi) / 2]`, with the lower half to the left of the median and
+/// the upper half to the right.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+}
+
+/// A photon found by a nearest neighbor search, ordered by its squared
+/// distance to the query point so it can live in a max-heap.
+struct Neighbor<'a> {
+    dist_sq: f64,
+    photon: &'a Photon,
+}
+
+impl PartialEq for Neighbor<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.dist_sq == other.dist_sq
+    }
+}
+
+impl Eq for Neighbor<'_> {}
+
+impl PartialOrd for Neighbor<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbor<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.dist_sq
+            .partial_cmp(&other.dist_sq)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        PhotonMap { photons, axes }
+    }
+
+    /// Return every photon within `radius` of `point`.
+    pub fn gather(&self, point: Point3, radius: f64) -> Vec<&Photon> {
+        let mut found = Vec::new();
+        self.gather_range(0, self.photons.len(), point, radius, &mut found);
+        found
+    }
+
+    fn gather_range<'a>(
+        &'a self,
+        lo: usize,
+        hi: usize,
+        point: Point3,
+        radius: f64,
+        found: &mut Vec<&'a Photon>,
+    ) {
+        if lo >= hi {
+            return;
+        }
+
+        let mid = (lo + hi) / 2;
+        let photon = &self.photons[mid];
+        if (photon.position - point).mag_sq() <= radius * radius {
+            found.push(photon);
+        }
+
+        // only descend into the far side if the sphere crosses the split
+        let axis = self.axes[mid];
+        let delta = point[axis] - photon.position[axis];
+        let (near, far) = if delta < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+
+        self.gather_range(near.0, near.1, point, radius, found);
+        if delta * delta <= radius * radius {
+            self.gather_range(far.0, far.1, point, radius, found);
+        }
+    }
+
+    /// Return up to `k` photons closest to `point` that are no further than
+    /// `max_radius` away, along with the squared distance to the furthest one
+    /// returned.
+    pub fn nearest(
+        &self,
+        point: Point3,
+        k: usize,
+        max_radius: f64,
+    ) -> (Vec<&Photon>, f64) {
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        let mut max_dist_sq = max_radius * max_radius;
+        self.nearest_range(
+            0,
+            self.photons.len(),
+            point,
+            k,
+            &mut max_dist_sq,
+            &mut heap,
+        );
+
+        let dist_sq = heap.peek().map_or(0.0, |n: &Neighbor| n.dist_sq);
+        let photons = heap.into_iter().map(|n| n.photon).collect();
+        (photons, dist_sq)
+    }
+
+    fn nearest_range<'a>(
+        &'a self,
+        lo: usize,
+        hi: usize,
+        point: Point3,
+        k: usize,
+        max_dist_sq: &mut f64,
+        heap: &mut BinaryHeap<Neighbor<'a>>,
+    ) {
+        if lo >= hi || k == 0 {
+            return;
+        }
+
+        let mid = (lo + hi) / 2;
+        let axis = self.axes[mid];
+        let photon = &self.photons[mid];
+        let delta = point[axis] - photon.position[axis];
+        let (near, far) = if delta < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+
+        self.nearest_range(near.0, near.1, point, k, max_dist_sq, heap);
+
+        let dist_sq = (photon.position - point).mag_sq();
+        if dist_sq <= *max_dist_sq {
+            heap.push(Neighbor { dist_sq, photon });
+            if heap.len() > k {
+                heap.pop();
+            }
+            // once the heap is full the search only has to look as far as the
+            // furthest photon found so far
+            if heap.len() == k {
+                *max_dist_sq = heap.peek().unwrap().dist_sq;
+            }
+        }
+
+        if delta * delta <= *max_dist_sq {
+            self.nearest_range(far.0, far.1, point, k, max_dist_sq, heap);
+        }
+    }
+
+    /// Estimate the irradiance arriving at `point` on a surface facing
+    /// `normal` from the `k` nearest photons within `max_radius`. Photons
+    /// that arrived from behind the surface are skipped. Reflected radiance
+    /// off a diffuse surface is this scaled by albedo / pi.
+    pub fn irradiance(
+        &self,
+        point: Point3,
+        normal: Normal3,
+        k: usize,
+        max_radius: f64,
+    ) -> Color {
+        let (photons, dist_sq) = self.nearest(point, k, max_radius);
+        if photons.is_empty() || dist_sq == 0.0 {
+            return Color::black();
+        }
+
+        let mut flux = Color::black();
+        for photon in photons {
+            if photon.direction.dot(normal) > 0.0 {
+                flux += photon.power;
+            }
+        }
+
+        flux * (1.0 / (PI * dist_sq))
+    }
+}
+
+/// Arrange `photons` into the implicit kd-tree layout described on
+/// `PhotonMap`, splitting along the axis where the photons are most spread
+/// out.
+fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.len() <= 1 {
+        return;
+    }
+
+    let mut min = photons[0].position;
+    let mut max = photons[0].position;
+    for photon in photons.iter() {
+        min = min.min_by_component(photon.position);
+        max = max.max_by_component(photon.position);
+    }
+    let extent = max - min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| {
+        a.position[axis]
+            .partial_cmp(&b.position[axis])
+            .unwrap_or(Ordering::Equal)
+    });
+    axes[mid] = axis;
+
+    let (lower, upper) = photons.split_at_mut(mid);
+    let (lower_axes, upper_axes) = axes.split_at_mut(mid);
+    build(lower, lower_axes);
+    build(&mut upper[1..], &mut upper_axes[1..]);
+}
+
+/// Per-pixel statistics for stochastic progressive photon mapping. Each
+/// iteration gathers the photons around the pixel's visible point within
+/// `radius`, then shrinks the radius so that the estimate converges to the
+/// right answer as more iterations are run.
+#[derive(Debug, Clone, Copy)]
+pub struct SppmPixel {
+    pub radius: f64,
+    photon_count: f64,
+    flux: Color,
+}
+
+impl SppmPixel {
+    pub fn new(initial_radius: f64) -> SppmPixel {
+        SppmPixel {
+            radius: initial_radius,
+            photon_count: 0.0,
+            flux: Color::black(),
+        }
+    }
+
+    /// Fold in the `count` photons, carrying a total of `flux` (already
+    /// weighted by the BSDF at the visible point), that were gathered this
+    /// iteration. `alpha` in (0, 1) controls how much of the new photons are
+    /// kept each iteration; 2/3 is the usual choice.
+    pub fn update(&mut self, count: usize, flux: Color, alpha: f64) {
+        if count == 0 {
+            return;
+        }
+
+        let count = count as f64;
+        let photon_count = self.photon_count + alpha * count;
+        let radius =
+            self.radius * f64::sqrt(photon_count / (self.photon_count + count));
+        let shrink = (radius * radius) / (self.radius * self.radius);
+
+        self.flux = (self.flux + flux) * shrink;
+        self.photon_count = photon_count;
+        self.radius = radius;
+    }
+
+    /// The radiance estimate after `emitted` photons have been shot from the
+    /// lights over every iteration so far.
+    pub fn radiance(&self, emitted: usize) -> Color {
+        let area = PI * self.radius * self.radius;
+        self.flux * (1.0 / (emitted as f64 * area))
+    }
+}
+
+/// How a photon map is built and read.
+#[derive(Debug, Clone, Copy)]
+pub struct PhotonMapping {
+    /// Photons shot from the lights.
+    pub photons: usize,
+    /// Photons each density estimate is made from.
+    pub nearest: usize,
+    /// Furthest a photon may be from a point to count towards its estimate.
+    pub max_radius: f64,
+    /// Rays traced from each surface seen from the camera to estimate the
+    /// light reflected where they land, or 0 to estimate it at the surface
+    /// itself.
+    pub final_gather: u32,
+}
+
+/// The first surface a ray hits, and the light leaving it that doesn't need
+/// the photons to find.
+struct VisiblePoint {
+    point: Point3,
+    wo: Vector3,
+    bsdf: Lambertian,
+    emitted: Color,
+    /// Light from one sample of one light reflected along `wo`.
+    direct: Color,
+}
+
+/// Traces photons from the lights and rays from the camera for the photon
+/// mapping integrators.
+struct PhotonTracer<'a, S: ?Sized> {
+    scene: &'a S,
+    lights: &'a [Box<dyn Light>],
+    path: PathTracer<'a, S>,
+    light_sampler: PowerLightSampler,
+}
+
+impl<'a, S: Scene + ?Sized> PhotonTracer<'a, S> {
+    fn new(
+        scene: &'a S,
+        lights: &'a [Box<dyn Light>],
+        materials: &'a [Box<dyn Material>],
+    ) -> PhotonTracer<'a, S> {
+        // only used for the light reaching single surfaces directly
+        let sampler = Box::new(PowerLightSampler::new(lights));
+        PhotonTracer {
+            scene,
+            lights,
+            path: PathTracer::new(scene, lights, materials, sampler, 1),
+            light_sampler: PowerLightSampler::new(lights),
+        }
+    }
+
+    /// Shoot `count` photons from the lights, picked by power, and return
+    /// where they land after being reflected at least once, up to the
+    /// `max_bounces`th surface they hit. Light arriving straight from the
+    /// lights is found by sampling them instead. Each photon carries the
+    /// power it would stand for if it were the only one shot.
+    fn emit<R: Rng + ?Sized>(
+        &self,
+        count: usize,
+        max_bounces: u32,
+        rng: &mut R,
+    ) -> Vec<Photon> {
+        let mut photons = Vec::new();
+        for _ in 0..count {
+            self.trace(max_bounces, &mut photons, rng);
+        }
+        photons
+    }
+
+    fn trace<R: Rng + ?Sized>(
+        &self,
+        max_bounces: u32,
+        photons: &mut Vec<Photon>,
+        rng: &mut R,
+    ) {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let up = Normal3::new(0.0, 1.0, 0.0);
+        let picked = self.light_sampler.sample(origin, up, rng.gen());
+        let (index, pmf) = match picked {
+            Some(picked) => picked,
+            None => return,
+        };
+        let u_pos = (rng.gen(), rng.gen());
+        let u_dir = (rng.gen(), rng.gen());
+        let sample = match self.lights[index].sample_le(u_pos, u_dir) {
+            Some(sample) => sample,
+            None => return,
+        };
+        let pdf = pmf * sample.pdf_pos * sample.pdf_dir;
+        if pdf == 0.0 {
+            return;
+        }
+
+        let cos = sample.normal.map_or(1.0, |n| n.dot(sample.w).abs());
+        let mut power = sample.radiance * (cos / pdf);
+        let mut ray = Ray::new(sample.point, sample.w);
+        for bounces in 0..max_bounces {
+            let hit = match self.scene.intersect(&ray) {
+                Some(hit) => hit,
+                None => break,
+            };
+            let wo = -ray.direction.normalized();
+            if bounces > 0 {
+                photons.push(Photon::new(hit.point, wo, power));
+            }
+
+            let bsdf = self.path.bsdf(&hit, wo);
+            let sample = match bsdf.sample_f(wo, (rng.gen(), rng.gen())) {
+                Some(sample) => sample,
+                None => break,
+            };
+            let cos = bsdf.normal().dot(sample.wi);
+            power = power * sample.f * (cos / sample.pdf);
+
+            if bounces + 1 >= MIN_BOUNCES {
+                let q = f64::max(0.05, 1.0 - path::max_component(power));
+                if rng.gen::<f64>() < q {
+                    break;
+                }
+                power = power * (1.0 / (1.0 - q));
+            }
+            ray = Ray::new(hit.point, sample.wi);
+        }
+    }
+
+    /// The first surface `ray` hits, if any.
+    fn visible<R: Rng + ?Sized>(
+        &self,
+        ray: &Ray,
+        rng: &mut R,
+    ) -> Option<VisiblePoint> {
+        let hit = self.scene.intersect(ray)?;
+        let wo = -ray.direction.normalized();
+        let bsdf = self.path.bsdf(&hit, wo);
+        let direct = self.path.sample_light(&bsdf, hit.point, wo, rng);
+        Some(VisiblePoint {
+            point: hit.point,
+            wo,
+            bsdf,
+            emitted: hit.emitted,
+            direct,
+        })
+    }
+
+    /// The light from the lights around the scene along a ray that misses
+    /// it.
+    fn background(&self, ray: &Ray) -> Color {
+        let mut background = Color::black();
+        for light in self.lights {
+            background += light.le(ray);
+        }
+        background
+    }
+}
+
+/// Finds the light reaching the camera by sampling the lights for the light
+/// they shine directly on surfaces, and estimating the rest from the density
+/// of photons shot from the lights beforehand.
+pub struct PhotonMapper<'a, S: ?Sized> {
+    tracer: PhotonTracer<'a, S>,
+    map: PhotonMap,
+    settings: PhotonMapping,
+    max_bounces: u32,
+}
+
+impl<'a, S: Scene + ?Sized> PhotonMapper<'a, S> {
+    /// Shoot photons into `scene` from `lights` for finding light that
+    /// scatters at most `max_bounces` times.
+    pub fn new<R: Rng + ?Sized>(
+        scene: &'a S,
+        lights: &'a [Box<dyn Light>],
+        materials: &'a [Box<dyn Material>],
+        max_bounces: u32,
+        settings: PhotonMapping,
+        rng: &mut R,
+    ) -> PhotonMapper<'a, S> {
+        let tracer = PhotonTracer::new(scene, lights, materials);
+
+        // final gathering reads the map one bounce further from the camera
+        let photon_bounces = if settings.final_gather > 0 {
+            max_bounces.saturating_sub(1)
+        } else {
+            max_bounces
+        };
+        let mut photons = tracer.emit(settings.photons, photon_bounces, rng);
+        let share = 1.0 / settings.photons as f64;
+        for photon in photons.iter_mut() {
+            photon.power = photon.power * share;
+        }
+
+        PhotonMapper {
+            tracer,
+            map: PhotonMap::new(photons),
+            settings,
+            max_bounces,
+        }
+    }
+
+    /// Return the radiance arriving along `ray`.
+    pub fn li<R: Rng + ?Sized>(&self, ray: &Ray, rng: &mut R) -> Color {
+        let point = match self.tracer.visible(ray, rng) {
+            Some(point) => point,
+            None => return self.tracer.background(ray),
+        };
+        if self.max_bounces == 0 {
+            return point.emitted;
+        }
+
+        let mut radiance = point.emitted + point.direct;
+        let rays = self.settings.final_gather;
+        if rays == 0 {
+            radiance += self.estimate(&point);
+        } else if self.max_bounces > 1 {
+            let mut gathered = Color::black();
+            for _ in 0..rays {
+                let u = (rng.gen(), rng.gen());
+                let sample = match point.bsdf.sample_f(point.wo, u) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+                // light that escapes was found by sampling the lights
+                let ray = Ray::new(point.point, sample.wi);
+                if let Some(next) = self.tracer.visible(&ray, rng) {
+                    let cos = point.bsdf.normal().dot(sample.wi);
+                    let reflected = next.direct + self.estimate(&next);
+                    gathered += sample.f * reflected * (cos / sample.pdf);
+                }
+            }
+            radiance += gathered * (1.0 / rays as f64);
+        }
+
+        radiance
+    }
+
+    /// Light reflected at `point` that the lights didn't shine on it
+    /// directly, from the density of photons around it.
+    fn estimate(&self, point: &VisiblePoint) -> Color {
+        let normal = point.bsdf.normal();
+        let irradiance = self.map.irradiance(
+            point.point,
+            normal,
+            self.settings.nearest,
+            self.settings.max_radius,
+        );
+        point.bsdf.f(point.wo, normal.into()) * irradiance
+    }
+}
+
+/// Stochastic progressive photon mapping, which alternates between tracing
+/// a ray from the camera through every pixel and shooting photons, and
+/// gathers the photons around each pixel's surface within a radius that
+/// shrinks as it finds them. Unlike a single photon map, its estimates
+/// converge to the right answer.
+pub struct Sppm<'a, S: ?Sized> {
+    tracer: PhotonTracer<'a, S>,
+    max_bounces: u32,
+    photons: usize,
+    radius: f64,
+}
+
+impl<'a, S: Scene + ?Sized> Sppm<'a, S> {
+    /// Find light scattering at most `max_bounces` times by shooting
+    /// `photons` photons each iteration and gathering them within `radius`
+    /// at first.
+    pub fn new(
+        scene: &'a S,
+        lights: &'a [Box<dyn Light>],
+        materials: &'a [Box<dyn Material>],
+        max_bounces: u32,
+        photons: usize,
+        radius: f64,
+    ) -> Sppm<'a, S> {
+        Sppm {
+            tracer: PhotonTracer::new(scene, lights, materials),
+            max_bounces,
+            photons,
+            radius,
+        }
+    }
+
+    /// Render the image `camera` sees over `iterations` iterations,
+    /// returning its pixels row by row.
+    pub fn render<R: Rng + ?Sized>(
+        &self,
+        camera: &Camera,
+        iterations: u32,
+        rng: &mut R,
+    ) -> Vec<Color> {
+        let (width, height) = (camera.width(), camera.height());
+        let count = (width * height) as usize;
+        let mut pixels = vec![SppmPixel::new(self.radius); count];
+        let mut direct = vec![Color::black(); count];
+
+        for _ in 0..iterations {
+            let mut points = Vec::with_capacity(count);
+            for j in 0..height {
+                for i in 0..width {
+                    let x = i as f64 + rng.gen::<f64>();
+                    let y = j as f64 + rng.gen::<f64>();
+                    let ray = camera.ray(x, y);
+                    let index = (j * width + i) as usize;
+                    let point = match self.tracer.visible(&ray, rng) {
+                        Some(point) => point,
+                        None => {
+                            direct[index] += self.tracer.background(&ray);
+                            points.push(None);
+                            continue;
+                        }
+                    };
+                    direct[index] += point.emitted;
+                    if self.max_bounces > 0 {
+                        direct[index] += point.direct;
+                        points.push(Some(point));
+                    } else {
+                        points.push(None);
+                    }
+                }
+            }
+
+            let photons = self.tracer.emit(self.photons, self.max_bounces, rng);
+            let map = PhotonMap::new(photons);
+            for (pixel, point) in pixels.iter_mut().zip(points.iter()) {
+                let point = match point {
+                    Some(point) => point,
+                    None => continue,
+                };
+                let mut found = 0;
+                let mut flux = Color::black();
+                for photon in map.gather(point.point, pixel.radius) {
+                    if point.bsdf.normal().dot(photon.direction) > 0.0 {
+                        found += 1;
+                        flux += point.bsdf.f(point.wo, photon.direction)
+                            * photon.power;
+                    }
+                }
+                pixel.update(found, flux, ALPHA);
+            }
+        }
+
+        let emitted = iterations as usize * self.photons;
+        let scale = 1.0 / iterations as f64;
+        pixels
+            .iter()
+            .zip(direct.iter())
+            .map(|(pixel, &direct)| direct * scale + pixel.radiance(emitted))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::light_sampler::{light_sampler, LightSampling};
+    use super::super::math;
+    use super::super::primitive::CornellBox;
+    use math::{normal, point, vector};
+    use math::test_util::assert_eps_eq;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use ultraviolet::mat::DMat4;
+
+    const EPS: f64 = 0.01;
+
+    /// Deterministically scatter `n` photons in the unit cube.
+    fn make_photons(n: usize) -> Vec<Photon> {
+        let mut state: u64 = 0x853c_49e6_748f_ea9b;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+
+        (0..n)
+            .map(|_| {
+                Photon::new(
+                    point(next(), next(), next()),
+                    vector(0.0, 0.0, 1.0),
+                    Color::new(1.0, 1.0, 1.0),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_photon_map_gather() {
+        let photons = make_photons(1000);
+        let map = PhotonMap::new(photons.clone());
+        let query = point(0.4, 0.6, 0.5);
+        let radius = 0.2;
+
+        let mut found: Vec<_> = map
+            .gather(query, radius)
+            .iter()
+            .map(|p| p.position.x)
+            .collect();
+        let mut expected: Vec<_> = photons
+            .iter()
+            .filter(|p| (p.position - query).mag_sq() <= radius * radius)
+            .map(|p| p.position.x)
+            .collect();
+        found.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        assert!(!expected.is_empty());
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_photon_map_nearest() {
+        let photons = make_photons(1000);
+        let map = PhotonMap::new(photons.clone());
+        let query = point(0.1, 0.9, 0.3);
+
+        let (found, dist_sq) = map.nearest(query, 10, 1.0);
+        let mut distances: Vec<_> = photons
+            .iter()
+            .map(|p| (p.position - query).mag_sq())
+            .collect();
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        assert_eq!(found.len(), 10);
+        assert_eps_eq(&dist_sq, &distances[9], 1.0e-12);
+        for photon in found {
+            assert!((photon.position - query).mag_sq() <= distances[9]);
+        }
+    }
+
+    #[test]
+    fn test_photon_map_nearest_max_radius() {
+        let map = PhotonMap::new(make_photons(1000));
+        let (found, _) = map.nearest(point(5.0, 5.0, 5.0), 10, 1.0);
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn test_photon_map_irradiance() {
+        // a 100x100 grid of photons on the unit square, each carrying 1e-4 so
+        // the whole square receives unit flux over unit area
+        let mut photons = Vec::new();
+        for i in 0..100 {
+            for j in 0..100 {
+                let x = (i as f64 + 0.5) / 100.0;
+                let y = (j as f64 + 0.5) / 100.0;
+                photons.push(Photon::new(
+                    point(x, y, 0.0),
+                    vector(0.0, 0.0, 1.0),
+                    Color::new(1.0e-4, 1.0e-4, 1.0e-4),
+                ));
+            }
+        }
+        let map = PhotonMap::new(photons);
+        let center = point(0.5, 0.5, 0.0);
+
+        let irradiance =
+            map.irradiance(center, normal(0.0, 0.0, 1.0), 200, 1.0);
+        assert!((irradiance.r() - 1.0).abs() < 0.1, "{:?}", irradiance);
+
+        // photons arriving from the other side of the surface don't count
+        let back = map.irradiance(center, normal(0.0, 0.0, -1.0), 200, 1.0);
+        assert_eq!(back, Color::black());
+    }
+
+    #[test]
+    fn test_sppm_radius_shrinks() {
+        let mut pixel = SppmPixel::new(1.0);
+        let mut last = pixel.radius;
+        for _ in 0..10 {
+            pixel.update(100, Color::new(1.0, 1.0, 1.0), 2.0 / 3.0);
+            assert!(pixel.radius < last);
+            last = pixel.radius;
+        }
+
+        // with no photons found the pixel is left alone
+        pixel.update(0, Color::black(), 2.0 / 3.0);
+        assert_eps_eq(&pixel.radius, &last, EPS);
+    }
+
+    #[test]
+    fn test_sppm_first_update() {
+        let mut pixel = SppmPixel::new(2.0);
+        pixel.update(9, Color::new(3.0, 3.0, 3.0), 2.0 / 3.0);
+
+        // N = 6 of the 9 photons are kept, so the radius shrinks by sqrt(6/9)
+        // and the flux by the ratio of the areas
+        assert_eps_eq(&pixel.radius, &(2.0 * f64::sqrt(6.0 / 9.0)), EPS);
+        assert_eps_eq(&pixel.flux.r(), &2.0, EPS);
+    }
+
+    #[test]
+    fn test_agrees_with_path_tracer() {
+        // the total light reaching a small view of the Cornell box, tilted
+        // down so the lamp is out of sight
+        let cornell = CornellBox::new();
+        let lights: Vec<Box<dyn Light>> = vec![Box::new(cornell.light())];
+        let materials = cornell.materials();
+        let (width, height) = (16, 16);
+        let fov = f64::to_radians(40.0);
+        let tilt = DMat4::from_rotation_x(f64::to_radians(-10.0));
+        let camera = Camera::new(tilt, fov, width, height);
+        let max_bounces = 3;
+        let samples = 64;
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let sampler = light_sampler(LightSampling::Power, &lights);
+        let path = PathTracer::new(
+            &cornell,
+            &lights,
+            &materials,
+            sampler,
+            max_bounces,
+        );
+        let mut settings = PhotonMapping {
+            photons: 20000,
+            nearest: 50,
+            max_radius: 0.5,
+            final_gather: 0,
+        };
+        let mapper = PhotonMapper::new(
+            &cornell,
+            &lights,
+            &materials,
+            max_bounces,
+            settings,
+            &mut rng,
+        );
+        settings.final_gather = 4;
+        let gatherer = PhotonMapper::new(
+            &cornell,
+            &lights,
+            &materials,
+            max_bounces,
+            settings,
+            &mut rng,
+        );
+
+        let mut expected = 0.0;
+        let mut mapped = 0.0;
+        let mut gathered = 0.0;
+        for j in 0..height {
+            for i in 0..width {
+                for _ in 0..samples {
+                    let x = i as f64 + rng.gen::<f64>();
+                    let y = j as f64 + rng.gen::<f64>();
+                    let ray = camera.ray(x, y);
+                    expected += path.li(&ray, &mut rng).luminance();
+                    mapped += mapper.li(&ray, &mut rng).luminance();
+                    gathered += gatherer.li(&ray, &mut rng).luminance();
+                }
+            }
+        }
+        let sppm =
+            Sppm::new(&cornell, &lights, &materials, max_bounces, 2000, 0.1);
+        let progressive: f64 = sppm
+            .render(&camera, samples, &mut rng)
+            .iter()
+            .map(|color| color.luminance())
+            .sum();
+        let expected = expected / samples as f64;
+        let mapped = mapped / samples as f64;
+        let gathered = gathered / samples as f64;
+        for &(name, total) in [
+            ("photon map", mapped),
+            ("final gathering", gathered),
+            ("progressive photon mapping", progressive),
+        ]
+        .iter()
+        {
+            assert!(
+                (total - expected).abs() < 0.03 * expected,
+                "{} gave {} where path tracing gave {}",
+                name,
+                total,
+                expected
+            );
+        }
+    }
+}
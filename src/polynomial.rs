@@ -0,0 +1,345 @@
+use std::f64::consts::PI;
+use std::ops::Deref;
+
+/// Newton steps taken to polish each root the closed form solutions find.
+const POLISH_ITERATIONS: usize = 4;
+
+/// The real roots of a t^2 + b t + c = 0 in increasing order, or `None` if
+/// there aren't any. A double root, or the one root of a linear equation
+/// when `a` is 0, is returned twice.
+///
+/// This avoids the cancellation in the textbook formula when b^2 is much
+/// larger than 4 a c, which loses the small root entirely.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return None;
+        }
+        let t = -c / b;
+        return Some((t, t));
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let q = -0.5 * (b + discriminant.sqrt().copysign(b));
+    let (t0, t1) = if q == 0.0 {
+        // b and c are both 0
+        (0.0, 0.0)
+    } else {
+        (q / a, c / q)
+    };
+    if t0 <= t1 {
+        Some((t0, t1))
+    } else {
+        Some((t1, t0))
+    }
+}
+
+/// Up to four real roots of a polynomial in increasing order, which derefs
+/// to a slice of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Roots {
+    values: [f64; 4],
+    count: usize,
+}
+
+impl Roots {
+    fn new() -> Roots {
+        Roots {
+            values: [0.0; 4],
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, root: f64) {
+        self.values[self.count] = root;
+        self.count += 1;
+    }
+
+    fn sorted(mut self) -> Roots {
+        self.values[..self.count]
+            .sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        self
+    }
+}
+
+impl Roots {
+    /// The roots after applying the increasing function `f` to each.
+    pub fn map<F: Fn(f64) -> f64>(mut self, f: F) -> Roots {
+        for root in self.values[..self.count].iter_mut() {
+            *root = f(*root);
+        }
+        self
+    }
+}
+
+impl Deref for Roots {
+    type Target = [f64];
+
+    fn deref(&self) -> &[f64] {
+        &self.values[..self.count]
+    }
+}
+
+/// Value of the polynomial with `coefficients`, highest degree first, and
+/// of its derivative at `x`.
+fn evaluate(coefficients: &[f64], x: f64) -> (f64, f64) {
+    coefficients[1..]
+        .iter()
+        .fold((coefficients[0], 0.0), |(p, dp), &c| {
+            (p * x + c, dp * x + p)
+        })
+}
+
+/// Improve a root found in closed form with a few steps of Newton's method,
+/// keeping the original if a step makes it worse.
+fn polish(coefficients: &[f64], root: f64) -> f64 {
+    let mut x = root;
+    let (mut fx, mut dfx) = evaluate(coefficients, x);
+    for _ in 0..POLISH_ITERATIONS {
+        if fx == 0.0 || dfx == 0.0 {
+            break;
+        }
+        let next = x - fx / dfx;
+        let (f_next, df_next) = evaluate(coefficients, next);
+        if f_next.is_nan() || f_next.abs() >= fx.abs() {
+            break;
+        }
+        x = next;
+        fx = f_next;
+        dfx = df_next;
+    }
+    x
+}
+
+/// The real roots of a t^3 + b t^2 + c t + d = 0 in increasing order: one
+/// or three of them, with repeated roots returned once for each time they
+/// repeat. Falls back to `solve_quadratic` when `a` is 0.
+pub fn solve_cubic(a: f64, b: f64, c: f64, d: f64) -> Roots {
+    let mut roots = Roots::new();
+    if a == 0.0 {
+        if let Some((t0, t1)) = solve_quadratic(b, c, d) {
+            roots.push(t0);
+            if b != 0.0 {
+                roots.push(t1);
+            }
+        }
+        return roots;
+    }
+
+    // substitute t = x - b / 3 to get the depressed cubic x^3 + p x + q
+    let (b, c, d) = (b / a, c / a, d / a);
+    let shift = b / 3.0;
+    let p = c - b * shift;
+    let q = 2.0 * shift * shift * shift - c * shift + d;
+
+    let discriminant = (q / 2.0).powi(2) + (p / 3.0).powi(3);
+    if p == 0.0 && q == 0.0 {
+        for _ in 0..3 {
+            roots.push(-shift);
+        }
+    } else if discriminant > 0.0 {
+        // one real root by Cardano's formula, choosing the sign that avoids
+        // cancellation and getting the second cube root from the first
+        let u = (-q / 2.0 - discriminant.sqrt().copysign(q)).cbrt();
+        let x = if u == 0.0 { 0.0 } else { u - p / (3.0 * u) };
+        roots.push(x - shift);
+    } else {
+        // three real roots, from the trigonometric solution
+        let m = 2.0 * (-p / 3.0).sqrt();
+        let cos = (3.0 * q / (p * m)).clamp(-1.0, 1.0);
+        let theta = cos.acos() / 3.0;
+        for k in 0..3 {
+            let x = m * (theta - 2.0 * PI * k as f64 / 3.0).cos();
+            roots.push(x - shift);
+        }
+    }
+
+    let coefficients = [1.0, b, c, d];
+    for root in roots.values[..roots.count].iter_mut() {
+        *root = polish(&coefficients, *root);
+    }
+    roots.sorted()
+}
+
+/// The real roots of a t^4 + b t^3 + c t^2 + d t + e = 0 in increasing
+/// order, with repeated roots returned once for each time they repeat.
+/// Falls back to `solve_cubic` when `a` is 0.
+///
+/// This is Ferrari's method, factoring the quartic into two quadratics with
+/// a root of its resolvent cubic, followed by a few Newton steps on each
+/// root against the original quartic to win back the precision the closed
+/// form loses when roots are close together.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Roots {
+    if a == 0.0 {
+        return solve_cubic(b, c, d, e);
+    }
+
+    // substitute t = x - b / 4 to get the depressed quartic
+    // x^4 + p x^2 + q x + r
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+    let shift = b / 4.0;
+    let shift_sq = shift * shift;
+    let p = c - 6.0 * shift_sq;
+    let q = d - 2.0 * c * shift + 8.0 * shift_sq * shift;
+    let r = e - d * shift + c * shift_sq - 3.0 * shift_sq * shift_sq;
+
+    let mut roots = Roots::new();
+    // x^4 + p x^2 + q x + r = (x^2 + p / 2 + m)^2 - 2 m (x - q / 4 m)^2 for
+    // any root m of 8 m^3 + 8 p m^2 + (2 p^2 - 8 r) m - q^2, and the largest
+    // root is the one that's always positive when q isn't 0
+    let scale = p.abs().max(r.abs().sqrt()).max(f64::MIN_POSITIVE);
+    let resolvent = solve_cubic(8.0, 8.0 * p, 2.0 * p * p - 8.0 * r, -q * q);
+    let m = resolvent.last().copied().unwrap_or(0.0);
+    if m <= 1.0e-12 * scale {
+        // with q (nearly) 0 it's a quadratic in x^2
+        if let Some((z0, z1)) = solve_quadratic(1.0, p, r) {
+            for &z in [z0, z1].iter() {
+                if z >= 0.0 {
+                    let x = z.sqrt();
+                    roots.push(-x - shift);
+                    roots.push(x - shift);
+                }
+            }
+        }
+    } else {
+        let s = (2.0 * m).sqrt();
+        let offset = q / (2.0 * s);
+        for &(b, c) in
+            [(-s, p / 2.0 + m + offset), (s, p / 2.0 + m - offset)].iter()
+        {
+            if let Some((x0, x1)) = solve_quadratic(1.0, b, c) {
+                roots.push(x0 - shift);
+                roots.push(x1 - shift);
+            }
+        }
+    }
+
+    let coefficients = [1.0, b, c, d, e];
+    for root in roots.values[..roots.count].iter_mut() {
+        *root = polish(&coefficients, *root);
+    }
+    roots.sorted()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::math::test_util::assert_eps_eq;
+
+    const EPS: f64 = 1.0e-9;
+
+    #[test]
+    fn test_solve_quadratic() {
+        // (t - 1)(t - 3)
+        assert_eps_eq(&solve_quadratic(1.0, -4.0, 3.0), &Some((1.0, 3.0)), EPS);
+        // 2 (t + 2)^2
+        assert_eps_eq(
+            &solve_quadratic(2.0, 8.0, 8.0),
+            &Some((-2.0, -2.0)),
+            EPS,
+        );
+        assert_eq!(solve_quadratic(1.0, 0.0, 1.0), None);
+        assert_eq!(solve_quadratic(1.0, 0.0, 0.0), Some((0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_solve_linear() {
+        assert_eq!(solve_quadratic(0.0, 2.0, -4.0), Some((2.0, 2.0)));
+        assert_eq!(solve_quadratic(0.0, 0.0, 1.0), None);
+    }
+
+    #[test]
+    fn test_solve_quadratic_precision() {
+        // roots 1e-9 and 1e9, where the textbook formula returns 0 for the
+        // small one
+        let (t0, t1) = solve_quadratic(1.0, -(1.0e9 + 1.0e-9), 1.0).unwrap();
+        assert!((t0 - 1.0e-9).abs() < 1.0e-20);
+        assert!((t1 - 1.0e9).abs() < 1.0e-6);
+    }
+
+    /// Check that `roots` match `expected` and actually solve the
+    /// polynomial, which is how precision is lost in practice.
+    fn assert_roots(roots: &[f64], expected: &[f64], eps: f64) {
+        assert_eq!(roots.len(), expected.len(), "{:?}", roots);
+        for (root, expected) in roots.iter().zip(expected) {
+            assert_eps_eq(root, expected, eps);
+        }
+    }
+
+    #[test]
+    fn test_solve_cubic() {
+        // (t + 1)(t - 2)(t - 3)
+        let roots = solve_cubic(2.0, -8.0, 2.0, 12.0);
+        assert_roots(&roots, &[-1.0, 2.0, 3.0], EPS);
+        // (t - 1)(t^2 + 1)
+        assert_roots(&solve_cubic(1.0, -1.0, 1.0, -1.0), &[1.0], EPS);
+        // (t - 2)^3
+        let roots = solve_cubic(1.0, -6.0, 12.0, -8.0);
+        assert_roots(&roots, &[2.0, 2.0, 2.0], 1.0e-5);
+        // falls back to the quadratic and linear cases
+        assert_roots(&solve_cubic(0.0, 1.0, -4.0, 3.0), &[1.0, 3.0], EPS);
+        assert_roots(&solve_cubic(0.0, 0.0, 2.0, -4.0), &[2.0], EPS);
+    }
+
+    #[test]
+    fn test_solve_quartic() {
+        // (t - 1)(t - 2)(t - 3)(t - 4)
+        let roots = solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0);
+        assert_roots(&roots, &[1.0, 2.0, 3.0, 4.0], EPS);
+        // (t^2 - 4)(t^2 + 1), a biquadratic
+        let roots = solve_quartic(1.0, 0.0, -3.0, 0.0, -4.0);
+        assert_roots(&roots, &[-2.0, 2.0], EPS);
+        // (t^2 + 1)(t^2 + 4) has no real roots
+        assert!(solve_quartic(1.0, 0.0, 5.0, 0.0, 4.0).is_empty());
+        // (t + 1)^2 (t - 5)(t - 0.5), with the leading coefficient scaled
+        let roots = solve_quartic(2.0, -7.0, -15.0, -1.0, 5.0);
+        assert_roots(&roots, &[-1.0, -1.0, 0.5, 5.0], 1.0e-6);
+        // (t - 1)(t - 2)(t - 3) as a degenerate quartic
+        let roots = solve_quartic(0.0, 1.0, -6.0, 11.0, -6.0);
+        assert_roots(&roots, &[1.0, 2.0, 3.0], EPS);
+    }
+
+    #[test]
+    fn test_solve_quartic_random() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..1000 {
+            let mut expected: Vec<f64> =
+                (0..4).map(|_| rng.gen_range(-10.0, 10.0)).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let scale = rng.gen_range(0.1, 10.0);
+
+            // expand scale (t - r0)(t - r1)(t - r2)(t - r3)
+            let mut coefficients = vec![scale];
+            for &root in expected.iter() {
+                coefficients.push(0.0);
+                for i in (1..coefficients.len()).rev() {
+                    coefficients[i] -= root * coefficients[i - 1];
+                }
+            }
+            let c = &coefficients;
+            let roots = solve_quartic(c[0], c[1], c[2], c[3], c[4]);
+
+            // close roots are ill conditioned, so only check the ones that
+            // are well separated from the others
+            assert_eq!(roots.len(), 4, "{:?} {:?}", roots, expected);
+            for (i, &root) in expected.iter().enumerate() {
+                let gap = expected
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .map(|(_, &other)| (other - root).abs())
+                    .fold(f64::INFINITY, f64::min);
+                if gap > 0.1 {
+                    assert_eps_eq(&roots[i], &root, 1.0e-6);
+                }
+            }
+        }
+    }
+}
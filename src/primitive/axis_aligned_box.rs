@@ -0,0 +1,190 @@
+use super::math::{Normal3, Point3, Ray, Vector3};
+use super::{Aabb, Bounded, Intersection, Scene, Solid, Span, Transformed};
+use super::MIN_DISTANCE;
+use ultraviolet::DVec2;
+
+/// A solid box with its faces along the coordinate axes. Rotated or sheared
+/// boxes are an `OrientedBox`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisAlignedBox {
+    bounds: Aabb,
+}
+
+/// A box in any orientation: an axis-aligned box in its own object space,
+/// transformed into the world.
+pub type OrientedBox = Transformed<AxisAlignedBox>;
+
+impl AxisAlignedBox {
+    /// The box spanned by two opposite corners.
+    pub fn new(a: Point3, b: Point3) -> AxisAlignedBox {
+        AxisAlignedBox {
+            bounds: Aabb::new(a, b),
+        }
+    }
+
+    /// The face `point` is on, as its axis and whether it's on the maximum
+    /// side of the box, picked as the face closest to the point.
+    fn face(&self, point: Point3) -> (usize, bool) {
+        let mut face = (0, false);
+        let mut closest = f64::INFINITY;
+        for axis in 0..3 {
+            let to_min = (point[axis] - self.bounds.min[axis]).abs();
+            let to_max = (point[axis] - self.bounds.max[axis]).abs();
+            if to_min < closest {
+                face = (axis, false);
+                closest = to_min;
+            }
+            if to_max < closest {
+                face = (axis, true);
+                closest = to_max;
+            }
+        }
+        face
+    }
+
+    /// The range of the whole line through `ray` that's inside the box.
+    fn range(&self, ray: &Ray, t_min: f64) -> Option<(f64, f64)> {
+        let d = ray.direction;
+        let inv_dir = Vector3::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+        self.bounds
+            .intersect_between(ray, inv_dir, t_min, f64::INFINITY)
+    }
+
+    /// The hit at `t` along the ray, which should be on the surface.
+    fn hit(&self, ray: &Ray, t: f64) -> Intersection {
+        let mut point = ray.position(t);
+        let (axis, is_max) = self.face(point);
+        let (side, sign) = if is_max {
+            (self.bounds.max[axis], 1.0)
+        } else {
+            (self.bounds.min[axis], -1.0)
+        };
+        // the slab test pads its exits, so put the point back on the face
+        point[axis] = side;
+
+        let mut normal = Normal3::new(0.0, 0.0, 0.0);
+        normal[axis] = sign;
+
+        // each face is parameterized by the two other axes, in the order
+        // (z, y) for x, (x, z) for y and (x, y) for z
+        let offset = self.bounds.offset(point);
+        let uv = match axis {
+            0 => DVec2::new(offset.z, offset.y),
+            1 => DVec2::new(offset.x, offset.z),
+            _ => DVec2::new(offset.x, offset.y),
+        };
+        Intersection::new(t, point, normal, uv)
+    }
+}
+
+impl Scene for AxisAlignedBox {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let (t0, t1) = self.range(ray, 0.0)?;
+        // the entry is clipped to 0 when the ray starts inside, in which
+        // case it hits the box on the way out
+        let t = if t0 > MIN_DISTANCE {
+            t0
+        } else if t1 > MIN_DISTANCE {
+            t1
+        } else {
+            return None;
+        };
+        Some(self.hit(ray, t))
+    }
+}
+
+impl Solid for AxisAlignedBox {
+    fn intersect_all(&self, ray: &Ray) -> Vec<Span> {
+        match self.range(ray, -f64::INFINITY) {
+            Some((t0, t1)) if t0 < t1 => vec![Span {
+                enter: self.hit(ray, t0),
+                exit: self.hit(ray, t1),
+            }],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Bounded for AxisAlignedBox {
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::math::test_util::assert_eps_eq;
+    use super::super::super::math::{normal, point, rotation_y, vector};
+    use super::*;
+    use std::f64::consts::PI;
+
+    const EPS: f64 = 1.0e-9;
+
+    fn unit_box() -> AxisAlignedBox {
+        AxisAlignedBox::new(point(1.0, 1.0, 1.0), point(-1.0, -1.0, -1.0))
+    }
+
+    #[test]
+    fn test_ray_box_intersect() {
+        let cube = unit_box();
+        let faces = [
+            (vector(1.0, 0.0, 0.0), normal(-1.0, 0.0, 0.0)),
+            (vector(-1.0, 0.0, 0.0), normal(1.0, 0.0, 0.0)),
+            (vector(0.0, 1.0, 0.0), normal(0.0, -1.0, 0.0)),
+            (vector(0.0, -1.0, 0.0), normal(0.0, 1.0, 0.0)),
+            (vector(0.0, 0.0, 1.0), normal(0.0, 0.0, -1.0)),
+            (vector(0.0, 0.0, -1.0), normal(0.0, 0.0, 1.0)),
+        ];
+        for &(direction, expected) in faces.iter() {
+            let origin = point(0.0, 0.0, 0.0) - direction * 5.0;
+            let hit = cube.intersect(&Ray::new(origin, direction)).unwrap();
+            assert_eps_eq(&hit.t, &4.0, EPS);
+            assert_eq!(hit.normal, expected);
+            assert_eps_eq(&hit.uv, &DVec2::new(0.5, 0.5), EPS);
+        }
+
+        let ray = Ray::new(point(-5.0, 0.5, -0.5), vector(1.0, 0.0, 0.0));
+        let hit = cube.intersect(&ray).unwrap();
+        assert_eq!(hit.point, point(-1.0, 0.5, -0.5));
+        assert_eps_eq(&hit.uv, &DVec2::new(0.25, 0.75), EPS);
+
+        let ray = Ray::new(point(-5.0, 1.5, 0.0), vector(1.0, 0.0, 0.0));
+        assert_eq!(cube.intersect(&ray), None);
+        let ray = Ray::new(point(-5.0, 0.0, 0.0), vector(-1.0, 0.0, 0.0));
+        assert_eq!(cube.intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_ray_box_intersect_inside() {
+        let cube = unit_box();
+        let ray = Ray::new(point(0.0, 0.5, 0.0), vector(0.0, 0.0, -2.0));
+        let hit = cube.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &0.5, EPS);
+        assert_eq!(hit.point, point(0.0, 0.5, -1.0));
+        assert_eq!(hit.normal, normal(0.0, 0.0, -1.0));
+
+        // leaving the surface from the outside doesn't hit it again
+        let ray = Ray::new(point(0.0, 1.0, 0.0), vector(0.0, 1.0, 0.0));
+        assert_eq!(cube.intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_ray_oriented_box_intersect() {
+        let cube: OrientedBox =
+            Transformed::new(unit_box(), rotation_y(PI / 4.0));
+        let ray = Ray::new(point(-5.0, 0.0, 0.0), vector(1.0, 0.0, 0.0));
+        let hit = cube.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &(5.0 - 2.0f64.sqrt()), EPS);
+
+        // just off the front corner, on one of the two faces meeting there
+        let ray = Ray::new(point(-5.0, 0.0, 0.1), vector(1.0, 0.0, 0.0));
+        let hit = cube.intersect(&ray).unwrap();
+        let s = 0.5f64.sqrt();
+        assert_eps_eq(&hit.normal.x, &-s, EPS);
+        assert_eps_eq(&hit.normal.z.abs(), &s, EPS);
+
+        let b = cube.bounds();
+        let r = 2.0f64.sqrt();
+        assert_eps_eq(&b.max, &point(r, 1.0, r), EPS);
+    }
+}
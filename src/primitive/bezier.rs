@@ -0,0 +1,621 @@
+use super::math::{Normal3, Point3, Ray, Vector3};
+use super::{Aabb, Bounded, Bvh, Intersection, IntersectMethod, Scene};
+use super::Triangle;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use ultraviolet::DVec2;
+
+/// The 32 patches of Martin Newell's teapot, in the format he published
+/// them in. The teapot sits on the xy plane with z up.
+const TEAPOT: &str = include_str!("../../data/patches/teapot.txt");
+
+/// Curves are never split into more segments than this when tessellating.
+const MAX_SEGMENTS: usize = 64;
+
+/// Something wrong with a file of Bézier patches.
+#[derive(Debug)]
+pub enum PatchError {
+    Io(io::Error),
+    /// The file ended before all of the patches and points were read.
+    UnexpectedEnd,
+    /// A value that should be a number isn't one.
+    BadNumber(String),
+    /// A patch refers to a control point that isn't in the file.
+    BadIndex(usize),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::Io(err) => write!(f, "{}", err),
+            PatchError::UnexpectedEnd => write!(f, "unexpected end of file"),
+            PatchError::BadNumber(s) => {
+                write!(f, "expected a number, found '{}'", s)
+            }
+            PatchError::BadIndex(i) => {
+                write!(f, "control point {} doesn't exist", i)
+            }
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+impl From<io::Error> for PatchError {
+    fn from(err: io::Error) -> PatchError {
+        PatchError::Io(err)
+    }
+}
+
+/// A bicubic Bézier patch, given by a 4 × 4 grid of control points.
+///
+/// The points are stored a row at a time, so `points[4 * j + i]` is the
+/// `i`th control point along u in the `j`th row along v.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BezierPatch {
+    points: [Point3; 16],
+}
+
+/// The cubic Bernstein polynomials at `t`.
+fn bernstein(t: f64) -> [f64; 4] {
+    let s = 1.0 - t;
+    [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t]
+}
+
+/// The derivatives of the cubic Bernstein polynomials at `t`.
+fn bernstein_derivative(t: f64) -> [f64; 4] {
+    let s = 1.0 - t;
+    [
+        -3.0 * s * s,
+        3.0 * s * s - 6.0 * t * s,
+        6.0 * t * s - 3.0 * t * t,
+        3.0 * t * t,
+    ]
+}
+
+/// The cubic Bézier curve through the control points `c` at `t`.
+///
+/// Curves shared by neighbouring patches may have their points in either
+/// order, so the curve is always evaluated from the same end to give
+/// bit-for-bit the same points on both patches.
+fn curve_point(c: [Point3; 4], t: f64) -> Point3 {
+    let key = |p: Point3| (p.x, p.y, p.z);
+    let (c, t) = if key(c[3]) < key(c[0]) {
+        ([c[3], c[2], c[1], c[0]], 1.0 - t)
+    } else {
+        (c, t)
+    };
+    let b = bernstein(t);
+    let mut sum = Vector3::new(0.0, 0.0, 0.0);
+    for (p, w) in c.iter().zip(b.iter()) {
+        sum += (*p - Point3::origin()) * *w;
+    }
+    Point3::origin() + sum
+}
+
+/// The number of straight segments needed to follow the cubic curve
+/// through `c` to within `tolerance`, as a power of two.
+///
+/// A polyline through `n` equal steps in t strays at most 3/4 of the
+/// largest second difference of the control points over `n²` from the
+/// curve.
+fn segments(c: [Point3; 4], tolerance: f64) -> usize {
+    let second_difference =
+        |i: usize| ((c[i + 2] - c[i + 1]) - (c[i + 1] - c[i])).mag();
+    let bend = second_difference(0).max(second_difference(1));
+    let mut n = 1;
+    while n < MAX_SEGMENTS && 0.75 * bend > tolerance * (n * n) as f64 {
+        n *= 2;
+    }
+    n
+}
+
+/// The point a fraction `t` along the polyline through `samples`, which
+/// are equally spaced in t.
+fn polyline_point(samples: &[Point3], t: f64) -> Point3 {
+    let n = samples.len() - 1;
+    let s = t * n as f64;
+    let k = (s.floor() as usize).min(n - 1);
+    samples[k].lerp(samples[k + 1], s - k as f64)
+}
+
+impl BezierPatch {
+    pub fn new(points: [Point3; 16]) -> BezierPatch {
+        BezierPatch { points }
+    }
+
+    fn row(&self, j: usize) -> [Point3; 4] {
+        let p = &self.points;
+        [p[4 * j], p[4 * j + 1], p[4 * j + 2], p[4 * j + 3]]
+    }
+
+    fn column(&self, i: usize) -> [Point3; 4] {
+        let p = &self.points;
+        [p[i], p[i + 4], p[i + 8], p[i + 12]]
+    }
+
+    /// The sum of the control points weighted by `wu` along u and `wv`
+    /// along v, relative to the origin.
+    fn weighted(&self, wu: [f64; 4], wv: [f64; 4]) -> Vector3 {
+        let mut sum = Vector3::new(0.0, 0.0, 0.0);
+        for (row, &v) in self.points.chunks(4).zip(wv.iter()) {
+            for (&p, &u) in row.iter().zip(wu.iter()) {
+                sum += (p - Point3::origin()) * (u * v);
+            }
+        }
+        sum
+    }
+
+    pub fn point(&self, u: f64, v: f64) -> Point3 {
+        Point3::origin() + self.weighted(bernstein(u), bernstein(v))
+    }
+
+    /// The partial derivatives of the surface along u and v.
+    pub fn partials(&self, u: f64, v: f64) -> (Vector3, Vector3) {
+        let (bu, bv) = (bernstein(u), bernstein(v));
+        let du = self.weighted(bernstein_derivative(u), bv);
+        let dv = self.weighted(bu, bernstein_derivative(v));
+        (du, dv)
+    }
+
+    /// The normalized normal at (u, v), on the side `du × dv` points to.
+    pub fn normal(&self, u: f64, v: f64) -> Normal3 {
+        let (du, dv) = self.partials(u, v);
+        let n = du.cross(dv);
+        if n.mag_sq() > 0.0 {
+            return n.normalized().into();
+        }
+
+        // a whole edge collapses to a point at the poles of the teapot's
+        // lid and bottom, where the normal is the limit from inside
+        let (u, v) = (u + (0.5 - u) * 1.0e-6, v + (0.5 - v) * 1.0e-6);
+        let (du, dv) = self.partials(u, v);
+        du.cross(dv).normalized().into()
+    }
+
+    /// A grid of triangles within about `tolerance` of the patch, with the
+    /// (u, v) of each vertex.
+    ///
+    /// Each edge of the patch is cut into as many segments as that edge
+    /// alone needs, so that patches sharing an edge cut it the same way and
+    /// leave no cracks between them. Rows of the grid may be finer than the
+    /// edge they end on, in which case their ends are put on the edge's
+    /// polyline.
+    fn tessellate(&self, tolerance: f64) -> (Vec<Point3>, Vec<DVec2>) {
+        let rows = [self.row(0), self.row(3)];
+        let columns = [self.column(0), self.column(3)];
+        let edge = |c: [Point3; 4]| {
+            let n = segments(c, tolerance);
+            (0..=n)
+                .map(|k| curve_point(c, k as f64 / n as f64))
+                .collect::<Vec<_>>()
+        };
+        let (bottom, top) = (edge(rows[0]), edge(rows[1]));
+        let (left, right) = (edge(columns[0]), edge(columns[1]));
+
+        let nu = (0..4).map(|j| segments(self.row(j), tolerance)).max();
+        let nv = (0..4).map(|i| segments(self.column(i), tolerance)).max();
+        let (mut nu, mut nv) = (nu.unwrap_or(1), nv.unwrap_or(1));
+
+        // a twisted patch can have straight rows and columns and still not
+        // be flat, and a cell of the grid strays from it by about 9/4 of
+        // the twist of the control points over the number of cells
+        let p = &self.points;
+        let mut twist: f64 = 0.0;
+        for j in 0..3 {
+            for i in 0..3 {
+                let k = 4 * j + i;
+                let d = (p[k + 5] - p[k + 4]) - (p[k + 1] - p[k]);
+                twist = twist.max(d.mag());
+            }
+        }
+        while nu * nv < MAX_SEGMENTS * MAX_SEGMENTS
+            && 2.25 * twist > tolerance * (nu * nv) as f64
+        {
+            if nu <= nv {
+                nu *= 2;
+            } else {
+                nv *= 2;
+            }
+        }
+
+        let mut grid = Vec::with_capacity((nu + 1) * (nv + 1));
+        let mut params = Vec::with_capacity((nu + 1) * (nv + 1));
+        for j in 0..=nv {
+            for i in 0..=nu {
+                let (u, v) = (i as f64 / nu as f64, j as f64 / nv as f64);
+                let p = if j == 0 {
+                    polyline_point(&bottom, u)
+                } else if j == nv {
+                    polyline_point(&top, u)
+                } else if i == 0 {
+                    polyline_point(&left, v)
+                } else if i == nu {
+                    polyline_point(&right, v)
+                } else {
+                    self.point(u, v)
+                };
+                grid.push(p);
+                params.push(DVec2::new(u, v));
+            }
+        }
+
+        let mut points = Vec::new();
+        let mut uvs = Vec::new();
+        for j in 0..nv {
+            for i in 0..nu {
+                let corner =
+                    |di: usize, dj: usize| (nu + 1) * (j + dj) + i + di;
+                let (a, b) = (corner(0, 0), corner(1, 0));
+                let (c, d) = (corner(1, 1), corner(0, 1));
+                for &triangle in [[a, b, c], [a, c, d]].iter() {
+                    let [p0, p1, p2] = triangle;
+                    let area = (grid[p1] - grid[p0]).cross(grid[p2] - grid[p0]);
+                    // cells next to a pole have a side of no length
+                    if area.mag_sq() == 0.0 {
+                        continue;
+                    }
+                    for &k in triangle.iter() {
+                        points.push(grid[k]);
+                        uvs.push(params[k]);
+                    }
+                }
+            }
+        }
+        (points, uvs)
+    }
+}
+
+impl Bounded for BezierPatch {
+    /// The convex hull of the control points holds the whole patch.
+    fn bounds(&self) -> Aabb {
+        self.points[1..]
+            .iter()
+            .fold(Aabb::new(self.points[0], self.points[0]), |b, &p| {
+                b.union_point(p)
+            })
+    }
+}
+
+impl Scene for BezierPatch {
+    /// Tessellates the patch on every call, so only meant for testing: put
+    /// patches in a `BezierSurface` to render them.
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        BezierSurface::new(vec![*self], 1.0e-3).intersect(ray)
+    }
+}
+
+/// Bézier patches rendered by adaptively tessellating them into triangles.
+///
+/// Hits report the (u, v) and exact normal of the patch they're on, for
+/// shading, and the index of the patch as their `primitive`.
+pub struct BezierSurface {
+    patches: Vec<BezierPatch>,
+    triangles: Bvh<Triangle>,
+    /// The patch each triangle came from, and the (u, v) of its vertices.
+    params: Vec<(usize, [DVec2; 3])>,
+}
+
+impl BezierSurface {
+    /// Tessellate `patches` so that the triangles are within about
+    /// `tolerance` of the surface.
+    pub fn new(patches: Vec<BezierPatch>, tolerance: f64) -> BezierSurface {
+        let mut triangles = Vec::new();
+        let mut params = Vec::new();
+        for (index, patch) in patches.iter().enumerate() {
+            let (points, uvs) = patch.tessellate(tolerance);
+            for (p, uv) in points.chunks(3).zip(uvs.chunks(3)) {
+                // the tessellation has no cracks, and rays shouldn't find
+                // any between its triangles either
+                let triangle = Triangle::new(p[0], p[1], p[2])
+                    .with_method(IntersectMethod::Watertight);
+                triangles.push(triangle);
+                params.push((index, [uv[0], uv[1], uv[2]]));
+            }
+        }
+
+        BezierSurface {
+            patches,
+            triangles: Bvh::new(triangles),
+            params,
+        }
+    }
+}
+
+impl Scene for BezierSurface {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let mut hit = self.triangles.intersect(ray)?;
+        let (index, uvs) = self.params[hit.primitive];
+        if let Some(b) = hit.barycentric {
+            hit.uv = uvs[0] * b.x + uvs[1] * b.y + uvs[2] * b.z;
+        }
+        hit.shading_normal = self.patches[index].normal(hit.uv.x, hit.uv.y);
+        hit.normal = hit.normal.face_forward(hit.shading_normal);
+        hit.primitive = index;
+        Some(hit)
+    }
+}
+
+impl Bounded for BezierSurface {
+    fn bounds(&self) -> Aabb {
+        self.triangles.bounds()
+    }
+}
+
+/// Read patches in the format of Newell's teapot: the number of patches,
+/// then 16 control point indices per patch counting from 1, then the
+/// number of control points, then their coordinates. Numbers are separated
+/// by commas or whitespace.
+pub fn parse_patches(text: &str) -> Result<Vec<BezierPatch>, PatchError> {
+    let mut values = text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty());
+    let mut next = || -> Result<&str, PatchError> {
+        values.next().ok_or(PatchError::UnexpectedEnd)
+    };
+    let mut next_index = || -> Result<usize, PatchError> {
+        let s = next()?;
+        s.parse().map_err(|_| PatchError::BadNumber(s.to_string()))
+    };
+
+    let patch_count = next_index()?;
+    let mut indices = Vec::with_capacity(patch_count);
+    for _ in 0..patch_count {
+        let mut patch = [0; 16];
+        for index in patch.iter_mut() {
+            *index = next_index()?;
+        }
+        indices.push(patch);
+    }
+
+    let point_count = next_index()?;
+    let mut next_coordinate = || -> Result<f64, PatchError> {
+        let s = next()?;
+        s.parse().map_err(|_| PatchError::BadNumber(s.to_string()))
+    };
+    let mut points = Vec::with_capacity(point_count);
+    for _ in 0..point_count {
+        let x = next_coordinate()?;
+        let y = next_coordinate()?;
+        let z = next_coordinate()?;
+        points.push(Point3::new(x, y, z));
+    }
+
+    indices
+        .iter()
+        .map(|patch| {
+            let mut control = [Point3::origin(); 16];
+            for (p, &index) in control.iter_mut().zip(patch.iter()) {
+                if index == 0 || index > points.len() {
+                    return Err(PatchError::BadIndex(index));
+                }
+                *p = points[index - 1];
+            }
+            Ok(BezierPatch::new(control))
+        })
+        .collect()
+}
+
+pub fn load_patches<P: AsRef<Path>>(
+    path: P,
+) -> Result<Vec<BezierPatch>, PatchError> {
+    parse_patches(&fs::read_to_string(path)?)
+}
+
+/// The 32 patches of the Utah teapot, with z up and the bottom on the xy
+/// plane. The body is 3 units across and the lid's knob is 3.15 units up.
+pub fn teapot() -> Vec<BezierPatch> {
+    parse_patches(TEAPOT).expect("the built in teapot is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::math::test_util::assert_eps_eq;
+    use super::super::super::math::{normal, point, vector};
+    use super::*;
+
+    const EPS: f64 = 1.0e-9;
+
+    /// A saddle over the square from (0, 0) to (3, 3), with its control
+    /// points at height (i - 1.5)(j - 1.5).
+    fn saddle() -> BezierPatch {
+        let mut points = [Point3::origin(); 16];
+        for j in 0..4 {
+            for i in 0..4 {
+                let (x, y) = (i as f64, j as f64);
+                points[4 * j + i] = point(x, y, (x - 1.5) * (y - 1.5));
+            }
+        }
+        BezierPatch::new(points)
+    }
+
+    #[test]
+    fn test_patch_evaluate() {
+        let patch = saddle();
+        assert_eq!(patch.point(0.0, 0.0), point(0.0, 0.0, 2.25));
+        assert_eq!(patch.point(1.0, 0.0), point(3.0, 0.0, -2.25));
+        assert_eq!(patch.point(1.0, 1.0), point(3.0, 3.0, 2.25));
+        // the control points are evenly spaced, so the patch is the
+        // hyperbolic paraboloid through them
+        assert_eps_eq(&patch.point(0.5, 0.5), &point(1.5, 1.5, 0.0), EPS);
+        assert_eps_eq(&patch.point(0.25, 0.5), &point(0.75, 1.5, 0.0), EPS);
+        assert_eps_eq(&patch.normal(0.5, 0.5), &normal(0.0, 0.0, 1.0), EPS);
+
+        let (du, dv) = patch.partials(0.0, 0.0);
+        assert_eps_eq(&du, &vector(3.0, 0.0, -4.5), EPS);
+        assert_eps_eq(&dv, &vector(0.0, 3.0, -4.5), EPS);
+    }
+
+    #[test]
+    fn test_patch_degenerate_normal() {
+        // the v = 0 edge collapses to the apex of a dome, which is flat
+        // there like the teapot's lid
+        let heights = [0.0, 0.0, -0.5, -1.0];
+        let mut points = [Point3::origin(); 16];
+        for j in 0..4 {
+            for i in 0..4 {
+                let angle = i as f64 / 3.0 * std::f64::consts::FRAC_PI_2;
+                let r = j as f64;
+                let (x, y) = (r * angle.cos(), r * angle.sin());
+                points[4 * j + i] = point(x, y, heights[j]);
+            }
+        }
+        let patch = BezierPatch::new(points);
+        let n = patch.normal(0.5, 0.0);
+        assert!(n.z.abs() > 0.999, "{:?}", n);
+    }
+
+    #[test]
+    fn test_tessellation_tolerance() {
+        // a bumpy patch, curved along both u and v
+        let heights = [
+            [0.0, 1.0, -1.0, 0.5],
+            [2.0, -1.0, 0.0, 1.0],
+            [0.0, 3.0, 1.0, -2.0],
+            [1.0, 0.0, -1.0, 0.0],
+        ];
+        let mut points = [Point3::origin(); 16];
+        for j in 0..4 {
+            for i in 0..4 {
+                points[4 * j + i] = point(i as f64, j as f64, heights[j][i]);
+            }
+        }
+        let patch = BezierPatch::new(points);
+
+        for &tolerance in [0.1, 0.01, 0.001].iter() {
+            let (points, uvs) = patch.tessellate(tolerance);
+            assert_eq!(points.len() % 3, 0);
+            for (p, uv) in points.iter().zip(uvs.iter()) {
+                assert!(p.distance(patch.point(uv.x, uv.y)) < tolerance);
+            }
+            // the centres of the triangles are close to the surface too
+            for (p, uv) in points.chunks(3).zip(uvs.chunks(3)) {
+                let centre = p[0].lerp(p[1], 0.5).lerp(p[2], 1.0 / 3.0);
+                let uv = (uv[0] + uv[1] + uv[2]) / 3.0;
+                let surface = patch.point(uv.x, uv.y);
+                assert!(centre.distance(surface) < 3.0 * tolerance);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tessellation_crack_free() {
+        // two patches meet along a curve bulging out in x, and the one on
+        // the right has ripples that need a much finer grid along v
+        let bulge = [0.0, 0.5, 0.5, 0.0];
+        let ripples = [0.0, 1.0, -1.0, 0.0];
+        let mut left = [Point3::origin(); 16];
+        let mut right = [Point3::origin(); 16];
+        for j in 0..4 {
+            for i in 0..4 {
+                let (x, y) = (i as f64, j as f64);
+                left[4 * j + i] = point(x - 3.0, y, 0.0);
+                let z = if i == 0 { 0.0 } else { ripples[j] };
+                right[4 * j + i] = point(x, y, z);
+            }
+            left[4 * j + 3].x += bulge[j];
+            right[4 * j].x += bulge[j];
+        }
+        let (left, right) = (BezierPatch::new(left), BezierPatch::new(right));
+        let (_, left_uvs) = left.tessellate(0.01);
+        let (_, right_uvs) = right.tessellate(0.01);
+        let rows = |uvs: &[DVec2]| uvs.iter().filter(|uv| uv.x == 0.0).count();
+        assert!(rows(&right_uvs) > rows(&left_uvs));
+
+        // rays straight down either side of the shared curve hit one patch
+        // or the other, including where the coarser polyline of the left
+        // patch sags a few thousandths inside the curve
+        let surface = BezierSurface::new(vec![left, right], 0.01);
+        for k in 0..100 {
+            let edge = left.point(1.0, (k as f64 + 0.5) / 100.0);
+            for &dx in [-3.0e-3, -1.0e-3, 1.0e-3].iter() {
+                let origin = point(edge.x + dx, edge.y, 5.0);
+                let ray = Ray::new(origin, vector(0.0, 0.0, -1.0));
+                assert!(surface.intersect(&ray).is_some(), "{:?}", origin);
+            }
+        }
+    }
+
+    #[test]
+    fn test_teapot_matches_obj() {
+        // the OBJ teapot is a tessellation of the same patches, with y up
+        let obj = include_str!("../../teapot.obj");
+        let mut vertices = Vec::new();
+        let mut faces = Vec::new();
+        for line in obj.lines() {
+            let mut fields = line.split_whitespace();
+            let kind = fields.next();
+            let values: Vec<f64> = fields.map(|s| s.parse().unwrap()).collect();
+            match kind {
+                Some("v") => {
+                    vertices.push(point(values[0], -values[2], values[1]))
+                }
+                Some("f") => faces.push([
+                    values[0] as usize - 1,
+                    values[1] as usize - 1,
+                    values[2] as usize - 1,
+                ]),
+                _ => (),
+            }
+        }
+        let mut face_normals = vec![Vec::new(); vertices.len()];
+        for f in faces.iter() {
+            let (p0, p1, p2) = (vertices[f[0]], vertices[f[1]], vertices[f[2]]);
+            let n = (p1 - p0).cross(p2 - p0);
+            if n.mag_sq() > 0.0 {
+                for &k in f.iter() {
+                    face_normals[k].push(n.normalized());
+                }
+            }
+        }
+
+        // rays fired at the OBJ's vertices along their normal from either
+        // side find the tessellated surface there, apart from at creases
+        // and at the open ends of the handle and spout
+        let tolerance = 1.0e-3;
+        let surface = BezierSurface::new(teapot(), tolerance);
+        let offset = 0.01;
+        let mut tested = 0;
+        for (&v, normals) in vertices.iter().zip(face_normals.iter()) {
+            let sum = normals
+                .iter()
+                .fold(Vector3::new(0.0, 0.0, 0.0), |sum, &n| sum + n);
+            let n = sum.normalized();
+            // vertices with fewer than the six triangles around a vertex of
+            // a grid are on the edge of a patch
+            let crease = normals.iter().any(|&m| m.dot(n) < 0.95);
+            if normals.len() < 6 || crease {
+                continue;
+            }
+            tested += 1;
+
+            let close = [n, -n].iter().any(|&d| {
+                let ray = Ray::new(v + d * offset, -d);
+                match surface.intersect(&ray) {
+                    Some(hit) => (hit.t - offset).abs() < 2.0 * tolerance,
+                    None => false,
+                }
+            });
+            assert!(close, "{:?} isn't on the surface", v);
+        }
+        assert!(tested > vertices.len() / 2);
+    }
+
+    #[test]
+    fn test_surface_intersect() {
+        let surface = BezierSurface::new(vec![saddle()], 1.0e-4);
+        let ray = Ray::new(point(1.5, 1.5, 5.0), vector(0.0, 0.0, -1.0));
+        let hit = surface.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &5.0, 1.0e-3);
+        assert_eps_eq(&hit.uv, &DVec2::new(0.5, 0.5), 1.0e-3);
+        assert_eps_eq(&hit.shading_normal, &normal(0.0, 0.0, 1.0), 1.0e-3);
+        assert!(hit.normal.dot(hit.shading_normal) > 0.99);
+        assert_eq!(hit.primitive, 0);
+
+        let ray = Ray::new(point(4.0, 1.5, 5.0), vector(0.0, 0.0, -1.0));
+        assert_eq!(surface.intersect(&ray), None);
+    }
+}
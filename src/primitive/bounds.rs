@@ -0,0 +1,352 @@
+use super::math::{point, vector, Point3, Ray, Transform, Vector3};
+use std::f64::consts::PI;
+
+const SLAB_PADDING: f64 = 1.0 + 2.0 * 3.0 * f64::EPSILON;
+
+/// An axis-aligned bounding box, given by its minimum and maximum corners.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    /// The box spanned by two opposite corners.
+    pub fn new(a: Point3, b: Point3) -> Aabb {
+        Aabb {
+            min: a.min_by_component(b),
+            max: a.max_by_component(b),
+        }
+    }
+
+    /// A box containing nothing, which is the identity for `union`.
+    pub fn empty() -> Aabb {
+        let inf = f64::INFINITY;
+        Aabb {
+            min: point(inf, inf, inf),
+            max: point(-inf, -inf, -inf),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x
+            || self.min.y > self.max.y
+            || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min_by_component(other.min),
+            max: self.max.max_by_component(other.max),
+        }
+    }
+
+    /// The box that's inside both boxes, which is empty if they don't
+    /// overlap.
+    pub fn intersection(&self, other: &Aabb) -> Aabb {
+        let both = Aabb {
+            min: self.min.max_by_component(other.min),
+            max: self.max.min_by_component(other.max),
+        };
+        if both.is_empty() {
+            Aabb::empty()
+        } else {
+            both
+        }
+    }
+
+    pub fn union_point(&self, p: Point3) -> Aabb {
+        Aabb {
+            min: self.min.min_by_component(p),
+            max: self.max.max_by_component(p),
+        }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        self.min.lerp(self.max, 0.5)
+    }
+
+    /// The vector from the minimum to the maximum corner.
+    pub fn diagonal(&self) -> Vector3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.diagonal();
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// The axis along which the box is longest: 0 for x, 1 for y, 2 for z.
+    pub fn largest_axis(&self) -> usize {
+        let d = self.diagonal();
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Position of `p` relative to the box along each axis, 0 at the minimum
+    /// corner and 1 at the maximum corner.
+    pub fn offset(&self, p: Point3) -> Vector3 {
+        let mut o = p - self.min;
+        let d = self.diagonal();
+        for axis in 0..3 {
+            if d[axis] > 0.0 {
+                o[axis] /= d[axis];
+            }
+        }
+        o
+    }
+
+    #[cfg(test)]
+    pub fn contains(&self, p: Point3) -> bool {
+        (0..3)
+            .all(|axis| p[axis] >= self.min[axis] && p[axis] <= self.max[axis])
+    }
+
+    /// The range of distances along `ray` that are inside the box, clipped
+    /// to [0, t_max], using the slab test. `inv_dir` is the reciprocal of
+    /// each component of the ray's direction, which callers testing many
+    /// boxes against the same ray only need to compute once.
+    pub fn intersect(
+        &self,
+        ray: &Ray,
+        inv_dir: Vector3,
+        t_max: f64,
+    ) -> Option<(f64, f64)> {
+        self.intersect_between(ray, inv_dir, 0.0, t_max)
+    }
+
+    /// Like `intersect`, but clipped to [t_min, t_max] instead, which may
+    /// start behind the ray.
+    pub fn intersect_between(
+        &self,
+        ray: &Ray,
+        inv_dir: Vector3,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<(f64, f64)> {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for axis in 0..3 {
+            let mut t_near =
+                (self.min[axis] - ray.origin[axis]) * inv_dir[axis];
+            let mut t_far = (self.max[axis] - ray.origin[axis]) * inv_dir[axis];
+            if t_near > t_far {
+                std::mem::swap(&mut t_near, &mut t_far);
+            }
+            // widen the far side by the worst case rounding error so that
+            // rays grazing an edge aren't lost (pbrt's 1 + 2 gamma(3))
+            t_far *= SLAB_PADDING;
+
+            // written so that NaNs, from rays in the plane of a slab, are
+            // ignored
+            if t_near > t0 {
+                t0 = t_near;
+            }
+            if t_far < t1 {
+                t1 = t_far;
+            }
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+
+    /// The box around all eight corners of this one after `transform`.
+    pub fn transformed(&self, transform: &Transform) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        (0..8).fold(Aabb::empty(), |bounds, corner| {
+            let pick = |axis: usize| {
+                if corner & (1 << axis) == 0 {
+                    self.min[axis]
+                } else {
+                    self.max[axis]
+                }
+            };
+            let p = point(pick(0), pick(1), pick(2));
+            bounds.union_point(transform.point(p))
+        })
+    }
+
+    /// The center and radius of a sphere enclosing the box.
+    pub fn bounding_sphere(&self) -> (Point3, f64) {
+        let center = self.centroid();
+        let radius = if self.is_empty() {
+            0.0
+        } else {
+            (self.max - center).mag()
+        };
+        (center, radius)
+    }
+}
+
+/// A cone of directions around the unit vector `w`, containing every
+/// direction within an angle theta of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionCone {
+    pub w: Vector3,
+    pub cos_theta: f64,
+}
+
+impl DirectionCone {
+    pub fn new(w: Vector3, cos_theta: f64) -> DirectionCone {
+        DirectionCone {
+            w: w.normalized(),
+            cos_theta,
+        }
+    }
+
+    /// The cone containing only the direction `w`.
+    pub fn from_direction(w: Vector3) -> DirectionCone {
+        DirectionCone::new(w, 1.0)
+    }
+
+    pub fn entire_sphere() -> DirectionCone {
+        DirectionCone::new(vector(0.0, 0.0, 1.0), -1.0)
+    }
+
+    /// The smallest cone containing both cones (Barbier and Hanika 2016).
+    pub fn union(&self, other: &DirectionCone) -> DirectionCone {
+        let theta_a = self.cos_theta.clamp(-1.0, 1.0).acos();
+        let theta_b = other.cos_theta.clamp(-1.0, 1.0).acos();
+        let theta_d = self.w.dot(other.w).clamp(-1.0, 1.0).acos();
+
+        // one cone may already contain the other
+        if f64::min(theta_d + theta_b, PI) <= theta_a {
+            return *self;
+        }
+        if f64::min(theta_d + theta_a, PI) <= theta_b {
+            return *other;
+        }
+
+        let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+        if theta_o >= PI {
+            return DirectionCone::entire_sphere();
+        }
+
+        // turn our axis towards the other cone's until it's in the middle
+        let axis = self.w.cross(other.w);
+        if axis.mag_sq() == 0.0 {
+            return DirectionCone::entire_sphere();
+        }
+        let axis = axis.normalized();
+        let theta_r = theta_o - theta_a;
+        let w = self.w * theta_r.cos()
+            + axis.cross(self.w) * theta_r.sin()
+            + axis * (axis.dot(self.w) * (1.0 - theta_r.cos()));
+        DirectionCone::new(w, theta_o.cos())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::math::test_util::assert_eps_eq;
+
+    const EPS: f64 = 1.0e-9;
+
+    #[test]
+    fn test_union() {
+        let a = Aabb::new(point(1.0, 0.0, 0.0), point(0.0, 1.0, 1.0));
+        let b = Aabb::new(point(2.0, -1.0, 0.5), point(3.0, 0.5, 0.5));
+        let both = a.union(&b);
+        assert_eq!(both.min, point(0.0, -1.0, 0.0));
+        assert_eq!(both.max, point(3.0, 1.0, 1.0));
+        assert_eq!(Aabb::empty().union(&a), a);
+
+        let overlap = a.intersection(&Aabb::new(
+            point(0.5, 0.5, -1.0),
+            point(2.0, 2.0, 0.5),
+        ));
+        assert_eq!(overlap.min, point(0.5, 0.5, 0.0));
+        assert_eq!(overlap.max, point(1.0, 1.0, 0.5));
+        assert!(a.intersection(&b).is_empty());
+        assert!(Aabb::empty().is_empty());
+        assert!(!a.is_empty());
+    }
+
+    #[test]
+    fn test_measurements() {
+        let b = Aabb::new(point(0.0, 0.0, 0.0), point(1.0, 2.0, 3.0));
+        assert_eq!(b.centroid(), point(0.5, 1.0, 1.5));
+        assert_eq!(b.surface_area(), 22.0);
+        assert_eq!(b.largest_axis(), 2);
+        assert_eq!(Aabb::empty().surface_area(), 0.0);
+        assert_eps_eq(&b.offset(point(0.5, 1.0, 3.0)).z, &1.0, EPS);
+        assert!(b.contains(point(0.5, 0.5, 0.5)));
+        assert!(!b.contains(point(0.5, 2.5, 0.5)));
+
+        let (center, radius) = b.bounding_sphere();
+        assert_eq!(center, b.centroid());
+        assert_eps_eq(&radius, &(14.0f64.sqrt() / 2.0), EPS);
+    }
+
+    #[test]
+    fn test_ray_intersection() {
+        let b = Aabb::new(point(-1.0, -1.0, -1.0), point(1.0, 1.0, 1.0));
+        let hit = |origin, direction: Vector3| {
+            let ray = Ray::new(origin, direction);
+            let inv_dir =
+                vector(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+            b.intersect(&ray, inv_dir, f64::INFINITY)
+        };
+
+        let (t0, t1) =
+            hit(point(0.0, 0.0, 5.0), vector(0.0, 0.0, -1.0)).unwrap();
+        assert_eps_eq(&t0, &4.0, EPS);
+        assert_eps_eq(&t1, &6.0, EPS);
+
+        // starting inside clips the range to the origin
+        let (t0, t1) =
+            hit(point(0.0, 0.0, 0.0), vector(1.0, 0.0, 0.0)).unwrap();
+        assert_eq!(t0, 0.0);
+        assert_eps_eq(&t1, &1.0, EPS);
+
+        // misses, boxes behind the ray, and a ray in the plane of a face
+        assert_eq!(hit(point(0.0, 2.0, 5.0), vector(0.0, 0.0, -1.0)), None);
+        assert_eq!(hit(point(0.0, 0.0, 5.0), vector(0.0, 0.0, 1.0)), None);
+        assert!(hit(point(1.0, 0.0, 5.0), vector(0.0, 0.0, -1.0)).is_some());
+
+        // the range is cut off at t_max
+        let ray = Ray::new(point(0.0, 0.0, 5.0), vector(0.0, 0.0, -1.0));
+        let inv_dir = vector(f64::INFINITY, f64::INFINITY, -1.0);
+        assert_eq!(b.intersect(&ray, inv_dir, 3.0), None);
+
+        // or not clipped at all, finding the box behind the ray
+        let ray = Ray::new(point(0.0, 0.0, 5.0), vector(0.0, 0.0, 1.0));
+        let inv_dir = vector(f64::INFINITY, f64::INFINITY, 1.0);
+        let inf = f64::INFINITY;
+        let range = b.intersect_between(&ray, inv_dir, -inf, inf);
+        assert_eps_eq(&range, &Some((-6.0, -4.0)), EPS);
+    }
+
+    #[test]
+    fn test_cone_union() {
+        let up = DirectionCone::from_direction(vector(0.0, 1.0, 0.0));
+        let right = DirectionCone::from_direction(vector(1.0, 0.0, 0.0));
+
+        // the two directions are 90 degrees apart, so the union is a 45
+        // degree cone halfway between them
+        let both = up.union(&right);
+        let half = f64::to_radians(45.0);
+        assert_eps_eq(&both.w, &vector(half.sin(), half.cos(), 0.0), EPS);
+        assert_eps_eq(&both.cos_theta, &half.cos(), EPS);
+
+        let wide = DirectionCone::new(vector(0.0, 1.0, 0.0), 0.0);
+        assert_eq!(wide.union(&up), wide);
+        assert_eq!(up.union(&wide), wide);
+
+        let down = DirectionCone::from_direction(vector(0.0, -1.0, 0.0));
+        assert_eq!(up.union(&down).cos_theta, -1.0);
+    }
+}
@@ -0,0 +1,425 @@
+use super::math::{vector, Ray};
+use super::transformed::Transformed;
+use super::{Aabb, Bounded, Intersection, Scene};
+use std::cell::Cell;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Leaves hold at most this many primitives, unless they can't be split.
+const MAX_PRIMITIVES_IN_NODE: usize = 4;
+
+/// Buckets along the split axis when looking for the best place to split a
+/// node.
+const SPLIT_BUCKETS: usize = 12;
+
+/// The cost of visiting a node, relative to intersecting one primitive.
+const TRAVERSAL_COST: f64 = 0.125;
+
+thread_local! {
+    static NODE_VISITS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// How many nodes every hierarchy has tested rays against on this thread,
+/// counting nodes of nested hierarchies too. The difference across a call
+/// to `intersect` is what that ray cost.
+pub fn node_visits() -> u64 {
+    NODE_VISITS.with(Cell::get)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NodeKind {
+    /// Holds `count` primitives starting at `first`.
+    Leaf { first: usize, count: usize },
+    /// The first child directly follows its parent in the node list, and
+    /// `second` is the index of the other. The children were split along
+    /// `axis`, which decides which of them a ray visits first.
+    Interior { second: usize, axis: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BvhNode {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+/// A bounding volume hierarchy, splitting its primitives with the surface
+/// area heuristic.
+///
+/// A hierarchy can itself be a primitive, so scenes can be built in two
+/// levels: a bottom-level hierarchy per mesh, shared by any number of
+/// `Instance`s, and a top-level hierarchy over the instances. Only the
+/// instances' transforms and bounds are stored per copy, and rays are moved
+/// into each instance's object space as they reach it.
+pub struct Bvh<T: Bounded> {
+    /// In the order the leaves refer to them.
+    primitives: Vec<T>,
+    /// Index of each primitive in the list the hierarchy was built from,
+    /// which is what hits report as their `primitive`.
+    indices: Vec<usize>,
+    nodes: Vec<BvhNode>,
+}
+
+/// A bottom-level hierarchy placed in the world by a transform.
+pub type Instance<T> = Transformed<Arc<Bvh<T>>>;
+
+impl<T: Bounded> Bvh<T> {
+    pub fn new(primitives: Vec<T>) -> Bvh<T> {
+        let mut info: Vec<(usize, Aabb)> =
+            primitives.iter().map(|p| p.bounds()).enumerate().collect();
+
+        let mut nodes = Vec::new();
+        if !info.is_empty() {
+            build(&mut nodes, &mut info, 0);
+        }
+
+        // put the primitives in the order the leaves expect
+        let mut primitives: Vec<Option<T>> =
+            primitives.into_iter().map(Some).collect();
+        let indices: Vec<usize> = info.iter().map(|(i, _)| *i).collect();
+        let primitives = indices
+            .iter()
+            .map(|&i| primitives[i].take().unwrap())
+            .collect();
+
+        Bvh {
+            primitives,
+            indices,
+            nodes,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.primitives.len()
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+}
+
+/// Add the nodes for `info` to the node list. `offset` is where `info`
+/// starts in the whole list of primitives.
+fn build(nodes: &mut Vec<BvhNode>, info: &mut [(usize, Aabb)], offset: usize) {
+    let bounds = info.iter().fold(Aabb::empty(), |b, (_, p)| b.union(p));
+    let leaf = BvhNode {
+        bounds,
+        kind: NodeKind::Leaf {
+            first: offset,
+            count: info.len(),
+        },
+    };
+
+    let (axis, mid) = match split(info, &bounds) {
+        Some(split) => split,
+        None => {
+            nodes.push(leaf);
+            return;
+        }
+    };
+
+    let node = nodes.len();
+    nodes.push(leaf);
+    let (first, second) = info.split_at_mut(mid);
+    build(nodes, first, offset);
+    let second_index = nodes.len();
+    build(nodes, second, offset + mid);
+    nodes[node].kind = NodeKind::Interior {
+        second: second_index,
+        axis,
+    };
+}
+
+/// Reorder `info` and return the axis and position to split it into two
+/// nodes at, or `None` if it's cheaper to keep it as a leaf.
+fn split(info: &mut [(usize, Aabb)], bounds: &Aabb) -> Option<(usize, usize)> {
+    if info.len() == 1 {
+        return None;
+    }
+
+    let centroid_bounds = info
+        .iter()
+        .fold(Aabb::empty(), |b, (_, p)| b.union_point(p.centroid()));
+    let axis = centroid_bounds.largest_axis();
+    if centroid_bounds.max[axis] == centroid_bounds.min[axis] {
+        // every centroid is in the same place, so there's nothing to split
+        return None;
+    }
+
+    let bucket = |b: &Aabb| {
+        let offset = centroid_bounds.offset(b.centroid())[axis];
+        ((offset * SPLIT_BUCKETS as f64) as usize).min(SPLIT_BUCKETS - 1)
+    };
+    let mut counts = [0usize; SPLIT_BUCKETS];
+    let mut boxes = [Aabb::empty(); SPLIT_BUCKETS];
+    for (_, b) in info.iter() {
+        let i = bucket(b);
+        counts[i] += 1;
+        boxes[i] = boxes[i].union(b);
+    }
+
+    // the expected cost of a ray that hits the node, for each split
+    let side = |buckets: Range<usize>| {
+        let count: usize = counts[buckets.clone()].iter().sum();
+        let area = boxes[buckets]
+            .iter()
+            .fold(Aabb::empty(), |b, bucket| b.union(bucket))
+            .surface_area();
+        count as f64 * area
+    };
+    let cost = |split: usize| {
+        let below = side(0..split);
+        let above = side(split..SPLIT_BUCKETS);
+        TRAVERSAL_COST + (below + above) / bounds.surface_area()
+    };
+    let (best_split, best_cost) =
+        (1..SPLIT_BUCKETS).map(|split| (split, cost(split))).fold(
+            (0, f64::INFINITY),
+            |best, c| if c.1 < best.1 { c } else { best },
+        );
+
+    if info.len() <= MAX_PRIMITIVES_IN_NODE && best_cost >= info.len() as f64 {
+        return None;
+    }
+
+    let mut mid = 0;
+    for i in 0..info.len() {
+        if bucket(&info[i].1) < best_split {
+            info.swap(i, mid);
+            mid += 1;
+        }
+    }
+
+    if mid == 0 || mid == info.len() {
+        // the buckets couldn't separate them, so split at the median
+        let key = |p: &(usize, Aabb)| p.1.centroid()[axis];
+        info.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        mid = info.len() / 2;
+    }
+    Some((axis, mid))
+}
+
+impl<T: Bounded> Scene for Bvh<T> {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let d = ray.direction;
+        let inv_dir = vector(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+        let mut closest: Option<Intersection> = None;
+        let mut t_max = f64::INFINITY;
+
+        let mut to_visit = Vec::with_capacity(64);
+        let mut node = 0;
+        let mut visits = 0;
+        loop {
+            visits += 1;
+            let n = &self.nodes[node];
+            if n.bounds.intersect(ray, inv_dir, t_max).is_some() {
+                match n.kind {
+                    NodeKind::Leaf { first, count } => {
+                        for i in first..first + count {
+                            match self.primitives[i].intersect(ray) {
+                                Some(mut hit) if hit.t < t_max => {
+                                    hit.primitive = self.indices[i];
+                                    t_max = hit.t;
+                                    closest = Some(hit);
+                                }
+                                _ => (),
+                            }
+                        }
+                    }
+                    NodeKind::Interior { second, axis } => {
+                        // visit the nearer child first, so that hits in it
+                        // can cull the other
+                        if inv_dir[axis] < 0.0 {
+                            to_visit.push(node + 1);
+                            node = second;
+                        } else {
+                            to_visit.push(second);
+                            node += 1;
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            match to_visit.pop() {
+                Some(next) => node = next,
+                None => break,
+            }
+        }
+
+        NODE_VISITS.with(|count| count.set(count.get() + visits));
+        closest
+    }
+}
+
+impl<T: Bounded> Bounded for Bvh<T> {
+    fn bounds(&self) -> Aabb {
+        match self.nodes.first() {
+            Some(root) => root.bounds,
+            None => Aabb::empty(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::math::test_util::assert_eps_eq;
+    use super::super::super::math::{point, translation, vector, Point3};
+    use super::super::{Shape, Sphere, Triangle};
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const EPS: f64 = 1.0e-9;
+
+    fn random_point(rng: &mut StdRng, size: f64) -> Point3 {
+        point(
+            rng.gen_range(-size, size),
+            rng.gen_range(-size, size),
+            rng.gen_range(-size, size),
+        )
+    }
+
+    fn random_triangles(rng: &mut StdRng, count: usize) -> Vec<Triangle> {
+        (0..count)
+            .map(|_| {
+                let p = random_point(rng, 10.0);
+                let a = p + (random_point(rng, 1.0) - Point3::origin());
+                let b = p + (random_point(rng, 1.0) - Point3::origin());
+                Triangle::new(p, a, b)
+            })
+            .collect()
+    }
+
+    fn random_ray(rng: &mut StdRng) -> Ray {
+        let origin = random_point(rng, 15.0);
+        let target = random_point(rng, 5.0);
+        Ray::new(origin, target - origin)
+    }
+
+    #[test]
+    fn test_matches_brute_force() {
+        let brute = random_triangles(&mut StdRng::seed_from_u64(1), 500);
+        let bvh =
+            Bvh::new(random_triangles(&mut StdRng::seed_from_u64(1), 500));
+        assert_eq!(bvh.len(), 500);
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = random_ray(&mut rng);
+            let expected = brute.intersect(&ray);
+            let found = bvh.intersect(&ray);
+            assert_eq!(found, expected);
+            if found.is_some() {
+                hits += 1;
+            }
+        }
+        // make sure the test isn't just comparing misses
+        assert!(hits > 100, "only {} hits", hits);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let spheres = vec![
+            Sphere::new(point(0.0, 0.0, 0.0), 1.0),
+            Sphere::new(point(5.0, 1.0, -2.0), 2.0),
+        ];
+        let bvh = Bvh::new(spheres);
+        assert_eq!(bvh.bounds().min, point(-1.0, -1.0, -4.0));
+        assert_eq!(bvh.bounds().max, point(7.0, 3.0, 1.0));
+
+        let empty: Bvh<Sphere> = Bvh::new(Vec::new());
+        assert!(empty.is_empty());
+        assert!(empty.bounds().is_empty());
+        let ray = Ray::new(point(0.0, 0.0, 5.0), vector(0.0, 0.0, -1.0));
+        assert_eq!(empty.intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_node_visits() {
+        let triangles = random_triangles(&mut StdRng::seed_from_u64(1), 500);
+        let bvh = Bvh::new(triangles);
+        let cost = |ray: &Ray| {
+            let before = node_visits();
+            bvh.intersect(ray);
+            node_visits() - before
+        };
+
+        // a ray that misses the root stops there
+        let ray = Ray::new(point(0.0, 100.0, 0.0), vector(0.0, 1.0, 0.0));
+        assert_eq!(cost(&ray), 1);
+        // and one through the middle of the scene visits more of the tree,
+        // but far from all of it
+        let ray = Ray::new(point(0.0, 0.0, 20.0), vector(0.0, 0.0, -1.0));
+        let visits = cost(&ray);
+        assert!(visits > 1 && visits < bvh.nodes.len() as u64);
+    }
+
+    #[test]
+    fn test_same_centroids() {
+        // nested spheres can't be split, and end up in one leaf
+        let spheres: Vec<Sphere> = (1..=10)
+            .map(|r| Sphere::new(point(0.0, 0.0, 0.0), r as f64))
+            .collect();
+        let bvh = Bvh::new(spheres);
+        let ray = Ray::new(point(0.0, 0.0, 20.0), vector(0.0, 0.0, -1.0));
+        let hit = bvh.intersect(&ray).unwrap();
+        assert_eq!(hit.primitive, 9);
+        assert_eps_eq(&hit.t, &10.0, EPS);
+    }
+
+    #[test]
+    fn test_instances() {
+        // a 100 by 100 grid of copies of one mesh
+        let triangles = random_triangles(&mut StdRng::seed_from_u64(2), 50);
+        let mesh = random_triangles(&mut StdRng::seed_from_u64(2), 50);
+        let mesh = Arc::new(Bvh::new(mesh));
+        let mut instances = Vec::new();
+        let mut brute = Vec::new();
+        for i in 0..100 {
+            for j in 0..100 {
+                let x = 30.0 * i as f64;
+                let z = -30.0 * j as f64;
+                let m = translation(x, 0.0, z);
+                instances.push(Instance::new(Arc::clone(&mesh), m));
+                brute.push(Transformed::new(Arc::clone(&mesh), m));
+            }
+        }
+        let scene = Bvh::new(instances);
+        assert_eq!(scene.len(), 10000);
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut hits = 0;
+        for _ in 0..200 {
+            // aim at a random triangle of a random copy, from above
+            let triangle = &triangles[rng.gen_range(0, triangles.len())];
+            let target = triangle.sample((rng.gen(), rng.gen())).point
+                + vector(
+                    30.0 * rng.gen_range(0, 100) as f64,
+                    0.0,
+                    -30.0 * rng.gen_range(0, 100) as f64,
+                );
+            let origin = target + vector(3.0, 40.0, 2.0);
+            let ray = Ray::new(origin, target - origin);
+            let expected = brute.intersect(&ray);
+            let found = scene.intersect(&ray);
+            if let Some(hit) = found {
+                hits += 1;
+                let expected = expected.unwrap();
+                assert_eq!(hit.primitive, expected.primitive);
+                assert_eps_eq(&hit.t, &expected.t, EPS);
+                assert_eps_eq(&hit.point, &expected.point, EPS);
+                assert_eps_eq(&hit.normal, &expected.normal, EPS);
+            } else {
+                assert_eq!(expected, None);
+            }
+        }
+        assert!(hits > 150, "only {} hits", hits);
+    }
+}
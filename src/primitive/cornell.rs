@@ -1,135 +1,247 @@
-use super::{Scene, Ray};
-use cgmath::{InnerSpace, Vector3, vec2, vec3};
+use super::{Aabb, Bounded, Bvh, DirectionCone, Intersection, Scene, Shape};
+use super::{AxisAlignedBox, OrientedBox, SurfaceSample, Transformed};
+use super::MIN_DISTANCE;
+use super::math::{Ray, point, vector, Normal3, Point3, Vector3};
+use super::math::TransformBuilder;
+use super::super::color::Color;
+use super::super::light::AreaLight;
+use super::super::material::{Material, MaterialList};
+use std::f64::consts::PI;
+use std::sync::Arc;
+use ultraviolet::vec::DVec2;
 
 /// Representation of a parallelogram in 3D space.
-struct Parallelogram {
-    p: Vector3<f64>,
-    u: Vector3<f64>,
-    v: Vector3<f64>,
-    normal: Vector3<f64>,
-    color: Vector3<u8>,
-    emittance: Option<Vector3<u8>>,
+pub struct Parallelogram {
+    p: Point3,
+    u: Vector3,
+    v: Vector3,
+    normal: Normal3,
+    /// Index into the scene's materials to give hits.
+    material: Option<usize>,
 }
 
 impl Parallelogram {
-    fn new(
-        p: Vector3<f64>,
-        u: Vector3<f64>,
-        v: Vector3<f64>,
-        color: Vector3<u8>,
-    ) -> Parallelogram {
+    /// The parallelogram with corners p, p + u, p + u + v and p + v. It faces
+    /// in the direction of u x v.
+    pub fn new(p: Point3, u: Vector3, v: Vector3) -> Parallelogram {
         Parallelogram {
-            p, u, v, color,
-            normal: u.cross(v).normalize(),
-            emittance: None,
+            p, u, v,
+            normal: u.cross(v).normalized().into(),
+            material: None,
         }
     }
 
-    fn set_emissive(&mut self, color: Vector3<u8>) {
-        self.emittance = Some(color);
+    /// Give hits the index `material` into the scene's materials.
+    pub fn with_material(mut self, material: usize) -> Parallelogram {
+        self.material = Some(material);
+        self
     }
 
-    fn intersect(&self, ray: &Ray) -> Option<Ray> {
+    /// Express a point on the parallelogram's plane as p + a * u + b * v,
+    /// returning (a, b) if the point is inside of the parallelogram.
+    fn check_bounds(&self, point: Point3) -> Option<(f64, f64)> {
+        let w = point - self.p;
+        let (u, v) = (self.u, self.v);
+        let n = u.cross(v);
+        let a = w.cross(v).dot(n) / n.mag_sq();
+        let b = u.cross(w).dot(n) / n.mag_sq();
+
+        // in this coordinate system the parallelogram is bounded by
+        // the square (0, 0), (0, 1), (1, 1), (1, 0)
+        if (0.0..=1.0).contains(&a) && (0.0..=1.0).contains(&b) {
+            Some((a, b))
+        } else {
+            None
+        }
+    }
+}
+
+impl Scene for Parallelogram {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
         let denom = ray.direction.dot(self.normal);
         if denom.abs() > 1.0e-4 {
             let t = (self.p - ray.origin).dot(self.normal) / denom;
-            if t > 1.0e-4 {
-                let intersect = ray.origin + ray.direction * t;
-                if self.check_bounds(intersect) {
-                    return Some(Ray::new(intersect, self.normal.clone()));
+            if t > MIN_DISTANCE {
+                let intersect = ray.position(t);
+                if let Some((a, b)) = self.check_bounds(intersect) {
+                    let uv = DVec2::new(a, b);
+                    let mut hit =
+                        Intersection::new(t, intersect, self.normal, uv);
+                    hit.material = self.material;
+                    return Some(hit);
                 }
             }
         }
 
-        // TODO: borrow normals from the surface instead of cloning them?
         None
     }
+}
 
-    fn check_bounds(&self, point: Vector3<f64>) -> bool {
-        // express the point as components of u and v
-        let point = point - self.p;
-        let point = vec2(point.dot(self.u), point.dot(self.v));
+impl Bounded for Parallelogram {
+    fn bounds(&self) -> Aabb {
+        Aabb::new(self.p, self.p + self.u + self.v)
+            .union_point(self.p + self.u)
+            .union_point(self.p + self.v)
+    }
+}
 
-        // in this coordinate system the parallelogram is bounded by
-        // the square (0, 0), (0, 1), (1, 1), (1, 0)
-        !(point.y < 0.0 || point.y > 1.0 || point.x < 0.0 || point.x > 1.0)
+impl Shape for Parallelogram {
+    fn area(&self) -> f64 {
+        self.u.cross(self.v).mag()
+    }
+
+    fn normal_bounds(&self) -> DirectionCone {
+        DirectionCone::from_direction(self.normal.into())
+    }
+
+    fn sample(&self, u: (f64, f64)) -> SurfaceSample {
+        SurfaceSample {
+            point: self.p + self.u * u.0 + self.v * u.1,
+            normal: self.normal,
+            pdf: 1.0 / self.area(),
+        }
     }
 }
 
+/// Indices of the Cornell box's materials.
+const WHITE: usize = 0;
+const RED: usize = 1;
+const GREEN: usize = 2;
+
+/// The Cornell box: a white room with a red wall on the left, a green wall on
+/// the right, a square light on the ceiling, and a short and a tall block
+/// standing on the floor. The room spans [-1, 1] along x and y, and [-3, -1]
+/// along z with the side facing the camera left open.
 pub struct CornellBox {
-    surfaces: Vec<Parallelogram>,
+    /// The albedos of the materials the box's hits refer to.
+    colors: Vec<Color>,
+    surfaces: Bvh<Parallelogram>,
+    blocks: Vec<OrientedBox>,
+    light: Arc<AreaLight<Parallelogram>>,
 }
 
 impl CornellBox {
     pub fn new() -> CornellBox {
-        let white = vec3(255, 255, 255);
-        let green = vec3(0, 255, 0);
-        let red = vec3(255, 0, 0);
-        let mut surfaces = Vec::new();
-
-        let test_a = Parallelogram::new(
-            vec3(0.0, 0.0, -2.0),
-            vec3(0.0, 1.0, 0.0),
-            vec3(1.0, 0.0, 0.0),
-            white,
-        );
+        let mut colors = vec![Color::black(); 3];
+        colors[WHITE] = Color::new(0.73, 0.73, 0.73);
+        colors[RED] = Color::new(0.65, 0.05, 0.05);
+        colors[GREEN] = Color::new(0.12, 0.45, 0.15);
+
+        // every wall faces into the room
+        let floor = Parallelogram::new(
+            point(-1.0, -1.0, -1.0),
+            vector(2.0, 0.0, 0.0),
+            vector(0.0, 0.0, -2.0),
+        )
+        .with_material(WHITE);
+        let ceiling = Parallelogram::new(
+            point(-1.0, 1.0, -1.0),
+            vector(0.0, 0.0, -2.0),
+            vector(2.0, 0.0, 0.0),
+        )
+        .with_material(WHITE);
+        let back = Parallelogram::new(
+            point(-1.0, -1.0, -3.0),
+            vector(2.0, 0.0, 0.0),
+            vector(0.0, 2.0, 0.0),
+        )
+        .with_material(WHITE);
+        let left = Parallelogram::new(
+            point(-1.0, -1.0, -1.0),
+            vector(0.0, 0.0, -2.0),
+            vector(0.0, 2.0, 0.0),
+        )
+        .with_material(RED);
+        let right = Parallelogram::new(
+            point(1.0, -1.0, -1.0),
+            vector(0.0, 2.0, 0.0),
+            vector(0.0, 0.0, -2.0),
+        )
+        .with_material(GREEN);
+
+        // hangs just below the ceiling, facing down
+        let lamp = Parallelogram::new(
+            point(-0.25, 0.99, -1.75),
+            vector(0.0, 0.0, -0.5),
+            vector(0.5, 0.0, 0.0),
+        )
+        .with_material(WHITE);
+        let light = AreaLight::new(lamp, Color::new(34.0, 24.0, 8.0), false);
 
-        let test_b = Parallelogram::new(
-            vec3(-1.0, -1.0, -2.0),
-            vec3(0.0, 1.0, 0.0),
-            vec3(1.0, 0.0, 0.0),
-            white,
+        // the short block at the front right turned a little to the left, and
+        // the tall one at the back left turned a little to the right
+        let short = Transformed::new(
+            AxisAlignedBox::new(point(-0.3, 0.0, -0.3), point(0.3, 0.6, 0.3)),
+            TransformBuilder::new()
+                .rotate_y(-0.1 * PI)
+                .translate(0.4, -1.0, -1.65)
+                .build(),
+        );
+        let tall = Transformed::new(
+            AxisAlignedBox::new(point(-0.3, 0.0, -0.3), point(0.3, 1.2, 0.3)),
+            TransformBuilder::new()
+                .rotate_y(0.08 * PI)
+                .translate(-0.4, -1.0, -2.4)
+                .build(),
         );
 
-        surfaces.push(test_a);
-        surfaces.push(test_b);
+        CornellBox {
+            colors,
+            surfaces: Bvh::new(vec![floor, ceiling, back, left, right]),
+            blocks: vec![short, tall],
+            light: Arc::new(light),
+        }
+    }
 
-        CornellBox { surfaces }
+    /// The materials of the walls, blocks and light, which the box's hits
+    /// refer to.
+    pub fn materials(&self) -> MaterialList {
+        self.colors
+            .iter()
+            .map(|&color| Box::new(color) as Box<dyn Material>)
+            .collect()
+    }
+
+    /// The light on the ceiling, which is also part of the box's geometry.
+    pub fn light(&self) -> Arc<AreaLight<Parallelogram>> {
+        Arc::clone(&self.light)
     }
 }
 
 impl Scene for CornellBox {
-    fn intersect(&self, ray: &Ray) -> Option<Ray> {
-        let mut min_dist = None;
-        let mut min_normal = None;
-
-        for surface in &self.surfaces {
-            if let Some(normal) = surface.intersect(ray) {
-                // avoid sqrt
-                let dist = (normal.origin - ray.origin).magnitude2();
-                min_dist = match min_dist {
-                    None => {
-                        min_normal = Some(normal);
-                        Some(dist)
-                    },
-                    Some(cur_min) => {
-                        if dist < cur_min {
-                            min_normal = Some(normal);
-                            Some(dist)
-                        } else {
-                            Some(cur_min)
-                        }
-                    }
-                }
-            }
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let walls = self.surfaces.intersect(ray);
+        let blocks = self.blocks.intersect(ray).map(|mut hit| {
+            hit.material = Some(WHITE);
+            hit
+        });
+        let walls = match (walls, blocks) {
+            (Some(wall), Some(block)) if block.t < wall.t => Some(block),
+            (None, block) => block,
+            (wall, _) => wall,
+        };
+        let light = self.light.intersect(ray);
+        match (walls, light) {
+            (Some(wall), Some(light)) if light.t < wall.t => Some(light),
+            (None, light) => light,
+            (wall, _) => wall,
         }
-
-        min_normal
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use cgmath::assert_abs_diff_eq;
+    use super::super::math::normal;
+    use super::super::math::test_util::assert_eps_eq;
+
+    const EPS: f64 = 1.0e-9;
 
     fn make_test_surface() -> Parallelogram {
-        let color = vec3(255, 255, 255);
         Parallelogram::new(
-            vec3(0.0, 0.0, 0.0),
-            vec3(2.0, 0.0, 0.0),
-            vec3(0.0, 2.0, 0.0),
-            color,
+            point(0.0, 0.0, 0.0),
+            vector(2.0, 0.0, 0.0),
+            vector(0.0, 2.0, 0.0),
         )
     }
 
@@ -137,23 +249,79 @@ mod tests {
     fn test_surface_intersection() {
         let surface = make_test_surface();
 
-        let ray_origin = vec3(1.0, 1.0, 10.0);
-        let ray_direction = vec3(0.0, 0.0, -1.0);
+        let ray_origin = point(1.0, 1.0, 10.0);
+        let ray_direction = vector(0.0, 0.0, -1.0);
         let ray = Ray::new(ray_origin, ray_direction);
 
         let intersection = surface.intersect(&ray).unwrap();
-        assert_eq!(intersection.origin, vec3(1.0, 1.0, 0.0));
-        assert_eq!(intersection.direction, vec3(0.0, 0.0, 1.0));
+        assert_eq!(intersection.point, point(1.0, 1.0, 0.0));
+        assert_eq!(intersection.normal, normal(0.0, 0.0, 1.0));
+        assert_eq!(intersection.uv, DVec2::new(0.5, 0.5));
     }
 
     #[test]
     fn test_surface_bounds() {
         let surface = make_test_surface();
 
-        let ray_origin = vec3(1.0, 1.0, 10.0);
-        let ray_direction = vec3(3.0, 0.0, -10.0);
+        let ray_origin = point(1.0, 1.0, 10.0);
+        let ray_direction = vector(3.0, 0.0, -10.0);
         let ray = Ray::new(ray_origin, ray_direction);
 
         assert_eq!(surface.intersect(&ray), None);
     }
+
+    #[test]
+    fn test_surface_sample() {
+        let surface = make_test_surface();
+        assert_eps_eq(&surface.area(), &4.0, EPS);
+
+        let sample = surface.sample((0.25, 0.75));
+        assert_eps_eq(&sample.point, &point(0.5, 1.5, 0.0), EPS);
+        assert_eps_eq(&sample.pdf, &0.25, EPS);
+    }
+
+    #[test]
+    fn test_cornell_box_light() {
+        let cornell = CornellBox::new();
+
+        // looking straight up from the middle of the floor sees the light
+        let ray = Ray::new(point(0.0, -1.0, -2.0), vector(0.0, 1.0, 0.0));
+        let hit = cornell.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.point.y, &0.99, EPS);
+        assert_eps_eq(&hit.normal, &normal(0.0, -1.0, 0.0), EPS);
+        assert_eq!(hit.emitted, Color::new(34.0, 24.0, 8.0));
+        assert_eq!(hit.material, Some(WHITE));
+
+        // and the back wall doesn't glow
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, -1.0));
+        let hit = cornell.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.point.z, &-3.0, EPS);
+        assert_eq!(hit.emitted, Color::black());
+
+        // the side walls are colored
+        let materials = cornell.materials();
+        let ray = Ray::new(point(0.0, 0.0, -2.0), vector(-1.0, 0.0, 0.0));
+        let hit = cornell.intersect(&ray).unwrap();
+        let albedo = materials[hit.material.unwrap()].albedo(hit.uv);
+        assert_eq!(albedo, Color::new(0.65, 0.05, 0.05));
+    }
+
+    #[test]
+    fn test_cornell_box_blocks() {
+        let cornell = CornellBox::new();
+
+        // the tops of the blocks, seen from above
+        let down = vector(0.0, -1.0, 0.0);
+        let ray = Ray::new(point(0.4, 0.5, -1.65), down);
+        let hit = cornell.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.point.y, &-0.4, EPS);
+        assert_eps_eq(&hit.normal, &normal(0.0, 1.0, 0.0), EPS);
+        assert_eq!(hit.material, Some(WHITE));
+        let ray = Ray::new(point(-0.4, 0.5, -2.4), down);
+        assert_eps_eq(&cornell.intersect(&ray).unwrap().point.y, &0.2, EPS);
+
+        // and the floor between them
+        let ray = Ray::new(point(0.0, 0.5, -1.2), down);
+        assert_eps_eq(&cornell.intersect(&ray).unwrap().point.y, &-1.0, EPS);
+    }
 }
@@ -0,0 +1,353 @@
+use super::math::Ray;
+use super::{Aabb, Bounded, Intersection, Scene, Solid, Span, MIN_DISTANCE};
+
+/// How a `Csg` node combines its two solids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOperation {
+    /// Everything inside either solid.
+    Union,
+    /// Everything inside both solids.
+    Intersection,
+    /// Everything inside the first solid but not the second.
+    Difference,
+}
+
+impl CsgOperation {
+    fn contains(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_a || in_b,
+            CsgOperation::Intersection => in_a && in_b,
+            CsgOperation::Difference => in_a && !in_b,
+        }
+    }
+}
+
+/// A boolean combination of two solids, which is a solid itself so that
+/// nodes can be nested into trees. Trees mixing different kinds of solids
+/// can use `Box<dyn Solid>` for their leaves.
+pub struct Csg<A: Solid, B: Solid> {
+    a: A,
+    b: B,
+    operation: CsgOperation,
+}
+
+impl<A: Solid, B: Solid> Csg<A, B> {
+    pub fn new(a: A, b: B, operation: CsgOperation) -> Csg<A, B> {
+        Csg { a, b, operation }
+    }
+
+    pub fn union(a: A, b: B) -> Csg<A, B> {
+        Csg::new(a, b, CsgOperation::Union)
+    }
+
+    pub fn intersection(a: A, b: B) -> Csg<A, B> {
+        Csg::new(a, b, CsgOperation::Intersection)
+    }
+
+    /// `a` with `b` cut out of it.
+    pub fn difference(a: A, b: B) -> Csg<A, B> {
+        Csg::new(a, b, CsgOperation::Difference)
+    }
+}
+
+/// A point where the ray crosses the surface of one of the two solids.
+struct Crossing {
+    hit: Intersection,
+    from_b: bool,
+    entering: bool,
+}
+
+fn crossings(spans: Vec<Span>, from_b: bool) -> impl Iterator<Item = Crossing> {
+    spans.into_iter().flat_map(move |span| {
+        let enter = Crossing {
+            hit: span.enter,
+            from_b,
+            entering: true,
+        };
+        let exit = Crossing {
+            hit: span.exit,
+            from_b,
+            entering: false,
+        };
+        vec![enter, exit]
+    })
+}
+
+impl<A: Solid, B: Solid> Solid for Csg<A, B> {
+    fn intersect_all(&self, ray: &Ray) -> Vec<Span> {
+        let a = self.a.intersect_all(ray);
+        let b = self.b.intersect_all(ray);
+        // nothing left to combine with
+        match self.operation {
+            CsgOperation::Union if b.is_empty() => return a,
+            CsgOperation::Union if a.is_empty() => return b,
+            CsgOperation::Intersection if a.is_empty() || b.is_empty() => {
+                return Vec::new()
+            }
+            CsgOperation::Difference if a.is_empty() || b.is_empty() => {
+                return a
+            }
+            _ => (),
+        }
+
+        // walk along the ray through every surface of either solid, keeping
+        // track of which solids we're in and recording where that changes
+        // whether we're inside the combination
+        let mut all: Vec<Crossing> =
+            crossings(a, false).chain(crossings(b, true)).collect();
+        all.sort_by(|x, y| x.hit.t.partial_cmp(&y.hit.t).unwrap());
+
+        let (mut in_a, mut in_b) = (false, false);
+        let mut enter: Option<Intersection> = None;
+        let mut spans = Vec::new();
+        for Crossing {
+            mut hit,
+            from_b,
+            entering,
+        } in all
+        {
+            if from_b {
+                in_b = entering;
+            } else {
+                in_a = entering;
+            }
+
+            // the surface of a subtracted solid faces into it, which is out
+            // of what's left
+            if from_b && self.operation == CsgOperation::Difference {
+                hit.normal = -hit.normal;
+                hit.shading_normal = -hit.shading_normal;
+            }
+
+            let inside = self.operation.contains(in_a, in_b);
+            match enter {
+                None if inside => enter = Some(hit),
+                Some(start) if !inside => {
+                    spans.push(Span {
+                        enter: start,
+                        exit: hit,
+                    });
+                    enter = None;
+                }
+                _ => (),
+            }
+        }
+        spans
+    }
+}
+
+impl<A: Solid, B: Solid> Scene for Csg<A, B> {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        self.intersect_all(ray)
+            .into_iter()
+            .flat_map(|span| vec![span.enter, span.exit])
+            .find(|hit| hit.t > MIN_DISTANCE)
+    }
+}
+
+impl<A, B> Bounded for Csg<A, B>
+where
+    A: Solid + Bounded,
+    B: Solid + Bounded,
+{
+    fn bounds(&self) -> Aabb {
+        let (a, b) = (self.a.bounds(), self.b.bounds());
+        match self.operation {
+            CsgOperation::Union => a.union(&b),
+            CsgOperation::Intersection => a.intersection(&b),
+            CsgOperation::Difference => a,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::math::test_util::assert_eps_eq;
+    use super::super::super::math::{normal, point, translation, vector};
+    use super::super::{AxisAlignedBox, Cylinder, Sphere, Torus, Transformed};
+    use super::*;
+
+    const EPS: f64 = 1.0e-9;
+
+    /// Spheres of radius 1 at x = 0 and x = 1.
+    fn spheres() -> (Sphere, Sphere) {
+        (
+            Sphere::new(point(0.0, 0.0, 0.0), 1.0),
+            Sphere::new(point(1.0, 0.0, 0.0), 1.0),
+        )
+    }
+
+    fn along_x(x: f64) -> Ray {
+        Ray::new(point(x, 0.0, 0.0), vector(1.0, 0.0, 0.0))
+    }
+
+    /// The x coordinates of the ends of every span.
+    fn ends(spans: &[Span]) -> Vec<f64> {
+        spans
+            .iter()
+            .flat_map(|span| vec![span.enter.point.x, span.exit.point.x])
+            .collect()
+    }
+
+    /// The y coordinates of the ends of a single span.
+    fn ends_y(spans: &[Span]) -> (f64, f64) {
+        assert_eq!(spans.len(), 1);
+        (spans[0].enter.point.y, spans[0].exit.point.y)
+    }
+
+    fn assert_ends(spans: &[Span], expected: &[f64]) {
+        let ends = ends(spans);
+        assert_eq!(ends.len(), expected.len(), "{:?}", ends);
+        for (end, expected) in ends.iter().zip(expected) {
+            assert_eps_eq(end, expected, EPS);
+        }
+    }
+
+    #[test]
+    fn test_solid_spans() {
+        let (sphere, _) = spheres();
+        assert_ends(&sphere.intersect_all(&along_x(5.0)), &[-1.0, 1.0]);
+
+        let cube =
+            AxisAlignedBox::new(point(-1.0, -1.0, -1.0), point(1.0, 1.0, 1.0));
+        let spans = cube.intersect_all(&along_x(0.0));
+        assert_ends(&spans, &[-1.0, 1.0]);
+        assert_eq!(spans[0].enter.normal, normal(-1.0, 0.0, 0.0));
+        assert_eq!(spans[0].exit.normal, normal(1.0, 0.0, 0.0));
+
+        let torus = Torus::new(2.0, 0.5);
+        let spans = torus.intersect_all(&along_x(-5.0));
+        assert_ends(&spans, &[-2.5, -1.5, 1.5, 2.5]);
+        assert_eps_eq(&spans[1].enter.normal, &normal(-1.0, 0.0, 0.0), EPS);
+
+        let moved = Transformed::new(sphere, translation(3.0, 0.0, 0.0));
+        assert_ends(&moved.intersect_all(&along_x(0.0)), &[2.0, 4.0]);
+    }
+
+    #[test]
+    fn test_cylinder_spans() {
+        // uncapped cylinders are closed off as solids
+        let cylinder = Cylinder::new(1.0, 0.0, 2.0);
+        let ray = Ray::new(point(0.5, -5.0, 0.0), vector(0.0, 1.0, 0.0));
+        let spans = cylinder.intersect_all(&ray);
+        assert_eq!(spans.len(), 1);
+        assert_eps_eq(&spans[0].enter.t, &5.0, EPS);
+        assert_eps_eq(&spans[0].exit.t, &7.0, EPS);
+        assert_eq!(spans[0].enter.normal, normal(0.0, -1.0, 0.0));
+        assert_eq!(spans[0].exit.normal, normal(0.0, 1.0, 0.0));
+
+        // in through the side and out through the top
+        let ray = Ray::new(point(-2.0, 0.0, 0.0), vector(1.0, 1.0, 0.0));
+        let spans = cylinder.intersect_all(&ray);
+        assert_eps_eq(&spans[0].enter.point, &point(-1.0, 1.0, 0.0), EPS);
+        assert_eps_eq(&spans[0].enter.normal, &normal(-1.0, 0.0, 0.0), EPS);
+        assert_eps_eq(&spans[0].exit.point, &point(0.0, 2.0, 0.0), EPS);
+        assert_eps_eq(&spans[0].exit.normal, &normal(0.0, 1.0, 0.0), EPS);
+
+        let ray = Ray::new(point(1.5, -5.0, 0.0), vector(0.0, 1.0, 0.0));
+        assert!(cylinder.intersect_all(&ray).is_empty());
+        let ray = Ray::new(point(-5.0, 3.0, 0.0), vector(1.0, 0.0, 0.0));
+        assert!(cylinder.intersect_all(&ray).is_empty());
+    }
+
+    #[test]
+    fn test_csg_union() {
+        let (a, b) = spheres();
+        let union = Csg::union(a, b);
+        assert_ends(&union.intersect_all(&along_x(-5.0)), &[-1.0, 2.0]);
+
+        let hit = union.intersect(&along_x(-5.0)).unwrap();
+        assert_eps_eq(&hit.t, &4.0, EPS);
+        assert_eps_eq(&hit.normal, &normal(-1.0, 0.0, 0.0), EPS);
+
+        // starting inside where the spheres overlap, the first hit is on the
+        // way out of both of them
+        let hit = union.intersect(&along_x(0.5)).unwrap();
+        assert_eps_eq(&hit.point, &point(2.0, 0.0, 0.0), EPS);
+        assert_eps_eq(&hit.normal, &normal(1.0, 0.0, 0.0), EPS);
+
+        let bounds = union.bounds();
+        assert_eq!(bounds.min, point(-1.0, -1.0, -1.0));
+        assert_eq!(bounds.max, point(2.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_csg_intersection() {
+        let (a, b) = spheres();
+        let lens = Csg::intersection(a, b);
+        assert_ends(&lens.intersect_all(&along_x(-5.0)), &[0.0, 1.0]);
+
+        // the front of the lens is the second sphere's surface
+        let hit = lens.intersect(&along_x(-5.0)).unwrap();
+        assert_eps_eq(&hit.t, &5.0, EPS);
+        assert_eps_eq(&hit.normal, &normal(-1.0, 0.0, 0.0), EPS);
+
+        // missing the lens while hitting one of the spheres
+        let ray = Ray::new(point(-0.9, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(lens.intersect(&ray), None);
+
+        let bounds = lens.bounds();
+        assert_eq!(bounds.min, point(0.0, -1.0, -1.0));
+        assert_eq!(bounds.max, point(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_csg_difference() {
+        let (a, b) = spheres();
+        let bitten = Csg::difference(a, b);
+        let spans = bitten.intersect_all(&along_x(-5.0));
+        assert_ends(&spans, &[-1.0, 0.0]);
+
+        // the bite is the second sphere's surface turned inside out, so
+        // coming at it from the bitten side sees it facing the ray
+        let hit = bitten
+            .intersect(&Ray::new(point(5.0, 0.0, 0.0), vector(-1.0, 0.0, 0.0)));
+        let hit = hit.unwrap();
+        assert_eps_eq(&hit.point, &point(0.0, 0.0, 0.0), EPS);
+        assert_eps_eq(&hit.normal, &normal(1.0, 0.0, 0.0), EPS);
+        assert_eps_eq(&hit.shading_normal, &normal(1.0, 0.0, 0.0), EPS);
+
+        // rays through the bite miss entirely
+        let ray = Ray::new(point(0.9, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(bitten.intersect(&ray), None);
+
+        // and taking away something that isn't there changes nothing
+        let ray = Ray::new(point(-0.5, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let hit = bitten.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &(5.0 - 0.75f64.sqrt()), EPS);
+    }
+
+    #[test]
+    fn test_csg_tree() {
+        // a cube with rounded corners and a hole drilled through it
+        let cube =
+            AxisAlignedBox::new(point(-1.0, -1.0, -1.0), point(1.0, 1.0, 1.0));
+        let ball = Sphere::new(point(0.0, 0.0, 0.0), 1.3);
+        let rounded: Box<dyn Solid> = Box::new(Csg::intersection(cube, ball));
+        let drill: Box<dyn Solid> = Box::new(Cylinder::new(0.5, -2.0, 2.0));
+        let part = Csg::difference(rounded, drill);
+
+        // through the hole
+        let ray = Ray::new(point(0.0, 5.0, 0.0), vector(0.0, -1.0, 0.0));
+        assert_eq!(part.intersect(&ray), None);
+
+        // beside the hole it's solid all the way through
+        let ray = Ray::new(point(0.0, 5.0, 0.7), vector(0.0, -1.0, 0.0));
+        assert_eps_eq(&ends_y(&part.intersect_all(&ray)), &(1.0, -1.0), EPS);
+
+        // across the hole it's in the side, out into the hole, back in on
+        // its other side and out again
+        let ray = Ray::new(point(-5.0, 0.0, 0.0), vector(1.0, 0.0, 0.0));
+        let spans = part.intersect_all(&ray);
+        assert_eq!(spans.len(), 2);
+        assert_ends(&spans, &[-1.0, -0.5, 0.5, 1.0]);
+        assert_eps_eq(&spans[0].exit.normal, &normal(1.0, 0.0, 0.0), EPS);
+        assert_eps_eq(&spans[1].enter.normal, &normal(-1.0, 0.0, 0.0), EPS);
+
+        // the corners are cut off by the ball
+        let ray = Ray::new(point(0.85, 5.0, 0.85), vector(0.0, -1.0, 0.0));
+        let hit = part.intersect(&ray).unwrap();
+        assert!(hit.point.y < 1.0);
+        assert_eps_eq(&hit.point.distance(point(0.0, 0.0, 0.0)), &1.3, EPS);
+    }
+}
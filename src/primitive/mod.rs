@@ -1,13 +1,294 @@
+mod axis_aligned_box;
+pub use axis_aligned_box::{AxisAlignedBox, OrientedBox};
+
+mod bezier;
+pub use bezier::{load_patches, teapot, BezierSurface};
+
+mod bounds;
+pub use bounds::{Aabb, DirectionCone};
+
+mod bvh;
+pub use bvh::{node_visits, Bvh, Instance};
+
+mod cornell;
+pub use cornell::CornellBox;
+#[cfg(test)]
+pub use cornell::Parallelogram;
+
+mod csg;
+pub use csg::Csg;
+
+mod quadric;
+pub use quadric::{Cone, Cylinder, Disk, Hyperboloid, Paraboloid, Plane};
+
+mod sdf;
+pub use sdf::{Sdf, SignedDistance};
+
 mod sphere;
 pub use sphere::Sphere;
 
+mod torus;
+pub use torus::Torus;
+
+mod transformed;
+pub use transformed::Transformed;
+
 mod triangle;
-pub use triangle::Triangle;
+pub use triangle::{IntersectMethod, Triangle};
 
+use super::color::Color;
 use super::math;
-use math::Ray;
+use super::sampling;
+use math::{Normal3, Point3, Ray, Vector3};
+use std::sync::Arc;
+use ultraviolet::vec::{DVec2, DVec3};
+
+/// Hits closer than this along a ray are ignored, so that rays leaving a
+/// surface don't immediately hit it again due to floating point error.
+pub const MIN_DISTANCE: f64 = 1.0e-6;
+
+/// Everything the renderer needs to know about where a ray hit a surface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Intersection {
+    /// Distance along the ray in multiples of the ray's direction.
+    pub t: f64,
+    pub point: Point3,
+    /// The normalized normal of the surface itself.
+    pub normal: Normal3,
+    /// The normalized normal to shade with. Primitives without interpolated
+    /// normals use the geometric normal.
+    pub shading_normal: Normal3,
+    /// The hit point in the surface's (u, v) parameterization.
+    pub uv: DVec2,
+    /// Barycentric coordinates of the hit point, if the surface is a triangle.
+    pub barycentric: Option<DVec3>,
+    /// Index of the hit primitive in the list it belongs to. Lists overwrite
+    /// this as the hit is passed up, so it ends up as the index of the
+    /// primitive in the outermost list.
+    pub primitive: usize,
+    /// Radiance emitted by the surface back along the ray.
+    pub emitted: Color,
+    /// Index of the surface's material in the scene's list of materials, if
+    /// it has one.
+    pub material: Option<usize>,
+    /// Index of the light the surface belongs to in the scene's list of
+    /// lights, for the surfaces of area lights.
+    pub light: Option<usize>,
+}
+
+impl Intersection {
+    pub fn new(
+        t: f64,
+        point: Point3,
+        normal: Normal3,
+        uv: DVec2,
+    ) -> Intersection {
+        Intersection {
+            t,
+            point,
+            normal,
+            shading_normal: normal,
+            uv,
+            barycentric: None,
+            primitive: 0,
+            emitted: Color::black(),
+            material: None,
+            light: None,
+        }
+    }
+}
 
 pub trait Scene {
-    /// Return the normal to the first intersection, if it exists.
-    fn intersect(&self, ray: &Ray) -> Option<Ray>;
+    /// Return the closest intersection in front of the ray, if it exists.
+    fn intersect(&self, ray: &Ray) -> Option<Intersection>;
+}
+
+impl<S: Scene + ?Sized> Scene for Box<S> {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        (**self).intersect(ray)
+    }
+}
+
+impl<S: Scene + ?Sized> Scene for Arc<S> {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        (**self).intersect(ray)
+    }
+}
+
+impl<S: Scene> Scene for Vec<S> {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let mut closest: Option<Intersection> = None;
+        for (i, primitive) in self.iter().enumerate() {
+            match (primitive.intersect(ray), closest) {
+                (Some(hit), Some(c)) if hit.t >= c.t => (),
+                (Some(mut hit), _) => {
+                    hit.primitive = i;
+                    closest = Some(hit);
+                }
+                (None, _) => (),
+            }
+        }
+
+        closest
+    }
+}
+
+/// A point sampled on the surface of a shape.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceSample {
+    pub point: Point3,
+    /// The normalized geometric normal at `point`.
+    pub normal: Normal3,
+    /// Probability density of having sampled `point`. Depending on how the
+    /// point was sampled this is with respect to surface area or to solid
+    /// angle at a reference point.
+    pub pdf: f64,
+}
+
+/// A scene that fits in a box, which is what it takes for it to go in a
+/// bounding volume hierarchy.
+pub trait Bounded: Scene {
+    fn bounds(&self) -> Aabb;
+}
+
+impl<S: Bounded + ?Sized> Bounded for Box<S> {
+    fn bounds(&self) -> Aabb {
+        (**self).bounds()
+    }
+}
+
+impl<S: Bounded + ?Sized> Bounded for Arc<S> {
+    fn bounds(&self) -> Aabb {
+        (**self).bounds()
+    }
+}
+
+/// The stretch of a ray from where it enters a solid to where it leaves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub enter: Intersection,
+    pub exit: Intersection,
+}
+
+/// A closed surface with an inside, which is what it takes for it to be
+/// combined with constructive solid geometry.
+pub trait Solid: Scene {
+    /// Every stretch of the line through `ray` that's inside the solid, in
+    /// increasing order and without overlaps. This includes the ones behind
+    /// the ray's origin, and ignores `MIN_DISTANCE`. Normals point out of
+    /// the solid at both ends of a span.
+    fn intersect_all(&self, ray: &Ray) -> Vec<Span>;
+}
+
+impl<S: Solid + ?Sized> Solid for Box<S> {
+    fn intersect_all(&self, ray: &Ray) -> Vec<Span> {
+        (**self).intersect_all(ray)
+    }
+}
+
+impl<S: Solid + ?Sized> Solid for Arc<S> {
+    fn intersect_all(&self, ray: &Ray) -> Vec<Span> {
+        (**self).intersect_all(ray)
+    }
+}
+
+/// A surface that points can be sampled on, which is what it takes for it
+/// to be turned into a light.
+pub trait Shape: Bounded {
+    fn area(&self) -> f64;
+
+    /// A cone containing the geometric normal at every point of the surface.
+    fn normal_bounds(&self) -> DirectionCone {
+        DirectionCone::entire_sphere()
+    }
+
+    /// Sample a point uniformly over the surface using the point `u` in the
+    /// unit square. The pdf is with respect to area.
+    fn sample(&self, u: (f64, f64)) -> SurfaceSample;
+
+    /// Sample a point on the surface that is visible from `reference`. The
+    /// pdf is with respect to solid angle at `reference`. By default this
+    /// samples by area and converts the density; shapes that can restrict
+    /// themselves to the part of the surface visible from `reference` do
+    /// better.
+    fn sample_from(
+        &self,
+        reference: Point3,
+        u: (f64, f64),
+    ) -> Option<SurfaceSample> {
+        to_solid_angle(self.sample(u), reference)
+    }
+
+    /// The density with which `sample_from` picks the point seen from
+    /// `reference` along the unit direction `wi`, with respect to solid
+    /// angle.
+    fn pdf_from(&self, reference: Point3, wi: Vector3) -> f64 {
+        area_pdf_to_solid_angle(self, reference, wi)
+    }
+}
+
+/// Convert the pdf of a point sampled by area to a pdf with respect to solid
+/// angle as seen from `reference`.
+fn to_solid_angle(
+    mut sample: SurfaceSample,
+    reference: Point3,
+) -> Option<SurfaceSample> {
+    let to_surface = sample.point - reference;
+    let dist_sq = to_surface.mag_sq();
+    if dist_sq == 0.0 {
+        return None;
+    }
+
+    let cos = sample.normal.dot(to_surface).abs() / dist_sq.sqrt();
+    if cos == 0.0 {
+        return None;
+    }
+
+    sample.pdf *= dist_sq / cos;
+    Some(sample)
+}
+
+/// The solid angle density, seen from `reference`, of sampling the point
+/// along `wi` uniformly by area.
+fn area_pdf_to_solid_angle<S>(shape: &S, reference: Point3, wi: Vector3) -> f64
+where
+    S: Shape + ?Sized,
+{
+    let hit = match shape.intersect(&Ray::new(reference, wi)) {
+        Some(hit) => hit,
+        None => return 0.0,
+    };
+
+    let cos = hit.normal.dot(wi).abs();
+    if cos == 0.0 {
+        return 0.0;
+    }
+    (hit.point - reference).mag_sq() / (cos * shape.area())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::{point, vector};
+
+    #[test]
+    fn test_list_closest_intersection() {
+        let scene: Vec<Box<dyn Scene>> = vec![
+            Box::new(Sphere::new(point(0.0, 0.0, -10.0), 1.0)),
+            Box::new(Sphere::new(point(0.0, 0.0, -5.0), 1.0)),
+            Box::new(Sphere::new(point(0.0, 0.0, 5.0), 1.0)),
+        ];
+
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, -1.0));
+        let hit = scene.intersect(&ray).unwrap();
+        assert_eq!(hit.primitive, 1);
+        assert_eq!(hit.t, 4.0);
+    }
+
+    #[test]
+    fn test_list_miss() {
+        let scene = vec![Sphere::new(point(0.0, 0.0, -5.0), 1.0)];
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 1.0, 0.0));
+        assert_eq!(scene.intersect(&ray), None);
+    }
 }
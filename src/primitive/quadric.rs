@@ -0,0 +1,568 @@
+//! Quadric surfaces in their own object space, with the y axis as their axis
+//! of symmetry. Place them in a scene with `Transformed`.
+
+use super::super::polynomial::solve_quadratic;
+use super::math::{normal, point, Point3, Ray};
+use super::{Aabb, Bounded, Intersection, Scene, Solid, Span, MIN_DISTANCE};
+use std::f64::consts::PI;
+use ultraviolet::DVec2;
+
+/// The angle of `p` around the y axis as a fraction of a full turn, which is
+/// the u coordinate of every surface of revolution here.
+pub(super) fn turn_fraction(p: Point3) -> f64 {
+    let phi = f64::atan2(p.z, p.x);
+    let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+    phi / (2.0 * PI)
+}
+
+/// The nearer of the two `roots` in front of the ray whose point on the ray
+/// passes `valid`, which is how the quadrics are cut to size.
+fn nearest_root<F>(
+    ray: &Ray,
+    roots: Option<(f64, f64)>,
+    valid: F,
+) -> Option<(f64, Point3)>
+where
+    F: Fn(Point3) -> bool,
+{
+    let (t0, t1) = roots?;
+    for &t in [t0, t1].iter() {
+        if t > MIN_DISTANCE {
+            let p = ray.position(t);
+            if valid(p) {
+                return Some((t, p));
+            }
+        }
+    }
+    None
+}
+
+/// Where the ray crosses the plane at the height `y`, if it does in front of
+/// its origin.
+fn plane_hit(ray: &Ray, y: f64) -> Option<(f64, Point3)> {
+    if ray.direction.y == 0.0 {
+        return None;
+    }
+    let t = (y - ray.origin.y) / ray.direction.y;
+    if t > MIN_DISTANCE {
+        Some((t, ray.position(t)))
+    } else {
+        None
+    }
+}
+
+fn closer(
+    a: Option<Intersection>,
+    b: Option<Intersection>,
+) -> Option<Intersection> {
+    match (a, b) {
+        (Some(a), Some(b)) if b.t < a.t => Some(b),
+        (None, b) => b,
+        (a, _) => a,
+    }
+}
+
+/// The infinite plane y = 0, facing +y. Its (u, v) coordinates are the x and
+/// z coordinates of the hit point, so textures repeat across it.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Plane;
+
+impl Plane {
+    pub fn new() -> Plane {
+        Plane
+    }
+}
+
+impl Scene for Plane {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let (t, p) = plane_hit(ray, 0.0)?;
+        let uv = DVec2::new(p.x, p.z);
+        Some(Intersection::new(t, p, normal(0.0, 1.0, 0.0), uv))
+    }
+}
+
+/// A disk in the plane y = 0, facing +y, with a hole of `inner_radius` in
+/// the middle. v goes from 0 on the outer edge to 1 on the inner one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Disk {
+    radius: f64,
+    inner_radius: f64,
+}
+
+impl Disk {
+    pub fn new(radius: f64, inner_radius: f64) -> Disk {
+        Disk {
+            radius,
+            inner_radius,
+        }
+    }
+}
+
+impl Scene for Disk {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let (t, p) = plane_hit(ray, 0.0)?;
+        let r = f64::sqrt(p.x * p.x + p.z * p.z);
+        if r > self.radius || r < self.inner_radius {
+            return None;
+        }
+
+        let v = (self.radius - r) / (self.radius - self.inner_radius);
+        let uv = DVec2::new(turn_fraction(p), v);
+        Some(Intersection::new(t, p, normal(0.0, 1.0, 0.0), uv))
+    }
+}
+
+impl Bounded for Disk {
+    fn bounds(&self) -> Aabb {
+        let r = self.radius;
+        Aabb::new(point(-r, 0.0, -r), point(r, 0.0, r))
+    }
+}
+
+/// A cylinder of `radius` around the y axis, from `y_min` to `y_max`. It's
+/// open at the ends unless it's capped, in which case v on the caps goes
+/// from 0 in the middle to 1 on the rim.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cylinder {
+    radius: f64,
+    y_min: f64,
+    y_max: f64,
+    capped: bool,
+}
+
+impl Cylinder {
+    pub fn new(radius: f64, y_min: f64, y_max: f64) -> Cylinder {
+        Cylinder {
+            radius,
+            y_min: y_min.min(y_max),
+            y_max: y_min.max(y_max),
+            capped: false,
+        }
+    }
+
+    /// The same cylinder closed off by a disk at each end.
+    pub fn capped(radius: f64, y_min: f64, y_max: f64) -> Cylinder {
+        Cylinder {
+            capped: true,
+            ..Cylinder::new(radius, y_min, y_max)
+        }
+    }
+
+    /// The coefficients of the quadratic in t whose roots are where the
+    /// ray crosses the infinitely long cylinder.
+    fn side_quadratic(&self, ray: &Ray) -> (f64, f64, f64) {
+        let (o, d) = (ray.origin, ray.direction);
+        let a = d.x * d.x + d.z * d.z;
+        let b = 2.0 * (o.x * d.x + o.z * d.z);
+        let c = o.x * o.x + o.z * o.z - self.radius * self.radius;
+        (a, b, c)
+    }
+
+    fn side_hit(&self, t: f64, p: Point3) -> Intersection {
+        let n = normal(p.x, 0.0, p.z) / self.radius;
+        let v = (p.y - self.y_min) / (self.y_max - self.y_min);
+        let uv = DVec2::new(turn_fraction(p), v);
+        Intersection::new(t, p, n, uv)
+    }
+
+    /// A hit on the cap at the bottom if `n` is -1, or the top if it's 1.
+    fn cap_hit(&self, t: f64, p: Point3, n: f64) -> Intersection {
+        let r = f64::sqrt(p.x * p.x + p.z * p.z);
+        let uv = DVec2::new(turn_fraction(p), r / self.radius);
+        Intersection::new(t, p, normal(0.0, n, 0.0), uv)
+    }
+
+    fn intersect_side(&self, ray: &Ray) -> Option<Intersection> {
+        let (a, b, c) = self.side_quadratic(ray);
+        let (t, p) = nearest_root(ray, solve_quadratic(a, b, c), |p| {
+            p.y >= self.y_min && p.y <= self.y_max
+        })?;
+        Some(self.side_hit(t, p))
+    }
+
+    fn intersect_cap(&self, ray: &Ray, y: f64, n: f64) -> Option<Intersection> {
+        let (t, p) = plane_hit(ray, y)?;
+        if p.x * p.x + p.z * p.z > self.radius * self.radius {
+            return None;
+        }
+        Some(self.cap_hit(t, p, n))
+    }
+}
+
+impl Scene for Cylinder {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let side = self.intersect_side(ray);
+        if !self.capped {
+            return side;
+        }
+
+        let bottom = self.intersect_cap(ray, self.y_min, -1.0);
+        let top = self.intersect_cap(ray, self.y_max, 1.0);
+        closer(closer(side, bottom), top)
+    }
+}
+
+/// Only a capped cylinder is closed, so as a solid a cylinder is always
+/// treated as capped.
+impl Solid for Cylinder {
+    fn intersect_all(&self, ray: &Ray) -> Vec<Span> {
+        let inf = f64::INFINITY;
+
+        // the range of the ray within the radius of the axis...
+        let (a, b, c) = self.side_quadratic(ray);
+        let (side0, side1) = if a == 0.0 {
+            // parallel to the axis, so either always or never within it
+            if c > 0.0 {
+                return Vec::new();
+            }
+            (-inf, inf)
+        } else {
+            match solve_quadratic(a, b, c) {
+                Some((t0, t1)) if t0 < t1 => (t0, t1),
+                _ => return Vec::new(),
+            }
+        };
+
+        // ...and between the caps
+        let (o, d) = (ray.origin.y, ray.direction.y);
+        let (cap0, cap1) = if d == 0.0 {
+            if o < self.y_min || o > self.y_max {
+                return Vec::new();
+            }
+            (-inf, inf)
+        } else {
+            let t0 = (self.y_min - o) / d;
+            let t1 = (self.y_max - o) / d;
+            (t0.min(t1), t0.max(t1))
+        };
+
+        let (t0, t1) = (side0.max(cap0), side1.min(cap1));
+        if t0 >= t1 {
+            return Vec::new();
+        }
+
+        // going up enters through the bottom and leaves through the top
+        let up = if d > 0.0 { 1.0 } else { -1.0 };
+        let hit = |t: f64, through_cap: bool, n: f64| {
+            let p = ray.position(t);
+            if through_cap {
+                self.cap_hit(t, p, n)
+            } else {
+                self.side_hit(t, p)
+            }
+        };
+        vec![Span {
+            enter: hit(t0, cap0 > side0, -up),
+            exit: hit(t1, cap1 < side1, up),
+        }]
+    }
+}
+
+impl Bounded for Cylinder {
+    fn bounds(&self) -> Aabb {
+        let r = self.radius;
+        Aabb::new(point(-r, self.y_min, -r), point(r, self.y_max, r))
+    }
+}
+
+/// A cone with its base of `radius` at y = 0 and its tip at y = `height`,
+/// open at the base. v goes from 0 at the base to 1 at the tip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cone {
+    radius: f64,
+    height: f64,
+}
+
+impl Cone {
+    pub fn new(radius: f64, height: f64) -> Cone {
+        Cone { radius, height }
+    }
+}
+
+impl Scene for Cone {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        // x^2 + z^2 = k^2 (height - y)^2
+        let (o, d) = (ray.origin, ray.direction);
+        let k_sq = (self.radius / self.height).powi(2);
+        let above = self.height - o.y;
+        let a = d.x * d.x + d.z * d.z - k_sq * d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.z * d.z + k_sq * above * d.y);
+        let c = o.x * o.x + o.z * o.z - k_sq * above * above;
+        let (t, p) = nearest_root(ray, solve_quadratic(a, b, c), |p| {
+            p.y >= 0.0 && p.y <= self.height
+        })?;
+
+        let n = normal(p.x, k_sq * (self.height - p.y), p.z).normalized();
+        let uv = DVec2::new(turn_fraction(p), p.y / self.height);
+        Some(Intersection::new(t, p, n, uv))
+    }
+}
+
+impl Bounded for Cone {
+    fn bounds(&self) -> Aabb {
+        let r = self.radius;
+        Aabb::new(point(-r, 0.0, -r), point(r, self.height, r))
+    }
+}
+
+/// The bowl y = y_max (x^2 + z^2) / radius^2, which is `radius` wide at
+/// `y_max`, cut off below `y_min`. Both heights should be at least 0. v goes
+/// from 0 at `y_min` to 1 at `y_max`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Paraboloid {
+    radius: f64,
+    y_min: f64,
+    y_max: f64,
+}
+
+impl Paraboloid {
+    pub fn new(radius: f64, y_min: f64, y_max: f64) -> Paraboloid {
+        Paraboloid {
+            radius,
+            y_min: y_min.min(y_max),
+            y_max: y_min.max(y_max),
+        }
+    }
+}
+
+impl Scene for Paraboloid {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        // x^2 + z^2 = k y
+        let (o, d) = (ray.origin, ray.direction);
+        let k = self.radius * self.radius / self.y_max;
+        let a = d.x * d.x + d.z * d.z;
+        let b = 2.0 * (o.x * d.x + o.z * d.z) - k * d.y;
+        let c = o.x * o.x + o.z * o.z - k * o.y;
+        let (t, p) = nearest_root(ray, solve_quadratic(a, b, c), |p| {
+            p.y >= self.y_min && p.y <= self.y_max
+        })?;
+
+        let n = normal(2.0 * p.x, -k, 2.0 * p.z).normalized();
+        let v = (p.y - self.y_min) / (self.y_max - self.y_min);
+        let uv = DVec2::new(turn_fraction(p), v);
+        Some(Intersection::new(t, p, n, uv))
+    }
+}
+
+impl Bounded for Paraboloid {
+    fn bounds(&self) -> Aabb {
+        let r = self.radius;
+        Aabb::new(point(-r, self.y_min, -r), point(r, self.y_max, r))
+    }
+}
+
+/// A hyperboloid of one sheet, x^2 + z^2 = radius^2 + (slope y)^2, from
+/// `y_min` to `y_max`. It's `radius` wide at its waist at y = 0 and widens
+/// towards a cone with the given slope. v goes from 0 at `y_min` to 1 at
+/// `y_max`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hyperboloid {
+    radius: f64,
+    slope: f64,
+    y_min: f64,
+    y_max: f64,
+}
+
+impl Hyperboloid {
+    pub fn new(radius: f64, slope: f64, y_min: f64, y_max: f64) -> Hyperboloid {
+        Hyperboloid {
+            radius,
+            slope,
+            y_min: y_min.min(y_max),
+            y_max: y_min.max(y_max),
+        }
+    }
+}
+
+impl Scene for Hyperboloid {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let (o, d) = (ray.origin, ray.direction);
+        let k = self.slope * self.slope;
+        let a = d.x * d.x + d.z * d.z - k * d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.z * d.z - k * o.y * d.y);
+        let c =
+            o.x * o.x + o.z * o.z - k * o.y * o.y - self.radius * self.radius;
+        let (t, p) = nearest_root(ray, solve_quadratic(a, b, c), |p| {
+            p.y >= self.y_min && p.y <= self.y_max
+        })?;
+
+        let n = normal(p.x, -k * p.y, p.z).normalized();
+        let v = (p.y - self.y_min) / (self.y_max - self.y_min);
+        let uv = DVec2::new(turn_fraction(p), v);
+        Some(Intersection::new(t, p, n, uv))
+    }
+}
+
+impl Bounded for Hyperboloid {
+    fn bounds(&self) -> Aabb {
+        let y = self.y_min.abs().max(self.y_max.abs());
+        let r = f64::sqrt(self.radius * self.radius + (self.slope * y).powi(2));
+        Aabb::new(point(-r, self.y_min, -r), point(r, self.y_max, r))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::math::test_util::assert_eps_eq;
+    use super::super::super::math::vector;
+    use super::*;
+
+    const EPS: f64 = 1.0e-9;
+
+    #[test]
+    fn test_ray_plane_intersect() {
+        let plane = Plane::new();
+        let ray = Ray::new(point(1.0, 2.0, 3.0), vector(0.0, -1.0, 0.0));
+        let hit = plane.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &2.0, EPS);
+        assert_eps_eq(&hit.normal, &normal(0.0, 1.0, 0.0), EPS);
+        assert_eq!(hit.uv, DVec2::new(1.0, 3.0));
+
+        // from below, still with the same normal
+        let ray = Ray::new(point(0.0, -1.0, 0.0), vector(0.0, 2.0, 0.0));
+        assert_eps_eq(&plane.intersect(&ray).unwrap().t, &0.5, EPS);
+
+        // parallel, and pointing away
+        let ray = Ray::new(point(0.0, 1.0, 0.0), vector(1.0, 0.0, 0.0));
+        assert_eq!(plane.intersect(&ray), None);
+        let ray = Ray::new(point(0.0, 1.0, 0.0), vector(0.0, 1.0, 0.0));
+        assert_eq!(plane.intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_ray_disk_intersect() {
+        let disk = Disk::new(2.0, 0.5);
+        let ray = Ray::new(point(0.0, 3.0, 1.0), vector(0.0, -1.0, 0.0));
+        let hit = disk.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &3.0, EPS);
+        assert_eps_eq(&hit.point, &point(0.0, 0.0, 1.0), EPS);
+        assert_eps_eq(&hit.uv.x, &0.25, EPS);
+        assert_eps_eq(&hit.uv.y, &(2.0 / 3.0), EPS);
+
+        // through the hole, and past the rim
+        let ray = Ray::new(point(0.2, 3.0, 0.0), vector(0.0, -1.0, 0.0));
+        assert_eq!(disk.intersect(&ray), None);
+        let ray = Ray::new(point(2.5, 3.0, 0.0), vector(0.0, -1.0, 0.0));
+        assert_eq!(disk.intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_ray_cylinder_intersect() {
+        let cylinder = Cylinder::new(1.0, 0.0, 2.0);
+        let ray = Ray::new(point(-5.0, 0.5, 0.0), vector(1.0, 0.0, 0.0));
+        let hit = cylinder.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &4.0, EPS);
+        assert_eps_eq(&hit.normal, &normal(-1.0, 0.0, 0.0), EPS);
+        assert_eps_eq(&hit.uv.x, &0.5, EPS);
+        assert_eps_eq(&hit.uv.y, &0.25, EPS);
+
+        // above the top, and straight down the open middle
+        let ray = Ray::new(point(-5.0, 2.5, 0.0), vector(1.0, 0.0, 0.0));
+        assert_eq!(cylinder.intersect(&ray), None);
+        let ray = Ray::new(point(0.0, 5.0, 0.0), vector(0.0, -1.0, 0.0));
+        assert_eq!(cylinder.intersect(&ray), None);
+
+        // in through the open top, hitting the far wall from inside
+        let ray = Ray::new(point(0.0, 3.0, 0.0), vector(1.0, -1.0, 0.0));
+        let hit = cylinder.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.point, &point(1.0, 2.0, 0.0), EPS);
+        assert_eps_eq(&hit.normal, &normal(1.0, 0.0, 0.0), EPS);
+    }
+
+    #[test]
+    fn test_ray_capped_cylinder_intersect() {
+        let cylinder = Cylinder::capped(1.0, 0.0, 2.0);
+        let ray = Ray::new(point(0.5, 5.0, 0.0), vector(0.0, -1.0, 0.0));
+        let hit = cylinder.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &3.0, EPS);
+        assert_eps_eq(&hit.normal, &normal(0.0, 1.0, 0.0), EPS);
+        assert_eps_eq(&hit.uv.y, &0.5, EPS);
+
+        let ray = Ray::new(point(0.0, -5.0, 0.0), vector(0.0, 1.0, 0.0));
+        let hit = cylinder.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &5.0, EPS);
+        assert_eps_eq(&hit.normal, &normal(0.0, -1.0, 0.0), EPS);
+
+        // the side is still in front of the caps from the side
+        let ray = Ray::new(point(-5.0, 0.5, 0.0), vector(1.0, 0.0, 0.0));
+        assert_eps_eq(&cylinder.intersect(&ray).unwrap().t, &4.0, EPS);
+    }
+
+    #[test]
+    fn test_ray_cone_intersect() {
+        let cone = Cone::new(1.0, 2.0);
+        let ray = Ray::new(point(-5.0, 1.0, 0.0), vector(1.0, 0.0, 0.0));
+        let hit = cone.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &4.5, EPS);
+        let expected = normal(-1.0, 0.5, 0.0).normalized();
+        assert_eps_eq(&hit.normal, &expected, EPS);
+        assert_eps_eq(&hit.uv.y, &0.5, EPS);
+
+        // the other nappe of the double cone above the tip isn't part of it
+        let ray = Ray::new(point(-5.0, 3.0, 0.0), vector(1.0, 0.0, 0.0));
+        assert_eq!(cone.intersect(&ray), None);
+
+        // up through the open base to the inside of the tip
+        let ray = Ray::new(point(0.0, -1.0, 0.0), vector(0.0, 1.0, 0.0));
+        let hit = cone.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.point, &point(0.0, 2.0, 0.0), 1.0e-6);
+    }
+
+    #[test]
+    fn test_ray_paraboloid_intersect() {
+        let paraboloid = Paraboloid::new(1.0, 0.0, 1.0);
+        let ray = Ray::new(point(0.5, 5.0, 0.0), vector(0.0, -1.0, 0.0));
+        let hit = paraboloid.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &4.75, EPS);
+        let expected = normal(1.0, -1.0, 0.0).normalized();
+        assert_eps_eq(&hit.normal, &expected, EPS);
+        assert_eps_eq(&hit.uv.y, &0.25, EPS);
+
+        // cut off above y_max
+        let ray = Ray::new(point(-5.0, 1.5, 0.0), vector(1.0, 0.0, 0.0));
+        assert_eq!(paraboloid.intersect(&ray), None);
+
+        // from the side, through the wall and into the bowl
+        let ray = Ray::new(point(-5.0, 0.25, 0.0), vector(1.0, 0.0, 0.0));
+        let hit = paraboloid.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.point, &point(-0.5, 0.25, 0.0), EPS);
+    }
+
+    #[test]
+    fn test_ray_hyperboloid_intersect() {
+        let hyperboloid = Hyperboloid::new(1.0, 1.0, -1.0, 1.0);
+        let ray = Ray::new(point(-5.0, 0.0, 0.0), vector(1.0, 0.0, 0.0));
+        let hit = hyperboloid.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &4.0, EPS);
+        assert_eps_eq(&hit.normal, &normal(-1.0, 0.0, 0.0), EPS);
+        assert_eps_eq(&hit.uv.y, &0.5, EPS);
+
+        // wider away from the waist
+        let ray = Ray::new(point(-5.0, 0.75, 0.0), vector(1.0, 0.0, 0.0));
+        let hit = hyperboloid.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &3.75, EPS);
+        let expected = normal(-1.25, -0.75, 0.0).normalized();
+        assert_eps_eq(&hit.normal, &expected, EPS);
+
+        // straight through the middle
+        let ray = Ray::new(point(0.0, 5.0, 0.0), vector(0.0, -1.0, 0.0));
+        assert_eq!(hyperboloid.intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_quadric_bounds() {
+        let b = Cylinder::new(2.0, 1.0, -1.0).bounds();
+        assert_eq!(b.min, point(-2.0, -1.0, -2.0));
+        assert_eq!(b.max, point(2.0, 1.0, 2.0));
+
+        let b = Hyperboloid::new(1.0, 1.0, -1.0, 0.5).bounds();
+        let r = f64::sqrt(2.0);
+        assert_eps_eq(&b.min, &point(-r, -1.0, -r), EPS);
+        assert_eps_eq(&b.max, &point(r, 0.5, r), EPS);
+
+        assert_eq!(Disk::new(1.0, 0.0).bounds().max, point(1.0, 0.0, 1.0));
+        assert_eq!(Cone::new(1.0, 3.0).bounds().max, point(1.0, 3.0, 1.0));
+        let b = Paraboloid::new(2.0, 0.5, 1.0).bounds();
+        assert_eq!(b.min, point(-2.0, 0.5, -2.0));
+    }
+}
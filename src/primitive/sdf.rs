@@ -0,0 +1,461 @@
+use super::math::{vector, Normal3, Point3, Ray, Vector3};
+use super::{Aabb, Bounded, Intersection, Scene, MIN_DISTANCE};
+use ultraviolet::{DMat4, DVec2};
+
+/// Most steps a ray takes through a distance field before giving up, which
+/// only happens for rays skimming along a surface.
+const MAX_STEPS: usize = 512;
+
+/// How close to the surface a step has to land to count as a hit, as a
+/// fraction of the size of the field's bounds.
+const RELATIVE_TOLERANCE: f64 = 1.0e-5;
+
+/// A signed distance field: a tree of shapes and operations that gives the
+/// distance from any point to the nearest surface, negative inside.
+///
+/// The leaves are exact, but the smooth and twisting operations only bound
+/// the distance from below, which sphere tracing copes with as long as
+/// steps aren't overestimated. Twists do overestimate them, by more the
+/// faster they turn, so a `SignedDistance` with a twist should take smaller
+/// steps with `with_step_scale`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sdf {
+    Sphere(f64),
+    /// A box given by half its size along each axis.
+    Cuboid(Vector3),
+    /// A torus around the y axis with major and minor radius.
+    Torus(f64, f64),
+    /// A capped cylinder around the y axis with radius and half its height.
+    Cylinder(f64, f64),
+    /// The Mandelbulb fractal of the given power, iterated the given number
+    /// of times. It fits in a sphere of radius 1.2 for power 8.
+    Mandelbulb(f64, usize),
+    Union(Box<Sdf>, Box<Sdf>),
+    Intersection(Box<Sdf>, Box<Sdf>),
+    Difference(Box<Sdf>, Box<Sdf>),
+    /// A union that blends the two shapes together wherever they're within
+    /// a distance `k` of each other.
+    SmoothUnion(Box<Sdf>, Box<Sdf>, f64),
+    Translate(Box<Sdf>, Vector3),
+    /// A rigid transform, given by the matrix from the parent's space into
+    /// the shape's. It can only rotate and translate, since anything else
+    /// would stretch distances.
+    Transform(Box<Sdf>, DMat4),
+    Scale(Box<Sdf>, f64),
+    /// Every surface grown by a radius, rounding off edges and corners.
+    Round(Box<Sdf>, f64),
+    /// Copies repeated forever with the given period along each axis, or
+    /// not at all along axes where the period is 0. Each copy should fit in
+    /// its cell.
+    Repeat(Box<Sdf>, Vector3),
+    /// Twisted around the y axis by the given angle per unit of height.
+    Twist(Box<Sdf>, f64),
+}
+
+impl Sdf {
+    pub fn sphere(radius: f64) -> Sdf {
+        Sdf::Sphere(radius)
+    }
+
+    /// The box spanning [-half_size, half_size].
+    pub fn cuboid(half_size: Vector3) -> Sdf {
+        Sdf::Cuboid(half_size)
+    }
+
+    /// A box of the same size as `cuboid` with its edges rounded off.
+    pub fn rounded_cuboid(half_size: Vector3, radius: f64) -> Sdf {
+        let inner = half_size - vector(radius, radius, radius);
+        Sdf::cuboid(inner).round(radius)
+    }
+
+    pub fn torus(major_radius: f64, minor_radius: f64) -> Sdf {
+        Sdf::Torus(major_radius, minor_radius)
+    }
+
+    pub fn cylinder(radius: f64, half_height: f64) -> Sdf {
+        Sdf::Cylinder(radius, half_height)
+    }
+
+    pub fn mandelbulb(power: f64, iterations: usize) -> Sdf {
+        Sdf::Mandelbulb(power, iterations)
+    }
+
+    pub fn union(self, other: Sdf) -> Sdf {
+        Sdf::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersection(self, other: Sdf) -> Sdf {
+        Sdf::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn difference(self, other: Sdf) -> Sdf {
+        Sdf::Difference(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: Sdf, k: f64) -> Sdf {
+        Sdf::SmoothUnion(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn translate(self, offset: Vector3) -> Sdf {
+        Sdf::Translate(Box::new(self), offset)
+    }
+
+    /// Place the shape with a rigid transform from its own space into the
+    /// parent's.
+    pub fn transform(self, m: DMat4) -> Sdf {
+        Sdf::Transform(Box::new(self), m.inversed())
+    }
+
+    pub fn scale(self, s: f64) -> Sdf {
+        Sdf::Scale(Box::new(self), s)
+    }
+
+    pub fn round(self, radius: f64) -> Sdf {
+        Sdf::Round(Box::new(self), radius)
+    }
+
+    pub fn repeat(self, period: Vector3) -> Sdf {
+        Sdf::Repeat(Box::new(self), period)
+    }
+
+    pub fn twist(self, rate: f64) -> Sdf {
+        Sdf::Twist(Box::new(self), rate)
+    }
+
+    pub fn distance(&self, p: Point3) -> f64 {
+        self.distance_from(p - Point3::origin())
+    }
+
+    /// The distance at the point at `p` from the origin, which is easier to
+    /// do arithmetic on than a point.
+    fn distance_from(&self, p: Vector3) -> f64 {
+        match self {
+            Sdf::Sphere(radius) => p.mag() - radius,
+            Sdf::Cuboid(half_size) => {
+                let q = p.abs() - *half_size;
+                let outside = q.max_by_component(vector(0.0, 0.0, 0.0)).mag();
+                outside + q.component_max().min(0.0)
+            }
+            Sdf::Torus(major_radius, minor_radius) => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (ring * ring + p.y * p.y).sqrt() - minor_radius
+            }
+            Sdf::Cylinder(radius, half_height) => {
+                let dx = (p.x * p.x + p.z * p.z).sqrt() - radius;
+                let dy = p.y.abs() - half_height;
+                let outside =
+                    (dx.max(0.0).powi(2) + dy.max(0.0).powi(2)).sqrt();
+                outside + dx.max(dy).min(0.0)
+            }
+            Sdf::Mandelbulb(power, iterations) => {
+                mandelbulb(p, *power, *iterations)
+            }
+            Sdf::Union(a, b) => a.distance_from(p).min(b.distance_from(p)),
+            Sdf::Intersection(a, b) => {
+                a.distance_from(p).max(b.distance_from(p))
+            }
+            Sdf::Difference(a, b) => {
+                a.distance_from(p).max(-b.distance_from(p))
+            }
+            Sdf::SmoothUnion(a, b, k) => {
+                // the polynomial smooth minimum
+                let (a, b) = (a.distance_from(p), b.distance_from(p));
+                let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+                b + (a - b) * h - k * h * (1.0 - h)
+            }
+            Sdf::Translate(sdf, offset) => sdf.distance_from(p - *offset),
+            Sdf::Transform(sdf, to_object) => {
+                let p = *to_object * (Point3::origin() + p);
+                sdf.distance_from(p - Point3::origin())
+            }
+            Sdf::Scale(sdf, s) => sdf.distance_from(p / *s) * s,
+            Sdf::Round(sdf, radius) => sdf.distance_from(p) - radius,
+            Sdf::Repeat(sdf, period) => {
+                let mut q = p;
+                for axis in 0..3 {
+                    if period[axis] != 0.0 {
+                        let cell = (p[axis] / period[axis]).round();
+                        q[axis] -= period[axis] * cell;
+                    }
+                }
+                sdf.distance_from(q)
+            }
+            Sdf::Twist(sdf, rate) => {
+                let (sin, cos) = (rate * p.y).sin_cos();
+                let q =
+                    vector(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z);
+                sdf.distance_from(q)
+            }
+        }
+    }
+}
+
+/// The distance estimate for the Mandelbulb, from the running derivative of
+/// its iteration z -> z^power + p in spherical coordinates.
+fn mandelbulb(p: Vector3, power: f64, iterations: usize) -> f64 {
+    let mut z = p;
+    let mut dr = 1.0;
+    let mut r = z.mag();
+    for _ in 0..iterations {
+        if r > 2.0 {
+            break;
+        }
+        if r == 0.0 {
+            // 0^power is 0, but its angles aren't defined
+            z = p;
+            dr = 1.0;
+            r = z.mag();
+            continue;
+        }
+        let theta = (z.y / r).acos() * power;
+        let phi = z.z.atan2(z.x) * power;
+        dr = power * r.powf(power - 1.0) * dr + 1.0;
+
+        let zr = r.powf(power);
+        z = vector(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        ) * zr
+            + p;
+        r = z.mag();
+    }
+    if r == 0.0 {
+        // the center, which is well inside
+        return -1.0;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+/// A distance field in the scene, found by sphere tracing: stepping along
+/// rays by the distance to the nearest surface until they're close enough
+/// to one to count as having hit it. The field is only traced inside its
+/// bounds, which also have to contain every surface of it that should be
+/// seen.
+///
+/// Hits have no (u, v) coordinates.
+pub struct SignedDistance {
+    sdf: Sdf,
+    bounds: Aabb,
+    tolerance: f64,
+    step_scale: f64,
+}
+
+impl SignedDistance {
+    pub fn new(sdf: Sdf, bounds: Aabb) -> SignedDistance {
+        SignedDistance {
+            sdf,
+            bounds,
+            tolerance: RELATIVE_TOLERANCE * bounds.diagonal().mag(),
+            step_scale: 1.0,
+        }
+    }
+
+    /// Take only this fraction of each step the field allows, for fields
+    /// that overestimate distances.
+    pub fn with_step_scale(self, step_scale: f64) -> SignedDistance {
+        SignedDistance { step_scale, ..self }
+    }
+
+    /// The gradient of the field by central differences, which points out
+    /// of the surface.
+    fn normal(&self, p: Point3) -> Normal3 {
+        let h = self.tolerance;
+        let mut n = Normal3::new(0.0, 0.0, 0.0);
+        for axis in 0..3 {
+            let mut offset = vector(0.0, 0.0, 0.0);
+            offset[axis] = h;
+            n[axis] =
+                self.sdf.distance(p + offset) - self.sdf.distance(p - offset);
+        }
+        n.normalized()
+    }
+}
+
+impl Scene for SignedDistance {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let d = ray.direction;
+        let inv_dir = vector(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+        let (t0, t1) = self.bounds.intersect(ray, inv_dir, f64::INFINITY)?;
+
+        // steps are distances in space, so they're converted to distances
+        // along the ray, whose direction may not be normalized
+        let length = d.mag();
+        let mut t = t0.max(MIN_DISTANCE);
+
+        // a ray leaving a surface starts within the tolerance of it, and has
+        // to get clear of it before it can hit anything
+        let mut clear = t0 > MIN_DISTANCE;
+        for _ in 0..MAX_STEPS {
+            if t > t1 {
+                return None;
+            }
+            let p = ray.position(t);
+            let distance = self.sdf.distance(p).abs() * self.step_scale;
+            if distance < self.tolerance {
+                if clear {
+                    let n = self.normal(p);
+                    return Some(Intersection::new(t, p, n, DVec2::zero()));
+                }
+                t += self.tolerance / length;
+            } else {
+                clear = true;
+                t += distance / length;
+            }
+        }
+        None
+    }
+}
+
+impl Bounded for SignedDistance {
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::math::test_util::assert_eps_eq;
+    use super::super::super::math::{normal, point, rotation_y, scaling};
+    use super::super::Transformed;
+    use super::*;
+    use std::f64::consts::PI;
+
+    const EPS: f64 = 1.0e-9;
+
+    fn unit_bounds(r: f64) -> Aabb {
+        Aabb::new(point(-r, -r, -r), point(r, r, r))
+    }
+
+    #[test]
+    fn test_sdf_shapes() {
+        let sphere = Sdf::sphere(1.0);
+        assert_eps_eq(&sphere.distance(point(3.0, 0.0, 0.0)), &2.0, EPS);
+        assert_eps_eq(&sphere.distance(point(0.0, 0.5, 0.0)), &-0.5, EPS);
+
+        let cuboid = Sdf::cuboid(vector(1.0, 2.0, 3.0));
+        assert_eps_eq(&cuboid.distance(point(0.0, 0.0, 5.0)), &2.0, EPS);
+        assert_eps_eq(&cuboid.distance(point(0.0, 1.5, 0.0)), &-0.5, EPS);
+        // off a corner
+        let corner = cuboid.distance(point(2.0, 3.0, 3.0));
+        assert_eps_eq(&corner, &2.0f64.sqrt(), EPS);
+
+        let rounded = Sdf::rounded_cuboid(vector(1.0, 1.0, 1.0), 0.25);
+        assert_eps_eq(&rounded.distance(point(2.0, 0.0, 0.0)), &1.0, EPS);
+        let corner = rounded.distance(point(1.0, 1.0, 1.0));
+        assert_eps_eq(&corner, &(0.25 * 3.0f64.sqrt() - 0.25), EPS);
+
+        let torus = Sdf::torus(2.0, 0.5);
+        assert_eps_eq(&torus.distance(point(0.0, 0.0, 0.0)), &1.5, EPS);
+        assert_eps_eq(&torus.distance(point(0.0, 1.0, 2.0)), &0.5, EPS);
+
+        let cylinder = Sdf::cylinder(1.0, 2.0);
+        assert_eps_eq(&cylinder.distance(point(0.0, 3.0, 0.0)), &1.0, EPS);
+        assert_eps_eq(&cylinder.distance(point(3.0, 0.0, 0.0)), &2.0, EPS);
+        assert_eps_eq(&cylinder.distance(point(0.5, 0.0, 0.0)), &-0.5, EPS);
+
+        let bulb = Sdf::mandelbulb(8.0, 8);
+        assert!(bulb.distance(point(0.0, 0.0, 0.0)) < 0.0);
+        assert!(bulb.distance(point(2.0, 0.0, 0.0)) > 0.5);
+    }
+
+    #[test]
+    fn test_sdf_operations() {
+        let a = Sdf::sphere(1.0);
+        let b = Sdf::sphere(1.0).translate(vector(1.5, 0.0, 0.0));
+        let p = point(-2.0, 0.0, 0.0);
+        assert_eps_eq(&a.clone().union(b.clone()).distance(p), &1.0, EPS);
+        let lens = a.clone().intersection(b.clone());
+        assert_eps_eq(&lens.distance(p), &2.5, EPS);
+        let bitten = a.clone().difference(b.clone());
+        assert_eps_eq(&bitten.distance(point(0.75, 0.0, 0.0)), &0.25, EPS);
+
+        // far from the seam a smooth union is an ordinary one, and close to
+        // it it bulges out
+        let blend = a.clone().smooth_union(b, 0.5);
+        assert_eps_eq(&blend.distance(p), &1.0, EPS);
+        let seam = point(0.75, 0.7, 0.0);
+        let union = Sdf::sphere(1.0)
+            .union(Sdf::sphere(1.0).translate(vector(1.5, 0.0, 0.0)));
+        assert!(blend.distance(seam) < union.distance(seam));
+
+        let scaled = a.clone().scale(2.0);
+        assert_eps_eq(&scaled.distance(point(0.0, 3.0, 0.0)), &1.0, EPS);
+
+        let cuboid = Sdf::cuboid(vector(1.0, 0.5, 0.5));
+        let turned = cuboid.clone().transform(rotation_y(PI / 2.0));
+        assert_eps_eq(&turned.distance(point(0.0, 0.0, 2.0)), &1.0, EPS);
+        assert_eps_eq(&turned.distance(point(2.0, 0.0, 0.0)), &1.5, EPS);
+
+        // half a turn per unit of height, so a quarter turn half way up
+        let twisted = cuboid.twist(PI);
+        assert_eps_eq(&twisted.distance(point(2.0, 0.0, 0.0)), &1.0, EPS);
+        assert_eps_eq(&twisted.distance(point(0.0, 0.5, 2.0)), &1.0, EPS);
+
+        let repeated = a.repeat(vector(4.0, 0.0, 4.0));
+        assert_eps_eq(&repeated.distance(point(8.0, 0.0, -4.0)), &-1.0, EPS);
+        assert_eps_eq(&repeated.distance(point(6.0, 0.0, 0.0)), &1.0, EPS);
+        assert_eps_eq(&repeated.distance(point(0.0, 8.0, 0.0)), &7.0, EPS);
+    }
+
+    #[test]
+    fn test_ray_sdf_intersect() {
+        let sphere = SignedDistance::new(Sdf::sphere(1.0), unit_bounds(1.0));
+        let ray = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let hit = sphere.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &4.0, 1.0e-4);
+        assert_eps_eq(&hit.normal, &normal(0.0, 0.0, -1.0), 1.0e-6);
+
+        // an unnormalized direction gives the same point
+        let ray = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 0.25));
+        let hit = sphere.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &16.0, 1.0e-3);
+
+        let ray = Ray::new(point(0.0, 1.1, -5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(sphere.intersect(&ray), None);
+        let ray = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, -1.0));
+        assert_eq!(sphere.intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_ray_sdf_from_surface() {
+        let sphere = SignedDistance::new(Sdf::sphere(1.0), unit_bounds(1.0));
+        let ray = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let hit = sphere.intersect(&ray).unwrap();
+
+        // leaving the surface outwards doesn't hit it again
+        let away = Ray::new(hit.point, vector(0.3, 0.0, -1.0));
+        assert_eq!(sphere.intersect(&away), None);
+
+        // and going in finds the far side, from the inside
+        let through = Ray::new(hit.point, vector(0.0, 0.0, 1.0));
+        let hit = sphere.intersect(&through).unwrap();
+        assert_eps_eq(&hit.point.z, &1.0, 1.0e-4);
+        assert_eps_eq(&hit.normal, &normal(0.0, 0.0, 1.0), 1.0e-6);
+    }
+
+    #[test]
+    fn test_ray_sdf_transformed() {
+        let shape = Sdf::rounded_cuboid(vector(1.0, 1.0, 1.0), 0.2)
+            .smooth_union(
+                Sdf::sphere(0.6).translate(vector(0.0, 1.2, 0.0)),
+                0.2,
+            )
+            .twist(0.2);
+        let bounds = Aabb::new(point(-1.5, -1.5, -1.5), point(1.5, 2.0, 1.5));
+        let field = SignedDistance::new(shape, bounds).with_step_scale(0.8);
+        let scene = Transformed::new(field, scaling(2.0, 2.0, 2.0));
+
+        // the top of the sphere on top of the box
+        let ray = Ray::new(point(0.0, 10.0, 0.0), vector(0.0, -1.0, 0.0));
+        let hit = scene.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.point.y, &3.6, 1.0e-3);
+        assert_eps_eq(&hit.normal, &normal(0.0, 1.0, 0.0), 1.0e-4);
+
+        // the side of the box at y = 0, where there's no twist
+        let ray = Ray::new(point(-10.0, 0.0, 0.0), vector(1.0, 0.0, 0.0));
+        let hit = scene.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.point.x, &-2.0, 1.0e-3);
+        assert_eps_eq(&hit.normal, &normal(-1.0, 0.0, 0.0), 1.0e-4);
+    }
+}
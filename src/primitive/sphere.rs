@@ -1,19 +1,60 @@
-use super::Scene;
-use super::math::{Ray, vector};
-use ultraviolet::DVec4;
+use super::{Aabb, Bounded, Intersection, Scene, Shape, Solid, Span};
+use super::{SurfaceSample, MIN_DISTANCE};
+use super::{area_pdf_to_solid_angle, to_solid_angle};
+use super::math::{Ray, vector, Normal3, Point3, Vector3};
+use super::sampling;
+use std::f64::consts::PI;
+use ultraviolet::DVec2;
 
 pub struct Sphere {
-    center: DVec4,
+    center: Point3,
     radius: f64,
+    /// Index into the scene's materials to give hits.
+    material: Option<usize>,
 }
 
 impl Sphere {
-    pub fn new(center: DVec4, radius: f64) -> Sphere {
-        Sphere { center, radius }
+    pub fn new(center: Point3, radius: f64) -> Sphere {
+        Sphere {
+            center,
+            radius,
+            material: None,
+        }
+    }
+
+    /// Give hits the index `material` into the scene's materials.
+    pub fn with_material(mut self, material: usize) -> Sphere {
+        self.material = Some(material);
+        self
+    }
+
+    /// Whether a point `dist_sq` squared from the center is inside the
+    /// sphere. Points on its surface count as inside, even when rounding
+    /// has put them a little way out, since they can see all of it.
+    fn encloses(&self, dist_sq: f64) -> bool {
+        dist_sq <= self.radius * self.radius * (1.0 + 1.0e-9)
+    }
+
+    fn normal(&self, point: Point3) -> Normal3 {
+        ((point - self.center) / self.radius).into()
+    }
+
+    /// Spherical coordinates of a point on the sphere, with u going around
+    /// the y axis and v going from the top of the sphere to the bottom.
+    fn uv(&self, point: Point3) -> DVec2 {
+        let p = (point - self.center) / self.radius;
+        let phi = f64::atan2(p.z, p.x);
+        let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+        let theta = f64::acos(p.y.clamp(-1.0, 1.0));
+        DVec2::new(phi / (2.0 * PI), theta / PI)
     }
 
-    fn normal(&self, point: DVec4) -> DVec4 {
-        point - self.center / self.radius
+    fn hit(&self, ray: &Ray, t: f64) -> Intersection {
+        let point = ray.position(t);
+        let normal = self.normal(point);
+        let mut hit = Intersection::new(t, point, normal, self.uv(point));
+        hit.material = self.material;
+        hit
     }
 
     fn solve_intersect(&self, ray: &Ray) -> Option<(f64, f64)> {
@@ -39,27 +80,128 @@ impl Sphere {
 }
 
 impl Scene for Sphere {
-    fn intersect(&self, ray: &Ray) -> Option<Ray> {
-        if let Some((solution_a, solution_b)) = self.solve_intersect(ray) {
-            let dist = solution_a.max(solution_b);
-            if dist >= 0.0 {
-                let intersect = ray.position(dist);
-                let normal = self.normal(intersect);
-                Some(Ray::new(intersect, normal))
-            } else {
-                None
-            }
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let (solution_a, solution_b) = self.solve_intersect(ray)?;
+        // solution_a is the smaller of the two, so only fall back to the far
+        // side of the sphere if the ray starts inside of it
+        let t = if solution_a > MIN_DISTANCE {
+            solution_a
+        } else if solution_b > MIN_DISTANCE {
+            solution_b
         } else {
-            None
+            return None;
+        };
+
+        Some(self.hit(ray, t))
+    }
+}
+
+impl Solid for Sphere {
+    fn intersect_all(&self, ray: &Ray) -> Vec<Span> {
+        match self.solve_intersect(ray) {
+            // a ray grazing the sphere doesn't go through any of it
+            Some((enter, exit)) if enter < exit => vec![Span {
+                enter: self.hit(ray, enter),
+                exit: self.hit(ray, exit),
+            }],
+            _ => Vec::new(),
         }
     }
 }
 
+impl Bounded for Sphere {
+    fn bounds(&self) -> Aabb {
+        let r = vector(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
+}
+
+impl Shape for Sphere {
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn sample(&self, u: (f64, f64)) -> SurfaceSample {
+        let z = 1.0 - 2.0 * u.0;
+        let r = f64::max(0.0, 1.0 - z * z).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let normal = vector(r * phi.cos(), r * phi.sin(), z);
+        SurfaceSample {
+            point: self.center + normal * self.radius,
+            normal: normal.into(),
+            pdf: 1.0 / self.area(),
+        }
+    }
+
+    /// Sample the cone of directions from `reference` that hit the sphere, so
+    /// that no samples are wasted on the far side of it.
+    fn sample_from(
+        &self,
+        reference: Point3,
+        u: (f64, f64),
+    ) -> Option<SurfaceSample> {
+        let to_center = self.center - reference;
+        let dist_sq = to_center.mag_sq();
+        if self.encloses(dist_sq) {
+            // every point on the sphere is visible from inside of it
+            return to_solid_angle(self.sample(u), reference);
+        }
+
+        let dist = dist_sq.sqrt();
+        let axis = to_center / dist;
+        let sin_theta_max_sq = self.radius * self.radius / dist_sq;
+        let cos_theta_max = f64::max(0.0, 1.0 - sin_theta_max_sq).sqrt();
+
+        // pick a direction uniformly within the cone
+        let cos_theta = (1.0 - u.0) + u.0 * cos_theta_max;
+        let sin_theta_sq = f64::max(0.0, 1.0 - cos_theta * cos_theta);
+        let phi = 2.0 * PI * u.1;
+
+        // find the angle from the center of the sphere to where that
+        // direction hits the sphere first
+        let dist_to_surface = dist * cos_theta
+            - f64::max(0.0, self.radius * self.radius - dist_sq * sin_theta_sq)
+                .sqrt();
+        let cos_alpha = (dist_sq + self.radius * self.radius
+            - dist_to_surface * dist_to_surface)
+            / (2.0 * dist * self.radius);
+        let sin_alpha = f64::max(0.0, 1.0 - cos_alpha * cos_alpha).sqrt();
+
+        // the normal points back towards the reference point, away from the
+        // axis by alpha
+        let (s, t) = sampling::orthonormal_basis(axis);
+        let normal = s * (-sin_alpha * phi.cos())
+            + t * (-sin_alpha * phi.sin())
+            - axis * cos_alpha;
+
+        Some(SurfaceSample {
+            point: self.center + normal * self.radius,
+            normal: normal.into(),
+            pdf: 1.0 / (2.0 * PI * (1.0 - cos_theta_max)),
+        })
+    }
+
+    fn pdf_from(&self, reference: Point3, wi: Vector3) -> f64 {
+        let to_center = self.center - reference;
+        let dist_sq = to_center.mag_sq();
+        if self.encloses(dist_sq) {
+            return area_pdf_to_solid_angle(self, reference, wi);
+        }
+
+        let sin_theta_max_sq = self.radius * self.radius / dist_sq;
+        let cos_theta_max = f64::max(0.0, 1.0 - sin_theta_max_sq).sqrt();
+        if wi.dot(to_center) / dist_sq.sqrt() < cos_theta_max {
+            return 0.0;
+        }
+        1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use super::super::math;
-    use math::point;
+    use math::{normal, point, vector};
     use math::test_util::assert_eps_eq;
 
     const EPS: f64 = 0.01;
@@ -79,8 +221,89 @@ mod tests {
         let point_on_sphere = point(0.0, 0.0, 1.0);
         assert_eps_eq(
             &sphere.normal(point_on_sphere),
-            &vector(0.0, 0.0, 1.0),
+            &normal(0.0, 0.0, 1.0),
             EPS,
         );
     }
+
+    #[test]
+    fn test_ray_sphere_normal_off_origin() {
+        let sphere = Sphere::new(point(1.0, 2.0, 3.0), 2.0 /* radius */);
+        let point_on_sphere = point(1.0, 4.0, 3.0);
+        assert_eps_eq(
+            &sphere.normal(point_on_sphere),
+            &normal(0.0, 1.0, 0.0),
+            EPS,
+        );
+    }
+
+    #[test]
+    fn test_ray_sphere_closest_hit() {
+        let sphere = Sphere::new(point(0.0, 0.0, 0.0), 1.0 /* radius */);
+        let ray = Ray::new(point(0.0, 0.0, -5.0), vector(0.0, 0.0, 1.0));
+        let hit = sphere.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &4.0, EPS);
+        assert_eps_eq(&hit.normal, &normal(0.0, 0.0, -1.0), EPS);
+    }
+
+    #[test]
+    fn test_ray_inside_sphere() {
+        let sphere = Sphere::new(point(0.0, 0.0, 0.0), 1.0 /* radius */);
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, 1.0));
+        let hit = sphere.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &1.0, EPS);
+    }
+
+    #[test]
+    fn test_ray_behind_sphere() {
+        let sphere = Sphere::new(point(0.0, 0.0, 0.0), 1.0 /* radius */);
+        let ray = Ray::new(point(0.0, 0.0, 5.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(sphere.intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_ray_sphere_uv() {
+        let sphere = Sphere::new(point(0.0, 0.0, 0.0), 1.0 /* radius */);
+        let top = sphere.uv(point(0.0, 1.0, 0.0));
+        let side = sphere.uv(point(0.0, 0.0, 1.0));
+        assert_eps_eq(&top.y, &0.0, EPS);
+        assert_eps_eq(&side.x, &0.25, EPS);
+        assert_eps_eq(&side.y, &0.5, EPS);
+    }
+
+    #[test]
+    fn test_sphere_sample() {
+        let sphere = Sphere::new(point(1.0, 2.0, 3.0), 2.0 /* radius */);
+        for &u in [(0.0, 0.0), (0.3, 0.7), (0.9, 0.1)].iter() {
+            let sample = sphere.sample(u);
+            assert_eps_eq(&(sample.point - sphere.center).mag(), &2.0, EPS);
+            assert_eps_eq(
+                &sphere.normal(sample.point),
+                &sample.normal,
+                EPS,
+            );
+            assert_eps_eq(&sample.pdf, &(1.0 / (16.0 * PI)), EPS);
+        }
+    }
+
+    #[test]
+    fn test_sphere_sample_from_outside() {
+        let sphere = Sphere::new(point(0.0, 0.0, -4.0), 1.0 /* radius */);
+        let reference = point(0.0, 0.0, 0.0);
+        for &u in [(0.0, 0.0), (0.5, 0.25), (0.99, 0.6)].iter() {
+            let sample = sphere.sample_from(reference, u).unwrap();
+            // the sampled point is on the sphere, on the side facing the
+            // reference point, and is the first thing the ray hits
+            let wi = (sample.point - reference).normalized();
+            assert_eps_eq(&(sample.point - sphere.center).mag(), &1.0, EPS);
+            assert!(sample.normal.dot(wi) <= 0.0);
+            let hit = sphere.intersect(&Ray::new(reference, wi)).unwrap();
+            assert_eps_eq(&hit.point, &sample.point, EPS);
+            assert_eps_eq(&sphere.pdf_from(reference, wi), &sample.pdf, EPS);
+        }
+
+        // directions outside of the cone can't be sampled
+        let away = vector(0.0, 1.0, 0.0);
+        assert_eq!(sphere.pdf_from(reference, away), 0.0);
+    }
 }
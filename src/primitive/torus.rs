@@ -0,0 +1,211 @@
+use super::super::polynomial::{solve_quartic, Roots};
+use super::math::{normal, point, Ray};
+use super::quadric::turn_fraction;
+use super::{Aabb, Bounded, Intersection, Scene, Solid, Span, MIN_DISTANCE};
+use std::f64::consts::PI;
+use ultraviolet::DVec2;
+
+/// A torus around the y axis: a tube of `minor_radius` swept around a circle
+/// of `major_radius` in the plane y = 0. u goes around the y axis like the
+/// quadrics, and v goes around the tube starting from its outer equator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Torus {
+    major_radius: f64,
+    minor_radius: f64,
+}
+
+impl Torus {
+    pub fn new(major_radius: f64, minor_radius: f64) -> Torus {
+        Torus {
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    fn hit(&self, ray: &Ray, t: f64) -> Intersection {
+        let p = ray.position(t);
+
+        // the normal points away from the closest point on the tube's center
+        // circle, which is where v is measured from too
+        let radial = f64::sqrt(p.x * p.x + p.z * p.z);
+        let (cos, sin) = if radial > 0.0 {
+            (p.x / radial, p.z / radial)
+        } else {
+            (1.0, 0.0)
+        };
+        let center =
+            point(self.major_radius * cos, 0.0, self.major_radius * sin);
+        let n = (p - center).normalized();
+
+        let theta = f64::atan2(p.y, radial - self.major_radius);
+        let theta = if theta < 0.0 { theta + 2.0 * PI } else { theta };
+        let uv = DVec2::new(turn_fraction(p), theta / (2.0 * PI));
+        Intersection::new(t, p, normal(n.x, n.y, n.z), uv)
+    }
+
+    /// All distances along the ray at which it crosses the surface, in
+    /// increasing order, including those behind the ray.
+    fn solve_intersect(&self, ray: &Ray) -> Roots {
+        // the coefficients grow with the fourth power of the distance to the
+        // torus, so solve from the point on the ray closest to its center,
+        // along a unit direction, and convert back afterwards
+        let length = ray.direction.mag();
+        let d = ray.direction / length;
+        let shift = -(ray.origin - point(0.0, 0.0, 0.0)).dot(d);
+        let o = ray.origin + d * shift;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2) with p = o + s d
+        let r_sq = self.major_radius * self.major_radius;
+        let o_d = o.x * d.x + o.y * d.y + o.z * d.z;
+        let k = o.x * o.x + o.y * o.y + o.z * o.z + r_sq
+            - self.minor_radius * self.minor_radius;
+        let roots = solve_quartic(
+            1.0,
+            4.0 * o_d,
+            2.0 * k + 4.0 * o_d * o_d - 4.0 * r_sq * (d.x * d.x + d.z * d.z),
+            4.0 * o_d * k - 8.0 * r_sq * (o.x * d.x + o.z * d.z),
+            k * k - 4.0 * r_sq * (o.x * o.x + o.z * o.z),
+        );
+        roots.map(|s| (s + shift) / length)
+    }
+}
+
+impl Scene for Torus {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let roots = self.solve_intersect(ray);
+        let t = *roots.iter().find(|&&t| t > MIN_DISTANCE)?;
+        Some(self.hit(ray, t))
+    }
+}
+
+impl Solid for Torus {
+    fn intersect_all(&self, ray: &Ray) -> Vec<Span> {
+        // the roots alternate between entering and leaving, except when
+        // rounding loses one of a pair of roots at a grazing hit, in which
+        // case the last one is dropped
+        self.solve_intersect(ray)
+            .chunks_exact(2)
+            .map(|pair| Span {
+                enter: self.hit(ray, pair[0]),
+                exit: self.hit(ray, pair[1]),
+            })
+            .collect()
+    }
+}
+
+impl Bounded for Torus {
+    fn bounds(&self) -> Aabb {
+        let r = self.major_radius + self.minor_radius;
+        let h = self.minor_radius;
+        Aabb::new(point(-r, -h, -r), point(r, h, r))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::math::test_util::assert_eps_eq;
+    use super::super::super::math::vector;
+    use super::*;
+
+    const EPS: f64 = 1.0e-6;
+
+    fn torus() -> Torus {
+        Torus::new(2.0, 0.5)
+    }
+
+    #[test]
+    fn test_ray_torus_intersect() {
+        // along the x axis through both sides of the ring and the hole
+        let ray = Ray::new(point(-5.0, 0.0, 0.0), vector(1.0, 0.0, 0.0));
+        let roots = torus().solve_intersect(&ray);
+        assert_eq!(roots.len(), 4);
+        for (t, expected) in roots.iter().zip(&[2.5, 3.5, 6.5, 7.5]) {
+            assert_eps_eq(t, expected, EPS);
+        }
+
+        let hit = torus().intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &2.5, EPS);
+        assert_eps_eq(&hit.normal, &normal(-1.0, 0.0, 0.0), EPS);
+        assert_eps_eq(&hit.uv.x, &0.5, EPS);
+        assert_eps_eq(&hit.uv.y, &0.0, EPS);
+    }
+
+    #[test]
+    fn test_ray_torus_through_hole() {
+        // straight down the middle misses
+        let ray = Ray::new(point(0.0, 5.0, 0.0), vector(0.0, -1.0, 0.0));
+        assert_eq!(torus().intersect(&ray), None);
+
+        // as does a slanted ray through the hole
+        let ray = Ray::new(point(-1.0, 5.0, 0.0), vector(0.2, -1.0, 0.1));
+        assert_eq!(torus().intersect(&ray), None);
+
+        // but one from inside the hole hits the inner wall
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, 3.0));
+        let hit = torus().intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &0.5, EPS);
+        assert_eps_eq(&hit.normal, &normal(0.0, 0.0, -1.0), EPS);
+        assert_eps_eq(&hit.uv.y, &0.5, EPS);
+    }
+
+    #[test]
+    fn test_ray_torus_top() {
+        let ray = Ray::new(point(2.0, 5.0, 0.0), vector(0.0, -2.0, 0.0));
+        let hit = torus().intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &2.25, EPS);
+        assert_eps_eq(&hit.point, &point(2.0, 0.5, 0.0), EPS);
+        assert_eps_eq(&hit.normal, &normal(0.0, 1.0, 0.0), EPS);
+        assert_eps_eq(&hit.uv.y, &0.25, EPS);
+    }
+
+    #[test]
+    fn test_ray_torus_grazing() {
+        // skimming along the top of the tube touches it at a double root,
+        // which may or may not survive rounding, but either way the hit has
+        // to be on the surface
+        let ray = Ray::new(point(-5.0, 0.5, 0.0), vector(1.0, 0.0, 0.0));
+        if let Some(hit) = torus().intersect(&ray) {
+            assert_eps_eq(&hit.point.y, &0.5, EPS);
+            assert_eps_eq(&hit.point.x.abs(), &2.0, 1.0e-3);
+        }
+
+        // just inside the top of the tube hits twice on each side
+        let ray = Ray::new(point(-5.0, 0.499, 0.0), vector(1.0, 0.0, 0.0));
+        let roots = torus().solve_intersect(&ray);
+        assert_eq!(roots.len(), 4);
+        let hit = torus().intersect(&ray).unwrap();
+        let half_chord = f64::sqrt(0.5f64.powi(2) - 0.499f64.powi(2));
+        assert_eps_eq(&hit.t, &(3.0 - half_chord), EPS);
+
+        // and just above it misses
+        let ray = Ray::new(point(-5.0, 0.501, 0.0), vector(1.0, 0.0, 0.0));
+        assert_eq!(torus().intersect(&ray), None);
+
+        // tangent to the outside of the ring from far away
+        let ray = Ray::new(point(-1000.0, 0.0, 2.499), vector(1.0, 0.0, 0.0));
+        let hit = torus().intersect(&ray).unwrap();
+        assert_eps_eq(&hit.point.z, &2.499, EPS);
+        let dist = f64::sqrt(hit.point.x.powi(2) + hit.point.z.powi(2)) - 2.0;
+        assert_eps_eq(&(dist.powi(2) + hit.point.y.powi(2)), &0.25, EPS);
+        let ray = Ray::new(point(-1000.0, 0.0, 2.501), vector(1.0, 0.0, 0.0));
+        assert_eq!(torus().intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_ray_torus_far_away() {
+        // a distant ray with a short direction still lands on the surface
+        let ray = Ray::new(point(1.0e4, 0.2, 0.3), vector(-1.0e-3, 0.0, 0.0));
+        let hit = torus().intersect(&ray).unwrap();
+        let p = hit.point;
+        let radial = f64::sqrt(p.x * p.x + p.z * p.z);
+        let dist_sq = (radial - 2.0).powi(2) + p.y * p.y;
+        assert_eps_eq(&dist_sq, &0.25, EPS);
+    }
+
+    #[test]
+    fn test_torus_bounds() {
+        let b = torus().bounds();
+        assert_eq!(b.min, point(-2.5, -0.5, -2.5));
+        assert_eq!(b.max, point(2.5, 0.5, 2.5));
+    }
+}
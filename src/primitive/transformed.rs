@@ -0,0 +1,141 @@
+use super::math::{Normal3, Ray, Transform};
+use super::{Aabb, Bounded, Intersection, Scene, Solid, Span};
+use ultraviolet::mat::DMat4;
+
+/// A scene placed in the world by an affine transform, so that the same
+/// geometry can be instanced many times without copying it.
+///
+/// Rays are moved into object space rather than the geometry into world
+/// space. The ray's direction isn't renormalized, so distances along it are
+/// the same in both spaces and `t` can be passed through as it is.
+pub struct Transformed<T: Scene> {
+    object: T,
+    object_to_world: Transform,
+    world_to_object: Transform,
+}
+
+impl<T: Scene> Transformed<T> {
+    pub fn new(object: T, object_to_world: DMat4) -> Transformed<T> {
+        let object_to_world = Transform::new(object_to_world);
+        Transformed {
+            object,
+            object_to_world,
+            world_to_object: object_to_world.inverse(),
+        }
+    }
+
+    fn normal_to_world(&self, normal: Normal3) -> Normal3 {
+        self.object_to_world.normal(normal).normalized()
+    }
+
+    /// Move a hit on the object along the object space version of `ray`
+    /// back into world space.
+    fn hit_to_world(&self, ray: &Ray, mut hit: Intersection) -> Intersection {
+        hit.point = ray.position(hit.t);
+        hit.normal = self.normal_to_world(hit.normal);
+        hit.shading_normal = self.normal_to_world(hit.shading_normal);
+        hit
+    }
+}
+
+impl<T: Bounded> Bounded for Transformed<T> {
+    /// The world space box around the transformed object's bounds.
+    fn bounds(&self) -> Aabb {
+        self.object.bounds().transformed(&self.object_to_world)
+    }
+}
+
+impl<T: Scene> Scene for Transformed<T> {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let object_ray = self.world_to_object.ray(ray);
+        let hit = self.object.intersect(&object_ray)?;
+        Some(self.hit_to_world(ray, hit))
+    }
+}
+
+impl<T: Solid> Solid for Transformed<T> {
+    fn intersect_all(&self, ray: &Ray) -> Vec<Span> {
+        let object_ray = self.world_to_object.ray(ray);
+        let mut spans = self.object.intersect_all(&object_ray);
+        for span in spans.iter_mut() {
+            span.enter = self.hit_to_world(ray, span.enter);
+            span.exit = self.hit_to_world(ray, span.exit);
+        }
+        spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::math::test_util::assert_eps_eq;
+    use super::super::super::math::{normal, point, scaling, translation};
+    use super::super::super::math::vector;
+    use super::super::Sphere;
+    use super::*;
+
+    const EPS: f64 = 1.0e-9;
+
+    #[test]
+    fn test_translated_sphere() {
+        let sphere = Transformed::new(
+            Sphere::new(point(0.0, 0.0, 0.0), 1.0),
+            translation(0.0, 0.0, -5.0),
+        );
+        let ray = Ray::new(point(0.0, 0.0, 0.0), vector(0.0, 0.0, -1.0));
+        let hit = sphere.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &4.0, EPS);
+        assert_eps_eq(&hit.point, &point(0.0, 0.0, -4.0), EPS);
+        assert_eps_eq(&hit.normal, &normal(0.0, 0.0, 1.0), EPS);
+
+        let miss = Ray::new(point(0.0, 2.0, 0.0), vector(0.0, 0.0, -1.0));
+        assert_eq!(sphere.intersect(&miss), None);
+    }
+
+    #[test]
+    fn test_scaled_sphere() {
+        // an ellipsoid twice as wide as it is tall
+        let ellipsoid = Transformed::new(
+            Sphere::new(point(0.0, 0.0, 0.0), 1.0),
+            scaling(2.0, 1.0, 1.0),
+        );
+
+        let ray = Ray::new(point(-5.0, 0.0, 0.0), vector(1.0, 0.0, 0.0));
+        let hit = ellipsoid.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &3.0, EPS);
+        assert_eps_eq(&hit.point, &point(-2.0, 0.0, 0.0), EPS);
+
+        // off of the axes the normal isn't just the scaled sphere normal
+        let s = f64::sqrt(0.5);
+        let ray = Ray::new(point(2.0 * s, s, 5.0), vector(0.0, 0.0, -1.0));
+        let hit = ellipsoid.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.point, &point(2.0 * s, s, 0.0), EPS);
+        let expected = normal(1.0, 2.0, 0.0).normalized();
+        assert_eps_eq(&hit.normal, &expected, EPS);
+        assert_eq!(hit.shading_normal, hit.normal);
+    }
+
+    #[test]
+    fn test_instances_share_an_object() {
+        let sphere =
+            std::sync::Arc::new(Sphere::new(point(0.0, 0.0, 0.0), 1.0));
+        let scene = vec![
+            Transformed::new(sphere.clone(), translation(-3.0, 0.0, 0.0)),
+            Transformed::new(sphere, translation(3.0, 0.0, 0.0)),
+        ];
+        let ray = Ray::new(point(3.0, 0.0, 5.0), vector(0.0, 0.0, -1.0));
+        let hit = scene.intersect(&ray).unwrap();
+        assert_eq!(hit.primitive, 1);
+        assert_eps_eq(&hit.point, &point(3.0, 0.0, 1.0), EPS);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let sphere = Transformed::new(
+            Sphere::new(point(0.0, 0.0, 0.0), 1.0),
+            translation(1.0, 2.0, 3.0) * scaling(2.0, 1.0, 1.0),
+        );
+        let bounds = sphere.bounds();
+        assert_eps_eq(&bounds.min, &point(-1.0, 1.0, 2.0), EPS);
+        assert_eps_eq(&bounds.max, &point(3.0, 3.0, 4.0), EPS);
+    }
+}
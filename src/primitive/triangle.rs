@@ -1,26 +1,53 @@
-use super::Scene;
-use super::math::{Ray, vector};
+use super::{Aabb, Bounded, DirectionCone, Intersection, Scene, Shape};
+use super::{SurfaceSample, MIN_DISTANCE};
+use super::math::{Ray, Normal3, Point3, Vector3};
 
-use ultraviolet::vec::{DVec3, DVec4};
+use ultraviolet::vec::{DVec2, DVec3};
+
+/// How rays are tested against a triangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntersectMethod {
+    /// Möller-Trumbore, which is fast but can let rays through the edges
+    /// and vertices that triangles share.
+    #[default]
+    MollerTrumbore,
+    /// The watertight test of Woop, Benthin and Wald, which never lets a
+    /// ray slip between triangles sharing an edge.
+    Watertight,
+}
+
+/// A bound on the relative error of `n` floating point operations, as in
+/// pbrt.
+fn gamma(n: f64) -> f64 {
+    let e = f64::EPSILON * 0.5;
+    n * e / (1.0 - n * e)
+}
 
 pub struct Triangle {
-    p0: DVec4,
-    p1: DVec4,
-    p2: DVec4,
-    e0: DVec4,
-    e1: DVec4,
-    normal: DVec4,
+    p0: Point3,
+    p1: Point3,
+    p2: Point3,
+    e0: Vector3,
+    e1: Vector3,
+    normal: Normal3,
+    /// Normals at p0, p1 and p2 to interpolate for shading, for triangles
+    /// approximating a smooth surface.
+    vertex_normals: Option<[Normal3; 3]>,
+    /// Texture coordinates at p0, p1 and p2.
+    vertex_uvs: Option<[DVec2; 3]>,
+    /// Index into the scene's materials to give hits.
+    material: Option<usize>,
+    method: IntersectMethod,
 }
 
 impl Triangle {
-    pub fn new(p0: DVec4, p1: DVec4, p2: DVec4) -> Triangle {
+    pub fn new(p0: Point3, p1: Point3, p2: Point3) -> Triangle {
         // compute edge vectors
         let e0 = p1 - p0;
         let e1 = p2 - p0;
 
         // compute normal vector
-        let mut normal = e1.xyz().cross(e0.xyz());
-        normal.normalize();
+        let normal = e1.cross(e0).normalized();
 
         Triangle {
             p0,
@@ -28,19 +55,84 @@ impl Triangle {
             p2,
             e0,
             e1,
-            normal: normal.xyzw(),
+            normal: normal.into(),
+            vertex_normals: None,
+            vertex_uvs: None,
+            material: None,
+            method: IntersectMethod::default(),
         }
     }
 
-    // TODO: stick this in an interface for primitive objects instead
-    pub fn normal(&self, point: DVec4) -> DVec4 {
-        self.normal
+    /// Test rays against the triangle with `method`.
+    pub fn with_method(mut self, method: IntersectMethod) -> Triangle {
+        self.method = method;
+        self
+    }
+
+    /// Shade the triangle with `normals` at p0, p1 and p2 interpolated over
+    /// it. Hits keep the flat normal of the triangle as their geometric
+    /// normal, turned to the side the interpolated normal is on.
+    pub fn with_normals(mut self, normals: [Normal3; 3]) -> Triangle {
+        self.vertex_normals = Some(normals);
+        self
+    }
+
+    /// Give hits the (u, v) interpolated from `uvs` at p0, p1 and p2,
+    /// instead of their barycentric coordinates.
+    pub fn with_uvs(mut self, uvs: [DVec2; 3]) -> Triangle {
+        self.vertex_uvs = Some(uvs);
+        self
+    }
+
+    /// Give hits the index `material` into the scene's materials.
+    pub fn with_material(mut self, material: usize) -> Triangle {
+        self.material = Some(material);
+        self
+    }
+
+    /// The normal to shade with at `point`, which should be on the
+    /// triangle.
+    #[cfg(test)]
+    pub fn normal(&self, point: Point3) -> Normal3 {
+        match self.vertex_normals {
+            Some(_) => self.shading_normal(self.barycentric(point)),
+            None => self.normal,
+        }
+    }
+
+    /// Barycentric coordinates of `point` projected onto the triangle's
+    /// plane, with respect to p0, p1 and p2.
+    #[cfg(test)]
+    fn barycentric(&self, point: Point3) -> DVec3 {
+        let d = point - self.p0;
+        let (d00, d01, d11) =
+            (self.e0.dot(self.e0), self.e0.dot(self.e1), self.e1.dot(self.e1));
+        let (d20, d21) = (d.dot(self.e0), d.dot(self.e1));
+        let denominator = d00 * d11 - d01 * d01;
+        let b1 = (d11 * d20 - d01 * d21) / denominator;
+        let b2 = (d00 * d21 - d01 * d20) / denominator;
+        DVec3::new(1.0 - b1 - b2, b1, b2)
+    }
+
+    /// The interpolated normal at barycentric coordinates `b`.
+    fn shading_normal(&self, b: DVec3) -> Normal3 {
+        let [n0, n1, n2] = match self.vertex_normals {
+            Some(normals) => normals,
+            None => return self.normal,
+        };
+        let n = n0 * b.x + n1 * b.y + n2 * b.z;
+        // opposite vertex normals can cancel out
+        if n.mag_sq() > 0.0 {
+            n.normalized()
+        } else {
+            self.normal
+        }
     }
 
     /// Returns the intersection point on the surface of the triangle if `ray` intersects.
     /// Uses the a geometric solution.
-    // TODO: this is broken
-    fn geometric_intersect(&self, ray: &Ray) -> Option<DVec4> {
+    #[cfg(test)]
+    fn geometric_intersect(&self, ray: &Ray) -> Option<Point3> {
         let denominator = self.normal.dot(ray.direction);
         // check that ray is not parallel to the plane
         if denominator == 0.0 {
@@ -58,15 +150,16 @@ impl Triangle {
 
         // inside outside tests to check if the intersection point is inside the triangle
         // calculate vectors relative to the p0 vertex of the triangle
-        let c0 = p.xyz() - self.p0.xyz();
-        let c1 = p.xyz() - self.p1.xyz();
-        let c2 = p.xyz() - self.p2.xyz();
-
-        // cross e0 and c0 and the resulting vector points in the same direction as the normal if
-        // c0 is "inside" (to the left of) the v_n -> v_{n+1} edge
-        if self.e0.xyz().cross(c0).dot(self.normal.xyz()) >= 0.0
-            && self.e0.xyz().cross(c1).dot(self.normal.xyz()) >= 0.0
-            && self.e1.xyz().cross(c2).dot(self.normal.xyz()) >= 0.0
+        let c0 = p - self.p0;
+        let c1 = p - self.p1;
+        let c2 = p - self.p2;
+
+        // cross each edge, going around p0 -> p1 -> p2, with the vector from
+        // its start to p. The normal is e1 x e0, so for points inside (to the
+        // left of) every edge the results point against the normal
+        if self.e0.cross(c0).dot(self.normal) <= 0.0
+            && (self.p2 - self.p1).cross(c1).dot(self.normal) <= 0.0
+            && (self.p0 - self.p2).cross(c2).dot(self.normal) <= 0.0
         {
             Some(p)
         } else {
@@ -79,40 +172,182 @@ impl Triangle {
     /// see this for faster algorithms: https://stackoverflow.com/questions/44275153
     /// see this for an explanation of moller-trumbore: https://www.scratchapixel.com/lessons/3d-basic-rendering/ray-tracing-rendering-a-triangle/moller-trumbore-ray-triangle-intersection
     ///
-    /// Returns the intersection point on the surface of the triangle if `ray` intersects.
-    fn moller_trumbore_intersect(&self, ray: &Ray) -> Option<DVec4> {
-        let E0 = self.e0.xyz();
-        let E1 = self.e1.xyz();
-        let P = ray.direction.xyz().cross(E1);
-        let denominator = P.dot(E0);
+    /// Returns the distance along the ray to the intersection, in multiples
+    /// of the ray's direction, and its barycentric coordinates (u, v) with
+    /// respect to p1 and p2, if `ray` intersects the triangle in front of its
+    /// origin.
+    fn moller_trumbore_intersect(
+        &self,
+        ray: &Ray,
+    ) -> Option<(f64, f64, f64)> {
+        let e0 = self.e0;
+        let e1 = self.e1;
+        let p = ray.direction.cross(e1);
+        let denominator = p.dot(e0);
         if denominator == 0.0 {  // if the denominator is < 0 then we hit the back of the triangle
-            return None
+            return None;
         }
 
-        let T = ray.origin.xyz() - self.p0.xyz();
+        let s = ray.origin - self.p0;
         let coefficient = 1.0 / denominator;
-        let u = coefficient * P.dot(T);
-        if u < 0.0 || u > 1.0 {
-            return None
+        let u = coefficient * p.dot(s);
+        if !(0.0..=1.0).contains(&u) {
+            return None;
         }
 
-        let Q = T.cross(E0);
-        let v = coefficient * Q.dot(ray.direction.xyz());
+        let q = s.cross(e0);
+        let v = coefficient * q.dot(ray.direction);
         if v < 0.0 || u + v > 1.0 {
-            return None
+            return None;
         }
 
-        let t = coefficient * Q.dot(E1);
-        Some(ray.position(t))
+        let t = coefficient * q.dot(e1);
+        if t < MIN_DISTANCE {
+            return None;
+        }
+
+        Some((t, u, v))
+    }
+
+    /// The watertight test from "Watertight Ray/Triangle Intersection" by
+    /// Woop, Benthin and Wald, following pbrt. The triangle is moved into a
+    /// space where the ray starts at the origin and runs along +z, so the
+    /// edge tests are 2D and come out exactly the same for both triangles
+    /// sharing an edge. Points on an edge count as inside, so a ray through
+    /// an edge hits both triangles rather than neither.
+    ///
+    /// Returns the same as `moller_trumbore_intersect`, and only hits
+    /// known to be in front of the origin despite rounding error.
+    fn watertight_intersect(&self, ray: &Ray) -> Option<(f64, f64, f64)> {
+        // the axis the ray is most along becomes z, keeping the winding
+        let d = ray.direction;
+        let abs = d.abs();
+        let kz = if abs.x > abs.y && abs.x > abs.z {
+            0
+        } else if abs.y > abs.z {
+            1
+        } else {
+            2
+        };
+        let kx = (kz + 1) % 3;
+        let ky = (kx + 1) % 3;
+        let permute = |v: Vector3| Vector3::new(v[kx], v[ky], v[kz]);
+        let d = permute(d);
+        if d.z == 0.0 {
+            return None;
+        }
+
+        // shear the ray onto +z, leaving z to be scaled only if needed
+        let sx = -d.x / d.z;
+        let sy = -d.y / d.z;
+        let sz = 1.0 / d.z;
+        let mut p = [self.p0, self.p1, self.p2]
+            .iter()
+            .map(|&p| permute(p - ray.origin))
+            .collect::<Vec<_>>();
+        for p in p.iter_mut() {
+            p.x += sx * p.z;
+            p.y += sy * p.z;
+        }
+
+        // twice the signed areas of the triangles the origin makes with
+        // each edge, as seen down the ray
+        let e0 = p[1].x * p[2].y - p[1].y * p[2].x;
+        let e1 = p[2].x * p[0].y - p[2].y * p[0].x;
+        let e2 = p[0].x * p[1].y - p[0].y * p[1].x;
+        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0)
+            && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0)
+        {
+            return None;
+        }
+        let det = e0 + e1 + e2;
+        if det == 0.0 {
+            return None;
+        }
+
+        for p in p.iter_mut() {
+            p.z *= sz;
+        }
+        let t_scaled = e0 * p[0].z + e1 * p[1].z + e2 * p[2].z;
+        if (det < 0.0 && t_scaled >= 0.0) || (det > 0.0 && t_scaled <= 0.0) {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let t = t_scaled * inv_det;
+
+        // t must be clear of zero by more than its worst rounding error
+        let max = |f: fn(&Vector3) -> f64| {
+            p.iter().map(|p| f(p).abs()).fold(0.0, f64::max)
+        };
+        let max_x = max(|p| p.x);
+        let max_y = max(|p| p.y);
+        let max_z = max(|p| p.z);
+        let delta_z = gamma(3.0) * max_z;
+        let delta_x = gamma(5.0) * (max_x + max_z);
+        let delta_y = gamma(5.0) * (max_y + max_z);
+        let delta_e = 2.0
+            * (gamma(2.0) * max_x * max_y + delta_y * max_x + delta_x * max_y);
+        let max_e = e0.abs().max(e1.abs()).max(e2.abs());
+        let delta_t = 3.0
+            * (gamma(3.0) * max_e * max_z + delta_e * max_z + delta_z * max_e)
+            * inv_det.abs();
+        if t <= delta_t || t < MIN_DISTANCE {
+            return None;
+        }
+
+        Some((t, e1 * inv_det, e2 * inv_det))
     }
 }
 
 impl Scene for Triangle {
-    fn intersect(&self, ray: &Ray) -> Option<Ray> {
-        if let Some(intersect) = self.moller_trumbore_intersect(ray) {
-            Some(Ray::new(intersect, self.normal(intersect)))
-        } else {
-            None
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        let (t, u, v) = match self.method {
+            IntersectMethod::MollerTrumbore => {
+                self.moller_trumbore_intersect(ray)?
+            }
+            IntersectMethod::Watertight => self.watertight_intersect(ray)?,
+        };
+        let point = ray.position(t);
+        let b = DVec3::new(1.0 - u - v, u, v);
+        let shading_normal = self.shading_normal(b);
+        let uv = match self.vertex_uvs {
+            Some([uv0, uv1, uv2]) => uv0 * b.x + uv1 * b.y + uv2 * b.z,
+            None => DVec2::new(u, v),
+        };
+        let normal = self.normal.face_forward(shading_normal);
+        let mut hit = Intersection::new(t, point, normal, uv);
+        hit.shading_normal = shading_normal;
+        hit.barycentric = Some(b);
+        hit.material = self.material;
+        Some(hit)
+    }
+}
+
+impl Bounded for Triangle {
+    fn bounds(&self) -> Aabb {
+        Aabb::new(self.p0, self.p1).union_point(self.p2)
+    }
+}
+
+impl Shape for Triangle {
+    fn area(&self) -> f64 {
+        0.5 * self.e0.cross(self.e1).mag()
+    }
+
+    fn normal_bounds(&self) -> DirectionCone {
+        DirectionCone::from_direction(self.normal.into())
+    }
+
+    fn sample(&self, u: (f64, f64)) -> SurfaceSample {
+        // uniformly distributed barycentric coordinates
+        let su0 = u.0.sqrt();
+        let b0 = 1.0 - su0;
+        let b1 = u.1 * su0;
+        SurfaceSample {
+            point: self.p0 + self.e0 * b1 + self.e1 * (1.0 - b0 - b1),
+            normal: self.normal,
+            pdf: 1.0 / self.area(),
         }
     }
 }
@@ -121,8 +356,12 @@ impl Scene for Triangle {
 mod tests {
     use super::*;
     use super::super::math;
-    use math::point;
+    use math::{normal, point, vector};
     use math::test_util::assert_eps_eq;
+    use super::super::Bvh;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashMap;
 
     const EPS: f64 = 0.01;
 
@@ -138,7 +377,7 @@ mod tests {
         assert_eps_eq(&triangle.p2, &p2, EPS);
         assert_eps_eq(&triangle.e0, &vector(-1.0, -1.0, 0.0), EPS);
         assert_eps_eq(&triangle.e1, &vector(1.0, -1.0, 0.0), EPS);
-        assert_eps_eq(&triangle.normal, &vector(0.0, 0.0, -1.0), EPS);
+        assert_eps_eq(&triangle.normal, &normal(0.0, 0.0, -1.0), EPS);
     }
 
     #[test]
@@ -179,4 +418,316 @@ mod tests {
         // ray is parallel to the triangle; no intersect
         assert_eq!(triangle.intersect(&ray), None);
     }
+
+    #[test]
+    fn test_triangle_intersect_in_front() {
+        let p0 = point(0.0, 1.0, 0.0);
+        let p1 = point(-1.0, 0.0, 0.0);
+        let p2 = point(1.0, 0.0, 0.0);
+        let triangle = Triangle::new(p0, p1, p2);
+
+        let ray = Ray::new(point(0.5, 0.25, 2.0), vector(0.0, 0.0, -1.0));
+        let hit = triangle.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &2.0, EPS);
+        assert_eps_eq(&hit.point, &point(0.5, 0.25, 0.0), EPS);
+        assert_eps_eq(
+            &hit.barycentric.unwrap(),
+            &DVec3::new(0.25, 0.125, 0.625),
+            EPS,
+        );
+
+        // the triangle is behind the ray
+        let ray = Ray::new(point(0.5, 0.25, 2.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(triangle.intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_geometric_intersect() {
+        let p0 = point(0.0, 1.0, 0.0);
+        let p1 = point(-1.0, 0.0, 0.0);
+        let p2 = point(1.0, 0.0, 0.0);
+        let triangle = Triangle::new(p0, p1, p2);
+
+        // agrees with moller-trumbore inside the triangle, from either side
+        for &z in &[2.0, -2.0] {
+            let ray = Ray::new(point(0.5, 0.25, z), vector(0.0, 0.0, -z));
+            let hit = triangle.intersect(&ray).unwrap();
+            let p = triangle.geometric_intersect(&ray).unwrap();
+            assert_eps_eq(&p, &hit.point, EPS);
+        }
+
+        // and misses outside of each edge in turn
+        for &(x, y) in &[(0.0, -0.5), (0.75, 0.75), (-0.75, 0.75)] {
+            let ray = Ray::new(point(x, y, 2.0), vector(0.0, 0.0, -1.0));
+            assert_eq!(triangle.intersect(&ray), None);
+            assert_eq!(triangle.geometric_intersect(&ray), None);
+        }
+    }
+
+    #[test]
+    fn test_triangle_sample() {
+        let p0 = point(0.0, 1.0, 0.0);
+        let p1 = point(-1.0, 0.0, 0.0);
+        let p2 = point(1.0, 0.0, 0.0);
+        let triangle = Triangle::new(p0, p1, p2);
+        assert_eps_eq(&triangle.area(), &1.0, EPS);
+
+        for &u in [(0.25, 0.5), (0.5, 0.5), (0.9, 0.1), (0.1, 0.9)].iter() {
+            let sample = triangle.sample(u);
+            assert_eps_eq(&sample.pdf, &1.0, EPS);
+            assert_eps_eq(&sample.normal, &triangle.normal, EPS);
+
+            // a ray fired straight at the sampled point hits the triangle
+            // right there
+            let origin = sample.point + vector(0.0, 0.0, 1.0);
+            let ray = Ray::new(origin, vector(0.0, 0.0, -1.0));
+            let hit = triangle.intersect(&ray).unwrap();
+            assert_eps_eq(&hit.point, &sample.point, EPS);
+        }
+    }
+
+    #[test]
+    fn test_triangle_vertex_normals() {
+        let p0 = point(0.0, 1.0, 0.0);
+        let p1 = point(-1.0, 0.0, 0.0);
+        let p2 = point(1.0, 0.0, 0.0);
+        let s = 0.5f64.sqrt();
+        let normals = [
+            normal(0.0, s, s),
+            normal(-s, 0.0, s),
+            normal(s, 0.0, s),
+        ];
+        let triangle = Triangle::new(p0, p1, p2).with_normals(normals);
+
+        // the vertex normals are on the +z side, so the geometric normal
+        // turns to face the same way
+        let ray = Ray::new(point(0.5, 0.25, 2.0), vector(0.0, 0.0, -1.0));
+        let hit = triangle.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.normal, &normal(0.0, 0.0, 1.0), EPS);
+        let b = hit.barycentric.unwrap();
+        let expected =
+            (normals[0] * b.x + normals[1] * b.y + normals[2] * b.z)
+                .normalized();
+        assert_eps_eq(&hit.shading_normal, &expected, EPS);
+        assert_eps_eq(&triangle.normal(hit.point), &expected, EPS);
+
+        // at the vertices the normals are the vertex normals
+        assert_eps_eq(&triangle.normal(p0), &normals[0], EPS);
+        assert_eps_eq(&triangle.normal(p1), &normals[1], EPS);
+        assert_eps_eq(&triangle.normal(p2), &normals[2], EPS);
+        let centre = point(0.0, 1.0 / 3.0, 0.0);
+        let expected = normal(0.0, 1.0, 3.0).normalized();
+        assert_eps_eq(&triangle.normal(centre), &expected, EPS);
+    }
+
+    #[test]
+    fn test_triangle_vertex_uvs() {
+        let p0 = point(0.0, 1.0, 0.0);
+        let p1 = point(-1.0, 0.0, 0.0);
+        let p2 = point(1.0, 0.0, 0.0);
+        let triangle = Triangle::new(p0, p1, p2).with_uvs([
+            DVec2::new(0.5, 1.0),
+            DVec2::new(0.0, 0.0),
+            DVec2::new(1.0, 0.0),
+        ]);
+
+        let ray = Ray::new(point(0.5, 0.25, 2.0), vector(0.0, 0.0, -1.0));
+        let hit = triangle.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.uv, &DVec2::new(0.75, 0.25), EPS);
+        // without vertex normals the triangle is flat shaded
+        assert_eq!(hit.shading_normal, hit.normal);
+        assert_eps_eq(&hit.normal, &triangle.normal, EPS);
+    }
+
+    /// A closed, lumpy sphere: an octahedron split `levels` times, with its
+    /// vertices pushed out to random distances from the origin.
+    fn lumpy_sphere(levels: usize, rng: &mut StdRng) -> Vec<[Point3; 3]> {
+        let mut vertices = vec![
+            vector(1.0, 0.0, 0.0),
+            vector(-1.0, 0.0, 0.0),
+            vector(0.0, 1.0, 0.0),
+            vector(0.0, -1.0, 0.0),
+            vector(0.0, 0.0, 1.0),
+            vector(0.0, 0.0, -1.0),
+        ];
+        let mut faces = vec![
+            [0, 2, 4],
+            [2, 1, 4],
+            [1, 3, 4],
+            [3, 0, 4],
+            [2, 0, 5],
+            [1, 2, 5],
+            [3, 1, 5],
+            [0, 3, 5],
+        ];
+        for _ in 0..levels {
+            let mut middles = HashMap::new();
+            let mut middle = |a: usize, b: usize| {
+                *middles.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    let m = (vertices[a] + vertices[b]).normalized();
+                    vertices.push(m);
+                    vertices.len() - 1
+                })
+            };
+            faces = faces
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let (ab, bc) = (middle(a, b), middle(b, c));
+                    let ca = middle(c, a);
+                    vec![[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        let points: Vec<Point3> = vertices
+            .iter()
+            .map(|&v| Point3::origin() + v * rng.gen_range(0.8, 1.2))
+            .collect();
+        faces
+            .iter()
+            .map(|&[a, b, c]| [points[a], points[b], points[c]])
+            .collect()
+    }
+
+    fn mesh(faces: &[[Point3; 3]], method: IntersectMethod) -> Vec<Triangle> {
+        faces
+            .iter()
+            .map(|&[a, b, c]| Triangle::new(a, b, c).with_method(method))
+            .collect()
+    }
+
+    #[test]
+    fn test_watertight_intersect() {
+        let p0 = point(0.0, 1.0, 0.0);
+        let p1 = point(-1.0, 0.0, 0.0);
+        let p2 = point(1.0, 0.0, 0.0);
+        let triangle = Triangle::new(p0, p1, p2)
+            .with_method(IntersectMethod::Watertight);
+
+        let ray = Ray::new(point(0.5, 0.25, 2.0), vector(0.0, 0.0, -1.0));
+        let hit = triangle.intersect(&ray).unwrap();
+        assert_eps_eq(&hit.t, &2.0, EPS);
+        assert_eps_eq(&hit.point, &point(0.5, 0.25, 0.0), EPS);
+        assert_eps_eq(
+            &hit.barycentric.unwrap(),
+            &DVec3::new(0.25, 0.125, 0.625),
+            EPS,
+        );
+
+        // parallel, behind and beside the triangle
+        let ray = Ray::new(point(0.0, -1.0, -2.0), vector(0.0, 1.0, 0.0));
+        assert_eq!(triangle.intersect(&ray), None);
+        let ray = Ray::new(point(0.0, 0.5, 2.0), vector(0.0, 0.0, 1.0));
+        assert_eq!(triangle.intersect(&ray), None);
+        let ray = Ray::new(point(0.0, 1.5, 2.0), vector(0.0, 0.0, -1.0));
+        assert_eq!(triangle.intersect(&ray), None);
+    }
+
+    #[test]
+    fn test_watertight_matches_moller_trumbore() {
+        let mut rng = StdRng::seed_from_u64(47);
+        let random_point = |rng: &mut StdRng, size: f64| {
+            point(
+                rng.gen_range(-size, size),
+                rng.gen_range(-size, size),
+                rng.gen_range(-size, size),
+            )
+        };
+        for _ in 0..1000 {
+            let (p0, p1, p2) = (
+                random_point(&mut rng, 1.0),
+                random_point(&mut rng, 1.0),
+                random_point(&mut rng, 1.0),
+            );
+            let origin = random_point(&mut rng, 3.0);
+            let target = random_point(&mut rng, 1.0);
+            let ray = Ray::new(origin, target - origin);
+
+            let fast = Triangle::new(p0, p1, p2);
+            let watertight = Triangle::new(p0, p1, p2)
+                .with_method(IntersectMethod::Watertight);
+            let (a, b) = (fast.intersect(&ray), watertight.intersect(&ray));
+            assert_eq!(a.is_some(), b.is_some());
+            if let (Some(a), Some(b)) = (a, b) {
+                assert_eps_eq(&a.t, &b.t, 1.0e-9);
+                assert_eps_eq(&a.barycentric, &b.barycentric, 1.0e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_watertight_shared_edges() {
+        // a grid of squares on the z = 0 plane, split along alternating
+        // diagonals so that vertices are shared by four or eight triangles
+        let mut faces = Vec::new();
+        for j in 0..4 {
+            for i in 0..4 {
+                let (x, y) = (i as f64 * 0.25, j as f64 * 0.25);
+                let a = point(x, y, 0.0);
+                let b = point(x + 0.25, y, 0.0);
+                let c = point(x + 0.25, y + 0.25, 0.0);
+                let d = point(x, y + 0.25, 0.0);
+                if (i + j) % 2 == 0 {
+                    faces.push([a, b, c]);
+                    faces.push([a, c, d]);
+                } else {
+                    faces.push([a, b, d]);
+                    faces.push([b, c, d]);
+                }
+            }
+        }
+        let grid = mesh(&faces, IntersectMethod::Watertight);
+
+        // straight and slanted rays exactly through every vertex, and
+        // through points on every edge
+        let directions = [
+            vector(0.0, 0.0, -1.0),
+            vector(0.3, -0.2, -1.0),
+            vector(-1.0, 0.5, -0.25),
+        ];
+        for face in faces.iter() {
+            for k in 0..3 {
+                let (a, b) = (face[k], face[(k + 1) % 3]);
+                for &f in [0.0, 0.5, 0.25, 1.0 / 3.0].iter() {
+                    let target = a.lerp(b, f);
+                    if target.x.min(target.y) == 0.0
+                        || target.x.max(target.y) == 1.0
+                    {
+                        continue;
+                    }
+                    for &d in directions.iter() {
+                        let ray = Ray::new(target - d * 2.0, d);
+                        let hit = grid.intersect(&ray);
+                        assert!(hit.is_some(), "{:?} missed", ray);
+                        assert_eps_eq(&hit.unwrap().t, &2.0, 1.0e-9);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_watertight_closed_mesh() {
+        // every ray from inside a closed mesh hits it, even when aimed
+        // right at the edges and vertices between its triangles
+        let mut rng = StdRng::seed_from_u64(7);
+        let faces = lumpy_sphere(3, &mut rng);
+        let sphere = Bvh::new(mesh(&faces, IntersectMethod::Watertight));
+        for _ in 0..10 {
+            let origin = point(
+                rng.gen_range(-0.3, 0.3),
+                rng.gen_range(-0.3, 0.3),
+                rng.gen_range(-0.3, 0.3),
+            );
+            for face in faces.iter() {
+                for k in 0..3 {
+                    let (a, b) = (face[k], face[(k + 1) % 3]);
+                    for &target in [a, a.lerp(b, 0.5), a.lerp(b, 0.3)].iter() {
+                        let ray = Ray::new(origin, target - origin);
+                        assert!(sphere.intersect(&ray).is_some());
+                    }
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,353 @@
+use super::math::{vector, Vector3};
+use std::f64::consts::PI;
+
+/// Map a point in the unit square to a point on the unit disk. Concentric
+/// rings of the square map to concentric rings of the disk, which keeps
+/// stratified samples well distributed (Shirley and Chiu 1997).
+pub fn concentric_disk(u: f64, v: f64) -> (f64, f64) {
+    // remap to [-1, 1] x [-1, 1]
+    let u = 2.0 * u - 1.0;
+    let v = 2.0 * v - 1.0;
+    if u == 0.0 && v == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, theta) = if u.abs() > v.abs() {
+        (u, PI / 4.0 * (v / u))
+    } else {
+        (v, PI / 2.0 - PI / 4.0 * (u / v))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Map a point in the unit square to a direction in the hemisphere around
+/// +z, distributed with density cos(theta) / pi. Points uniformly distributed
+/// on the disk project up onto a cosine distribution (Malley's method).
+pub fn cosine_hemisphere(u: f64, v: f64) -> Vector3 {
+    let (x, y) = concentric_disk(u, v);
+    let z = f64::max(0.0, 1.0 - x * x - y * y).sqrt();
+    vector(x, y, z)
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f64) -> f64 {
+    cos_theta / PI
+}
+
+/// Map a point in the unit square to a direction distributed uniformly
+/// within the cone of directions around +z with cos(theta) at least
+/// `cos_theta_max`.
+pub fn uniform_cone(u: f64, v: f64, cos_theta_max: f64) -> Vector3 {
+    let cos_theta = (1.0 - u) + u * cos_theta_max;
+    let sin_theta = f64::max(0.0, 1.0 - cos_theta * cos_theta).sqrt();
+    let phi = 2.0 * PI * v;
+    vector(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+pub fn uniform_cone_pdf(cos_theta_max: f64) -> f64 {
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
+
+/// Map a point in the unit square to a direction by longitude and latitude,
+/// the way latitude-longitude environment maps are laid out: u goes around
+/// the y axis with the center of the map at -z and u = 0.75 at +x, and v
+/// goes from +y at the top to -y at the bottom.
+pub fn latlong_to_direction(u: f64, v: f64) -> Vector3 {
+    let theta = PI * v;
+    let phi = 2.0 * PI * (u - 0.5);
+    let (sin_theta, cos_theta) = theta.sin_cos();
+    vector(sin_theta * phi.sin(), cos_theta, -sin_theta * phi.cos())
+}
+
+/// The inverse of `latlong_to_direction` for the unit vector `w`.
+pub fn direction_to_latlong(w: Vector3) -> (f64, f64) {
+    let theta = w.y.clamp(-1.0, 1.0).acos();
+    let phi = w.x.atan2(-w.z);
+    (0.5 + phi / (2.0 * PI), theta / PI)
+}
+
+/// Build two unit vectors that together with the unit vector `n` form an
+/// orthonormal basis (Duff et al. 2017).
+pub fn orthonormal_basis(n: Vector3) -> (Vector3, Vector3) {
+    let sign = 1.0f64.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    let s = vector(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+    let t = vector(b, sign + n.y * n.y * a, -n.y);
+    (s, t)
+}
+
+/// Express the direction `local`, given relative to +z, relative to the unit
+/// vector `n` instead.
+pub fn to_world(local: Vector3, n: Vector3) -> Vector3 {
+    let (s, t) = orthonormal_basis(n);
+    s * local.x + t * local.y + n * local.z
+}
+
+/// A piecewise constant distribution over [0, 1), made of `n` equally wide
+/// steps whose heights are given by a function sampled at `n` points.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    func_int: f64,
+}
+
+impl Distribution1D {
+    /// Build the distribution of the non-negative step heights `func`. If
+    /// they're all zero the distribution is uniform.
+    pub fn new(func: Vec<f64>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for i in 0..n {
+            cdf.push(cdf[i] + func[i] / n as f64);
+        }
+
+        let func_int = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if func_int == 0.0 {
+                i as f64 / n as f64
+            } else {
+                *c / func_int
+            };
+        }
+
+        Distribution1D {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// The integral of the step function over [0, 1).
+    pub fn integral(&self) -> f64 {
+        self.func_int
+    }
+
+    /// Map `u` in [0, 1) to a point in [0, 1) distributed proportionally to
+    /// the step function. Also returns the density at that point and the
+    /// index of the step it lies in.
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        // the last step whose cdf value is at most u
+        let n = self.count();
+        let offset = match self.cdf.iter().rposition(|&c| c <= u) {
+            Some(i) => i.min(n - 1),
+            None => 0,
+        };
+
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+
+        let pdf = if self.func_int > 0.0 {
+            self.func[offset] / self.func_int
+        } else {
+            0.0
+        };
+        ((offset as f64 + du) / n as f64, pdf, offset)
+    }
+
+    /// The density of sampling the point `x` in [0, 1).
+    pub fn pdf(&self, x: f64) -> f64 {
+        if self.func_int == 0.0 {
+            return 0.0;
+        }
+        let i = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        self.func[i] / self.func_int
+    }
+}
+
+/// A piecewise constant distribution over the unit square, made of a grid of
+/// `nu` by `nv` cells. A point is sampled by picking a row from the marginal
+/// distribution of the rows and then a point within that row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `func` holds the cell values row by row, with `nu` cells per row.
+    pub fn new(func: &[f64], nu: usize, nv: usize) -> Distribution2D {
+        let conditional: Vec<_> = func
+            .chunks(nu)
+            .take(nv)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(
+            conditional.iter().map(Distribution1D::integral).collect(),
+        );
+
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Map `u` in the unit square to a point distributed proportionally to
+    /// the cell values, along with its density.
+    pub fn sample_continuous(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (y, pdf_y, row) = self.marginal.sample_continuous(u.1);
+        let (x, pdf_x, _) = self.conditional[row].sample_continuous(u.0);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    /// The density of sampling the point `p` in the unit square.
+    pub fn pdf(&self, p: (f64, f64)) -> f64 {
+        let nu = self.conditional[0].count();
+        let nv = self.marginal.count();
+        let iu = ((p.0 * nu as f64) as usize).min(nu - 1);
+        let iv = ((p.1 * nv as f64) as usize).min(nv - 1);
+
+        let integral = self.marginal.integral();
+        if integral == 0.0 {
+            return 0.0;
+        }
+        self.conditional[iv].func[iu] / integral
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::math::test_util::assert_eps_eq;
+
+    const EPS: f64 = 1.0e-9;
+
+    #[test]
+    fn test_concentric_disk_bounds() {
+        for i in 0..=10 {
+            for j in 0..=10 {
+                let (x, y) = concentric_disk(i as f64 / 10.0, j as f64 / 10.0);
+                assert!(x * x + y * y <= 1.0 + EPS);
+            }
+        }
+        assert_eq!(concentric_disk(0.5, 0.5), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_cosine_hemisphere() {
+        for i in 0..=10 {
+            for j in 0..=10 {
+                let dir = cosine_hemisphere(i as f64 / 10.0, j as f64 / 10.0);
+                assert!(dir.z >= 0.0);
+                assert_eps_eq(&dir.mag(), &1.0, EPS);
+            }
+        }
+    }
+
+    #[test]
+    fn test_orthonormal_basis() {
+        let normals = [
+            vector(0.0, 0.0, 1.0),
+            vector(0.0, 0.0, -1.0),
+            vector(1.0, 2.0, 3.0).normalized(),
+            vector(-0.3, 0.1, -0.8).normalized(),
+        ];
+
+        for &n in normals.iter() {
+            let (s, t) = orthonormal_basis(n);
+            assert_eps_eq(&s.mag(), &1.0, EPS);
+            assert_eps_eq(&t.mag(), &1.0, EPS);
+            assert_eps_eq(&s.dot(t), &0.0, EPS);
+            assert_eps_eq(&s.dot(n), &0.0, EPS);
+            assert_eps_eq(&t.dot(n), &0.0, EPS);
+        }
+    }
+
+    #[test]
+    fn test_to_world() {
+        let n = vector(1.0, 1.0, 0.0).normalized();
+        let up = to_world(vector(0.0, 0.0, 1.0), n);
+        assert_eps_eq(&up, &n, EPS);
+    }
+
+    #[test]
+    fn test_uniform_cone() {
+        let cos_theta_max = f64::to_radians(10.0).cos();
+        for i in 0..=10 {
+            for j in 0..=10 {
+                let (u, v) = (i as f64 / 10.0, j as f64 / 10.0);
+                let dir = uniform_cone(u, v, cos_theta_max);
+                assert_eps_eq(&dir.mag(), &1.0, EPS);
+                assert!(dir.z >= cos_theta_max - EPS);
+            }
+        }
+    }
+
+    #[test]
+    fn test_latlong_round_trip() {
+        assert_eps_eq(
+            &latlong_to_direction(0.5, 0.5),
+            &vector(0.0, 0.0, -1.0),
+            EPS,
+        );
+        assert_eps_eq(
+            &latlong_to_direction(0.75, 0.5),
+            &vector(1.0, 0.0, 0.0),
+            EPS,
+        );
+        assert_eps_eq(
+            &latlong_to_direction(0.3, 0.0),
+            &vector(0.0, 1.0, 0.0),
+            EPS,
+        );
+
+        for &(u, v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)].iter() {
+            let (u2, v2) = direction_to_latlong(latlong_to_direction(u, v));
+            assert_eps_eq(&(u2, v2), &(u, v), EPS);
+        }
+    }
+
+    #[test]
+    fn test_distribution_1d() {
+        let d = Distribution1D::new(vec![1.0, 3.0, 0.0, 4.0]);
+        assert_eps_eq(&d.integral(), &2.0, EPS);
+
+        // a quarter of the mass is in the second step, after an eighth in
+        // the first
+        let (x, pdf, offset) = d.sample_continuous(0.125 + 0.1875);
+        assert_eq!(offset, 1);
+        assert_eps_eq(&x, &0.375, EPS);
+        assert_eps_eq(&pdf, &1.5, EPS);
+        assert_eps_eq(&d.pdf(x), &pdf, EPS);
+
+        // the empty step is never picked
+        for i in 0..100 {
+            let (x, pdf, offset) = d.sample_continuous(i as f64 / 100.0);
+            assert_ne!(offset, 2);
+            assert!(pdf > 0.0);
+            assert_eps_eq(&d.pdf(x), &pdf, EPS);
+        }
+    }
+
+    #[test]
+    fn test_distribution_1d_all_zero() {
+        let d = Distribution1D::new(vec![0.0, 0.0]);
+        let (x, pdf, _) = d.sample_continuous(0.75);
+        assert_eps_eq(&x, &0.75, EPS);
+        assert_eq!(pdf, 0.0);
+    }
+
+    #[test]
+    fn test_distribution_2d() {
+        // 2 by 2 cells with all of the weight in the bottom right one
+        let d = Distribution2D::new(&[0.0, 0.0, 0.0, 1.0], 2, 2);
+        for i in 0..10 {
+            for j in 0..10 {
+                let u = (i as f64 / 10.0, j as f64 / 10.0);
+                let ((x, y), pdf) = d.sample_continuous(u);
+                assert!(x >= 0.5 && y >= 0.5);
+                assert_eps_eq(&pdf, &4.0, EPS);
+                assert_eps_eq(&d.pdf((x, y)), &4.0, EPS);
+            }
+        }
+        assert_eq!(d.pdf((0.25, 0.25)), 0.0);
+    }
+}
@@ -0,0 +1,448 @@
+use super::color::Color;
+use super::hdr::HdrImage;
+use super::light::{Light, LightSample};
+use super::math::{vector, Point3, Ray, Vector3};
+use super::sampling::{self, Distribution2D};
+use std::f64::consts::PI;
+
+/// Angular radius of the sun as seen from the earth, in radians.
+const SUN_ANGULAR_RADIUS: f64 = 0.004_65;
+
+/// Luminance of the sun before it passes through the atmosphere, in the
+/// same units as the sky (kcd/m^2).
+const SUN_LUMINANCE: f64 = 2.0e6;
+
+/// The clear sky model of Preetham, Shirley and Smits, "A Practical Analytic
+/// Model for Daylight" (1999). Radiance is returned in kcd/m^2, converted
+/// from the model's CIE xyY to linear sRGB. The sky is black below the
+/// horizon; there is no ground.
+///
+/// Directions use the same frame as the rest of the scene: y is up, an
+/// azimuth of 0 points towards -z and an azimuth of 90 degrees towards +x.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sky {
+    /// Unit vector pointing towards the sun.
+    sun_direction: Vector3,
+    turbidity: f64,
+    /// Luminance and chromaticity (Y, x, y) at the zenith.
+    zenith: [f64; 3],
+    /// Perez distribution coefficients A to E for Y, x and y.
+    perez: [[f64; 5]; 3],
+    /// The Perez function evaluated at the zenith, for normalizing.
+    perez_zenith: [f64; 3],
+    /// Multiplies all radiance, to bring it into the scene's units.
+    scale: f64,
+}
+
+impl Sky {
+    /// `elevation` is the sun's angle above the horizon and `azimuth` its
+    /// angle around the vertical axis, both in radians. `turbidity` measures
+    /// haze, from about 2 for a very clear sky to 10 for a hazy one.
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64) -> Sky {
+        let t = turbidity;
+        let theta_s = PI / 2.0 - elevation;
+        let sun_direction = vector(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance =
+            (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |c: [f64; 4]| {
+            c[0] * theta_s.powi(3)
+                + c[1] * theta_s.powi(2)
+                + c[2] * theta_s
+                + c[3]
+        };
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+        let perez_zenith = [
+            perez_function(&perez[0], 0.0, theta_s),
+            perez_function(&perez[1], 0.0, theta_s),
+            perez_function(&perez[2], 0.0, theta_s),
+        ];
+
+        Sky {
+            sun_direction,
+            turbidity,
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            perez,
+            perez_zenith,
+            scale: 1.0,
+        }
+    }
+
+    /// Multiply the radiance of the sky and the sun by `scale`.
+    pub fn with_scale(mut self, scale: f64) -> Sky {
+        self.scale = scale;
+        self
+    }
+
+    pub fn sun_direction(&self) -> Vector3 {
+        self.sun_direction
+    }
+
+    /// Radiance of the sky, without the sun, arriving from the unit
+    /// direction `w`.
+    pub fn radiance(&self, w: Vector3) -> Color {
+        if w.y <= 0.0 {
+            return Color::black();
+        }
+
+        let theta = w.y.min(1.0).acos();
+        let gamma = w.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+        let value = |i: usize| {
+            self.zenith[i] * perez_function(&self.perez[i], theta, gamma)
+                / self.perez_zenith[i]
+        };
+        xyy_to_rgb(value(0), value(1), value(2)) * self.scale
+    }
+
+    /// Radiance of the sun's disk after passing through the atmosphere,
+    /// using the Rayleigh and aerosol transmittance from the paper's
+    /// appendix at a red, green and blue wavelength.
+    pub fn sun_radiance(&self) -> Color {
+        let cos_theta_s = self.sun_direction.y;
+        if cos_theta_s <= 0.0 {
+            return Color::black();
+        }
+
+        // relative optical mass of the air the sunlight passes through
+        let theta_s_degrees = cos_theta_s.acos().to_degrees();
+        let mass = 1.0
+            / (cos_theta_s + 0.15 * (93.885 - theta_s_degrees).powf(-1.253));
+
+        let beta = 0.046_08 * self.turbidity - 0.045_86;
+        let transmittance = |lambda: f64| {
+            let rayleigh = (-0.008_735 * lambda.powf(-4.08) * mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+            SUN_LUMINANCE * rayleigh * aerosol * self.scale
+        };
+
+        // wavelengths in micrometers
+        Color::new(
+            transmittance(0.68),
+            transmittance(0.55),
+            transmittance(0.44),
+        )
+    }
+
+    /// Render the sky, without the sun, into a latitude-longitude map laid
+    /// out as in `sampling::latlong_to_direction`.
+    pub fn bake(&self, width: usize, height: usize) -> HdrImage {
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let u = (x as f64 + 0.5) / width as f64;
+                let v = (y as f64 + 0.5) / height as f64;
+                pixels
+                    .push(self.radiance(sampling::latlong_to_direction(u, v)));
+            }
+        }
+        HdrImage::new(width, height, pixels)
+    }
+}
+
+/// The Perez et al. sky luminance distribution for a direction at angle
+/// `theta` from the zenith and `gamma` from the sun.
+fn perez_function(c: &[f64; 5], theta: f64, gamma: f64) -> f64 {
+    let cos_gamma = gamma.cos();
+    (1.0 + c[0] * (c[1] / theta.cos()).exp())
+        * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+}
+
+/// Convert CIE luminance `big_y` and chromaticity (x, y) to linear sRGB.
+fn xyy_to_rgb(big_y: f64, x: f64, y: f64) -> Color {
+    if y == 0.0 {
+        return Color::black();
+    }
+    let big_x = x / y * big_y;
+    let big_z = (1.0 - x - y) / y * big_y;
+    // colors outside of the sRGB gamut come out slightly negative
+    Color::new(
+        f64::max(0.0, 3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z),
+        f64::max(0.0, -0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z),
+        f64::max(0.0, 0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z),
+    )
+}
+
+/// Resolution of the map used to importance sample the sky. The height is
+/// even so that the horizon falls between rows.
+const SKY_MAP_WIDTH: usize = 128;
+const SKY_MAP_HEIGHT: usize = 64;
+
+/// The sky as a light surrounding the scene. Directions are importance
+/// sampled from a baked map of the sky, but the radiance itself is always
+/// evaluated from the model.
+pub struct SkyLight {
+    sky: Sky,
+    distribution: Distribution2D,
+    scene_radius: f64,
+    /// Average radiance over the whole sphere of directions.
+    average: Color,
+}
+
+impl SkyLight {
+    /// `scene_radius` is the radius of a sphere bounding the scene, which
+    /// determines how much power the light delivers to it.
+    pub fn new(sky: Sky, scene_radius: f64) -> SkyLight {
+        let map = sky.bake(SKY_MAP_WIDTH, SKY_MAP_HEIGHT);
+
+        let mut func = Vec::with_capacity(SKY_MAP_WIDTH * SKY_MAP_HEIGHT);
+        let mut average = Color::black();
+        let mut total_weight = 0.0;
+        for y in 0..SKY_MAP_HEIGHT {
+            let sin_theta =
+                (PI * (y as f64 + 0.5) / SKY_MAP_HEIGHT as f64).sin();
+            for x in 0..SKY_MAP_WIDTH {
+                let radiance = map.get(x, y);
+                func.push(radiance.luminance() * sin_theta);
+                average += radiance * sin_theta;
+                total_weight += sin_theta;
+            }
+        }
+
+        SkyLight {
+            sky,
+            distribution: Distribution2D::new(
+                &func,
+                SKY_MAP_WIDTH,
+                SKY_MAP_HEIGHT,
+            ),
+            scene_radius,
+            average: average * (1.0 / total_weight),
+        }
+    }
+}
+
+impl Light for SkyLight {
+    fn sample_li(&self, _point: Point3, u: (f64, f64)) -> Option<LightSample> {
+        let ((u, v), map_pdf) = self.distribution.sample_continuous(u);
+        let sin_theta = (PI * v).sin();
+        if map_pdf == 0.0 || sin_theta == 0.0 {
+            return None;
+        }
+
+        let wi = sampling::latlong_to_direction(u, v);
+        Some(LightSample {
+            wi,
+            radiance: self.sky.radiance(wi),
+            pdf: map_pdf / (2.0 * PI * PI * sin_theta),
+            distance: f64::INFINITY,
+            normal: None,
+        })
+    }
+
+    fn pdf(&self, _point: Point3, wi: Vector3) -> f64 {
+        let (u, v) = sampling::direction_to_latlong(wi.normalized());
+        let sin_theta = (PI * v).sin();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf((u, v)) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn power(&self) -> Color {
+        self.average * (PI * self.scene_radius * self.scene_radius)
+    }
+
+    fn le(&self, ray: &Ray) -> Color {
+        self.sky.radiance(ray.direction.normalized())
+    }
+}
+
+/// The disk of the sun, seen through the atmosphere of a `Sky`.
+pub struct SunLight {
+    direction: Vector3,
+    radiance: Color,
+    cos_theta_max: f64,
+    scene_radius: f64,
+}
+
+impl SunLight {
+    /// `scene_radius` is the radius of a sphere bounding the scene, which
+    /// determines how much power the light delivers to it.
+    pub fn new(sky: &Sky, scene_radius: f64) -> SunLight {
+        SunLight {
+            direction: sky.sun_direction(),
+            radiance: sky.sun_radiance(),
+            cos_theta_max: SUN_ANGULAR_RADIUS.cos(),
+            scene_radius,
+        }
+    }
+}
+
+impl Light for SunLight {
+    fn sample_li(&self, _point: Point3, u: (f64, f64)) -> Option<LightSample> {
+        if self.radiance == Color::black() {
+            return None;
+        }
+
+        let local = sampling::uniform_cone(u.0, u.1, self.cos_theta_max);
+        Some(LightSample {
+            wi: sampling::to_world(local, self.direction),
+            radiance: self.radiance,
+            pdf: sampling::uniform_cone_pdf(self.cos_theta_max),
+            distance: f64::INFINITY,
+            normal: None,
+        })
+    }
+
+    fn pdf(&self, _point: Point3, wi: Vector3) -> f64 {
+        if wi.normalized().dot(self.direction) < self.cos_theta_max {
+            return 0.0;
+        }
+        sampling::uniform_cone_pdf(self.cos_theta_max)
+    }
+
+    fn power(&self) -> Color {
+        // radiance times the sun's solid angle is the irradiance it delivers
+        let solid_angle = 2.0 * PI * (1.0 - self.cos_theta_max);
+        self.radiance
+            * (solid_angle * PI * self.scene_radius * self.scene_radius)
+    }
+
+    fn le(&self, ray: &Ray) -> Color {
+        if ray.direction.normalized().dot(self.direction) < self.cos_theta_max {
+            Color::black()
+        } else {
+            self.radiance
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::math::point;
+    use super::super::math::test_util::assert_eps_eq;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const EPS: f64 = 1.0e-9;
+
+    #[test]
+    fn test_sun_direction() {
+        let sky = Sky::new(f64::to_radians(90.0), 0.0, 3.0);
+        assert_eps_eq(&sky.sun_direction(), &vector(0.0, 1.0, 0.0), EPS);
+
+        let sky = Sky::new(0.0, f64::to_radians(90.0), 3.0);
+        assert_eps_eq(&sky.sun_direction(), &vector(1.0, 0.0, 0.0), EPS);
+    }
+
+    #[test]
+    fn test_sky_radiance() {
+        let sky = Sky::new(f64::to_radians(30.0), 0.0, 3.0);
+
+        // blue overhead and brighter towards the sun
+        let zenith = sky.radiance(vector(0.0, 1.0, 0.0));
+        assert!(zenith.b() > zenith.r());
+        let near_sun = sky.radiance(
+            (sky.sun_direction() + vector(0.0, 0.1, 0.0)).normalized(),
+        );
+        let away_from_sun = sky.radiance(vector(0.0, 0.6, 1.0).normalized());
+        assert!(near_sun.luminance() > away_from_sun.luminance());
+
+        // matches the zenith luminance of the model
+        assert_eps_eq(&zenith.luminance(), &sky.zenith[0], 1.0e-2);
+        assert_eq!(sky.radiance(vector(0.0, -1.0, 0.0)), Color::black());
+    }
+
+    #[test]
+    fn test_scale() {
+        let sky = Sky::new(f64::to_radians(30.0), 0.0, 3.0);
+        let scaled = sky.clone().with_scale(0.5);
+        let w = vector(0.3, 0.8, 0.1).normalized();
+        assert_eps_eq(
+            &scaled.radiance(w).luminance(),
+            &(0.5 * sky.radiance(w).luminance()),
+            EPS,
+        );
+        assert_eps_eq(
+            &scaled.sun_radiance().luminance(),
+            &(0.5 * sky.sun_radiance().luminance()),
+            1.0e-6,
+        );
+    }
+
+    #[test]
+    fn test_sun_reddens_near_horizon() {
+        let high = Sky::new(f64::to_radians(60.0), 0.0, 3.0).sun_radiance();
+        let low = Sky::new(f64::to_radians(5.0), 0.0, 3.0).sun_radiance();
+        assert!(low.r() / low.b() > high.r() / high.b());
+        assert!(low.luminance() < high.luminance());
+    }
+
+    #[test]
+    fn test_sky_light_sampling() {
+        let light =
+            SkyLight::new(Sky::new(f64::to_radians(40.0), 1.0, 3.0), 1.0);
+        let p = point(0.0, 0.0, 0.0);
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let sample = light.sample_li(p, (rng.gen(), rng.gen())).unwrap();
+            assert!(sample.wi.y > 0.0);
+            assert_eps_eq(&light.pdf(p, sample.wi), &sample.pdf, 1.0e-6);
+            let seen = light.le(&Ray::new(p, sample.wi)).luminance();
+            assert_eps_eq(&seen, &sample.radiance.luminance(), 1.0e-9);
+        }
+    }
+
+    #[test]
+    fn test_sun_light() {
+        let sky = Sky::new(f64::to_radians(45.0), 0.0, 3.0);
+        let sun = SunLight::new(&sky, 1.0);
+        let p = point(0.0, 0.0, 0.0);
+
+        let sample = sun.sample_li(p, (0.3, 0.6)).unwrap();
+        assert!(sample.wi.dot(sky.sun_direction()) >= SUN_ANGULAR_RADIUS.cos());
+        assert_eq!(sun.le(&Ray::new(p, sample.wi)), sky.sun_radiance());
+        assert_eq!(sun.pdf(p, sample.wi), sample.pdf);
+
+        let away = vector(0.0, 1.0, 0.0);
+        assert_eq!(sun.le(&Ray::new(p, away)), Color::black());
+        assert_eq!(sun.pdf(p, away), 0.0);
+    }
+
+    #[test]
+    fn test_bake() {
+        let sky = Sky::new(f64::to_radians(20.0), 0.0, 4.0);
+        let map = sky.bake(8, 4);
+        assert_eq!((map.width(), map.height()), (8, 4));
+        assert_eq!(map.get(0, 3), Color::black());
+        assert_eq!(
+            map.get(2, 0),
+            sky.radiance(sampling::latlong_to_direction(2.5 / 8.0, 0.5 / 4.0))
+        );
+    }
+}